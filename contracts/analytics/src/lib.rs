@@ -0,0 +1,779 @@
+#![no_std]
+#![allow(clippy::too_many_arguments)]
+
+use soroban_sdk::{
+    contract, contracterror, contractimpl, contracttype, symbol_short, vec, Address, BytesN, Env, Map, Symbol,
+    Vec,
+};
+
+#[cfg(test)]
+mod test;
+
+// Import the orderbook contract
+mod orderbook_wasm {
+    soroban_sdk::contractimport!(
+        file = "../../target/wasm32v1-none/release/darkpool_orderbook.wasm"
+    );
+}
+
+mod registry_wasm {
+    soroban_sdk::contractimport!(
+        file = "../../target/wasm32v1-none/release/darkpool_registry.wasm"
+    );
+}
+
+mod settlement_wasm {
+    soroban_sdk::contractimport!(
+        file = "../../target/wasm32v1-none/release/darkpool_settlement.wasm"
+    );
+}
+
+use orderbook_wasm::{MatchRecord, OrderCommitment, OrderSide, OrderStatus, OrderbookError};
+
+// Storage keys
+const ORDERBOOK_KEY: Symbol = symbol_short!("orderbook");
+const LIQUIDITY_KEY: Symbol = symbol_short!("liquidity");
+const DAILY_REPORTS_KEY: Symbol = symbol_short!("daily_rpt");
+
+// Entries read per orderbook call; the orderbook caps its pages at this
+const PAGE_SIZE: u32 = 100;
+const SECONDS_PER_DAY: u64 = 86_400;
+
+#[contracterror]
+#[derive(Copy, Clone, Debug, Eq, PartialEq, PartialOrd, Ord)]
+#[repr(u32)]
+pub enum AnalyticsError {
+    ArithmeticOverflow = 1,
+    TopOfBookNotPublished = 2,
+    OnlyAdmin = 3,
+    ReportAlreadyExists = 4,
+    DayNotClosed = 5,
+}
+
+/// A match together with the orders it filled
+///
+/// `orders` holds the buy order then the sell order, omitting any that is
+/// no longer stored.
+#[derive(Clone)]
+#[contracttype]
+pub struct MatchDetail {
+    pub match_record: MatchRecord,
+    pub orders: Vec<OrderCommitment>,
+}
+
+/// Net balance movements applied to each side of a match
+///
+/// Settlement moves the notional between the two sides and nothing else;
+/// the fee and rebate on the match record are not charged, so they are
+/// not reported here.
+#[derive(Clone)]
+#[contracttype]
+pub struct MatchFlows {
+    pub buyer_debit: i128,
+    pub seller_credit: i128,
+}
+
+/// Time taken by each stage of a match's lifecycle, in seconds
+///
+/// `submit_to_match` runs from the older order's submission and is None
+/// once neither order is stored. `match_to_settle` is None until the match
+/// settles.
+#[derive(Clone)]
+#[contracttype]
+pub struct MatchLatencies {
+    pub submit_to_match: Option<u64>,
+    pub match_to_settle: Option<u64>,
+}
+
+/// End-of-day summary of an asset's settled matches
+///
+/// `day` counts whole days since the Unix epoch; a match belongs to the
+/// day of its `settled_timestamp`. `volume` is the settled quantity and
+/// `vwap` the quantity-weighted price, 0 when nothing settled.
+#[derive(Clone, Debug, PartialEq, Eq)]
+#[contracttype]
+pub struct DailyReport {
+    pub asset_address: Address,
+    pub day: u64,
+    pub volume: i128,
+    pub trade_count: u32,
+    pub vwap: i128,
+    pub fees: i128,
+    pub snapshot_timestamp: u64,
+}
+
+/// Read-only reporting over an orderbook's orders and matches
+///
+/// Everything here is derived from the orderbook's public getters, so the
+/// views need no hooks in the orderbook and stay out of its contract size.
+#[contract]
+pub struct DarkPoolAnalytics;
+
+#[contractimpl]
+impl DarkPoolAnalytics {
+    /// Initialize the analytics contract
+    ///
+    /// # Arguments
+    /// * `orderbook_address` - The orderbook contract to report on
+    pub fn __constructor(env: Env, orderbook_address: Address) {
+        env.storage().instance().set(&ORDERBOOK_KEY, &orderbook_address);
+    }
+
+    /// Get the orderbook contract address
+    pub fn get_orderbook(env: Env) -> Address {
+        env.storage().instance().get(&ORDERBOOK_KEY).unwrap()
+    }
+
+    /// Publish aggregate matchable quantity per side for an asset
+    ///
+    /// # Arguments
+    /// * `matcher` - Matching engine (the orderbook admin)
+    /// * `asset_address` - The RWA token
+    /// * `buy_qty` - Total matchable buy quantity
+    /// * `sell_qty` - Total matchable sell quantity
+    pub fn set_side_liquidity(
+        env: Env,
+        matcher: Address,
+        asset_address: Address,
+        buy_qty: i128,
+        sell_qty: i128,
+    ) -> Result<(), AnalyticsError> {
+        matcher.require_auth();
+        Self::require_admin(&env, &matcher)?;
+
+        let mut liquidity: Map<Address, (i128, i128)> = env
+            .storage()
+            .instance()
+            .get(&LIQUIDITY_KEY)
+            .unwrap_or(Map::new(&env));
+        liquidity.set(asset_address, (buy_qty, sell_qty));
+        env.storage().instance().set(&LIQUIDITY_KEY, &liquidity);
+        Ok(())
+    }
+
+    /// Get the published (buy, sell) matchable quantity for an asset
+    pub fn get_side_liquidity(env: Env, asset_address: Address) -> (i128, i128) {
+        let liquidity: Map<Address, (i128, i128)> = env
+            .storage()
+            .instance()
+            .get(&LIQUIDITY_KEY)
+            .unwrap_or(Map::new(&env));
+        liquidity.get(asset_address).unwrap_or((0, 0))
+    }
+
+    /// Get the (earliest, latest) submission timestamp across an asset's
+    /// orders, in any status
+    ///
+    /// None when the asset has no orders.
+    pub fn get_order_time_range(env: Env, asset_address: Address) -> Option<(u64, u64)> {
+        let mut range: Option<(u64, u64)> = None;
+        for order in Self::asset_orders(&env, &asset_address).iter() {
+            range = Some(match range {
+                Some((earliest, latest)) => (earliest.min(order.timestamp), latest.max(order.timestamp)),
+                None => (order.timestamp, order.timestamp),
+            });
+        }
+        range
+    }
+
+    /// Get a trader's orders for an asset, optionally filtered by status
+    pub fn get_orders_by_asset_and_trader(
+        env: Env,
+        asset_address: Address,
+        trader: Address,
+        status: Option<OrderStatus>,
+    ) -> Vec<OrderCommitment> {
+        let mut filtered: Vec<OrderCommitment> = vec![&env];
+        for order in Self::asset_orders(&env, &asset_address).iter() {
+            if order.trader == trader {
+                match status {
+                    Some(s) if order.status == s => filtered.push_back(order),
+                    None => filtered.push_back(order),
+                    _ => {}
+                }
+            }
+        }
+        filtered
+    }
+
+    /// Get the number of each trader's orders that have not been filled,
+    /// cancelled or expired, in the order the traders were given
+    pub fn get_order_counts_by_trader(env: Env, traders: Vec<Address>) -> Vec<u32> {
+        let mut counts: Vec<u32> = vec![&env];
+        for trader in traders.iter() {
+            let open = Self::trader_orders(&env, &trader)
+                .iter()
+                .filter(|order| matches!(order.status, OrderStatus::Active | OrderStatus::PartiallyFilled))
+                .count();
+            counts.push_back(open as u32);
+        }
+        counts
+    }
+
+    /// Get a trader's cancelled orders with `from <= cancelled_at <= to`
+    pub fn get_cancelled_orders(env: Env, trader: Address, from: u64, to: u64) -> Vec<OrderCommitment> {
+        let mut cancelled: Vec<OrderCommitment> = vec![&env];
+        for order in Self::trader_orders(&env, &trader).iter() {
+            if order.status == OrderStatus::Cancelled
+                && from <= order.cancelled_at
+                && order.cancelled_at <= to
+            {
+                cancelled.push_back(order);
+            }
+        }
+        cancelled
+    }
+
+    /// Get an asset's matches recorded with `from <= timestamp <= to`, oldest first
+    pub fn get_matches_in_range(env: Env, asset_address: Address, from: u64, to: u64) -> Vec<MatchRecord> {
+        let mut in_range: Vec<MatchRecord> = vec![&env];
+        for m in Self::all_matches(&env).iter() {
+            if m.asset_address == asset_address && from <= m.timestamp && m.timestamp <= to {
+                in_range.push_back(m);
+            }
+        }
+        in_range
+    }
+
+    /// Get a match and both of its orders (where still present) in one read
+    pub fn get_match_detail(env: Env, match_id: BytesN<32>) -> Option<MatchDetail> {
+        let orderbook = Self::orderbook(&env);
+        let match_record = orderbook.get_match(&match_id)?;
+
+        let mut orders: Vec<OrderCommitment> = vec![&env];
+        if let Some(order) = orderbook.get_order(&match_record.buy_commitment) {
+            orders.push_back(order);
+        }
+        if let Some(order) = orderbook.get_order(&match_record.sell_commitment) {
+            orders.push_back(order);
+        }
+        Some(MatchDetail { match_record, orders })
+    }
+
+    /// Get the cash flows settlement applies for a match
+    ///
+    /// The buyer pays and the seller receives the full notional. For
+    /// negative-price matches the notional (and so both flows) is negative.
+    pub fn get_match_flows(env: Env, match_id: BytesN<32>) -> Option<MatchFlows> {
+        let m = Self::orderbook(&env).get_match(&match_id)?;
+
+        // Notional was overflow-checked when the orderbook computed the fee
+        let notional = m.quantity * m.price;
+        Some(MatchFlows {
+            buyer_debit: notional,
+            seller_credit: notional,
+        })
+    }
+
+    /// Get how long a match took from submission to match and from match
+    /// to settlement
+    pub fn get_match_latencies(env: Env, match_id: BytesN<32>) -> Option<MatchLatencies> {
+        let orderbook = Self::orderbook(&env);
+        let m = orderbook.get_match(&match_id)?;
+
+        let mut submitted_at: Option<u64> = None;
+        for commitment in [&m.buy_commitment, &m.sell_commitment] {
+            if let Some(order) = orderbook.get_order(commitment) {
+                submitted_at = Some(submitted_at.map_or(order.timestamp, |t| t.min(order.timestamp)));
+            }
+        }
+        let match_to_settle = if m.is_settled {
+            Some(m.settled_timestamp.saturating_sub(m.timestamp))
+        } else {
+            None
+        };
+        Some(MatchLatencies {
+            submit_to_match: submitted_at.map(|t| m.timestamp.saturating_sub(t)),
+            match_to_settle,
+        })
+    }
+
+    /// Check whether an order's linked nullifier has been spent in settlement
+    ///
+    /// False for unknown orders and orders with no linked nullifier.
+    pub fn is_order_nullifier_spent(env: Env, commitment: BytesN<32>) -> bool {
+        let orderbook = Self::orderbook(&env);
+        let Some(nullifier) = orderbook.get_order(&commitment).and_then(|order| order.nullifier) else {
+            return false;
+        };
+        let settlement = settlement_wasm::Client::new(&env, &orderbook.get_settlement());
+        settlement.is_nullifier_used(&nullifier)
+    }
+
+    /// Check whether a recorded match could settle now, without changing state
+    ///
+    /// Mirrors the orderbook's settlement path: the match must be unsettled,
+    /// not failed and inside its settlement window, the asset needs a
+    /// settlement asset, the seller must have `quantity` of the asset locked
+    /// and the buyer `quantity * price` of the settlement asset, and any
+    /// nullifiers linked to the two orders must be distinct and unspent.
+    ///
+    /// # Returns
+    /// * The orderbook error settlement would fail with first, if any
+    pub fn can_settle(env: Env, match_id: BytesN<32>) -> Result<(), OrderbookError> {
+        let orderbook = Self::orderbook(&env);
+        let m = orderbook.get_match(&match_id).ok_or(OrderbookError::MatchNotFound)?;
+        if m.is_settled {
+            return Err(OrderbookError::MatchAlreadySettled);
+        }
+        if m.failed {
+            return Err(OrderbookError::MaxRetriesExceeded);
+        }
+        let interval = orderbook.get_asset_config(&m.asset_address).settle_interval;
+        if interval != 0 && env.ledger().timestamp() / interval <= m.timestamp / interval {
+            return Err(OrderbookError::NotInSettlementWindow);
+        }
+
+        let registry = registry_wasm::Client::new(&env, &orderbook.get_registry());
+        let quote_asset = registry
+            .get_settlement_asset(&m.asset_address)
+            .ok_or(OrderbookError::NoSettlementAsset)?;
+
+        let payment = m.quantity.checked_mul(m.price).ok_or(OrderbookError::ArithmeticOverflow)?;
+        let settlement = settlement_wasm::Client::new(&env, &orderbook.get_settlement());
+        if settlement.get_locked_balance(&m.seller, &m.asset_address) < m.quantity
+            || settlement.get_locked_balance(&m.buyer, &quote_asset) < payment
+        {
+            return Err(OrderbookError::InsufficientEscrow);
+        }
+
+        let buy_nullifier = orderbook.get_order(&m.buy_commitment).and_then(|order| order.nullifier);
+        let sell_nullifier = orderbook.get_order(&m.sell_commitment).and_then(|order| order.nullifier);
+        if buy_nullifier.is_some() && buy_nullifier == sell_nullifier {
+            return Err(OrderbookError::NullifierUsed);
+        }
+        for nullifier in [buy_nullifier, sell_nullifier].into_iter().flatten() {
+            if settlement.is_nullifier_used(&nullifier) {
+                return Err(OrderbookError::NullifierUsed);
+            }
+        }
+        Ok(())
+    }
+
+    /// Check if an asset can currently be traded
+    ///
+    /// True only when the orderbook is not paused and the asset is active
+    /// in the registry, not halted, not delisted and within its trading
+    /// hours.
+    pub fn is_asset_tradable(env: Env, asset_address: Address) -> bool {
+        let orderbook = Self::orderbook(&env);
+        if orderbook.is_paused()
+            || orderbook.is_asset_delisted(&asset_address)
+            || orderbook.get_asset_config(&asset_address).halted
+            || !orderbook.is_within_trading_hours(&asset_address)
+        {
+            return false;
+        }
+        let registry = registry_wasm::Client::new(&env, &orderbook.get_registry());
+        registry.is_asset_eligible(&asset_address)
+    }
+
+    /// Check whether two orders could be matched, ignoring price
+    ///
+    /// Runs the orderbook's non-price matching criteria (opposite sides,
+    /// same asset or a tradable pair, both active and unexpired, different
+    /// traders, both traders eligible in the registry) and returns the
+    /// error for the first check that fails.
+    pub fn are_compatible(
+        env: Env,
+        commitment_a: BytesN<32>,
+        commitment_b: BytesN<32>,
+    ) -> Result<(), OrderbookError> {
+        let orderbook = Self::orderbook(&env);
+        let order_a = orderbook.get_order(&commitment_a).ok_or(OrderbookError::OrderNotFound)?;
+        let order_b = orderbook.get_order(&commitment_b).ok_or(OrderbookError::OrderNotFound)?;
+
+        if order_a.side == order_b.side {
+            return Err(OrderbookError::InvalidOrderSide);
+        }
+        if order_a.asset_address != order_b.asset_address
+            && !orderbook.is_pair_tradable(&order_a.asset_address, &order_b.asset_address)
+        {
+            return Err(OrderbookError::AssetMismatch);
+        }
+
+        Self::require_open(&env, &order_a)?;
+        Self::require_open(&env, &order_b)?;
+
+        if order_a.trader == order_b.trader {
+            return Err(OrderbookError::SelfMatchNotAllowed);
+        }
+
+        let registry = registry_wasm::Client::new(&env, &orderbook.get_registry());
+        if !registry.is_participant_eligible(&order_a.trader) || !registry.is_participant_eligible(&order_b.trader) {
+            return Err(OrderbookError::ParticipantNotEligible);
+        }
+        Ok(())
+    }
+
+    /// Find matches whose buy or sell order no longer exists
+    ///
+    /// Data-integrity monitor: returns up to `max` match ids referencing a
+    /// commitment that `get_order` can no longer resolve.
+    pub fn find_orphaned_matches(env: Env, max: u32) -> Vec<BytesN<32>> {
+        let orderbook = Self::orderbook(&env);
+        let mut orphaned: Vec<BytesN<32>> = vec![&env];
+        for m in Self::all_matches(&env).iter() {
+            if orphaned.len() >= max {
+                break;
+            }
+            if orderbook.get_order(&m.buy_commitment).is_none() || orderbook.get_order(&m.sell_commitment).is_none() {
+                orphaned.push_back(m.match_id);
+            }
+        }
+        orphaned
+    }
+
+    /// Find commitments indexed under more than one order slot
+    ///
+    /// Data-integrity monitor: returns up to `max` commitments listed more
+    /// than once across the per-asset order index, each reported once.
+    pub fn find_duplicate_commitments(env: Env, max: u32) -> Vec<BytesN<32>> {
+        let mut seen: Map<BytesN<32>, u32> = Map::new(&env);
+        let mut duplicates: Vec<BytesN<32>> = vec![&env];
+        for asset_address in Self::orderbook(&env).get_order_assets().iter() {
+            for order in Self::asset_orders(&env, &asset_address).iter() {
+                if duplicates.len() >= max {
+                    return duplicates;
+                }
+                let count = seen.get(order.commitment.clone()).unwrap_or(0) + 1;
+                if count == 2 {
+                    duplicates.push_back(order.commitment.clone());
+                }
+                seen.set(order.commitment, count);
+            }
+        }
+        duplicates
+    }
+
+    /// Get an order's zero-based FIFO rank among active same-side orders
+    ///
+    /// Orders are ranked by timestamp, then submission order. Returns
+    /// `None` if the order is unknown or no longer active.
+    pub fn get_queue_position(env: Env, commitment: BytesN<32>) -> Option<u32> {
+        let orderbook = Self::orderbook(&env);
+        let target = orderbook.get_order(&commitment)?;
+        let now = env.ledger().timestamp();
+        if !matches!(target.status, OrderStatus::Active | OrderStatus::PartiallyFilled) || target.expiry <= now {
+            return None;
+        }
+
+        let mut ahead: u32 = 0;
+        let mut cursor = Some(0);
+        while let Some(start) = cursor {
+            let page = orderbook.get_active_orders_cursor(&target.asset_address, &start, &PAGE_SIZE);
+            for o in page.orders.iter() {
+                if o.side == target.side
+                    && (o.timestamp < target.timestamp
+                        || (o.timestamp == target.timestamp && o.tree_index < target.tree_index))
+                {
+                    ahead += 1;
+                }
+            }
+            cursor = page.next_cursor;
+        }
+        Some(ahead)
+    }
+
+    /// Get the seconds left until an order expires, 0 once it has
+    pub fn get_time_to_expiry(env: Env, commitment: BytesN<32>) -> Option<u64> {
+        let order = Self::orderbook(&env).get_order(&commitment)?;
+        Some(order.expiry.saturating_sub(env.ledger().timestamp()))
+    }
+
+    /// Snapshot an asset's settled activity for a closed day (orderbook
+    /// admin only)
+    ///
+    /// The report is stored once and never recomputed; snapshotting the
+    /// same asset and day again fails with `ReportAlreadyExists`.
+    ///
+    /// # Arguments
+    /// * `admin` - Must be the orderbook admin
+    /// * `asset_address` - The RWA token to report on
+    /// * `day` - Days since the Unix epoch; must have ended
+    pub fn snapshot_daily_report(
+        env: Env,
+        admin: Address,
+        asset_address: Address,
+        day: u64,
+    ) -> Result<DailyReport, AnalyticsError> {
+        admin.require_auth();
+        Self::require_admin(&env, &admin)?;
+
+        let now = env.ledger().timestamp();
+        let day_start = day.checked_mul(SECONDS_PER_DAY).ok_or(AnalyticsError::ArithmeticOverflow)?;
+        let day_end = day_start.checked_add(SECONDS_PER_DAY).ok_or(AnalyticsError::ArithmeticOverflow)?;
+        if now < day_end {
+            return Err(AnalyticsError::DayNotClosed);
+        }
+
+        let mut reports: Map<(Address, u64), DailyReport> = env
+            .storage()
+            .instance()
+            .get(&DAILY_REPORTS_KEY)
+            .unwrap_or(Map::new(&env));
+        let key = (asset_address.clone(), day);
+        if reports.contains_key(key.clone()) {
+            return Err(AnalyticsError::ReportAlreadyExists);
+        }
+
+        let mut volume: i128 = 0;
+        let mut notional: i128 = 0;
+        let mut fees: i128 = 0;
+        let mut trade_count: u32 = 0;
+        for m in Self::all_matches(&env).iter() {
+            if m.asset_address != asset_address
+                || !m.is_settled
+                || m.settled_timestamp < day_start
+                || m.settled_timestamp >= day_end
+            {
+                continue;
+            }
+            volume = volume.checked_add(m.quantity).ok_or(AnalyticsError::ArithmeticOverflow)?;
+            notional = m
+                .quantity
+                .checked_mul(m.price)
+                .and_then(|value| notional.checked_add(value))
+                .ok_or(AnalyticsError::ArithmeticOverflow)?;
+            fees = fees.checked_add(m.fee).ok_or(AnalyticsError::ArithmeticOverflow)?;
+            trade_count += 1;
+        }
+
+        let report = DailyReport {
+            asset_address,
+            day,
+            volume,
+            trade_count,
+            vwap: if volume == 0 { 0 } else { notional / volume },
+            fees,
+            snapshot_timestamp: now,
+        };
+        reports.set(key, report.clone());
+        env.storage().instance().set(&DAILY_REPORTS_KEY, &reports);
+        Ok(report)
+    }
+
+    /// Get a stored daily report for an asset
+    pub fn get_daily_report(env: Env, asset_address: Address, day: u64) -> Option<DailyReport> {
+        let reports: Map<(Address, u64), DailyReport> = env
+            .storage()
+            .instance()
+            .get(&DAILY_REPORTS_KEY)
+            .unwrap_or(Map::new(&env));
+        reports.get((asset_address, day))
+    }
+
+    /// Get an asset's time-weighted average settled price
+    ///
+    /// Each settled match's price is weighted by its quantity and by how
+    /// long it stood as the latest settlement, so the average is an
+    /// approximation: prices between settlements are assumed flat. A
+    /// lookback reaching back before the first settlement starts at it.
+    /// Returns the latest settled price when the window has no elapsed
+    /// time, and 0 if the asset has never settled.
+    pub fn get_twap(env: Env, asset_address: Address, lookback_seconds: u64) -> i128 {
+        let settled = Self::settled_matches(&env, &asset_address);
+        let Some(latest) = settled.last() else {
+            return 0;
+        };
+
+        let now = env.ledger().timestamp();
+        let start = now.saturating_sub(lookback_seconds);
+        let mut price_volume_time: i128 = 0;
+        let mut volume_time: i128 = 0;
+        for (i, m) in settled.iter().enumerate() {
+            // The match's price stands until the next settlement
+            let until = settled.get(i as u32 + 1).map_or(now, |next| next.settled_timestamp);
+            let elapsed = until.saturating_sub(m.settled_timestamp.max(start)) as i128;
+            let weight = m.quantity.saturating_mul(elapsed);
+            price_volume_time = price_volume_time.saturating_add(weight.saturating_mul(m.price));
+            volume_time = volume_time.saturating_add(weight);
+        }
+        if volume_time == 0 {
+            return latest.price;
+        }
+        price_volume_time / volume_time
+    }
+
+    /// Get the number of distinct addresses a trader has matched against
+    pub fn get_counterparty_count(env: Env, trader: Address) -> u32 {
+        let mut counterparties: Map<Address, bool> = Map::new(&env);
+        for m in Self::all_matches(&env).iter() {
+            if m.buyer == trader {
+                counterparties.set(m.seller, true);
+            } else if m.seller == trader {
+                counterparties.set(m.buyer, true);
+            }
+        }
+        counterparties.len()
+    }
+
+    /// Get the cumulative fees a trader has paid across all matches
+    ///
+    /// The taker pays each match's fee, and it is counted once the match
+    /// settles.
+    pub fn get_trader_fees_paid(env: Env, trader: Address) -> i128 {
+        let mut total: i128 = 0;
+        for m in Self::all_matches(&env).iter() {
+            let taker = match m.maker_side {
+                OrderSide::Buy => &m.seller,
+                OrderSide::Sell => &m.buyer,
+            };
+            if m.is_settled && *taker == trader {
+                total = total.saturating_add(m.fee);
+            }
+        }
+        total
+    }
+
+    /// Get the total notional of unsettled matches for an asset
+    pub fn get_pending_settlement_value(env: Env, asset_address: Address) -> Result<i128, AnalyticsError> {
+        let mut total: i128 = 0;
+        for m in Self::all_matches(&env).iter() {
+            if m.is_settled || m.asset_address != asset_address {
+                continue;
+            }
+            total = m
+                .quantity
+                .checked_mul(m.price)
+                .and_then(|notional| total.checked_add(notional))
+                .ok_or(AnalyticsError::ArithmeticOverflow)?;
+        }
+        Ok(total)
+    }
+
+    /// Estimate how far a limit price is from the opposing top of book
+    ///
+    /// Buys are measured against the best ask and sells against the best
+    /// bid. The result is positive when the order would cross, negative
+    /// by the price gap still to close otherwise.
+    pub fn estimate_fill_distance(
+        env: Env,
+        asset_address: Address,
+        side: OrderSide,
+        limit_price: i128,
+    ) -> Result<i128, AnalyticsError> {
+        let top = Self::orderbook(&env)
+            .get_top_of_book(&asset_address)
+            .ok_or(AnalyticsError::TopOfBookNotPublished)?;
+        let distance = match side {
+            OrderSide::Buy => limit_price.checked_sub(top.best_ask),
+            OrderSide::Sell => top.best_bid.checked_sub(limit_price),
+        };
+        distance.ok_or(AnalyticsError::ArithmeticOverflow)
+    }
+
+    /// Get a trader's matched but unsettled notional, per asset
+    ///
+    /// Covers matches where the trader is either the buyer or the seller.
+    pub fn get_unsettled_exposure(env: Env, trader: Address) -> Vec<(Address, i128)> {
+        let mut by_asset: Map<Address, i128> = Map::new(&env);
+        for m in Self::all_matches(&env).iter() {
+            if m.is_settled || (m.buyer != trader && m.seller != trader) {
+                continue;
+            }
+            let exposure = by_asset
+                .get(m.asset_address.clone())
+                .unwrap_or(0)
+                .saturating_add(m.quantity.saturating_mul(m.price));
+            by_asset.set(m.asset_address, exposure);
+        }
+
+        let mut exposures: Vec<(Address, i128)> = vec![&env];
+        for (asset_address, exposure) in by_asset.iter() {
+            exposures.push_back((asset_address, exposure));
+        }
+        exposures
+    }
+
+    fn orderbook(env: &Env) -> orderbook_wasm::Client<'_> {
+        let orderbook_address: Address = env.storage().instance().get(&ORDERBOOK_KEY).unwrap();
+        orderbook_wasm::Client::new(env, &orderbook_address)
+    }
+
+    /// Every match the orderbook holds, oldest first
+    fn all_matches(env: &Env) -> Vec<MatchRecord> {
+        let orderbook = Self::orderbook(env);
+        let mut matches: Vec<MatchRecord> = vec![env];
+        loop {
+            let page = orderbook.get_matches_paged(&matches.len(), &PAGE_SIZE);
+            let full = page.len() == PAGE_SIZE;
+            matches.append(&page);
+            if !full {
+                return matches;
+            }
+        }
+    }
+
+    /// An asset's settled matches, in order of settlement
+    fn settled_matches(env: &Env, asset_address: &Address) -> Vec<MatchRecord> {
+        let mut settled: Vec<MatchRecord> = vec![env];
+        for m in Self::all_matches(env).iter() {
+            if !m.is_settled || m.asset_address != *asset_address {
+                continue;
+            }
+            let mut at = settled.len();
+            while at > 0 && settled.get_unchecked(at - 1).settled_timestamp > m.settled_timestamp {
+                at -= 1;
+            }
+            settled.insert(at, m);
+        }
+        settled
+    }
+
+    /// An asset's orders, in submission order
+    fn asset_orders(env: &Env, asset_address: &Address) -> Vec<OrderCommitment> {
+        let orderbook = Self::orderbook(env);
+        let mut orders: Vec<OrderCommitment> = vec![env];
+        loop {
+            let page = orderbook.get_orders_by_asset_paged(asset_address, &None, &None, &orders.len(), &PAGE_SIZE);
+            let full = page.len() == PAGE_SIZE;
+            orders.append(&page);
+            if !full {
+                return orders;
+            }
+        }
+    }
+
+    /// A trader's orders, in submission order
+    fn trader_orders(env: &Env, trader: &Address) -> Vec<OrderCommitment> {
+        let orderbook = Self::orderbook(env);
+        let mut orders: Vec<OrderCommitment> = vec![env];
+        loop {
+            let page = orderbook.get_orders_by_trader_paged(trader, &orders.len(), &PAGE_SIZE);
+            let full = page.len() == PAGE_SIZE;
+            orders.append(&page);
+            if !full {
+                return orders;
+            }
+        }
+    }
+
+    /// Fail unless the order is still open and unexpired
+    fn require_open(env: &Env, order: &OrderCommitment) -> Result<(), OrderbookError> {
+        match order.status {
+            OrderStatus::Matched | OrderStatus::Settled => {
+                return Err(OrderbookError::OrderAlreadyMatched);
+            }
+            OrderStatus::Cancelled => {
+                return Err(OrderbookError::OrderAlreadyCancelled);
+            }
+            OrderStatus::Expired => {
+                return Err(OrderbookError::OrderExpired);
+            }
+            OrderStatus::Active | OrderStatus::PartiallyFilled => {}
+        }
+
+        if order.expiry <= env.ledger().timestamp() {
+            return Err(OrderbookError::OrderExpired);
+        }
+        Ok(())
+    }
+
+    fn require_admin(env: &Env, caller: &Address) -> Result<(), AnalyticsError> {
+        if *caller != Self::orderbook(env).get_admin() {
+            return Err(AnalyticsError::OnlyAdmin);
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,1025 @@
+#![cfg(test)]
+
+use super::*;
+use darkpool_orderbook::DarkPoolOrderbook;
+use orderbook_wasm::{AssetConfig, DataKey, OrderIndex, OrderbookConfig};
+use soroban_sdk::{testutils::{Address as _, Ledger}, Bytes, BytesN, Env};
+
+struct Setup<'a> {
+    client: DarkPoolAnalyticsClient<'a>,
+    orderbook: orderbook_wasm::Client<'a>,
+    admin: Address,
+}
+
+/// Settlement stand-in that accepts every escrow lock, with directly
+/// settable locked balances and spent nullifiers
+#[contract]
+pub struct MockSettlement;
+
+#[contractimpl]
+impl MockSettlement {
+    pub fn get_available_balance(_env: Env, _participant: Address, _asset: Address) -> i128 {
+        i128::MAX
+    }
+
+    pub fn increase_lock(_env: Env, _caller: Address, _participant: Address, _asset: Address, _additional: i128) {}
+
+    pub fn decrease_lock(_env: Env, _caller: Address, _participant: Address, _asset: Address, _amount: i128) {}
+
+    pub fn set_locked_balance(env: Env, participant: Address, asset: Address, amount: i128) {
+        env.storage().instance().set(&(participant, asset), &amount);
+    }
+
+    pub fn get_locked_balance(env: Env, participant: Address, asset: Address) -> i128 {
+        env.storage().instance().get(&(participant, asset)).unwrap_or(0)
+    }
+
+    pub fn spend_nullifier(env: Env, nullifier: BytesN<32>) {
+        env.storage().instance().set(&nullifier, &true);
+    }
+
+    pub fn is_nullifier_used(env: Env, nullifier: BytesN<32>) -> bool {
+        env.storage().instance().get(&nullifier).unwrap_or(false)
+    }
+}
+
+/// Registry stand-in that reports settlement assets and eligibility from
+/// storage
+#[contract]
+pub struct MockRegistry;
+
+#[contractimpl]
+impl MockRegistry {
+    pub fn set_settlement_asset(env: Env, asset: Address, settlement_asset: Option<Address>) {
+        env.storage().instance().set(&(symbol_short!("settle_as"), asset), &settlement_asset);
+    }
+
+    pub fn get_settlement_asset(env: Env, asset: Address) -> Option<Address> {
+        env.storage().instance().get(&(symbol_short!("settle_as"), asset)).flatten()
+    }
+
+    pub fn set_asset_eligible(env: Env, asset: Address, eligible: bool) {
+        env.storage().instance().set(&(symbol_short!("asset"), asset), &eligible);
+    }
+
+    pub fn is_asset_eligible(env: Env, asset: Address) -> bool {
+        env.storage().instance().get(&(symbol_short!("asset"), asset)).unwrap_or(false)
+    }
+
+    pub fn set_blacklisted(env: Env, trader: Address, blacklisted: bool) {
+        env.storage().instance().set(&(symbol_short!("blocked"), trader), &blacklisted);
+    }
+
+    pub fn is_participant_eligible(env: Env, trader: Address) -> bool {
+        !env.storage().instance().get(&(symbol_short!("blocked"), trader)).unwrap_or(false)
+    }
+}
+
+/// An orderbook in test mode, so cancels need no ownership proof, with an
+/// analytics contract reading from it
+fn setup(env: &Env) -> Setup<'_> {
+    let admin = Address::generate(env);
+    let registry = env.register(MockRegistry, ());
+    let settlement = env.register(MockSettlement, ());
+    let verifier = Address::generate(env);
+    let vk_bytes = Bytes::from_slice(env, &[0u8; 100]);
+    let orderbook_id = env.register(DarkPoolOrderbook, (&admin, &registry, &settlement, &verifier, &vk_bytes, true));
+    let contract_id = env.register(DarkPoolAnalytics, (&orderbook_id,));
+    Setup {
+        client: DarkPoolAnalyticsClient::new(env, &contract_id),
+        orderbook: orderbook_wasm::Client::new(env, &orderbook_id),
+        admin,
+    }
+}
+
+fn commitment(env: &Env, id: u8) -> BytesN<32> {
+    BytesN::from_array(env, &[id; 32])
+}
+
+fn submit(env: &Env, orderbook: &orderbook_wasm::Client, trader: &Address, id: u8, asset: &Address, side: OrderSide, expiry: u64) {
+    orderbook.submit_order(trader, &commitment(env, id), asset, &side, &expiry, &1_000, &0, &0);
+}
+
+fn cancel(env: &Env, orderbook: &orderbook_wasm::Client, trader: &Address, id: u8) {
+    let proof = Bytes::from_slice(env, &[1u8; 4]);
+    orderbook.cancel_order(trader, &commitment(env, id), &proof, &Bytes::new(env));
+}
+
+fn submit_pair(env: &Env, orderbook: &orderbook_wasm::Client, asset: &Address, buy_id: u8, sell_id: u8) -> (Address, Address) {
+    let buyer = Address::generate(env);
+    let seller = Address::generate(env);
+    submit(env, orderbook, &buyer, buy_id, asset, OrderSide::Buy, 100_000);
+    submit(env, orderbook, &seller, sell_id, asset, OrderSide::Sell, 100_000);
+    (buyer, seller)
+}
+
+fn record_match(
+    env: &Env,
+    s: &Setup,
+    match_id: u8,
+    buy_id: u8,
+    sell_id: u8,
+    asset: &Address,
+    buyer: &Address,
+    seller: &Address,
+    quantity: i128,
+    price: i128,
+) {
+    s.orderbook.record_match(
+        &s.admin, &commitment(env, match_id), &commitment(env, buy_id), &commitment(env, sell_id), asset, buyer,
+        seller, &quantity, &price, &None,
+    );
+}
+
+fn update_config(s: &Setup, update: impl FnOnce(&mut OrderbookConfig)) {
+    let mut config = s.orderbook.get_config();
+    update(&mut config);
+    s.orderbook.set_config(&s.admin, &config);
+}
+
+fn update_asset_config(s: &Setup, asset: &Address, update: impl FnOnce(&mut AssetConfig)) {
+    let mut config = s.orderbook.get_asset_config(asset);
+    update(&mut config);
+    s.orderbook.set_asset_config(&s.admin, asset, &config);
+}
+
+#[test]
+fn test_constructor() {
+    let env = Env::default();
+    let s = setup(&env);
+    assert_eq!(s.client.get_orderbook(), s.orderbook.address);
+}
+
+#[test]
+fn test_get_orders_by_asset_and_trader() {
+    let env = Env::default();
+    env.mock_all_auths();
+    let s = setup(&env);
+
+    let alice = Address::generate(&env);
+    let bob = Address::generate(&env);
+    let bond = Address::generate(&env);
+    let bill = Address::generate(&env);
+
+    submit(&env, &s.orderbook, &alice, 1, &bond, OrderSide::Buy, 3600);
+    submit(&env, &s.orderbook, &alice, 2, &bond, OrderSide::Sell, 3600);
+    submit(&env, &s.orderbook, &alice, 3, &bill, OrderSide::Buy, 3600);
+    submit(&env, &s.orderbook, &bob, 4, &bond, OrderSide::Buy, 3600);
+    submit(&env, &s.orderbook, &bob, 5, &bill, OrderSide::Sell, 3600);
+    cancel(&env, &s.orderbook, &alice, 2);
+
+    let alice_bond = s.client.get_orders_by_asset_and_trader(&bond, &alice, &None);
+    assert_eq!(alice_bond.len(), 2);
+    assert_eq!(alice_bond.get(0).unwrap().commitment, commitment(&env, 1));
+    assert_eq!(alice_bond.get(1).unwrap().commitment, commitment(&env, 2));
+
+    let alice_bond_active = s.client.get_orders_by_asset_and_trader(&bond, &alice, &Some(OrderStatus::Active));
+    assert_eq!(alice_bond_active.len(), 1);
+    assert_eq!(alice_bond_active.get(0).unwrap().commitment, commitment(&env, 1));
+
+    let bob_bill = s.client.get_orders_by_asset_and_trader(&bill, &bob, &None);
+    assert_eq!(bob_bill.len(), 1);
+    assert_eq!(bob_bill.get(0).unwrap().commitment, commitment(&env, 5));
+
+    let bob_bill_cancelled = s.client.get_orders_by_asset_and_trader(&bill, &bob, &Some(OrderStatus::Cancelled));
+    assert_eq!(bob_bill_cancelled.len(), 0);
+}
+
+#[test]
+fn test_get_match_flows() {
+    let env = Env::default();
+    env.mock_all_auths();
+    let s = setup(&env);
+    let asset = Address::generate(&env);
+
+    update_config(&s, |c| c.fee_bps = 25);
+    let (buyer, seller) = submit_pair(&env, &s.orderbook, &asset, 1, 2);
+    record_match(&env, &s, 10, 1, 2, &asset, &buyer, &seller, 400, 125);
+
+    // The fee is recorded on the match but settlement doesn't charge it
+    assert_eq!(s.orderbook.get_match(&commitment(&env, 10)).unwrap().fee, 125);
+    let flows = s.client.get_match_flows(&commitment(&env, 10)).unwrap();
+    assert_eq!(flows.buyer_debit, 50_000);
+    assert_eq!(flows.seller_credit, 50_000);
+
+    assert!(s.client.get_match_flows(&commitment(&env, 11)).is_none());
+}
+
+#[test]
+fn test_get_match_flows_by_maker_side() {
+    let env = Env::default();
+    env.mock_all_auths();
+    let s = setup(&env);
+    let asset = Address::generate(&env);
+
+    update_config(&s, |c| {
+        c.fee_bps = 25;
+        c.maker_rebate_bps = 10;
+    });
+
+    // Neither side's flow depends on who is the maker
+    let (buyer, seller) = submit_pair(&env, &s.orderbook, &asset, 1, 2);
+    s.orderbook.record_match(
+        &s.admin, &commitment(&env, 10), &commitment(&env, 1), &commitment(&env, 2), &asset, &buyer, &seller,
+        &400, &125, &Some(OrderSide::Buy),
+    );
+    let flows = s.client.get_match_flows(&commitment(&env, 10)).unwrap();
+    assert_eq!(flows.buyer_debit, 50_000);
+    assert_eq!(flows.seller_credit, 50_000);
+
+    // Seller designated maker
+    let (buyer, seller) = submit_pair(&env, &s.orderbook, &asset, 3, 4);
+    s.orderbook.record_match(
+        &s.admin, &commitment(&env, 11), &commitment(&env, 3), &commitment(&env, 4), &asset, &buyer, &seller,
+        &400, &125, &Some(OrderSide::Sell),
+    );
+    let flows = s.client.get_match_flows(&commitment(&env, 11)).unwrap();
+    assert_eq!(flows.buyer_debit, 50_000);
+    assert_eq!(flows.seller_credit, 50_000);
+}
+
+#[test]
+fn test_get_match_flows_negative_price() {
+    let env = Env::default();
+    env.mock_all_auths();
+    let s = setup(&env);
+    let asset = Address::generate(&env);
+
+    update_config(&s, |c| c.fee_bps = 100);
+    update_asset_config(&s, &asset, |c| c.negative_price_allowed = true);
+    let (buyer, seller) = submit_pair(&env, &s.orderbook, &asset, 1, 2);
+    record_match(&env, &s, 10, 1, 2, &asset, &buyer, &seller, 100, -5);
+
+    let flows = s.client.get_match_flows(&commitment(&env, 10)).unwrap();
+    assert_eq!(flows.buyer_debit, -500);
+    assert_eq!(flows.seller_credit, -500);
+}
+
+#[test]
+fn test_find_orphaned_matches() {
+    let env = Env::default();
+    env.mock_all_auths();
+    let s = setup(&env);
+    let asset = Address::generate(&env);
+
+    let (buyer, seller) = submit_pair(&env, &s.orderbook, &asset, 1, 2);
+    record_match(&env, &s, 10, 1, 2, &asset, &buyer, &seller, 100, 10);
+    let (buyer, seller) = submit_pair(&env, &s.orderbook, &asset, 3, 4);
+    record_match(&env, &s, 11, 3, 4, &asset, &buyer, &seller, 100, 10);
+    assert_eq!(s.client.find_orphaned_matches(&10).len(), 0);
+
+    // Prune the sell order behind the second match directly from storage
+    env.as_contract(&s.orderbook.address, || {
+        env.storage().persistent().remove(&DataKey::Order(commitment(&env, 4)));
+    });
+
+    let orphaned = s.client.find_orphaned_matches(&10);
+    assert_eq!(orphaned.len(), 1);
+    assert_eq!(orphaned.get(0).unwrap(), commitment(&env, 11));
+    assert_eq!(s.client.find_orphaned_matches(&0).len(), 0);
+}
+
+/// Append a commitment to the first page of an orderbook order index
+fn push_index(env: &Env, index: OrderIndex, commitment: &BytesN<32>) {
+    let len: u32 = env.storage().persistent().get(&DataKey::IndexLen(index.clone())).unwrap_or(0);
+    let page_key = DataKey::IndexPage(index.clone(), 0);
+    let mut page: Vec<BytesN<32>> = env.storage().persistent().get(&page_key).unwrap_or(vec![env]);
+    page.push_back(commitment.clone());
+    env.storage().persistent().set(&page_key, &page);
+    env.storage().persistent().set(&DataKey::IndexLen(index), &(len + 1));
+}
+
+#[test]
+fn test_find_duplicate_commitments() {
+    let env = Env::default();
+    env.mock_all_auths();
+    let s = setup(&env);
+    let asset = Address::generate(&env);
+
+    submit_pair(&env, &s.orderbook, &asset, 1, 2);
+    submit_pair(&env, &s.orderbook, &asset, 3, 4);
+    assert_eq!(s.client.find_duplicate_commitments(&10).len(), 0);
+
+    // Seed two extra index entries for one order and one for another,
+    // including one under a different asset, directly in storage
+    env.as_contract(&s.orderbook.address, || {
+        push_index(&env, OrderIndex::Asset(asset.clone()), &commitment(&env, 2));
+        push_index(&env, OrderIndex::Asset(asset.clone()), &commitment(&env, 2));
+        let other_asset = Address::generate(&env);
+        push_index(&env, OrderIndex::Asset(other_asset.clone()), &commitment(&env, 4));
+        let assets_key = symbol_short!("ord_asset");
+        let mut assets: Vec<Address> = env.storage().instance().get(&assets_key).unwrap();
+        assets.push_back(other_asset);
+        env.storage().instance().set(&assets_key, &assets);
+    });
+
+    let duplicates = s.client.find_duplicate_commitments(&10);
+    assert_eq!(duplicates.len(), 2);
+    assert!(duplicates.contains(commitment(&env, 2)));
+    assert!(duplicates.contains(commitment(&env, 4)));
+    assert_eq!(s.client.find_duplicate_commitments(&1).len(), 1);
+}
+
+#[test]
+fn test_get_match_detail() {
+    let env = Env::default();
+    env.mock_all_auths();
+    let s = setup(&env);
+    let asset = Address::generate(&env);
+
+    let (buyer, seller) = submit_pair(&env, &s.orderbook, &asset, 1, 2);
+    record_match(&env, &s, 10, 1, 2, &asset, &buyer, &seller, 100, 10);
+
+    let detail = s.client.get_match_detail(&commitment(&env, 10)).unwrap();
+    let match_record = s.orderbook.get_match(&commitment(&env, 10)).unwrap();
+    let buy_order = s.orderbook.get_order(&commitment(&env, 1)).unwrap();
+    let sell_order = s.orderbook.get_order(&commitment(&env, 2)).unwrap();
+
+    assert_eq!(detail.match_record.match_id, match_record.match_id);
+    assert_eq!(detail.match_record.quantity, match_record.quantity);
+    assert_eq!(detail.match_record.buyer, buyer);
+    assert_eq!(detail.orders.len(), 2);
+    let detail_buy = detail.orders.get(0).unwrap();
+    assert_eq!(detail_buy.commitment, buy_order.commitment);
+    assert_eq!(detail_buy.status, buy_order.status);
+    let detail_sell = detail.orders.get(1).unwrap();
+    assert_eq!(detail_sell.commitment, sell_order.commitment);
+    assert_eq!(detail_sell.trader, seller);
+
+    assert!(s.client.get_match_detail(&commitment(&env, 11)).is_none());
+}
+
+#[test]
+fn test_get_match_latencies() {
+    let env = Env::default();
+    env.mock_all_auths();
+    let s = setup(&env);
+    let asset = Address::generate(&env);
+    let buyer = Address::generate(&env);
+    let seller = Address::generate(&env);
+
+    env.ledger().with_mut(|l| l.timestamp = 1_000);
+    submit(&env, &s.orderbook, &seller, 2, &asset, OrderSide::Sell, 3600);
+    env.ledger().with_mut(|l| l.timestamp = 1_100);
+    submit(&env, &s.orderbook, &buyer, 1, &asset, OrderSide::Buy, 3600);
+
+    env.ledger().with_mut(|l| l.timestamp = 1_250);
+    record_match(&env, &s, 10, 1, 2, &asset, &buyer, &seller, 1_000, 5);
+    let latencies = s.client.get_match_latencies(&commitment(&env, 10)).unwrap();
+    // Measured from the older (sell) order
+    assert_eq!(latencies.submit_to_match, Some(250));
+    assert_eq!(latencies.match_to_settle, None);
+
+    env.ledger().with_mut(|l| l.timestamp = 1_290);
+    s.orderbook.mark_settled(&s.admin, &commitment(&env, 10));
+    let latencies = s.client.get_match_latencies(&commitment(&env, 10)).unwrap();
+    assert_eq!(latencies.submit_to_match, Some(250));
+    assert_eq!(latencies.match_to_settle, Some(40));
+
+    assert!(s.client.get_match_latencies(&commitment(&env, 11)).is_none());
+}
+
+#[test]
+fn test_get_order_time_range() {
+    let env = Env::default();
+    env.mock_all_auths();
+    let s = setup(&env);
+    let asset = Address::generate(&env);
+    let trader = Address::generate(&env);
+    assert_eq!(s.client.get_order_time_range(&asset), None);
+
+    env.ledger().with_mut(|li| li.timestamp = 1_000);
+    submit(&env, &s.orderbook, &trader, 1, &asset, OrderSide::Buy, 3_600);
+    assert_eq!(s.client.get_order_time_range(&asset), Some((1_000, 1_000)));
+
+    env.ledger().with_mut(|li| li.timestamp = 1_500);
+    submit(&env, &s.orderbook, &trader, 2, &asset, OrderSide::Sell, 3_600);
+    env.ledger().with_mut(|li| li.timestamp = 2_200);
+    submit(&env, &s.orderbook, &trader, 3, &asset, OrderSide::Buy, 3_600);
+
+    // Cancelled orders still count; other assets do not
+    cancel(&env, &s.orderbook, &trader, 1);
+    submit(&env, &s.orderbook, &trader, 4, &Address::generate(&env), OrderSide::Buy, 3_600);
+    assert_eq!(s.client.get_order_time_range(&asset), Some((1_000, 2_200)));
+}
+
+#[test]
+fn test_get_time_to_expiry() {
+    let env = Env::default();
+    env.mock_all_auths();
+    let s = setup(&env);
+    let asset = Address::generate(&env);
+
+    env.ledger().with_mut(|l| l.timestamp = 1_000);
+    submit(&env, &s.orderbook, &Address::generate(&env), 1, &asset, OrderSide::Buy, 3600);
+    assert_eq!(s.client.get_time_to_expiry(&commitment(&env, 1)), Some(3_600));
+
+    env.ledger().with_mut(|l| l.timestamp = 2_000);
+    assert_eq!(s.client.get_time_to_expiry(&commitment(&env, 1)), Some(2_600));
+
+    env.ledger().with_mut(|l| l.timestamp = 10_000);
+    assert_eq!(s.client.get_time_to_expiry(&commitment(&env, 1)), Some(0));
+    assert_eq!(s.client.get_time_to_expiry(&commitment(&env, 2)), None);
+}
+
+#[test]
+fn test_get_cancelled_orders() {
+    let env = Env::default();
+    env.mock_all_auths();
+    let s = setup(&env);
+    let asset = Address::generate(&env);
+    let trader = Address::generate(&env);
+    let other = Address::generate(&env);
+    for id in 1..=4u8 {
+        submit(&env, &s.orderbook, &trader, id, &asset, OrderSide::Buy, 86_400);
+    }
+    submit(&env, &s.orderbook, &other, 5, &asset, OrderSide::Sell, 86_400);
+
+    env.ledger().with_mut(|li| li.timestamp = 1_000);
+    cancel(&env, &s.orderbook, &trader, 1);
+    env.ledger().with_mut(|li| li.timestamp = 2_000);
+    cancel(&env, &s.orderbook, &trader, 2);
+    cancel(&env, &s.orderbook, &other, 5);
+    env.ledger().with_mut(|li| li.timestamp = 3_000);
+    assert_eq!(s.orderbook.global_cancel(&s.admin, &1), 1);
+    assert_eq!(s.orderbook.get_order(&commitment(&env, 3)).unwrap().cancelled_at, 3_000);
+    assert_eq!(s.orderbook.get_order(&commitment(&env, 4)).unwrap().cancelled_at, 0);
+
+    let window = s.client.get_cancelled_orders(&trader, &1_500, &3_000);
+    assert_eq!(window.len(), 2);
+    assert!(window.iter().any(|order| order.commitment == commitment(&env, 2) && order.cancelled_at == 2_000));
+    assert!(window.iter().any(|order| order.commitment == commitment(&env, 3)));
+    assert_eq!(s.client.get_cancelled_orders(&trader, &0, &u64::MAX).len(), 3);
+    assert_eq!(s.client.get_cancelled_orders(&trader, &1_001, &1_999).len(), 0);
+    assert_eq!(s.client.get_cancelled_orders(&other, &0, &u64::MAX).len(), 1);
+}
+
+#[test]
+fn test_get_unsettled_exposure() {
+    let env = Env::default();
+    env.mock_all_auths();
+    let s = setup(&env);
+    let asset_a = Address::generate(&env);
+    let asset_b = Address::generate(&env);
+    let trader = Address::generate(&env);
+    let other = Address::generate(&env);
+    assert_eq!(s.client.get_unsettled_exposure(&trader).len(), 0);
+
+    // Trader buys asset A and sells asset B twice; one B match settles
+    submit(&env, &s.orderbook, &trader, 1, &asset_a, OrderSide::Buy, 3600);
+    submit(&env, &s.orderbook, &other, 2, &asset_a, OrderSide::Sell, 3600);
+    record_match(&env, &s, 10, 1, 2, &asset_a, &trader, &other, 100, 50);
+    submit(&env, &s.orderbook, &other, 3, &asset_b, OrderSide::Buy, 3600);
+    submit(&env, &s.orderbook, &trader, 4, &asset_b, OrderSide::Sell, 3600);
+    record_match(&env, &s, 11, 3, 4, &asset_b, &other, &trader, 20, 25);
+    record_match(&env, &s, 12, 3, 4, &asset_b, &other, &trader, 7, 1_000);
+    s.orderbook.mark_settled(&s.admin, &commitment(&env, 12));
+
+    // Matches between other traders are not counted
+    let (buyer, seller) = submit_pair(&env, &s.orderbook, &asset_a, 5, 6);
+    record_match(&env, &s, 13, 5, 6, &asset_a, &buyer, &seller, 1, 1);
+
+    let exposure = s.client.get_unsettled_exposure(&trader);
+    assert_eq!(exposure.len(), 2);
+    assert!(exposure.contains((asset_a.clone(), 5_000)));
+    assert!(exposure.contains((asset_b.clone(), 500)));
+}
+
+#[test]
+fn test_get_pending_settlement_value() {
+    let env = Env::default();
+    env.mock_all_auths();
+    let s = setup(&env);
+    let asset = Address::generate(&env);
+    let other_asset = Address::generate(&env);
+
+    assert_eq!(s.client.get_pending_settlement_value(&asset), 0);
+
+    let (buyer, seller) = submit_pair(&env, &s.orderbook, &asset, 1, 2);
+    record_match(&env, &s, 10, 1, 2, &asset, &buyer, &seller, 100, 50);
+    let (buyer, seller) = submit_pair(&env, &s.orderbook, &asset, 3, 4);
+    record_match(&env, &s, 11, 3, 4, &asset, &buyer, &seller, 20, 25);
+    let (buyer, seller) = submit_pair(&env, &s.orderbook, &asset, 5, 6);
+    record_match(&env, &s, 12, 5, 6, &asset, &buyer, &seller, 7, 1_000);
+    let (buyer, seller) = submit_pair(&env, &s.orderbook, &other_asset, 7, 8);
+    record_match(&env, &s, 13, 7, 8, &other_asset, &buyer, &seller, 1, 1);
+
+    assert_eq!(s.client.get_pending_settlement_value(&asset), 5_000 + 500 + 7_000);
+
+    // Settled matches drop out of the aggregate
+    s.orderbook.mark_settled(&s.admin, &commitment(&env, 12));
+    assert_eq!(s.client.get_pending_settlement_value(&asset), 5_500);
+    assert_eq!(s.client.get_pending_settlement_value(&other_asset), 1);
+}
+
+#[test]
+fn test_estimate_fill_distance() {
+    let env = Env::default();
+    env.mock_all_auths();
+    let s = setup(&env);
+    let asset = Address::generate(&env);
+
+    let result = s.client.try_estimate_fill_distance(&asset, &OrderSide::Buy, &100);
+    assert_eq!(result, Err(Ok(AnalyticsError::TopOfBookNotPublished)));
+
+    s.orderbook.publish_top_of_book(&s.admin, &asset, &98, &102);
+
+    // Buys are measured against the ask
+    assert_eq!(s.client.estimate_fill_distance(&asset, &OrderSide::Buy, &105), 3);
+    assert_eq!(s.client.estimate_fill_distance(&asset, &OrderSide::Buy, &100), -2);
+
+    // Sells are measured against the bid
+    assert_eq!(s.client.estimate_fill_distance(&asset, &OrderSide::Sell, &95), 3);
+    assert_eq!(s.client.estimate_fill_distance(&asset, &OrderSide::Sell, &101), -3);
+}
+
+#[test]
+fn test_get_matches_in_range() {
+    let env = Env::default();
+    env.mock_all_auths();
+    let s = setup(&env);
+    let asset = Address::generate(&env);
+    let other_asset = Address::generate(&env);
+
+    for (i, timestamp) in [(1u8, 100u64), (2, 200), (3, 300), (4, 400)] {
+        env.ledger().with_mut(|l| l.timestamp = timestamp);
+        let (buyer, seller) = submit_pair(&env, &s.orderbook, &asset, i * 2, i * 2 + 1);
+        record_match(&env, &s, 100 + i, i * 2, i * 2 + 1, &asset, &buyer, &seller, 10, 5);
+    }
+    let (buyer, seller) = submit_pair(&env, &s.orderbook, &other_asset, 20, 21);
+    record_match(&env, &s, 120, 20, 21, &other_asset, &buyer, &seller, 10, 5);
+
+    // Both bounds are inclusive
+    let matches = s.client.get_matches_in_range(&asset, &200, &300);
+    assert_eq!(matches.len(), 2);
+    assert_eq!(matches.get(0).unwrap().match_id, commitment(&env, 102));
+    assert_eq!(matches.get(1).unwrap().match_id, commitment(&env, 103));
+
+    assert_eq!(s.client.get_matches_in_range(&asset, &0, &u64::MAX).len(), 4);
+    assert_eq!(s.client.get_matches_in_range(&asset, &401, &500).len(), 0);
+}
+
+#[test]
+fn test_get_queue_position() {
+    let env = Env::default();
+    env.mock_all_auths();
+    let s = setup(&env);
+    let asset = Address::generate(&env);
+    let trader = Address::generate(&env);
+
+    for i in 0..4u8 {
+        env.ledger().with_mut(|l| l.timestamp = 100 + i as u64);
+        submit(&env, &s.orderbook, &trader, i, &asset, OrderSide::Buy, 3600);
+    }
+    // Opposite side and same-timestamp orders
+    submit(&env, &s.orderbook, &trader, 10, &asset, OrderSide::Sell, 3600);
+    submit(&env, &s.orderbook, &trader, 4, &asset, OrderSide::Buy, 3600);
+
+    for i in 0..5u8 {
+        assert_eq!(s.client.get_queue_position(&commitment(&env, i)), Some(i as u32));
+    }
+    assert_eq!(s.client.get_queue_position(&commitment(&env, 10)), Some(0));
+
+    // Cancelled orders leave the queue and later orders move up
+    cancel(&env, &s.orderbook, &trader, 1);
+    assert_eq!(s.client.get_queue_position(&commitment(&env, 1)), None);
+    assert_eq!(s.client.get_queue_position(&commitment(&env, 3)), Some(2));
+    assert_eq!(s.client.get_queue_position(&commitment(&env, 99)), None);
+
+    // Partially filled orders keep their place
+    let seller = Address::generate(&env);
+    submit(&env, &s.orderbook, &seller, 20, &asset, OrderSide::Sell, 3600);
+    record_match(&env, &s, 30, 0, 20, &asset, &trader, &seller, 250, 5);
+    assert_eq!(s.client.get_queue_position(&commitment(&env, 0)), Some(0));
+}
+
+#[test]
+fn test_side_liquidity() {
+    let env = Env::default();
+    env.mock_all_auths();
+    let s = setup(&env);
+    let asset = Address::generate(&env);
+    let other_asset = Address::generate(&env);
+
+    assert_eq!(s.client.get_side_liquidity(&asset), (0, 0));
+
+    s.client.set_side_liquidity(&s.admin, &asset, &1_500, &900);
+    assert_eq!(s.client.get_side_liquidity(&asset), (1_500, 900));
+    assert_eq!(s.client.get_side_liquidity(&other_asset), (0, 0));
+
+    s.client.set_side_liquidity(&s.admin, &asset, &0, &2_000);
+    assert_eq!(s.client.get_side_liquidity(&asset), (0, 2_000));
+}
+
+#[test]
+fn test_side_liquidity_rejects_non_matcher() {
+    let env = Env::default();
+    env.mock_all_auths();
+    let s = setup(&env);
+    let asset = Address::generate(&env);
+
+    let stranger = Address::generate(&env);
+    let result = s.client.try_set_side_liquidity(&stranger, &asset, &1_500, &900);
+    assert_eq!(result, Err(Ok(AnalyticsError::OnlyAdmin)));
+    assert_eq!(s.client.get_side_liquidity(&asset), (0, 0));
+}
+
+#[test]
+fn test_daily_report() {
+    let env = Env::default();
+    env.mock_all_auths();
+    let s = setup(&env);
+    let asset = Address::generate(&env);
+    let other_asset = Address::generate(&env);
+    update_config(&s, |c| c.fee_bps = 100);
+
+    // Day 1: two settled matches, one left unsettled, one on another asset
+    env.ledger().with_mut(|l| l.timestamp = 86_400 + 3_600);
+    let (buyer, seller) = submit_pair(&env, &s.orderbook, &asset, 1, 2);
+    record_match(&env, &s, 10, 1, 2, &asset, &buyer, &seller, 100, 10);
+    record_match(&env, &s, 11, 1, 2, &asset, &buyer, &seller, 300, 20);
+    record_match(&env, &s, 12, 1, 2, &asset, &buyer, &seller, 50, 30);
+    let (other_buyer, other_seller) = submit_pair(&env, &s.orderbook, &other_asset, 3, 4);
+    record_match(&env, &s, 13, 3, 4, &other_asset, &other_buyer, &other_seller, 100, 99);
+    s.orderbook.mark_settled(&s.admin, &commitment(&env, 10));
+    s.orderbook.mark_settled(&s.admin, &commitment(&env, 11));
+    s.orderbook.mark_settled(&s.admin, &commitment(&env, 13));
+
+    let result = s.client.try_snapshot_daily_report(&s.admin, &asset, &1);
+    assert_eq!(result, Err(Ok(AnalyticsError::DayNotClosed)));
+
+    // Settled on day 2, so not part of day 1
+    env.ledger().with_mut(|l| l.timestamp = 2 * 86_400 + 60);
+    s.orderbook.mark_settled(&s.admin, &commitment(&env, 12));
+
+    let result = s.client.try_snapshot_daily_report(&buyer, &asset, &1);
+    assert_eq!(result, Err(Ok(AnalyticsError::OnlyAdmin)));
+
+    let fees = s.orderbook.get_match(&commitment(&env, 10)).unwrap().fee
+        + s.orderbook.get_match(&commitment(&env, 11)).unwrap().fee;
+    let report = s.client.snapshot_daily_report(&s.admin, &asset, &1);
+    assert_eq!(report.volume, 400);
+    assert_eq!(report.trade_count, 2);
+    assert_eq!(report.vwap, 17);
+    assert_eq!(report.fees, fees);
+    assert_eq!(report.snapshot_timestamp, 2 * 86_400 + 60);
+    assert_eq!(s.client.get_daily_report(&asset, &1), Some(report));
+    assert_eq!(s.client.get_daily_report(&asset, &0), None);
+
+    let result = s.client.try_snapshot_daily_report(&s.admin, &asset, &1);
+    assert_eq!(result, Err(Ok(AnalyticsError::ReportAlreadyExists)));
+}
+
+#[test]
+fn test_twap_over_settled_matches() {
+    let env = Env::default();
+    env.mock_all_auths();
+    let s = setup(&env);
+    let asset = Address::generate(&env);
+    assert_eq!(s.client.get_twap(&asset, &3_600), 0);
+
+    // Matched in order 100, 200, 160, but 200 settles first
+    env.ledger().with_mut(|l| l.timestamp = 10_000);
+    for (i, price) in [100i128, 200, 160].iter().enumerate() {
+        let (buy_id, sell_id, match_id) = (3 * i as u8, 3 * i as u8 + 1, 3 * i as u8 + 2);
+        let (buyer, seller) = submit_pair(&env, &s.orderbook, &asset, buy_id, sell_id);
+        record_match(&env, &s, match_id, buy_id, sell_id, &asset, &buyer, &seller, 10, *price);
+    }
+    for match_id in [5u8, 2, 8] {
+        s.orderbook.mark_settled(&s.admin, &commitment(&env, match_id));
+        env.ledger().with_mut(|l| l.timestamp += 600);
+    }
+
+    // 200 and 100 each stood for 600s, 160 for the last 600s
+    assert_eq!(s.client.get_twap(&asset, &1_800), 153);
+    assert_eq!(s.client.get_twap(&asset, &600), 160);
+    let twap = s.client.get_twap(&asset, &1_200);
+    assert!(twap > 100 && twap < 160);
+
+    // A lookback before the first settlement starts at the first settlement
+    assert_eq!(s.client.get_twap(&asset, &100_000), 153);
+    assert_eq!(s.client.get_twap(&asset, &0), 160);
+
+    // A long history still reaches back to the first two prices
+    for i in 0..35u8 {
+        let (buy_id, sell_id, match_id) = (100 + 3 * i, 101 + 3 * i, 102 + 3 * i);
+        let (buyer, seller) = submit_pair(&env, &s.orderbook, &asset, buy_id, sell_id);
+        record_match(&env, &s, match_id, buy_id, sell_id, &asset, &buyer, &seller, 10, 160);
+        s.orderbook.mark_settled(&s.admin, &commitment(&env, match_id));
+        env.ledger().with_mut(|l| l.timestamp += 60);
+    }
+    // (200 * 600 + 100 * 600 + 160 * 2_700) / 3_900
+    assert_eq!(s.client.get_twap(&asset, &100_000), 156);
+    assert_eq!(s.client.get_twap(&asset, &2_700), 160);
+}
+
+#[test]
+fn test_can_settle() {
+    let env = Env::default();
+    env.mock_all_auths();
+    let s = setup(&env);
+    let registry = MockRegistryClient::new(&env, &s.orderbook.get_registry());
+    let settlement = MockSettlementClient::new(&env, &s.orderbook.get_settlement());
+
+    let asset = Address::generate(&env);
+    let cash = Address::generate(&env);
+    let (buyer, seller) = submit_pair(&env, &s.orderbook, &asset, 1, 2);
+    let match_id = commitment(&env, 10);
+    record_match(&env, &s, 10, 1, 2, &asset, &buyer, &seller, 100, 50);
+
+    let result = s.client.try_can_settle(&commitment(&env, 99));
+    assert_eq!(result, Err(Ok(OrderbookError::MatchNotFound)));
+    assert_eq!(s.client.try_can_settle(&match_id), Err(Ok(OrderbookError::NoSettlementAsset)));
+    registry.set_settlement_asset(&asset, &Some(cash.clone()));
+
+    // Each leg needs its lock
+    assert_eq!(s.client.try_can_settle(&match_id), Err(Ok(OrderbookError::InsufficientEscrow)));
+    settlement.set_locked_balance(&seller, &asset, &100);
+    assert_eq!(s.client.try_can_settle(&match_id), Err(Ok(OrderbookError::InsufficientEscrow)));
+    // The buyer's lock must cover the whole notional, not the unit price
+    settlement.set_locked_balance(&buyer, &cash, &4_999);
+    assert_eq!(s.client.try_can_settle(&match_id), Err(Ok(OrderbookError::InsufficientEscrow)));
+    settlement.set_locked_balance(&buyer, &cash, &5_000);
+    s.client.can_settle(&match_id);
+
+    // Linked nullifiers must be distinct and unspent
+    s.orderbook.set_order_nullifier(&buyer, &commitment(&env, 1), &commitment(&env, 50));
+    s.orderbook.set_order_nullifier(&seller, &commitment(&env, 2), &commitment(&env, 50));
+    assert_eq!(s.client.try_can_settle(&match_id), Err(Ok(OrderbookError::NullifierUsed)));
+    s.orderbook.set_order_nullifier(&seller, &commitment(&env, 2), &commitment(&env, 51));
+    s.client.can_settle(&match_id);
+    settlement.spend_nullifier(&commitment(&env, 51));
+    assert_eq!(s.client.try_can_settle(&match_id), Err(Ok(OrderbookError::NullifierUsed)));
+
+    // Settling waits for the asset's next settlement window
+    update_asset_config(&s, &asset, |config| config.settle_interval = 3_600);
+    assert_eq!(s.client.try_can_settle(&match_id), Err(Ok(OrderbookError::NotInSettlementWindow)));
+    update_asset_config(&s, &asset, |config| config.settle_interval = 0);
+
+    s.orderbook.mark_settled(&s.admin, &match_id);
+    assert_eq!(s.client.try_can_settle(&match_id), Err(Ok(OrderbookError::MatchAlreadySettled)));
+}
+
+#[test]
+fn test_are_compatible() {
+    let env = Env::default();
+    env.mock_all_auths();
+    let s = setup(&env);
+
+    let asset = Address::generate(&env);
+    let other_asset = Address::generate(&env);
+    let buyer = Address::generate(&env);
+    let seller = Address::generate(&env);
+    submit(&env, &s.orderbook, &buyer, 1, &asset, OrderSide::Buy, 3600);
+    submit(&env, &s.orderbook, &seller, 2, &asset, OrderSide::Sell, 3600);
+    submit(&env, &s.orderbook, &seller, 3, &other_asset, OrderSide::Sell, 3600);
+
+    s.client.are_compatible(&commitment(&env, 1), &commitment(&env, 2));
+    s.client.are_compatible(&commitment(&env, 2), &commitment(&env, 1));
+
+    let result = s.client.try_are_compatible(&commitment(&env, 1), &commitment(&env, 9));
+    assert_eq!(result, Err(Ok(OrderbookError::OrderNotFound)));
+
+    // Different assets need a tradable pair
+    let result = s.client.try_are_compatible(&commitment(&env, 1), &commitment(&env, 3));
+    assert_eq!(result, Err(Ok(OrderbookError::AssetMismatch)));
+    s.orderbook.set_tradable_pair(&s.admin, &other_asset, &asset, &true);
+    s.client.are_compatible(&commitment(&env, 1), &commitment(&env, 3));
+}
+
+#[test]
+fn test_are_compatible_same_side_and_self_trade() {
+    let env = Env::default();
+    env.mock_all_auths();
+    let s = setup(&env);
+
+    let asset = Address::generate(&env);
+    let trader = Address::generate(&env);
+    submit(&env, &s.orderbook, &trader, 1, &asset, OrderSide::Buy, 3600);
+    submit(&env, &s.orderbook, &Address::generate(&env), 2, &asset, OrderSide::Buy, 3600);
+    submit(&env, &s.orderbook, &trader, 3, &asset, OrderSide::Sell, 3600);
+
+    let result = s.client.try_are_compatible(&commitment(&env, 1), &commitment(&env, 2));
+    assert_eq!(result, Err(Ok(OrderbookError::InvalidOrderSide)));
+    let result = s.client.try_are_compatible(&commitment(&env, 1), &commitment(&env, 3));
+    assert_eq!(result, Err(Ok(OrderbookError::SelfMatchNotAllowed)));
+}
+
+#[test]
+fn test_are_compatible_inactive_orders() {
+    let env = Env::default();
+    env.mock_all_auths();
+    let s = setup(&env);
+
+    let buyer = Address::generate(&env);
+    let seller = Address::generate(&env);
+    let asset = Address::generate(&env);
+    submit(&env, &s.orderbook, &buyer, 1, &asset, OrderSide::Buy, 3600);
+    submit(&env, &s.orderbook, &seller, 2, &asset, OrderSide::Sell, 3600);
+    submit(&env, &s.orderbook, &seller, 3, &asset, OrderSide::Sell, 3600);
+    submit(&env, &s.orderbook, &buyer, 4, &asset, OrderSide::Buy, 3600);
+    submit(&env, &s.orderbook, &seller, 5, &asset, OrderSide::Sell, 60);
+
+    cancel(&env, &s.orderbook, &seller, 3);
+    let result = s.client.try_are_compatible(&commitment(&env, 1), &commitment(&env, 3));
+    assert_eq!(result, Err(Ok(OrderbookError::OrderAlreadyCancelled)));
+
+    record_match(&env, &s, 10, 4, 2, &asset, &buyer, &seller, 1_000, 50);
+    let result = s.client.try_are_compatible(&commitment(&env, 1), &commitment(&env, 2));
+    assert_eq!(result, Err(Ok(OrderbookError::OrderAlreadyMatched)));
+
+    // Past its expiry an order is incompatible before it is swept
+    env.ledger().with_mut(|l| l.timestamp += 61);
+    let result = s.client.try_are_compatible(&commitment(&env, 1), &commitment(&env, 5));
+    assert_eq!(result, Err(Ok(OrderbookError::OrderExpired)));
+}
+
+#[test]
+fn test_are_compatible_ineligible_participant() {
+    let env = Env::default();
+    env.mock_all_auths();
+    let s = setup(&env);
+    let registry = MockRegistryClient::new(&env, &s.orderbook.get_registry());
+
+    let buyer = Address::generate(&env);
+    let seller = Address::generate(&env);
+    let asset = Address::generate(&env);
+    submit(&env, &s.orderbook, &buyer, 1, &asset, OrderSide::Buy, 3600);
+    submit(&env, &s.orderbook, &seller, 2, &asset, OrderSide::Sell, 3600);
+
+    // Either side being blacklisted makes the pair incompatible
+    registry.set_blacklisted(&seller, &true);
+    let result = s.client.try_are_compatible(&commitment(&env, 1), &commitment(&env, 2));
+    assert_eq!(result, Err(Ok(OrderbookError::ParticipantNotEligible)));
+    let result = s.client.try_are_compatible(&commitment(&env, 2), &commitment(&env, 1));
+    assert_eq!(result, Err(Ok(OrderbookError::ParticipantNotEligible)));
+
+    registry.set_blacklisted(&seller, &false);
+    s.client.are_compatible(&commitment(&env, 1), &commitment(&env, 2));
+}
+
+#[test]
+fn test_is_asset_tradable() {
+    let env = Env::default();
+    env.mock_all_auths();
+    let s = setup(&env);
+    let registry = MockRegistryClient::new(&env, &s.orderbook.get_registry());
+
+    let asset = Address::generate(&env);
+    env.ledger().with_mut(|l| l.timestamp = 12 * 3600);
+
+    // Not registered
+    assert!(!s.client.is_asset_tradable(&asset));
+    registry.set_asset_eligible(&asset, &true);
+    assert!(s.client.is_asset_tradable(&asset));
+
+    s.orderbook.set_paused(&s.admin, &true);
+    assert!(!s.client.is_asset_tradable(&asset));
+    s.orderbook.set_paused(&s.admin, &false);
+
+    update_asset_config(&s, &asset, |c| c.halted = true);
+    assert!(!s.client.is_asset_tradable(&asset));
+    update_asset_config(&s, &asset, |c| c.halted = false);
+
+    update_asset_config(&s, &asset, |c| {
+        c.trading_open_seconds = 13 * 3600;
+        c.trading_close_seconds = 20 * 3600;
+    });
+    assert!(!s.client.is_asset_tradable(&asset));
+    env.ledger().with_mut(|l| l.timestamp = 86_400 + 14 * 3600);
+    assert!(s.client.is_asset_tradable(&asset));
+
+    s.orderbook.delist_asset(&s.admin, &asset, &10);
+    assert!(!s.client.is_asset_tradable(&asset));
+}
+
+#[test]
+fn test_is_order_nullifier_spent() {
+    let env = Env::default();
+    env.mock_all_auths();
+    let s = setup(&env);
+    let settlement = MockSettlementClient::new(&env, &s.orderbook.get_settlement());
+    let asset = Address::generate(&env);
+    let (buyer, _seller) = submit_pair(&env, &s.orderbook, &asset, 1, 2);
+    submit(&env, &s.orderbook, &buyer, 3, &asset, OrderSide::Buy, 3600);
+
+    let nullifier = BytesN::from_array(&env, &[11u8; 32]);
+    assert!(!s.client.is_order_nullifier_spent(&commitment(&env, 9)));
+    assert!(!s.client.is_order_nullifier_spent(&commitment(&env, 1)));
+    s.orderbook.set_order_nullifier(&buyer, &commitment(&env, 1), &nullifier);
+    assert!(!s.client.is_order_nullifier_spent(&commitment(&env, 1)));
+
+    // A second order reusing the nullifier is caught once it is spent
+    s.orderbook.set_order_nullifier(&buyer, &commitment(&env, 3), &nullifier);
+    settlement.spend_nullifier(&nullifier);
+    assert!(s.client.is_order_nullifier_spent(&commitment(&env, 1)));
+    assert!(s.client.is_order_nullifier_spent(&commitment(&env, 3)));
+    assert!(!s.client.is_order_nullifier_spent(&commitment(&env, 2)));
+}
+
+#[test]
+fn test_get_order_counts_by_trader() {
+    let env = Env::default();
+    env.mock_all_auths();
+    let s = setup(&env);
+    let asset_a = Address::generate(&env);
+    let asset_b = Address::generate(&env);
+    let busy = Address::generate(&env);
+    let casual = Address::generate(&env);
+    let idle = Address::generate(&env);
+
+    for i in 1..=3u8 {
+        submit(&env, &s.orderbook, &busy, i, &asset_a, OrderSide::Buy, 3600);
+    }
+    submit(&env, &s.orderbook, &busy, 4, &asset_b, OrderSide::Sell, 60);
+    submit(&env, &s.orderbook, &casual, 5, &asset_a, OrderSide::Sell, 3600);
+
+    let traders = vec![&env, busy.clone(), casual.clone(), idle.clone()];
+    assert_eq!(s.client.get_order_counts_by_trader(&traders), vec![&env, 4u32, 1, 0]);
+
+    // Cancelled, expired and filled orders drop out of the counts, and a
+    // reverted fill puts the order back
+    cancel(&env, &s.orderbook, &busy, 1);
+    env.ledger().with_mut(|l| l.timestamp += 61);
+    s.orderbook.expire_orders_for_asset(&s.admin, &asset_b);
+    assert_eq!(s.client.get_order_counts_by_trader(&traders), vec![&env, 2u32, 1, 0]);
+
+    record_match(&env, &s, 10, 2, 5, &asset_a, &busy, &casual, 1_000, 5);
+    assert_eq!(s.client.get_order_counts_by_trader(&traders), vec![&env, 1u32, 0, 0]);
+    s.orderbook.revert_match(&s.admin, &commitment(&env, 10));
+    assert_eq!(s.client.get_order_counts_by_trader(&traders), vec![&env, 2u32, 1, 0]);
+}
+
+#[test]
+fn test_get_trader_fees_paid() {
+    let env = Env::default();
+    env.mock_all_auths();
+    let s = setup(&env);
+    let asset = Address::generate(&env);
+    let buyer = Address::generate(&env);
+    let seller = Address::generate(&env);
+
+    update_config(&s, |c| c.fee_bps = 50);
+    // Notionals of 10_000, 4_000 and 2_000 at 50 bps
+    for (i, quantity, price) in [(1u8, 100i128, 100i128), (2, 40, 100), (3, 20, 100)] {
+        submit(&env, &s.orderbook, &buyer, i * 2, &asset, OrderSide::Buy, 3600);
+        submit(&env, &s.orderbook, &seller, i * 2 + 1, &asset, OrderSide::Sell, 3600);
+        record_match(&env, &s, 100 + i, i * 2, i * 2 + 1, &asset, &buyer, &seller, quantity, price);
+    }
+
+    // Fees count once their match settles
+    assert_eq!(s.client.get_trader_fees_paid(&seller), 0);
+    s.orderbook.mark_settled(&s.admin, &commitment(&env, 101));
+    s.orderbook.mark_settled(&s.admin, &commitment(&env, 102));
+    assert_eq!(s.client.get_trader_fees_paid(&seller), 50 + 20);
+    // Settling a match again doesn't count its fee twice
+    s.orderbook.mark_settled(&s.admin, &commitment(&env, 103));
+    s.orderbook.mark_settled(&s.admin, &commitment(&env, 103));
+    assert_eq!(s.client.get_trader_fees_paid(&seller), 50 + 20 + 10);
+    assert_eq!(s.client.get_trader_fees_paid(&buyer), 0);
+
+    // With the seller designated maker the buyer pays
+    submit(&env, &s.orderbook, &buyer, 20, &asset, OrderSide::Buy, 3600);
+    submit(&env, &s.orderbook, &seller, 21, &asset, OrderSide::Sell, 3600);
+    s.orderbook.record_match(
+        &s.admin, &commitment(&env, 110), &commitment(&env, 20), &commitment(&env, 21), &asset, &buyer, &seller,
+        &100, &100, &Some(OrderSide::Sell),
+    );
+    s.orderbook.mark_settled(&s.admin, &commitment(&env, 110));
+    assert_eq!(s.client.get_trader_fees_paid(&buyer), 50);
+    assert_eq!(s.client.get_trader_fees_paid(&seller), 50 + 20 + 10);
+}
+
+#[test]
+fn test_get_counterparty_count() {
+    let env = Env::default();
+    env.mock_all_auths();
+    let s = setup(&env);
+    let asset = Address::generate(&env);
+
+    let trader = Address::generate(&env);
+    let alice = Address::generate(&env);
+    let bob = Address::generate(&env);
+    assert_eq!(s.client.get_counterparty_count(&trader), 0);
+
+    // trader buys from alice twice and sells to bob once
+    let fills = [(1u8, &alice, true), (2, &alice, true), (3, &bob, false)];
+    for (i, other, trader_buys) in fills {
+        let (buyer, seller) = if trader_buys { (&trader, other) } else { (other, &trader) };
+        submit(&env, &s.orderbook, buyer, i * 2, &asset, OrderSide::Buy, 3600);
+        submit(&env, &s.orderbook, seller, i * 2 + 1, &asset, OrderSide::Sell, 3600);
+        record_match(&env, &s, 100 + i, i * 2, i * 2 + 1, &asset, buyer, seller, 10, 5);
+    }
+
+    assert_eq!(s.client.get_counterparty_count(&trader), 2);
+    assert_eq!(s.client.get_counterparty_count(&alice), 1);
+    assert_eq!(s.client.get_counterparty_count(&bob), 1);
+
+    // Reverting the only match with bob drops the pair, while alice stays
+    // after one of two matches with the trader is reverted
+    s.orderbook.revert_match(&s.admin, &commitment(&env, 103));
+    s.orderbook.revert_match(&s.admin, &commitment(&env, 101));
+    assert_eq!(s.client.get_counterparty_count(&trader), 1);
+    assert_eq!(s.client.get_counterparty_count(&alice), 1);
+    assert_eq!(s.client.get_counterparty_count(&bob), 0);
+}
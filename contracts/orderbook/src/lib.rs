@@ -1,19 +1,91 @@
 #![no_std]
+#![allow(clippy::too_many_arguments)]
 
 use soroban_sdk::{
-    contract, contracterror, contractimpl, contracttype, symbol_short, vec,
-    Address, Bytes, BytesN, Env, Symbol, Vec,
+    contract, contracterror, contractevent, contractimpl, contracttype, symbol_short, token, vec,
+    Address, Bytes, BytesN, Env, Map, Symbol, Val, Vec,
 };
 
 #[cfg(test)]
 mod test;
 
+// Import the settlement contract
+mod settlement_wasm {
+    soroban_sdk::contractimport!(
+        file = "../../target/wasm32v1-none/release/darkpool_settlement.wasm"
+    );
+}
+
+mod verifier_wasm {
+    soroban_sdk::contractimport!(
+        file = "../../target/wasm32v1-none/release/groth16_verifier_bn254.wasm"
+    );
+}
+
+mod registry_wasm {
+    soroban_sdk::contractimport!(
+        file = "../../target/wasm32v1-none/release/darkpool_registry.wasm"
+    );
+}
+
 // Storage keys
 const ADMIN_KEY: Symbol = symbol_short!("admin");
 const REGISTRY_KEY: Symbol = symbol_short!("registry");
 const SETTLEMENT_KEY: Symbol = symbol_short!("settl");
-const ORDERS_KEY: Symbol = symbol_short!("orders");
+const VERIFIER_KEY: Symbol = symbol_short!("verifier");
+const OWNERSHIP_VK_KEY: Symbol = symbol_short!("own_vk");
+const TEST_MODE_KEY: Symbol = symbol_short!("test_mode");
+const ORDER_SEQ_KEY: Symbol = symbol_short!("order_seq");
+const ORDER_ASSETS_KEY: Symbol = symbol_short!("ord_asset");
 const MATCHES_KEY: Symbol = symbol_short!("matches");
+const CONFIG_KEY: Symbol = symbol_short!("config");
+const ASSET_CONFIG_KEY: Symbol = symbol_short!("asset_cfg");
+const DELISTED_KEY: Symbol = symbol_short!("delisted");
+const KEEPER_POOL_KEY: Symbol = symbol_short!("kpr_pool");
+const PAIRS_KEY: Symbol = symbol_short!("pairs");
+const TOPS_KEY: Symbol = symbol_short!("tops");
+const PROOF_ATTEMPTS_KEY: Symbol = symbol_short!("prf_tries");
+const STATS_KEY: Symbol = symbol_short!("stats");
+const STATS_OPT_OUT_KEY: Symbol = symbol_short!("stats_out");
+const DEFAULT_EXPIRY_KEY: Symbol = symbol_short!("dflt_exp");
+const PAUSED_KEY: Symbol = symbol_short!("paused");
+const PENDING_ADMIN_KEY: Symbol = symbol_short!("pend_adm");
+const ORDER_COUNTS_KEY: Symbol = symbol_short!("ord_count");
+const MATCH_SEQ_KEY: Symbol = symbol_short!("match_seq");
+const NOTIONAL_KEY: Symbol = symbol_short!("notional");
+const OPEN_INTEREST_KEY: Symbol = symbol_short!("open_int");
+
+// Fees are expressed in basis points of match notional
+const BPS_DENOMINATOR: i128 = 10_000;
+
+// Upper bound on entries returned by unpaged getters, to stay within
+// Soroban's return value limits on large books
+pub const MAX_RETURN_ENTRIES: u32 = 100;
+
+// Commitments held by one page of an order index, so index entries stay
+// small however large the book grows
+pub const INDEX_PAGE_SIZE: u32 = 64;
+
+// Approximate ledger close time, used to size temporary storage TTLs
+const LEDGER_SECONDS: u64 = 5;
+
+// Order lifetime applied when a trader submits with no expiry and has no
+// stored preference
+pub const DEFAULT_EXPIRY_SECONDS: u64 = 86_400;
+
+// Longest order lifetime accepted until the admin configures another
+pub const DEFAULT_MAX_EXPIRY_SECONDS: u64 = 30 * 86_400;
+
+// Shortest lifetime for which an unfunded order earns a keeper reward on
+// expiry, so short-lived self-submitted orders cannot drain the pool
+pub const KEEPER_MIN_LIFETIME_SECONDS: u64 = 3_600;
+
+// Trading hours are expressed in seconds since midnight UTC
+const SECONDS_PER_DAY: u64 = 86_400;
+
+// Function an order's expiry callback contract is invoked with, passing the
+// expired order's commitment
+pub const EXPIRY_CALLBACK_FN: Symbol = symbol_short!("on_expiry");
 
 #[contracterror]
 #[derive(Copy, Clone, Debug, Eq, PartialEq, PartialOrd, Ord)]
@@ -29,10 +101,77 @@ pub enum OrderbookError {
     MatchNotFound = 8,
     InvalidOrderSide = 9,
     AssetMismatch = 10,
+    SelfMatchNotAllowed = 11,
+    InvalidFeeConfig = 12,
+    ArithmeticOverflow = 13,
+    MatchCooldown = 14,
+    AssetDelisted = 15,
+    NegativePriceNotAllowed = 16,
+    KeeperRewardNotConfigured = 17,
+    PairNotTradable = 18,
+    NotInSettlementWindow = 19,
+    InsufficientFundsForOrder = 20,
+    ProofExpired = 21,
+    TopOfBookNotPublished = 22,
+    ProofRateLimited = 23,
+    InexactSettlement = 24,
+    InvalidPriceScale = 25,
+    PegPriceNotPositive = 26,
+    PriceBeyondPeg = 27,
+    AssetHalted = 28,
+    OutsideTradingHours = 29,
+    InvalidTradingHours = 30,
+    InvalidQuantity = 31,
+    QuantityIncreaseNotAllowed = 32,
+    InsufficientRemainingQuantity = 33,
+    NullifierUsed = 34,
+    InsufficientEscrow = 35,
+    SettlementFailed = 36,
+    QuoteAssetNotConfigured = 37,
+    AccreditationRequired = 38,
+    NotionalLimitExceeded = 39,
+    NoSettlementAsset = 40,
+    DuplicateCommitment = 41,
+    InvalidExpiry = 42,
+    BelowMinFill = 43,
+    ContractPaused = 44,
+    NotPendingAdmin = 47,
+    MatchAlreadySettled = 48,
+    OpenInterestCapReached = 49,
+    InvalidMatchParameters = 50,
+    MinRestingNotElapsed = 51,
+    DuplicateMatch = 52,
+    RootTooStale = 53,
+    TooManyActiveOrders = 54,
+    MaxRetriesExceeded = 55,
+    BelowMinimumQuantity = 56,
+    ParticipantNotEligible = 57,
+    InvalidLimitPrice = 58,
+}
+
+/// Persistent storage keys for orders and their indexes
+///
+/// Each order lives in its own entry, so touching one order never reads or
+/// rewrites the rest of the book.
+#[derive(Clone)]
+#[contracttype]
+pub enum DataKey {
+    Order(BytesN<32>),
+    IndexLen(OrderIndex),
+    IndexPage(OrderIndex, u32),
+    OcoGroup(BytesN<32>),
+}
+
+/// A list of order commitments, kept in pages of `INDEX_PAGE_SIZE`
+#[derive(Clone)]
+#[contracttype]
+pub enum OrderIndex {
+    Asset(Address),
+    Trader(Address),
 }
 
 /// Order side (buy or sell)
-#[derive(Clone, Copy, PartialEq, Eq)]
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
 #[contracttype]
 #[repr(u32)]
 pub enum OrderSide {
@@ -41,7 +180,7 @@ pub enum OrderSide {
 }
 
 /// Order status
-#[derive(Clone, Copy, PartialEq, Eq)]
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
 #[contracttype]
 #[repr(u32)]
 pub enum OrderStatus {
@@ -50,10 +189,25 @@ pub enum OrderStatus {
     Settled = 2,
     Cancelled = 3,
     Expired = 4,
+    PartiallyFilled = 5,
+}
+
+impl OrderStatus {
+    /// Whether an order in this status can still be matched
+    pub fn is_open(&self) -> bool {
+        matches!(self, OrderStatus::Active | OrderStatus::PartiallyFilled)
+    }
 }
 
 /// Order commitment stored in the orderbook
 /// The actual order details (quantity, price) are hidden in the commitment
+///
+/// `cancelled_at` is when the order was cancelled, 0 if it never was.
+/// Orders sharing an `oco_group` are one-cancels-other siblings.
+/// `expected_root` is the whitelist root the order was built against, produced
+/// by registry insertion `expected_root_index`. `min_display_quantity` is the
+/// public floor below which no match, not even a full fill, is accepted, and
+/// no partial fill may leave a smaller remainder on the book.
 #[derive(Clone)]
 #[contracttype]
 pub struct OrderCommitment {
@@ -65,9 +219,45 @@ pub struct OrderCommitment {
     pub expiry: u64,
     pub status: OrderStatus,
     pub tree_index: u32,
+    pub last_match_timestamp: u64,
+    pub proof_valid_until: u64,
+    pub locked_asset: Address,
+    pub locked_amount: i128,
+    pub limit_price: i128,
+    pub expiry_callback: Option<Address>,
+    pub exclude_from_stats: bool,
+    pub peg_offset: Option<i128>,
+    pub total_quantity: i128,
+    pub filled_quantity: i128,
+    pub remaining_quantity: i128,
+    pub min_fill_quantity: i128,
+    pub min_display_quantity: i128,
+    pub nullifier: Option<BytesN<32>>,
+    pub cancelled_at: u64,
+    pub oco_group: Option<BytesN<32>>,
+    pub expected_root: Option<BytesN<32>>,
+    pub expected_root_index: u32,
+}
+
+/// Confirmation returned when an order is submitted
+///
+/// `required_lock` is the escrow locked in settlement for the order, 0
+/// unless funded submission is enabled.
+#[derive(Clone, Debug, PartialEq, Eq)]
+#[contracttype]
+pub struct SubmitReceipt {
+    pub index: u32,
+    pub required_lock: i128,
+    pub expiry: u64,
 }
 
 /// Matched trade record
+///
+/// `price` is per unit, so the buyer pays `quantity * price`.
+/// `timestamp` is when the match was recorded and `settled_timestamp` when
+/// it was settled, 0 until then. `settle_attempts` counts reported
+/// settlement failures; `failed` is set once they reach the configured
+/// maximum and the match can no longer settle.
 #[derive(Clone)]
 #[contracttype]
 pub struct MatchRecord {
@@ -81,6 +271,232 @@ pub struct MatchRecord {
     pub price: i128,
     pub timestamp: u64,
     pub is_settled: bool,
+    pub settled_timestamp: u64,
+    pub fee: i128,
+    pub maker_side: OrderSide,
+    pub rebate: i128,
+    pub settle_attempts: u32,
+    pub failed: bool,
+}
+
+/// Emitted when an order is submitted
+///
+/// Carries only what is already public on the book; the order's hidden
+/// terms stay behind the commitment.
+#[contractevent(topics = ["order", "submitted"])]
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct OrderSubmitted {
+    #[topic]
+    pub commitment: BytesN<32>,
+    pub asset_address: Address,
+    pub side: OrderSide,
+}
+
+/// Emitted when a trader cancels an order
+#[contractevent(topics = ["order", "cancelled"])]
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct OrderCancelled {
+    #[topic]
+    pub commitment: BytesN<32>,
+}
+
+/// Emitted when an order expires
+///
+/// Carries the order's expiry callback, if any. The callback is invoked
+/// after the event is published, and a failing one cannot hold up expiry.
+#[contractevent(topics = ["order", "expired"])]
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct OrderExpired {
+    #[topic]
+    pub commitment: BytesN<32>,
+    pub expiry_callback: Option<Address>,
+}
+
+/// Emitted whenever a test-mode deployment skips an ownership proof
+///
+/// Indexers should treat any cancel or reduction accompanied by this
+/// event as unproven.
+#[contractevent]
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct TestModeBypass {
+    #[topic]
+    pub commitment: BytesN<32>,
+}
+
+/// Emitted when the verifier rejects an ownership proof
+///
+/// The call that carried the proof still succeeds, so the attempt counts
+/// against the trader's proof rate limit.
+#[contractevent(topics = ["proof", "rejected"])]
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct ProofRejected {
+    #[topic]
+    pub trader: Address,
+    pub commitment: BytesN<32>,
+}
+
+/// Emitted when a match is recorded
+///
+/// `reemitted` is set when an admin replays the event for a stored match,
+/// so indexers can drop duplicates. `decimals` is the asset's display
+/// precision as configured in the registry at emission, `None` if unset.
+#[contractevent(topics = ["match", "recorded"])]
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct MatchRecorded {
+    #[topic]
+    pub match_id: BytesN<32>,
+    #[topic]
+    pub asset_address: Address,
+    pub buy_commitment: BytesN<32>,
+    pub sell_commitment: BytesN<32>,
+    pub quantity: i128,
+    pub price: i128,
+    pub timestamp: u64,
+    pub reemitted: bool,
+    pub decimals: Option<u32>,
+}
+
+/// Emitted when an admin reverts an unsettled match
+#[contractevent(topics = ["match", "reverted"])]
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct MatchReverted {
+    #[topic]
+    pub match_id: BytesN<32>,
+}
+
+/// Best bid and ask for an asset as published by the matching engine
+#[derive(Clone)]
+#[contracttype]
+pub struct TopOfBook {
+    pub best_bid: i128,
+    pub best_ask: i128,
+    pub timestamp: u64,
+}
+
+/// A trader's proof attempts in the current rate-limit window
+#[derive(Clone)]
+#[contracttype]
+pub struct ProofAttempts {
+    pub window_start: u64,
+    pub count: u32,
+}
+
+/// Public per-asset market-data counters
+///
+/// Orders whose trader opted out of stats are not counted, nor are
+/// matches involving them.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+#[contracttype]
+pub struct AssetStats {
+    pub order_count: u32,
+    pub match_count: u32,
+    pub matched_volume: i128,
+}
+
+/// Orderbook-wide settings, as returned by `get_config` and taken by
+/// `set_config`
+///
+/// Orders may live for at most `max_expiry` seconds. No asset may hold more
+/// than `max_active_orders_per_asset` orders that have not been cancelled
+/// or expired. Limits and windows left at 0 are disabled. Fees are waived
+/// for `fee_holiday_start <= now < fee_holiday_end`, and the maker rebate
+/// is paid out of the taker's fee. `match_cooldown` is the minimum number
+/// of seconds between two fills of the same order, and a match is marked
+/// failed after `max_settle_attempts` failed settlements. An order pinned
+/// to an expected whitelist root is not matched once the registry has moved
+/// more than `max_root_lag` insertions past it. An order's proofs are
+/// accepted for `proof_validity` seconds after it is submitted, its trader
+/// may not cancel it for `min_resting_seconds`, and a matched order may
+/// still be cancelled for `post_match_cancel_grace` seconds after its last
+/// match. Each trader may make `proof_rate_limit` proof-verifying calls per
+/// `proof_rate_window` seconds, counting calls whose proof the verifier
+/// rejects. With `require_funded_submission` set, new orders lock
+/// settlement escrow: sells lock the order's asset and buys
+/// `funded_quote_asset`, and no trader may have more than
+/// `max_outstanding_notional` locked by live or unsettled orders. Keepers
+/// earn `keeper_reward_per_order` of `keeper_reward_token` per order they
+/// expire; pools are kept per token, so switching tokens leaves the
+/// previous pool in place.
+#[derive(Clone)]
+#[contracttype]
+pub struct OrderbookConfig {
+    pub fee_bps: u32,
+    pub maker_rebate_bps: u32,
+    pub fee_holiday_start: u64,
+    pub fee_holiday_end: u64,
+    pub match_cooldown: u64,
+    pub max_settle_attempts: u32,
+    pub max_outstanding_notional: i128,
+    pub max_active_orders_per_asset: u32,
+    pub max_root_lag: u32,
+    pub max_expiry: u64,
+    pub proof_validity: u64,
+    pub post_match_cancel_grace: u64,
+    pub min_resting_seconds: u64,
+    pub proof_rate_limit: u32,
+    pub proof_rate_window: u64,
+    pub require_funded_submission: bool,
+    pub funded_quote_asset: Option<Address>,
+    pub keeper_reward_token: Option<Address>,
+    pub keeper_reward_per_order: i128,
+}
+
+/// Per-asset settings, as returned by `get_asset_config` and taken by
+/// `set_asset_config`
+///
+/// A halt stops new orders but, unlike delisting, leaves resting orders in
+/// place. New orders are accepted from `trading_open_seconds` until
+/// `trading_close_seconds` since midnight UTC; a window that opens after it
+/// closes wraps past midnight, and equal values trade around the clock.
+/// With `accredited_only` set, only traders the registry reports as
+/// accredited may submit orders, and `min_quote_lifetime` overrides the
+/// orderbook's `min_resting_seconds` when set. New orders that would push
+/// the asset's open interest past `max_open_interest` are rejected; 0
+/// leaves it uncapped. Negative prices are rejected unless
+/// `negative_price_allowed` is set. Settlement batches align to multiples
+/// of `settle_interval` since the epoch; 0 lets the asset settle at any
+/// time. With `exact_settlement_scale` set, matches whose
+/// `quantity * price` is not a multiple of it are rejected so no party
+/// loses a rounding unit.
+#[derive(Clone, Default)]
+#[contracttype]
+pub struct AssetConfig {
+    pub halted: bool,
+    pub accredited_only: bool,
+    pub negative_price_allowed: bool,
+    pub max_open_interest: i128,
+    pub trading_open_seconds: u64,
+    pub trading_close_seconds: u64,
+    pub settle_interval: u64,
+    pub min_quote_lifetime: Option<u64>,
+    pub exact_settlement_scale: Option<i128>,
+}
+
+/// Orders returned by an unpaged getter, capped at `MAX_RETURN_ENTRIES`
+#[derive(Clone)]
+#[contracttype]
+pub struct OrderList {
+    pub orders: Vec<OrderCommitment>,
+    pub truncated: bool,
+}
+
+/// One page of active orders
+///
+/// `next_cursor` resumes the scan on the next call, or is `None` once the
+/// book has been fully read.
+#[derive(Clone)]
+#[contracttype]
+pub struct ActivePage {
+    pub orders: Vec<OrderCommitment>,
+    pub next_cursor: Option<u32>,
+}
+
+/// Matches returned by an unpaged getter, capped at `MAX_RETURN_ENTRIES`
+#[derive(Clone)]
+#[contracttype]
+pub struct MatchList {
+    pub matches: Vec<MatchRecord>,
+    pub truncated: bool,
 }
 
 #[contract]
@@ -94,20 +510,28 @@ impl DarkPoolOrderbook {
     /// * `admin` - Admin address
     /// * `registry_address` - Address of the registry contract
     /// * `settlement_address` - Address of the settlement contract
+    /// * `verifier_address` - Address of the Groth16 verifier contract
+    /// * `ownership_vk_bytes` - Verification key for order ownership proofs
+    /// * `test_mode` - Skip ownership proof verification; only for tests and
+    ///   testnets, and fixed for the life of the contract
     pub fn __constructor(
         env: Env,
         admin: Address,
         registry_address: Address,
         settlement_address: Address,
+        verifier_address: Address,
+        ownership_vk_bytes: Bytes,
+        test_mode: bool,
     ) {
         env.storage().instance().set(&ADMIN_KEY, &admin);
         env.storage().instance().set(&REGISTRY_KEY, &registry_address);
         env.storage().instance().set(&SETTLEMENT_KEY, &settlement_address);
+        env.storage().instance().set(&VERIFIER_KEY, &verifier_address);
+        env.storage().instance().set(&OWNERSHIP_VK_KEY, &ownership_vk_bytes);
+        env.storage().instance().set(&TEST_MODE_KEY, &test_mode);
 
-        // Initialize empty orders and matches
-        let orders: Vec<OrderCommitment> = vec![&env];
+        // Initialize empty matches; orders live in their own entries
         let matches: Vec<MatchRecord> = vec![&env];
-        env.storage().instance().set(&ORDERS_KEY, &orders);
         env.storage().instance().set(&MATCHES_KEY, &matches);
     }
 
@@ -118,10 +542,18 @@ impl DarkPoolOrderbook {
     /// * `commitment` - Hash commitment of the order (Poseidon(asset, side, qty, price, nonce, secret))
     /// * `asset_address` - The RWA token address (public for matching)
     /// * `side` - Buy or Sell (public for matching)
-    /// * `expiry_seconds` - How many seconds until order expires
+    /// * `expiry_seconds` - How many seconds until order expires; 0 uses the
+    ///   trader's default expiry. Capped at the config's `max_expiry`
+    /// * `quantity` - Total order size, disclosed so it can later be reduced
+    /// * `limit_price` - Highest price per unit a buy will pay, or lowest a
+    ///   sell will take. With funded submission enabled it must be positive;
+    ///   a buy locks `quantity * limit_price` of the quote asset and a sell
+    ///   locks `quantity`, valued at `limit_price` towards the trader's
+    ///   outstanding notional
+    /// * `min_display_quantity` - Public minimum match size, 0 for none
     ///
     /// # Returns
-    /// * The index of the order in the orderbook
+    /// * A receipt with the order's index, escrow locked and effective expiry
     pub fn submit_order(
         env: Env,
         trader: Address,
@@ -129,374 +561,2679 @@ impl DarkPoolOrderbook {
         asset_address: Address,
         side: OrderSide,
         expiry_seconds: u64,
-    ) -> Result<u32, OrderbookError> {
+        quantity: i128,
+        limit_price: i128,
+        min_display_quantity: i128,
+    ) -> Result<SubmitReceipt, OrderbookError> {
         trader.require_auth();
-
-        let current_time = env.ledger().timestamp();
-        let expiry = current_time + expiry_seconds;
-
-        let mut orders: Vec<OrderCommitment> = env
-            .storage()
-            .instance()
-            .get(&ORDERS_KEY)
-            .unwrap_or(vec![&env]);
-
-        let tree_index = orders.len() as u32;
-
-        let order = OrderCommitment {
-            commitment: commitment.clone(),
-            trader: trader.clone(),
-            asset_address: asset_address.clone(),
+        Self::submit_own_order(
+            &env,
+            &trader,
+            commitment,
+            asset_address,
             side,
-            timestamp: current_time,
-            expiry,
-            status: OrderStatus::Active,
-            tree_index,
-        };
+            expiry_seconds,
+            quantity,
+            limit_price,
+            min_display_quantity,
+        )
+    }
 
-        orders.push_back(order);
-        env.storage().instance().set(&ORDERS_KEY, &orders);
+    /// Submit several order commitments sharing an asset, side and terms
+    ///
+    /// The trader authenticates once. Each commitment is submitted as by
+    /// `submit_order` with the same `quantity`, `limit_price` and
+    /// `min_display_quantity`; if any
+    /// fails, including a duplicate commitment, the whole batch is rolled
+    /// back.
+    ///
+    /// # Returns
+    /// * The tree index assigned to each commitment, in order
+    pub fn submit_orders_batch(
+        env: Env,
+        trader: Address,
+        commitments: Vec<BytesN<32>>,
+        asset_address: Address,
+        side: OrderSide,
+        expiry_seconds: u64,
+        quantity: i128,
+        limit_price: i128,
+        min_display_quantity: i128,
+    ) -> Result<Vec<u32>, OrderbookError> {
+        trader.require_auth();
 
-        Ok(tree_index)
+        let mut indices: Vec<u32> = vec![&env];
+        for commitment in commitments.iter() {
+            let receipt = Self::submit_own_order(
+                &env,
+                &trader,
+                commitment,
+                asset_address.clone(),
+                side,
+                expiry_seconds,
+                quantity,
+                limit_price,
+                min_display_quantity,
+            )?;
+            indices.push_back(receipt.index);
+        }
+        Ok(indices)
     }
 
     /// Cancel an order with ownership proof
     ///
+    /// The proof is rejected once the order's `proof_valid_until` has passed.
+    /// Within the post-match cancel grace, a matched or partially filled
+    /// order can still be cancelled; its unsettled matches from the grace
+    /// are removed and the counterparty orders become active again.
+    ///
+    /// A proof the verifier rejects is reported through the return value and
+    /// a `ProofRejected` event rather than an error, so the attempt stays
+    /// counted against the trader's proof rate limit.
+    ///
     /// # Arguments
     /// * `trader` - Address of the trader (must authenticate)
     /// * `commitment` - The order commitment to cancel
     /// * `proof_bytes` - ZK proof of order ownership
     /// * `pub_signals_bytes` - Public signals for the proof
+    ///
+    /// # Returns
+    /// * `true` once cancelled, `false` if the verifier rejected the proof
     pub fn cancel_order(
         env: Env,
         trader: Address,
         commitment: BytesN<32>,
-        _proof_bytes: Bytes,
-        _pub_signals_bytes: Bytes,
-    ) -> Result<(), OrderbookError> {
+        proof_bytes: Bytes,
+        pub_signals_bytes: Bytes,
+    ) -> Result<bool, OrderbookError> {
         trader.require_auth();
+        Self::cancel_own_order(&env, &trader, &commitment, &proof_bytes, &pub_signals_bytes, None)
+    }
 
-        let orders: Vec<OrderCommitment> = env
-            .storage()
-            .instance()
-            .get(&ORDERS_KEY)
-            .unwrap_or(vec![&env]);
-
-        let mut found = false;
-        let mut updated_orders: Vec<OrderCommitment> = vec![&env];
-
-        for order in orders.iter() {
-            if order.commitment == commitment {
-                // Verify trader owns the order
-                if order.trader != trader {
-                    return Err(OrderbookError::UnauthorizedCancellation);
-                }
-
-                // Check order is still active
-                match order.status {
-                    OrderStatus::Matched | OrderStatus::Settled => {
-                        return Err(OrderbookError::OrderAlreadyMatched);
-                    }
-                    OrderStatus::Cancelled => {
-                        return Err(OrderbookError::OrderAlreadyCancelled);
-                    }
-                    _ => {}
-                }
+    /// Cancel an order and release its lock with a settlement unlock proof
+    ///
+    /// Same as `cancel_order`, except the lock is released through the
+    /// settlement's `unlock_with_proof`, bound to this order's commitment,
+    /// the trader and the locked asset. The proven amount may not exceed
+    /// what the order has locked.
+    ///
+    /// # Arguments
+    /// * `trader` - Address of the trader (must authenticate)
+    /// * `commitment` - The order commitment to cancel
+    /// * `proof_bytes` - ZK proof of order ownership
+    /// * `pub_signals_bytes` - Public signals for the proof
+    /// * `unlock_proof_bytes` - ZK proof for the settlement unlock
+    /// * `unlock_signals_bytes` - Public signals for the unlock proof
+    ///
+    /// # Returns
+    /// * `true` once cancelled, `false` if the verifier rejected the
+    ///   ownership proof
+    pub fn cancel_order_with_unlock_proof(
+        env: Env,
+        trader: Address,
+        commitment: BytesN<32>,
+        proof_bytes: Bytes,
+        pub_signals_bytes: Bytes,
+        unlock_proof_bytes: Bytes,
+        unlock_signals_bytes: Bytes,
+    ) -> Result<bool, OrderbookError> {
+        trader.require_auth();
+        Self::cancel_own_order(
+            &env,
+            &trader,
+            &commitment,
+            &proof_bytes,
+            &pub_signals_bytes,
+            Some((&unlock_proof_bytes, &unlock_signals_bytes)),
+        )
+    }
 
-                // TODO: In production, verify the ZK proof of ownership
-                // For now, we just check the trader address matches
+    /// Cancel all of a trader's active orders, optionally on one asset only
+    ///
+    /// Orders still inside their minimum quote lifetime are left on the
+    /// book. One-cancels-other siblings of a cancelled order are cancelled
+    /// too, even on other assets.
+    ///
+    /// # Returns
+    /// * The number of orders cancelled
+    pub fn cancel_all_for_trader(env: Env, trader: Address, asset_address: Option<Address>) -> u32 {
+        trader.require_auth();
 
-                let mut cancelled_order = order.clone();
-                cancelled_order.status = OrderStatus::Cancelled;
-                updated_orders.push_back(cancelled_order);
-                found = true;
-            } else {
-                updated_orders.push_back(order);
+        let now = env.ledger().timestamp();
+        let mut cancelled: Vec<BytesN<32>> = vec![&env];
+        let mut groups: Vec<BytesN<32>> = vec![&env];
+        for mut order in Self::trader_orders(&env, &trader).iter() {
+            if !order.status.is_open()
+                || asset_address.as_ref().is_some_and(|asset| *asset != order.asset_address)
+            {
+                continue;
             }
+            let lifetime = Self::get_min_quote_lifetime(env.clone(), order.asset_address.clone());
+            if now < order.timestamp.saturating_add(lifetime) {
+                continue;
+            }
+            if let Some(group) = order.oco_group.clone() {
+                groups.push_back(group);
+            }
+            Self::cancel_open_order(&env, &mut order);
+            Self::store_order(&env, &order);
+            cancelled.push_back(order.commitment);
         }
-
-        if !found {
-            return Err(OrderbookError::OrderNotFound);
+        for group in groups.iter() {
+            Self::cancel_oco_siblings(&env, &group, &mut cancelled);
         }
 
-        env.storage().instance().set(&ORDERS_KEY, &updated_orders);
-        Ok(())
+        for commitment in cancelled.iter() {
+            OrderCancelled { commitment }.publish(&env);
+        }
+        cancelled.len()
     }
 
-    /// Record a matched trade (called by matching engine)
+    /// Cancel an order and withdraw the trader's freed balance in one call
+    ///
+    /// After the cancel releases the order's lock, the trader's whole
+    /// available settlement balance of `asset_address` is paid out to them.
     ///
     /// # Arguments
-    /// * `admin` - Must be admin
-    /// * `match_id` - Unique identifier for the match
-    /// * `buy_commitment` - The buy order commitment
-    /// * `sell_commitment` - The sell order commitment
-    /// * `asset_address` - The RWA token being traded
-    /// * `buyer` - Buyer address
-    /// * `seller` - Seller address
-    /// * `quantity` - Matched quantity
-    /// * `price` - Execution price
-    pub fn record_match(
+    /// * `trader` - Address of the trader (must authenticate)
+    /// * `commitment` - The order commitment to cancel
+    /// * `asset_address` - Asset to withdraw, normally the order's locked asset
+    /// * `proof_bytes` - ZK proof of order ownership
+    /// * `pub_signals_bytes` - Public signals for the proof
+    ///
+    /// # Returns
+    /// * The amount withdrawn, or None if the verifier rejected the proof
+    pub fn cancel_and_withdraw(
         env: Env,
-        admin: Address,
-        match_id: BytesN<32>,
-        buy_commitment: BytesN<32>,
-        sell_commitment: BytesN<32>,
+        trader: Address,
+        commitment: BytesN<32>,
         asset_address: Address,
-        buyer: Address,
-        seller: Address,
-        quantity: i128,
-        price: i128,
-    ) -> Result<(), OrderbookError> {
-        admin.require_auth();
-        Self::require_admin(&env, &admin)?;
+        proof_bytes: Bytes,
+        pub_signals_bytes: Bytes,
+    ) -> Result<Option<i128>, OrderbookError> {
+        trader.require_auth();
+        if !Self::cancel_own_order(&env, &trader, &commitment, &proof_bytes, &pub_signals_bytes, None)? {
+            return Ok(None);
+        }
 
-        // Update order statuses
-        let orders: Vec<OrderCommitment> = env
-            .storage()
-            .instance()
-            .get(&ORDERS_KEY)
-            .unwrap_or(vec![&env]);
+        let settlement_address = Self::get_settlement(env.clone());
+        let settlement_client = settlement_wasm::Client::new(&env, &settlement_address);
+        let available = settlement_client.get_available_balance(&trader, &asset_address);
+        if available <= 0 {
+            return Ok(Some(0));
+        }
+        settlement_client.withdraw(&trader, &asset_address, &available);
+        Ok(Some(available))
+    }
 
-        let mut updated_orders: Vec<OrderCommitment> = vec![&env];
-        let mut buy_found = false;
-        let mut sell_found = false;
+    /// Shrink an order's size, releasing the escrow the removed size backed
+    ///
+    /// The lock is released in proportion to the reduction of the unfilled
+    /// size. Sizes can only go down, and never below what has been filled;
+    /// reducing to the filled size takes the order off the book.
+    ///
+    /// # Arguments
+    /// * `trader` - Address of the trader (must authenticate)
+    /// * `commitment` - The order commitment to reduce
+    /// * `new_total_quantity` - The order's new total size
+    /// * `proof_bytes` - ZK proof of order ownership
+    /// * `pub_signals_bytes` - Public signals for the proof
+    ///
+    /// # Returns
+    /// * The amount of escrow released, or None if the verifier rejected the
+    ///   proof
+    pub fn reduce_order(
+        env: Env,
+        trader: Address,
+        commitment: BytesN<32>,
+        new_total_quantity: i128,
+        proof_bytes: Bytes,
+        pub_signals_bytes: Bytes,
+    ) -> Result<Option<i128>, OrderbookError> {
+        trader.require_auth();
+        Self::consume_proof_attempt(&env, &trader)?;
 
-        for order in orders.iter() {
-            if order.commitment == buy_commitment {
-                if order.asset_address != asset_address {
-                    return Err(OrderbookError::AssetMismatch);
-                }
-                let mut matched_order = order.clone();
-                matched_order.status = OrderStatus::Matched;
-                updated_orders.push_back(matched_order);
-                buy_found = true;
-            } else if order.commitment == sell_commitment {
-                if order.asset_address != asset_address {
-                    return Err(OrderbookError::AssetMismatch);
-                }
-                let mut matched_order = order.clone();
-                matched_order.status = OrderStatus::Matched;
-                updated_orders.push_back(matched_order);
-                sell_found = true;
-            } else {
-                updated_orders.push_back(order);
+        let mut order = Self::get_order(env.clone(), commitment.clone()).ok_or(OrderbookError::OrderNotFound)?;
+        if order.trader != trader {
+            return Err(OrderbookError::UnauthorizedCancellation);
+        }
+        if env.ledger().timestamp() > order.proof_valid_until {
+            return Err(OrderbookError::ProofExpired);
+        }
+        match order.status {
+            OrderStatus::Matched | OrderStatus::Settled => {
+                return Err(OrderbookError::OrderAlreadyMatched);
             }
+            OrderStatus::Cancelled => return Err(OrderbookError::OrderAlreadyCancelled),
+            OrderStatus::Expired => return Err(OrderbookError::OrderExpired),
+            OrderStatus::Active | OrderStatus::PartiallyFilled => {}
         }
-
-        if !buy_found || !sell_found {
-            return Err(OrderbookError::OrderNotFound);
+        if new_total_quantity > order.total_quantity {
+            return Err(OrderbookError::QuantityIncreaseNotAllowed);
+        }
+        if new_total_quantity < order.filled_quantity {
+            return Err(OrderbookError::InvalidQuantity);
         }
 
-        env.storage().instance().set(&ORDERS_KEY, &updated_orders);
+        if !Self::verify_ownership_proof(&env, &trader, &commitment, &proof_bytes, &pub_signals_bytes)? {
+            return Ok(None);
+        }
 
-        // Create match record
-        let match_record = MatchRecord {
-            match_id: match_id.clone(),
-            buy_commitment,
-            sell_commitment,
-            asset_address,
-            buyer,
-            seller,
-            quantity,
-            price,
-            timestamp: env.ledger().timestamp(),
-            is_settled: false,
+        let released = if order.remaining_quantity == 0 {
+            0
+        } else {
+            order
+                .locked_amount
+                .checked_mul(order.total_quantity - new_total_quantity)
+                .ok_or(OrderbookError::ArithmeticOverflow)?
+                / order.remaining_quantity
         };
+        if released > 0 {
+            let settlement_address = Self::get_settlement(env.clone());
+            let settlement_client = settlement_wasm::Client::new(&env, &settlement_address);
+            settlement_client.decrease_lock(
+                &env.current_contract_address(),
+                &order.trader,
+                &order.locked_asset,
+                &released,
+            );
+            let remaining_lock = order.locked_amount - released;
+            Self::set_order_lock(&env, &mut order, remaining_lock);
+        }
+        let reduced_by = order.total_quantity - new_total_quantity;
+        Self::adjust_total(&env, &OPEN_INTEREST_KEY, &order.asset_address, -reduced_by);
+        order.total_quantity = new_total_quantity;
+        order.remaining_quantity = new_total_quantity - order.filled_quantity;
 
-        let mut matches: Vec<MatchRecord> = env
-            .storage()
-            .instance()
-            .get(&MATCHES_KEY)
-            .unwrap_or(vec![&env]);
-        matches.push_back(match_record);
-        env.storage().instance().set(&MATCHES_KEY, &matches);
-
-        Ok(())
+        // Nothing left to fill takes the order off the book
+        if order.remaining_quantity == 0 {
+            if order.filled_quantity == 0 {
+                Self::cancel_open_order(&env, &mut order);
+            } else {
+                Self::decrease_order_count(&env, &order);
+                order.status = OrderStatus::Matched;
+            }
+        }
+        Self::store_order(&env, &order);
+        Ok(Some(released))
     }
 
-    /// Mark a match as settled (called after successful settlement)
-    pub fn mark_settled(
+    /// Set or clear the contract notified when an order expires
+    ///
+    /// On expiry the callback contract's `on_expiry` is invoked with the
+    /// order commitment. Its failures are ignored, so it cannot block the
+    /// expiry.
+    pub fn set_expiry_callback(
         env: Env,
-        admin: Address,
-        match_id: BytesN<32>,
-    ) -> Result<(), OrderbookError> {
-        admin.require_auth();
-        Self::require_admin(&env, &admin)?;
-
-        let matches: Vec<MatchRecord> = env
-            .storage()
-            .instance()
-            .get(&MATCHES_KEY)
-            .unwrap_or(vec![&env]);
+        trader: Address,
+        commitment: BytesN<32>,
+        expiry_callback: Option<Address>,
+    ) -> Result<(), OrderbookError> {
+        trader.require_auth();
+        Self::update_own_order(&env, &trader, &commitment, |order| order.expiry_callback = expiry_callback)
+    }
 
-        let mut found = false;
-        let mut updated_matches: Vec<MatchRecord> = vec![&env];
+    /// Push back the expiry of several of a trader's orders at once
+    ///
+    /// Orders that are unknown, owned by someone else, no longer open or
+    /// already past expiry are skipped, as are orders whose new expiry
+    /// would exceed the config's `max_expiry` from now.
+    ///
+    /// # Returns
+    /// * The number of orders extended
+    pub fn extend_expiries_batch(
+        env: Env,
+        trader: Address,
+        commitments: Vec<BytesN<32>>,
+        additional_seconds: u64,
+    ) -> u32 {
+        trader.require_auth();
 
-        for m in matches.iter() {
-            if m.match_id == match_id {
-                let mut settled = m.clone();
-                settled.is_settled = true;
-                updated_matches.push_back(settled);
-                found = true;
-            } else {
-                updated_matches.push_back(m);
+        let now = env.ledger().timestamp();
+        let latest = now.saturating_add(Self::get_config(env.clone()).max_expiry);
+        let mut extended: u32 = 0;
+        for commitment in commitments.iter() {
+            let Some(mut order) = Self::load_order(&env, &commitment) else {
+                continue;
+            };
+            if order.trader != trader || !order.status.is_open() || order.expiry <= now {
+                continue;
+            }
+            match order.expiry.checked_add(additional_seconds) {
+                Some(expiry) if expiry <= latest => order.expiry = expiry,
+                _ => continue,
             }
+            Self::store_order(&env, &order);
+            extended += 1;
         }
+        extended
+    }
 
-        if !found {
-            return Err(OrderbookError::MatchNotFound);
+    /// Set the smallest partial fill an order accepts; 0 allows any size
+    ///
+    /// A fill of the order's whole remaining quantity is always allowed.
+    pub fn set_min_fill_quantity(
+        env: Env,
+        trader: Address,
+        commitment: BytesN<32>,
+        min_fill_quantity: i128,
+    ) -> Result<(), OrderbookError> {
+        trader.require_auth();
+        if min_fill_quantity < 0 {
+            return Err(OrderbookError::InvalidQuantity);
         }
+        Self::update_own_order(&env, &trader, &commitment, |order| order.min_fill_quantity = min_fill_quantity)
+    }
 
-        env.storage().instance().set(&MATCHES_KEY, &updated_matches);
+    /// Peg an order to the published mid, or clear the peg
+    ///
+    /// A pegged order's limit is `mid + peg_offset` at match time: buys
+    /// fill at or below it, sells at or above it.
+    pub fn set_peg_offset(
+        env: Env,
+        trader: Address,
+        commitment: BytesN<32>,
+        peg_offset: Option<i128>,
+    ) -> Result<(), OrderbookError> {
+        trader.require_auth();
+        Self::update_own_order(&env, &trader, &commitment, |order| order.peg_offset = peg_offset)
+    }
 
-        // Also update order statuses to Settled
-        let orders: Vec<OrderCommitment> = env
-            .storage()
-            .instance()
-            .get(&ORDERS_KEY)
-            .unwrap_or(vec![&env]);
+    /// Link an order into a one-cancels-other group, or unlink it
+    ///
+    /// Cancelling any order in a group also cancels its open siblings. A
+    /// group only holds one trader's orders.
+    pub fn set_oco_group(
+        env: Env,
+        trader: Address,
+        commitment: BytesN<32>,
+        oco_group: Option<BytesN<32>>,
+    ) -> Result<(), OrderbookError> {
+        trader.require_auth();
+        if let Some(group) = oco_group.clone() {
+            let foreign = Self::get_oco_group(env.clone(), group).iter().any(|order| order.trader != trader);
+            if foreign {
+                return Err(OrderbookError::UnauthorizedCancellation);
+            }
+        }
+        Self::update_own_order(&env, &trader, &commitment, |order| order.oco_group = oco_group.clone())?;
 
-        // Find the match to get commitments
-        let match_record = Self::get_match(env.clone(), match_id.clone()).unwrap();
+        // Members are only ever added; unlinked orders are filtered on read
+        if let Some(group) = oco_group {
+            let key = DataKey::OcoGroup(group);
+            let mut members: Vec<BytesN<32>> = env.storage().persistent().get(&key).unwrap_or(vec![&env]);
+            if !members.contains(&commitment) {
+                members.push_back(commitment);
+                env.storage().persistent().set(&key, &members);
+            }
+        }
+        Ok(())
+    }
 
-        let mut updated_orders: Vec<OrderCommitment> = vec![&env];
-        for order in orders.iter() {
-            if order.commitment == match_record.buy_commitment
-                || order.commitment == match_record.sell_commitment
+    /// Get the open orders in a one-cancels-other group
+    ///
+    /// These are the orders a cancel of any one of them takes down.
+    pub fn get_oco_group(env: Env, oco_group: BytesN<32>) -> Vec<OrderCommitment> {
+        let members: Vec<BytesN<32>> = env
+            .storage()
+            .persistent()
+            .get(&DataKey::OcoGroup(oco_group.clone()))
+            .unwrap_or(vec![&env]);
+        let mut group: Vec<OrderCommitment> = vec![&env];
+        for commitment in members.iter() {
+            if let Some(order) = Self::load_order(&env, &commitment)
+                && order.status.is_open()
+                && order.oco_group.as_ref() == Some(&oco_group)
             {
-                let mut settled_order = order.clone();
-                settled_order.status = OrderStatus::Settled;
-                updated_orders.push_back(settled_order);
-            } else {
-                updated_orders.push_back(order);
+                group.push_back(order);
             }
         }
+        group
+    }
 
-        env.storage().instance().set(&ORDERS_KEY, &updated_orders);
+    /// Pin an order to the whitelist root produced by registry insertion
+    /// `root_index`, or unpin it
+    ///
+    /// Once pinned, the order stops matching when the whitelist has grown
+    /// more than `max_root_lag` insertions past that root. Fails with
+    /// `RootTooStale` if the registry no longer knows the root.
+    pub fn set_expected_root(
+        env: Env,
+        trader: Address,
+        commitment: BytesN<32>,
+        root_index: Option<u32>,
+    ) -> Result<(), OrderbookError> {
+        trader.require_auth();
+        let expected_root = match root_index {
+            Some(index) => {
+                let registry = registry_wasm::Client::new(&env, &Self::get_registry(env.clone()));
+                Some(registry.get_root_at(&index).ok_or(OrderbookError::RootTooStale)?)
+            }
+            None => None,
+        };
+        Self::update_own_order(&env, &trader, &commitment, |order| {
+            order.expected_root = expected_root;
+            order.expected_root_index = root_index.unwrap_or(0);
+        })
+    }
 
-        Ok(())
+    /// Link an order to the nullifier its settlement proof will spend
+    pub fn set_order_nullifier(
+        env: Env,
+        trader: Address,
+        commitment: BytesN<32>,
+        nullifier: BytesN<32>,
+    ) -> Result<(), OrderbookError> {
+        trader.require_auth();
+        Self::update_own_order(&env, &trader, &commitment, |order| order.nullifier = Some(nullifier))
     }
 
-    /// Get all orders for an asset and side
-    pub fn get_orders_by_asset(
+    /// Record a matched trade (called by matching engine)
+    ///
+    /// # Arguments
+    /// * `admin` - Must be admin
+    /// * `match_id` - Unique identifier for the match
+    /// * `buy_commitment` - The buy order commitment
+    /// * `sell_commitment` - The sell order commitment
+    /// * `asset_address` - The RWA token being traded; a sell order on a
+    ///   different asset is a cross-asset match and needs a tradable pair
+    /// * `buyer` - Buyer address
+    /// * `seller` - Seller address
+    /// * `quantity` - Matched quantity
+    /// * `price` - Execution price per unit; the match's notional is
+    ///   `quantity * price`
+    /// * `maker_side` - Side of the resting order; when None the earlier
+    ///   order is taken as the maker. The taker pays the fee and the maker
+    ///   earns the rebate.
+    pub fn record_match(
         env: Env,
+        admin: Address,
+        match_id: BytesN<32>,
+        buy_commitment: BytesN<32>,
+        sell_commitment: BytesN<32>,
         asset_address: Address,
-        side: Option<OrderSide>,
-    ) -> Vec<OrderCommitment> {
-        let orders: Vec<OrderCommitment> = env
-            .storage()
-            .instance()
-            .get(&ORDERS_KEY)
-            .unwrap_or(vec![&env]);
+        buyer: Address,
+        seller: Address,
+        quantity: i128,
+        price: i128,
+        maker_side: Option<OrderSide>,
+    ) -> Result<(), OrderbookError> {
+        admin.require_auth();
+        Self::require_admin(&env, &admin)?;
 
-        let mut filtered: Vec<OrderCommitment> = vec![&env];
-        for order in orders.iter() {
-            if order.asset_address == asset_address {
-                match side {
-                    Some(s) if order.side == s => filtered.push_back(order),
-                    None => filtered.push_back(order),
-                    _ => {}
-                }
-            }
-        }
-        filtered
+        Self::apply_match(
+            &env,
+            match_id,
+            buy_commitment,
+            sell_commitment,
+            asset_address,
+            buyer,
+            seller,
+            quantity,
+            price,
+            maker_side,
+        )
     }
 
-    /// Get active orders only
-    pub fn get_active_orders(env: Env, asset_address: Address) -> Vec<OrderCommitment> {
-        let orders: Vec<OrderCommitment> = env
-            .storage()
-            .instance()
-            .get(&ORDERS_KEY)
-            .unwrap_or(vec![&env]);
+    /// Match two orders and settle them in one transaction (matcher only)
+    ///
+    /// Verifies the proof, records the match, spends both nullifiers and
+    /// moves the locked funds through the settlement contract, paying in the
+    /// settlement asset the registry configures for the traded asset. The
+    /// registry is consulted on every call, so changes there take effect
+    /// immediately. The match settles immediately, so any settlement
+    /// interval does not apply. If any step fails nothing is kept.
+    ///
+    /// # Arguments
+    /// * `matcher` - Must be admin
+    /// * `buy_commitment` - The buy order commitment
+    /// * `sell_commitment` - The sell order commitment
+    /// * `proof_bytes` - Serialized ZK settlement proof
+    /// * `pub_signals_bytes` - Serialized public signals
+    /// * `buy_nullifier` - Nullifier spent by the buy order
+    /// * `sell_nullifier` - Nullifier spent by the sell order
+    /// * `quantity` - Matched quantity
+    /// * `price` - Price per unit in the quote asset; the buyer pays
+    ///   `quantity * price`
+    ///
+    /// # Returns
+    /// * The match id, derived from the two commitments and a match
+    ///   sequence number, so repeated partial fills of one pair get
+    ///   distinct ids
+    pub fn match_and_settle_private(
+        env: Env,
+        matcher: Address,
+        buy_commitment: BytesN<32>,
+        sell_commitment: BytesN<32>,
+        proof_bytes: Bytes,
+        pub_signals_bytes: Bytes,
+        buy_nullifier: BytesN<32>,
+        sell_nullifier: BytesN<32>,
+        quantity: i128,
+        price: i128,
+    ) -> Result<BytesN<32>, OrderbookError> {
+        matcher.require_auth();
+        Self::require_admin(&env, &matcher)?;
 
-        let current_time = env.ledger().timestamp();
-        let mut active: Vec<OrderCommitment> = vec![&env];
+        let buy_order = Self::get_order(env.clone(), buy_commitment.clone())
+            .ok_or(OrderbookError::OrderNotFound)?;
+        let sell_order = Self::get_order(env.clone(), sell_commitment.clone())
+            .ok_or(OrderbookError::OrderNotFound)?;
+        let registry = registry_wasm::Client::new(&env, &Self::get_registry(env.clone()));
+        let quote_asset = registry
+            .get_settlement_asset(&buy_order.asset_address)
+            .ok_or(OrderbookError::NoSettlementAsset)?;
 
-        for order in orders.iter() {
-            if order.asset_address == asset_address
-                && order.status == OrderStatus::Active
-                && order.expiry > current_time
-            {
-                active.push_back(order);
+        let payment = quantity.checked_mul(price).ok_or(OrderbookError::ArithmeticOverflow)?;
+
+        let seq: u64 = env.storage().instance().get(&MATCH_SEQ_KEY).unwrap_or(0);
+        env.storage().instance().set(&MATCH_SEQ_KEY, &(seq + 1));
+        let mut id_preimage = Bytes::from_array(&env, &buy_commitment.to_array());
+        id_preimage.append(&Bytes::from_array(&env, &sell_commitment.to_array()));
+        id_preimage.append(&Bytes::from_array(&env, &seq.to_be_bytes()));
+        let match_id: BytesN<32> = env.crypto().sha256(&id_preimage).into();
+
+        Self::apply_match(
+            &env,
+            match_id.clone(),
+            buy_commitment,
+            sell_commitment,
+            buy_order.asset_address.clone(),
+            buy_order.trader.clone(),
+            sell_order.trader.clone(),
+            quantity,
+            price,
+            None,
+        )?;
+
+        let settlement_address = Self::get_settlement(env.clone());
+        let settlement_client = settlement_wasm::Client::new(&env, &settlement_address);
+        let settled = settlement_client.try_settle_private(
+            &env.current_contract_address(),
+            &match_id,
+            &buy_order.trader,
+            &sell_order.trader,
+            &buy_order.asset_address,
+            &quote_asset,
+            &quantity,
+            &payment,
+            &proof_bytes,
+            &pub_signals_bytes,
+            &buy_nullifier,
+            &sell_nullifier,
+        );
+        match settled {
+            Ok(Ok(_)) => {}
+            Err(Ok(settlement_wasm::SettlementError::InvalidProof)) => {
+                return Err(OrderbookError::InvalidProof);
+            }
+            Err(Ok(settlement_wasm::SettlementError::NullifierUsed)) => {
+                return Err(OrderbookError::NullifierUsed);
             }
+            Err(Ok(settlement_wasm::SettlementError::InsufficientEscrow))
+            | Err(Ok(settlement_wasm::SettlementError::InsufficientLockedFunds)) => {
+                return Err(OrderbookError::InsufficientEscrow);
+            }
+            _ => return Err(OrderbookError::SettlementFailed),
         }
-        active
+
+        Self::settle_match_record(&env, &match_id, false)?;
+
+        // Settlement spent part of each order's lock and its nullifier
+        for (commitment, spent, nullifier) in [
+            (buy_order.commitment, payment, buy_nullifier),
+            (sell_order.commitment, quantity, sell_nullifier),
+        ] {
+            let mut order = Self::load_order(&env, &commitment).unwrap();
+            let remaining_lock = (order.locked_amount - spent).max(0);
+            Self::set_order_lock(&env, &mut order, remaining_lock);
+            order.nullifier = Some(nullifier);
+            Self::store_order(&env, &order);
+        }
+
+        Ok(match_id)
     }
 
-    /// Get an order by commitment
-    pub fn get_order(env: Env, commitment: BytesN<32>) -> Option<OrderCommitment> {
-        let orders: Vec<OrderCommitment> = env
+    /// Mark a match as settled (called after successful settlement)
+    ///
+    /// If the asset has a settlement interval, a match can only be settled
+    /// once the batch it was recorded in has closed.
+    pub fn mark_settled(
+        env: Env,
+        admin: Address,
+        match_id: BytesN<32>,
+    ) -> Result<(), OrderbookError> {
+        admin.require_auth();
+        Self::require_admin(&env, &admin)?;
+
+        Self::settle_match_record(&env, &match_id, true)
+    }
+
+    /// Record a failed settlement attempt for a match (admin only)
+    ///
+    /// Once the match has failed `max_settle_attempts` times it is marked
+    /// failed: it can no longer be settled and further reports are rejected
+    /// with `MaxRetriesExceeded`.
+    ///
+    /// # Returns
+    /// * The number of failed attempts so far
+    pub fn report_settle_failure(env: Env, admin: Address, match_id: BytesN<32>) -> Result<u32, OrderbookError> {
+        admin.require_auth();
+        Self::require_admin(&env, &admin)?;
+
+        let mut matches: Vec<MatchRecord> = env
             .storage()
             .instance()
-            .get(&ORDERS_KEY)
+            .get(&MATCHES_KEY)
             .unwrap_or(vec![&env]);
-
-        for order in orders.iter() {
-            if order.commitment == commitment {
-                return Some(order);
+        let max_attempts = Self::get_config(env.clone()).max_settle_attempts;
+        for (index, mut m) in matches.clone().iter().enumerate() {
+            if m.match_id != match_id {
+                continue;
+            }
+            if m.is_settled {
+                return Err(OrderbookError::MatchAlreadySettled);
             }
+            if m.failed {
+                return Err(OrderbookError::MaxRetriesExceeded);
+            }
+            m.settle_attempts = m.settle_attempts.saturating_add(1);
+            m.failed = max_attempts > 0 && m.settle_attempts >= max_attempts;
+            let attempts = m.settle_attempts;
+            matches.set(index as u32, m);
+            env.storage().instance().set(&MATCHES_KEY, &matches);
+            return Ok(attempts);
         }
-        None
+        Err(OrderbookError::MatchNotFound)
     }
 
-    /// Get all matches
-    pub fn get_matches(env: Env) -> Vec<MatchRecord> {
-        env.storage()
-            .instance()
-            .get(&MATCHES_KEY)
-            .unwrap_or(vec![&env])
-    }
+    /// Reverse an unsettled match, e.g. after a dispute (admin only)
+    ///
+    /// The match record is removed, the asset stats it added are backed
+    /// out, and both orders get the matched quantity back. An order whose expiry has passed in the meantime becomes
+    /// `Expired`, with its escrow released, rather than returning to the book.
+    pub fn revert_match(env: Env, admin: Address, match_id: BytesN<32>) -> Result<(), OrderbookError> {
+        admin.require_auth();
+        Self::require_admin(&env, &admin)?;
 
-    /// Get a specific match
-    pub fn get_match(env: Env, match_id: BytesN<32>) -> Option<MatchRecord> {
         let matches: Vec<MatchRecord> = env
             .storage()
             .instance()
             .get(&MATCHES_KEY)
             .unwrap_or(vec![&env]);
-
+        let mut reverted: Option<MatchRecord> = None;
+        let mut remaining: Vec<MatchRecord> = vec![&env];
         for m in matches.iter() {
-            if m.match_id == match_id {
-                return Some(m);
+            if reverted.is_none() && m.match_id == match_id {
+                reverted = Some(m);
+            } else {
+                remaining.push_back(m);
+            }
+        }
+        let m = reverted.ok_or(OrderbookError::MatchNotFound)?;
+        if m.is_settled {
+            return Err(OrderbookError::MatchAlreadySettled);
+        }
+        env.storage().instance().set(&MATCHES_KEY, &remaining);
+        Self::unwind_match(&env, &m)?;
+
+        let now = env.ledger().timestamp();
+        for commitment in [m.buy_commitment, m.sell_commitment] {
+            if let Some(mut order) = Self::reopen_order(&env, &commitment, m.quantity)
+                && order.expiry <= now
+            {
+                Self::expire_open_order(&env, &mut order);
             }
         }
-        None
+
+        MatchReverted { match_id }.publish(&env);
+        Ok(())
     }
 
-    /// Get pending (unsettle) matches
-    pub fn get_pending_matches(env: Env) -> Vec<MatchRecord> {
-        let matches: Vec<MatchRecord> = env
+    /// Delist an asset, cancelling its active orders in bounded batches
+    ///
+    /// The asset is halted against new orders on the first call. Each call
+    /// cancels up to `max` remaining active orders, releasing any escrow
+    /// locked for them, and returns how many it cancelled; call again until it returns 0 to drain the asset.
+    ///
+    /// # Arguments
+    /// * `admin` - Must be admin
+    /// * `asset_address` - The RWA token being delisted
+    /// * `max` - Maximum number of orders to cancel in this call
+    pub fn delist_asset(
+        env: Env,
+        admin: Address,
+        asset_address: Address,
+        max: u32,
+    ) -> Result<u32, OrderbookError> {
+        admin.require_auth();
+        Self::require_admin(&env, &admin)?;
+
+        let mut delisted: Map<Address, bool> = env
             .storage()
             .instance()
-            .get(&MATCHES_KEY)
-            .unwrap_or(vec![&env]);
+            .get(&DELISTED_KEY)
+            .unwrap_or(Map::new(&env));
+        delisted.set(asset_address.clone(), true);
+        env.storage().instance().set(&DELISTED_KEY, &delisted);
 
-        let mut pending: Vec<MatchRecord> = vec![&env];
-        for m in matches.iter() {
-            if !m.is_settled {
-                pending.push_back(m);
+        let mut cancelled: u32 = 0;
+        for mut order in Self::asset_orders(&env, &asset_address).iter() {
+            if cancelled >= max {
+                break;
+            }
+            if order.status.is_open() {
+                Self::cancel_open_order(&env, &mut order);
+                Self::store_order(&env, &order);
+                cancelled += 1;
             }
         }
-        pending
+        Ok(cancelled)
     }
 
-    /// Get admin address
-    pub fn get_admin(env: Env) -> Address {
-        env.storage().instance().get(&ADMIN_KEY).unwrap()
+    /// Expire every open order for an asset now, regardless of its expiry
+    ///
+    /// Any escrow locked for the orders is released and their expiry
+    /// callbacks are invoked. Orders for other assets are untouched.
+    ///
+    /// # Arguments
+    /// * `admin` - Must be admin
+    /// * `asset_address` - The RWA token whose orders are invalidated
+    ///
+    /// # Returns
+    /// * The number of orders expired
+    pub fn expire_orders_for_asset(env: Env, admin: Address, asset_address: Address) -> Result<u32, OrderbookError> {
+        admin.require_auth();
+        Self::require_admin(&env, &admin)?;
+
+        let mut expired: u32 = 0;
+        for mut order in Self::asset_orders(&env, &asset_address).iter() {
+            if order.status.is_open() {
+                Self::expire_open_order(&env, &mut order);
+                expired += 1;
+            }
+        }
+        Ok(expired)
     }
 
-    /// Get registry address
-    pub fn get_registry(env: Env) -> Address {
-        env.storage().instance().get(&REGISTRY_KEY).unwrap()
+    /// Cancel active orders across every asset for an emergency shutdown
+    ///
+    /// Each call cancels up to `max` active orders, releasing any escrow
+    /// locked for them, and returns how many it cancelled; call again
+    /// until it returns 0 to empty the book.
+    ///
+    /// # Arguments
+    /// * `admin` - Must be admin
+    /// * `max` - Maximum number of orders to cancel in this call
+    pub fn global_cancel(env: Env, admin: Address, max: u32) -> Result<u32, OrderbookError> {
+        admin.require_auth();
+        Self::require_admin(&env, &admin)?;
+
+        let mut cancelled: u32 = 0;
+        for asset_address in Self::order_assets(&env).iter() {
+            for mut order in Self::asset_orders(&env, &asset_address).iter() {
+                if cancelled >= max {
+                    return Ok(cancelled);
+                }
+                if order.status.is_open() {
+                    Self::cancel_open_order(&env, &mut order);
+                    Self::store_order(&env, &order);
+                    cancelled += 1;
+                }
+            }
+        }
+        Ok(cancelled)
     }
 
-    /// Get settlement address
-    pub fn get_settlement(env: Env) -> Address {
-        env.storage().instance().get(&SETTLEMENT_KEY).unwrap()
+    /// Expire stale orders for an asset and reward the caller
+    ///
+    /// Permissionless crank: flips up to `max` active orders whose expiry
+    /// has passed to `Expired`. If a keeper reward is configured, the
+    /// keeper is paid `reward_per_order` from the admin-funded pool for
+    /// each expired order that carried an escrow lock or was submitted
+    /// for at least `KEEPER_MIN_LIFETIME_SECONDS`, capped at what the pool
+    /// holds. A keeper earns nothing for expiring their own orders.
+    ///
+    /// # Returns
+    /// * The number of orders expired
+    pub fn expire_batch(env: Env, keeper: Address, asset_address: Address, max: u32) -> u32 {
+        keeper.require_auth();
+
+        let (expired, rewardable) = Self::expire_orders(&env, &asset_address, max, Some(&keeper));
+
+        let config = Self::get_config(env.clone());
+        if let Some(reward_token) = config.keeper_reward_token {
+            let pool = Self::get_keeper_pool(env.clone());
+            let payout = config
+                .keeper_reward_per_order
+                .saturating_mul(rewardable as i128)
+                .min(pool);
+            if payout > 0 {
+                Self::set_keeper_pool(&env, &reward_token, pool - payout);
+                let token_client = token::Client::new(&env, &reward_token);
+                token_client.transfer(&env.current_contract_address(), &keeper, &payout);
+            }
+        }
+
+        expired
+    }
+
+    /// Reap expired orders for an asset without paying a keeper reward
+    ///
+    /// Flips up to `max_count` open orders whose expiry has passed to
+    /// `Expired`. Callers page through a large book by calling repeatedly
+    /// until it returns 0.
+    ///
+    /// # Returns
+    /// * The number of orders reaped
+    pub fn sweep_expired(env: Env, asset_address: Address, max_count: u32) -> u32 {
+        Self::expire_orders(&env, &asset_address, max_count, None).0
+    }
+
+    /// Top up the keeper reward pool from the admin's balance (admin only)
+    pub fn fund_keeper_pool(env: Env, admin: Address, amount: i128) -> Result<i128, OrderbookError> {
+        admin.require_auth();
+        Self::require_admin(&env, &admin)?;
+
+        if amount <= 0 {
+            return Err(OrderbookError::InvalidQuantity);
+        }
+        let reward_token = Self::get_config(env.clone())
+            .keeper_reward_token
+            .ok_or(OrderbookError::KeeperRewardNotConfigured)?;
+        let token_client = token::Client::new(&env, &reward_token);
+        token_client.transfer(&admin, env.current_contract_address(), &amount);
+
+        let pool = Self::get_keeper_pool(env.clone())
+            .checked_add(amount)
+            .ok_or(OrderbookError::ArithmeticOverflow)?;
+        Self::set_keeper_pool(&env, &reward_token, pool);
+        Ok(pool)
+    }
+
+    /// Get the remaining keeper reward pool balance for the configured token
+    pub fn get_keeper_pool(env: Env) -> i128 {
+        let Some(reward_token) = Self::get_config(env.clone()).keeper_reward_token else {
+            return 0;
+        };
+        let pools: Map<Address, i128> = env
+            .storage()
+            .instance()
+            .get(&KEEPER_POOL_KEY)
+            .unwrap_or(Map::new(&env));
+        pools.get(reward_token).unwrap_or(0)
+    }
+
+    /// Check if an asset has been delisted from the orderbook
+    pub fn is_asset_delisted(env: Env, asset_address: Address) -> bool {
+        let delisted: Map<Address, bool> = env
+            .storage()
+            .instance()
+            .get(&DELISTED_KEY)
+            .unwrap_or(Map::new(&env));
+        delisted.get(asset_address).unwrap_or(false)
+    }
+
+    /// Pause or resume new orders and matches across the whole book
+    ///
+    /// Cancellations stay open while paused so traders can exit.
+    pub fn set_paused(env: Env, admin: Address, paused: bool) -> Result<(), OrderbookError> {
+        admin.require_auth();
+        Self::require_admin(&env, &admin)?;
+        env.storage().instance().set(&PAUSED_KEY, &paused);
+        Ok(())
+    }
+
+    /// Check if the orderbook is paused
+    pub fn is_paused(env: Env) -> bool {
+        env.storage().instance().get(&PAUSED_KEY).unwrap_or(false)
+    }
+
+    /// Get the value of the escrow locked by a trader's open orders and
+    /// unsettled matches, in the settlement asset
+    ///
+    /// A buy's lock is already in the quote asset; a sell's locked quantity
+    /// is valued at its limit price. The total is kept as locks change, so
+    /// submitting adds an order's notional, settlement subtracts what it
+    /// spends, and cancelling or expiring frees the rest. An order past its
+    /// expiry counts until it is expired.
+    pub fn get_outstanding_notional(env: Env, trader: Address) -> i128 {
+        let totals: Map<Address, i128> = env
+            .storage()
+            .instance()
+            .get(&NOTIONAL_KEY)
+            .unwrap_or(Map::new(&env));
+        totals.get(trader).unwrap_or(0)
+    }
+
+    /// Get an asset's open interest: the unfilled quantity of its active
+    /// orders
+    ///
+    /// The total is kept as orders are submitted, filled, cancelled and
+    /// expired. An order past its expiry counts until it is expired.
+    pub fn get_open_interest(env: Env, asset_address: Address) -> i128 {
+        let totals: Map<Address, i128> = env
+            .storage()
+            .instance()
+            .get(&OPEN_INTEREST_KEY)
+            .unwrap_or(Map::new(&env));
+        totals.get(asset_address).unwrap_or(0)
+    }
+
+    /// Check if the current ledger time falls in an asset's trading window
+    pub fn is_within_trading_hours(env: Env, asset_address: Address) -> bool {
+        let config = Self::get_asset_config(env.clone(), asset_address);
+        let (open, close) = (config.trading_open_seconds, config.trading_close_seconds);
+        let now = env.ledger().timestamp() % SECONDS_PER_DAY;
+        if open == close {
+            true
+        } else if open < close {
+            now >= open && now < close
+        } else {
+            now >= open || now < close
+        }
+    }
+
+    /// Publish the matcher's current top of book for an asset (admin only)
+    pub fn publish_top_of_book(
+        env: Env,
+        admin: Address,
+        asset_address: Address,
+        best_bid: i128,
+        best_ask: i128,
+    ) -> Result<(), OrderbookError> {
+        admin.require_auth();
+        Self::require_admin(&env, &admin)?;
+
+        let mut tops: Map<Address, TopOfBook> = env
+            .storage()
+            .instance()
+            .get(&TOPS_KEY)
+            .unwrap_or(Map::new(&env));
+        let top = TopOfBook {
+            best_bid,
+            best_ask,
+            timestamp: env.ledger().timestamp(),
+        };
+        tops.set(asset_address, top);
+        env.storage().instance().set(&TOPS_KEY, &tops);
+        Ok(())
+    }
+
+    /// Get the last published top of book for an asset, if any
+    pub fn get_top_of_book(env: Env, asset_address: Address) -> Option<TopOfBook> {
+        let tops: Map<Address, TopOfBook> = env
+            .storage()
+            .instance()
+            .get(&TOPS_KEY)
+            .unwrap_or(Map::new(&env));
+        tops.get(asset_address)
+    }
+
+    /// Get the number of orders that have not been filled, cancelled or
+    /// expired
+    pub fn get_order_count(env: Env) -> u32 {
+        let counts: Map<Address, u32> = env
+            .storage()
+            .instance()
+            .get(&ORDER_COUNTS_KEY)
+            .unwrap_or(Map::new(&env));
+        counts.values().iter().fold(0u32, |total, count| total.saturating_add(count))
+    }
+
+    /// Get the number of an asset's orders that have not been filled,
+    /// cancelled or expired
+    pub fn get_order_count_by_asset(env: Env, asset_address: Address) -> u32 {
+        let counts: Map<Address, u32> = env
+            .storage()
+            .instance()
+            .get(&ORDER_COUNTS_KEY)
+            .unwrap_or(Map::new(&env));
+        counts.get(asset_address).unwrap_or(0)
+    }
+
+    /// Get orders for an asset, optionally filtered by side and status
+    ///
+    /// Returns at most `MAX_RETURN_ENTRIES` orders; `truncated` is set when
+    /// more orders matched the filter.
+    pub fn get_orders_by_asset(
+        env: Env,
+        asset_address: Address,
+        side: Option<OrderSide>,
+        status: Option<OrderStatus>,
+    ) -> OrderList {
+        let orders = Self::filter_asset_orders(&env, &asset_address, side, status, 0, MAX_RETURN_ENTRIES + 1);
+        let truncated = orders.len() > MAX_RETURN_ENTRIES;
+        let orders = if truncated {
+            orders.slice(0..MAX_RETURN_ENTRIES)
+        } else {
+            orders
+        };
+        OrderList { orders, truncated }
+    }
+
+    /// Page through an asset's orders, optionally filtered by side and status
+    ///
+    /// # Arguments
+    /// * `asset_address` - The RWA token
+    /// * `side` - Only orders on this side, if set
+    /// * `status` - Only orders in this status, if set
+    /// * `start` - Number of matching orders to skip
+    /// * `limit` - Maximum orders to return, capped at `MAX_RETURN_ENTRIES`
+    pub fn get_orders_by_asset_paged(
+        env: Env,
+        asset_address: Address,
+        side: Option<OrderSide>,
+        status: Option<OrderStatus>,
+        start: u32,
+        limit: u32,
+    ) -> Vec<OrderCommitment> {
+        Self::filter_asset_orders(&env, &asset_address, side, status, start, limit.min(MAX_RETURN_ENTRIES))
+    }
+
+    /// Get every asset that has had an order submitted, in first-seen order
+    pub fn get_order_assets(env: Env) -> Vec<Address> {
+        Self::order_assets(&env)
+    }
+
+    /// Get up to `limit` of a trader's orders starting at index `start`, in
+    /// submission order
+    ///
+    /// `limit` is capped at `MAX_RETURN_ENTRIES`.
+    pub fn get_orders_by_trader_paged(env: Env, trader: Address, start: u32, limit: u32) -> Vec<OrderCommitment> {
+        let orders = Self::trader_orders(&env, &trader);
+        let len = orders.len();
+        if start >= len {
+            return vec![&env];
+        }
+        let end = start.saturating_add(limit.min(MAX_RETURN_ENTRIES)).min(len);
+        orders.slice(start..end)
+    }
+
+    /// Get active orders only
+    ///
+    /// The result is unbounded and can exceed Soroban's return value limits
+    /// on a large book; prefer `get_active_orders_cursor`.
+    pub fn get_active_orders(env: Env, asset_address: Address) -> Vec<OrderCommitment> {
+        let current_time = env.ledger().timestamp();
+        let mut active: Vec<OrderCommitment> = vec![&env];
+
+        for order in Self::asset_orders(&env, &asset_address).iter() {
+            if order.status.is_open() && order.expiry > current_time {
+                active.push_back(order);
+            }
+        }
+        active
+    }
+
+    /// Page through an asset's active orders
+    ///
+    /// # Arguments
+    /// * `asset_address` - The RWA token
+    /// * `cursor` - 0 for the first page, then the previous `next_cursor`
+    /// * `limit` - Maximum orders to return, clamped to `1..=MAX_RETURN_ENTRIES`
+    pub fn get_active_orders_cursor(
+        env: Env,
+        asset_address: Address,
+        cursor: u32,
+        limit: u32,
+    ) -> ActivePage {
+        let index = OrderIndex::Asset(asset_address);
+        // A zero limit would hand back the same cursor forever
+        let limit = limit.clamp(1, MAX_RETURN_ENTRIES);
+        let current_time = env.ledger().timestamp();
+        let mut page: Vec<OrderCommitment> = vec![&env];
+
+        // Only the index pages and orders from the cursor on are read
+        let mut commitments: Vec<BytesN<32>> = vec![&env];
+        for i in cursor..Self::index_len(&env, &index) {
+            if i == cursor || i % INDEX_PAGE_SIZE == 0 {
+                commitments = Self::index_page(&env, &index, i / INDEX_PAGE_SIZE);
+            }
+            let Some(order) = Self::load_order(&env, &commitments.get(i % INDEX_PAGE_SIZE).unwrap()) else {
+                continue;
+            };
+            if order.status.is_open() && order.expiry > current_time {
+                if page.len() == limit {
+                    return ActivePage {
+                        orders: page,
+                        next_cursor: Some(i),
+                    };
+                }
+                page.push_back(order);
+            }
+        }
+        ActivePage {
+            orders: page,
+            next_cursor: None,
+        }
+    }
+
+    /// Get an order by commitment
+    pub fn get_order(env: Env, commitment: BytesN<32>) -> Option<OrderCommitment> {
+        Self::load_order(&env, &commitment)
+    }
+
+    /// Get matches, oldest first
+    ///
+    /// Returns at most `MAX_RETURN_ENTRIES` matches; `truncated` is set when
+    /// more matches exist. Use `get_matches_paged` to read the rest.
+    pub fn get_matches(env: Env) -> MatchList {
+        let matches: Vec<MatchRecord> = env
+            .storage()
+            .instance()
+            .get(&MATCHES_KEY)
+            .unwrap_or(vec![&env]);
+
+        let truncated = matches.len() > MAX_RETURN_ENTRIES;
+        let matches = if truncated {
+            matches.slice(0..MAX_RETURN_ENTRIES)
+        } else {
+            matches
+        };
+        MatchList { matches, truncated }
+    }
+
+    /// Get up to `limit` matches starting at index `start`, oldest first
+    ///
+    /// `limit` is capped at `MAX_RETURN_ENTRIES`.
+    pub fn get_matches_paged(env: Env, start: u32, limit: u32) -> Vec<MatchRecord> {
+        let matches: Vec<MatchRecord> = env
+            .storage()
+            .instance()
+            .get(&MATCHES_KEY)
+            .unwrap_or(vec![&env]);
+
+        let len = matches.len();
+        if start >= len {
+            return vec![&env];
+        }
+        let end = start.saturating_add(limit.min(MAX_RETURN_ENTRIES)).min(len);
+        matches.slice(start..end)
+    }
+
+    /// Replay `MatchRecorded` events for stored matches (admin only)
+    ///
+    /// Publishes the events for up to `limit` matches starting at index
+    /// `start`, marked `reemitted`, and returns how many were published.
+    /// `limit` is capped at `MAX_RETURN_ENTRIES`.
+    pub fn reemit_match_events(
+        env: Env,
+        admin: Address,
+        start: u32,
+        limit: u32,
+    ) -> Result<u32, OrderbookError> {
+        admin.require_auth();
+        Self::require_admin(&env, &admin)?;
+
+        let matches: Vec<MatchRecord> = env
+            .storage()
+            .instance()
+            .get(&MATCHES_KEY)
+            .unwrap_or(vec![&env]);
+
+        let end = start.saturating_add(limit.min(MAX_RETURN_ENTRIES)).min(matches.len());
+        for i in start..end {
+            Self::publish_match_recorded(&env, &matches.get(i).unwrap(), true);
+        }
+        Ok(end.saturating_sub(start))
+    }
+
+    /// Get a specific match
+    pub fn get_match(env: Env, match_id: BytesN<32>) -> Option<MatchRecord> {
+        let matches: Vec<MatchRecord> = env
+            .storage()
+            .instance()
+            .get(&MATCHES_KEY)
+            .unwrap_or(vec![&env]);
+
+        matches.iter().find(|m| m.match_id == match_id)
+    }
+
+    /// Get the public order and match counters for an asset
+    pub fn get_asset_stats(env: Env, asset_address: Address) -> AssetStats {
+        let stats: Map<Address, AssetStats> = env
+            .storage()
+            .instance()
+            .get(&STATS_KEY)
+            .unwrap_or(Map::new(&env));
+        stats.get(asset_address).unwrap_or_default()
+    }
+
+    /// Store the expiry applied to the trader's orders submitted with an
+    /// expiry of 0; 0 clears the preference
+    pub fn set_default_expiry(env: Env, trader: Address, seconds: u64) {
+        trader.require_auth();
+
+        let mut defaults: Map<Address, u64> = env
+            .storage()
+            .instance()
+            .get(&DEFAULT_EXPIRY_KEY)
+            .unwrap_or(Map::new(&env));
+        if seconds == 0 {
+            defaults.remove(trader);
+        } else {
+            defaults.set(trader, seconds);
+        }
+        env.storage().instance().set(&DEFAULT_EXPIRY_KEY, &defaults);
+    }
+
+    /// Get the expiry applied to a trader's orders submitted with an expiry
+    /// of 0, falling back to `DEFAULT_EXPIRY_SECONDS`
+    pub fn get_default_expiry(env: Env, trader: Address) -> u64 {
+        let defaults: Map<Address, u64> = env
+            .storage()
+            .instance()
+            .get(&DEFAULT_EXPIRY_KEY)
+            .unwrap_or(Map::new(&env));
+        defaults.get(trader).unwrap_or(DEFAULT_EXPIRY_SECONDS)
+    }
+
+    /// Opt a trader's future orders in or out of public stats
+    pub fn set_stats_opt_out(env: Env, trader: Address, opt_out: bool) {
+        trader.require_auth();
+
+        let mut opt_outs: Map<Address, bool> = env
+            .storage()
+            .instance()
+            .get(&STATS_OPT_OUT_KEY)
+            .unwrap_or(Map::new(&env));
+        opt_outs.set(trader, opt_out);
+        env.storage().instance().set(&STATS_OPT_OUT_KEY, &opt_outs);
+    }
+
+    /// Check if a trader's new orders are excluded from public stats
+    pub fn is_stats_opt_out(env: Env, trader: Address) -> bool {
+        let opt_outs: Map<Address, bool> = env
+            .storage()
+            .instance()
+            .get(&STATS_OPT_OUT_KEY)
+            .unwrap_or(Map::new(&env));
+        opt_outs.get(trader).unwrap_or(false)
+    }
+
+    /// Get pending (unsettle) matches
+    pub fn get_pending_matches(env: Env) -> Vec<MatchRecord> {
+        let matches: Vec<MatchRecord> = env
+            .storage()
+            .instance()
+            .get(&MATCHES_KEY)
+            .unwrap_or(vec![&env]);
+
+        let mut pending: Vec<MatchRecord> = vec![&env];
+        for m in matches.iter() {
+            if !m.is_settled {
+                pending.push_back(m);
+            }
+        }
+        pending
+    }
+
+    /// Enable or disable cross-asset matching between two assets (admin only)
+    ///
+    /// Pairs are unordered: (A, B) and (B, A) share one setting.
+    pub fn set_tradable_pair(
+        env: Env,
+        admin: Address,
+        asset_a: Address,
+        asset_b: Address,
+        enabled: bool,
+    ) -> Result<(), OrderbookError> {
+        admin.require_auth();
+        Self::require_admin(&env, &admin)?;
+
+        let mut pairs: Map<(Address, Address), bool> = env
+            .storage()
+            .instance()
+            .get(&PAIRS_KEY)
+            .unwrap_or(Map::new(&env));
+        pairs.set(Self::pair_key(asset_a, asset_b), enabled);
+        env.storage().instance().set(&PAIRS_KEY, &pairs);
+        Ok(())
+    }
+
+    /// Check if two assets may be matched against each other
+    pub fn is_pair_tradable(env: Env, asset_a: Address, asset_b: Address) -> bool {
+        let pairs: Map<(Address, Address), bool> = env
+            .storage()
+            .instance()
+            .get(&PAIRS_KEY)
+            .unwrap_or(Map::new(&env));
+        pairs.get(Self::pair_key(asset_a, asset_b)).unwrap_or(false)
+    }
+
+    /// Get the minimum resting time enforced on an asset's orders, falling
+    /// back to the global `min_resting_seconds`
+    fn get_min_quote_lifetime(env: Env, asset_address: Address) -> u64 {
+        Self::get_asset_config(env.clone(), asset_address)
+            .min_quote_lifetime
+            .unwrap_or_else(|| Self::get_config(env).min_resting_seconds)
+    }
+
+    /// Replace the orderbook-wide settings (admin only)
+    ///
+    /// Fee and rebate may not exceed 100% of notional, a fee holiday may
+    /// not end before it starts and `max_expiry` must be non-zero. Funded
+    /// submission needs a quote asset, the notional cap may not be negative
+    /// and the keeper reward must be positive while a reward token is set.
+    /// Changing the reward token returns the old token's pool to the admin.
+    pub fn set_config(env: Env, admin: Address, config: OrderbookConfig) -> Result<(), OrderbookError> {
+        admin.require_auth();
+        Self::require_admin(&env, &admin)?;
+
+        if config.fee_bps as i128 > BPS_DENOMINATOR
+            || config.maker_rebate_bps as i128 > BPS_DENOMINATOR
+            || config.fee_holiday_start > config.fee_holiday_end
+        {
+            return Err(OrderbookError::InvalidFeeConfig);
+        }
+        if config.max_expiry == 0 {
+            return Err(OrderbookError::InvalidExpiry);
+        }
+        if config.require_funded_submission && config.funded_quote_asset.is_none() {
+            return Err(OrderbookError::QuoteAssetNotConfigured);
+        }
+        if config.max_outstanding_notional < 0
+            || (config.keeper_reward_token.is_some() && config.keeper_reward_per_order <= 0)
+        {
+            return Err(OrderbookError::InvalidQuantity);
+        }
+
+        let old_token = Self::get_config(env.clone()).keeper_reward_token;
+        if let Some(old_token) = old_token.filter(|token| config.keeper_reward_token.as_ref() != Some(token)) {
+            let pool = Self::get_keeper_pool(env.clone());
+            if pool > 0 {
+                Self::set_keeper_pool(&env, &old_token, 0);
+                let token_client = token::Client::new(&env, &old_token);
+                token_client.transfer(&env.current_contract_address(), &admin, &pool);
+            }
+        }
+        env.storage().instance().set(&CONFIG_KEY, &config);
+        Ok(())
+    }
+
+    /// Get the orderbook-wide settings
+    pub fn get_config(env: Env) -> OrderbookConfig {
+        env.storage().instance().get(&CONFIG_KEY).unwrap_or(OrderbookConfig {
+            fee_bps: 0,
+            maker_rebate_bps: 0,
+            fee_holiday_start: 0,
+            fee_holiday_end: 0,
+            match_cooldown: 0,
+            max_settle_attempts: 0,
+            max_outstanding_notional: 0,
+            max_active_orders_per_asset: 0,
+            max_root_lag: 0,
+            max_expiry: DEFAULT_MAX_EXPIRY_SECONDS,
+            proof_validity: 0,
+            post_match_cancel_grace: 0,
+            min_resting_seconds: 0,
+            proof_rate_limit: 0,
+            proof_rate_window: 0,
+            require_funded_submission: false,
+            funded_quote_asset: None,
+            keeper_reward_token: None,
+            keeper_reward_per_order: 0,
+        })
+    }
+
+    /// Replace an asset's settings (admin only)
+    ///
+    /// Trading hours must fall within a day, the open interest cap may not be
+    /// negative and an exact settlement scale must be positive.
+    pub fn set_asset_config(
+        env: Env,
+        admin: Address,
+        asset_address: Address,
+        config: AssetConfig,
+    ) -> Result<(), OrderbookError> {
+        admin.require_auth();
+        Self::require_admin(&env, &admin)?;
+
+        if config.trading_open_seconds >= SECONDS_PER_DAY || config.trading_close_seconds >= SECONDS_PER_DAY {
+            return Err(OrderbookError::InvalidTradingHours);
+        }
+        if config.max_open_interest < 0 {
+            return Err(OrderbookError::InvalidQuantity);
+        }
+        if config.exact_settlement_scale.is_some_and(|scale| scale <= 0) {
+            return Err(OrderbookError::InvalidPriceScale);
+        }
+        let mut configs: Map<Address, AssetConfig> = env
+            .storage()
+            .instance()
+            .get(&ASSET_CONFIG_KEY)
+            .unwrap_or(Map::new(&env));
+        configs.set(asset_address, config);
+        env.storage().instance().set(&ASSET_CONFIG_KEY, &configs);
+        Ok(())
+    }
+
+    /// Get an asset's settings
+    pub fn get_asset_config(env: Env, asset_address: Address) -> AssetConfig {
+        let configs: Map<Address, AssetConfig> = env
+            .storage()
+            .instance()
+            .get(&ASSET_CONFIG_KEY)
+            .unwrap_or(Map::new(&env));
+        configs.get(asset_address).unwrap_or_default()
+    }
+
+    /// Get admin address
+    pub fn get_admin(env: Env) -> Address {
+        env.storage().instance().get(&ADMIN_KEY).unwrap()
+    }
+
+    /// Propose a new admin; the handover completes when they call
+    /// `accept_admin`. A later proposal replaces an earlier one.
+    pub fn propose_admin(env: Env, current_admin: Address, new_admin: Address) -> Result<(), OrderbookError> {
+        current_admin.require_auth();
+        Self::require_admin(&env, &current_admin)?;
+        env.storage().instance().set(&PENDING_ADMIN_KEY, &new_admin);
+        Ok(())
+    }
+
+    /// Accept a pending admin proposal, becoming the admin
+    pub fn accept_admin(env: Env, new_admin: Address) -> Result<(), OrderbookError> {
+        new_admin.require_auth();
+        if Self::get_pending_admin(env.clone()) != Some(new_admin.clone()) {
+            return Err(OrderbookError::NotPendingAdmin);
+        }
+        env.storage().instance().set(&ADMIN_KEY, &new_admin);
+        env.storage().instance().remove(&PENDING_ADMIN_KEY);
+        Ok(())
+    }
+
+    /// Get the proposed admin awaiting acceptance, if any
+    pub fn get_pending_admin(env: Env) -> Option<Address> {
+        env.storage().instance().get(&PENDING_ADMIN_KEY)
+    }
+
+    /// Get registry address
+    pub fn get_registry(env: Env) -> Address {
+        env.storage().instance().get(&REGISTRY_KEY).unwrap()
+    }
+
+    /// Get settlement address
+    pub fn get_settlement(env: Env) -> Address {
+        env.storage().instance().get(&SETTLEMENT_KEY).unwrap()
+    }
+
+    /// Get verifier contract address
+    pub fn get_verifier(env: Env) -> Address {
+        env.storage().instance().get(&VERIFIER_KEY).unwrap()
+    }
+
+    /// Check if the contract was deployed in test mode, skipping ownership
+    /// proof verification
+    pub fn is_test_mode(env: Env) -> bool {
+        env.storage().instance().get(&TEST_MODE_KEY).unwrap_or(false)
+    }
+
+    // Internal helpers
+
+    /// Verify a ZK proof of order ownership against the stored verifier
+    ///
+    /// The first public signal must be the commitment itself, so a proof
+    /// generated for one order cannot be replayed to cancel another; such
+    /// proofs fail with `InvalidProof` before reaching the verifier. A proof
+    /// the verifier rejects returns false and emits `ProofRejected`. In
+    /// test mode the check is skipped loudly.
+    fn verify_ownership_proof(
+        env: &Env,
+        trader: &Address,
+        commitment: &BytesN<32>,
+        proof_bytes: &Bytes,
+        pub_signals_bytes: &Bytes,
+    ) -> Result<bool, OrderbookError> {
+        if Self::is_test_mode(env.clone()) {
+            TestModeBypass {
+                commitment: commitment.clone(),
+            }
+            .publish(env);
+            return Ok(true);
+        }
+
+        let signals = Self::parse_public_signals(env, pub_signals_bytes)?;
+        if signals.get(0) != Some(commitment.clone()) {
+            return Err(OrderbookError::InvalidProof);
+        }
+
+        let verifier_address = Self::get_verifier(env.clone());
+        let vk_bytes: Bytes = env.storage().instance().get(&OWNERSHIP_VK_KEY).unwrap();
+        let verifier_client = verifier_wasm::Client::new(env, &verifier_address);
+        if verifier_client.try_verify_proof_bytes(&vk_bytes, proof_bytes, pub_signals_bytes) == Ok(Ok(true)) {
+            return Ok(true);
+        }
+        ProofRejected {
+            trader: trader.clone(),
+            commitment: commitment.clone(),
+        }
+        .publish(env);
+        Ok(false)
+    }
+
+    /// Parse public signals: a 4-byte big-endian count followed by 32-byte entries
+    fn parse_public_signals(env: &Env, bytes: &Bytes) -> Result<Vec<BytesN<32>>, OrderbookError> {
+        if bytes.len() < 4 {
+            return Err(OrderbookError::InvalidProof);
+        }
+        let mut len_bytes = [0u8; 4];
+        bytes.slice(0..4).copy_into_slice(&mut len_bytes);
+        let len = u32::from_be_bytes(len_bytes);
+
+        let mut signals = Vec::new(env);
+        let mut pos = 4u32;
+        for _ in 0..len {
+            if pos + 32 > bytes.len() {
+                return Err(OrderbookError::InvalidProof);
+            }
+            let mut arr = [0u8; 32];
+            bytes.slice(pos..pos + 32).copy_into_slice(&mut arr);
+            pos += 32;
+            signals.push_back(BytesN::from_array(env, &arr));
+        }
+
+        Ok(signals)
+    }
+
+    /// Load an order from its own persistent entry
+    fn load_order(env: &Env, commitment: &BytesN<32>) -> Option<OrderCommitment> {
+        env.storage().persistent().get(&DataKey::Order(commitment.clone()))
+    }
+
+    /// Write a single order to its own persistent entry
+    fn store_order(env: &Env, order: &OrderCommitment) {
+        env.storage()
+            .persistent()
+            .set(&DataKey::Order(order.commitment.clone()), order);
+    }
+
+    /// Append a commitment to an order index, starting a new page once the
+    /// last one holds `INDEX_PAGE_SIZE` entries
+    fn push_index(env: &Env, index: OrderIndex, commitment: &BytesN<32>) {
+        let len = Self::index_len(env, &index);
+        let page_key = DataKey::IndexPage(index.clone(), len / INDEX_PAGE_SIZE);
+        let mut page: Vec<BytesN<32>> = env.storage().persistent().get(&page_key).unwrap_or(vec![env]);
+        page.push_back(commitment.clone());
+        env.storage().persistent().set(&page_key, &page);
+        env.storage().persistent().set(&DataKey::IndexLen(index), &(len + 1));
+    }
+
+    fn index_len(env: &Env, index: &OrderIndex) -> u32 {
+        env.storage()
+            .persistent()
+            .get(&DataKey::IndexLen(index.clone()))
+            .unwrap_or(0)
+    }
+
+    /// One page of an order index
+    fn index_page(env: &Env, index: &OrderIndex, page: u32) -> Vec<BytesN<32>> {
+        env.storage()
+            .persistent()
+            .get(&DataKey::IndexPage(index.clone(), page))
+            .unwrap_or(vec![env])
+    }
+
+    /// Every commitment in an order index, in insertion order
+    fn index_commitments(env: &Env, index: &OrderIndex) -> Vec<BytesN<32>> {
+        let mut commitments: Vec<BytesN<32>> = vec![env];
+        for page in 0..Self::index_len(env, index).div_ceil(INDEX_PAGE_SIZE) {
+            commitments.append(&Self::index_page(env, index, page));
+        }
+        commitments
+    }
+
+    /// Every order in an order index, in insertion order
+    fn indexed_orders(env: &Env, index: &OrderIndex) -> Vec<OrderCommitment> {
+        let mut orders: Vec<OrderCommitment> = vec![env];
+        for commitment in Self::index_commitments(env, index).iter() {
+            if let Some(order) = Self::load_order(env, &commitment) {
+                orders.push_back(order);
+            }
+        }
+        orders
+    }
+
+    /// Up to `limit` of an asset's orders matching the filters, after
+    /// skipping the first `start` matches
+    fn filter_asset_orders(
+        env: &Env,
+        asset_address: &Address,
+        side: Option<OrderSide>,
+        status: Option<OrderStatus>,
+        start: u32,
+        limit: u32,
+    ) -> Vec<OrderCommitment> {
+        let mut filtered: Vec<OrderCommitment> = vec![env];
+        let mut skipped: u32 = 0;
+        for order in Self::asset_orders(env, asset_address).iter() {
+            if filtered.len() == limit {
+                break;
+            }
+            match side {
+                Some(s) if order.side != s => continue,
+                _ => {}
+            }
+            match status {
+                Some(s) if order.status != s => continue,
+                _ => {}
+            }
+            if skipped < start {
+                skipped += 1;
+                continue;
+            }
+            filtered.push_back(order);
+        }
+        filtered
+    }
+
+    /// Count a newly submitted or reopened order towards its asset's live
+    /// orders, and its unfilled quantity towards the asset's open interest
+    fn increase_order_count(env: &Env, order: &OrderCommitment) {
+        Self::adjust_count(env, &ORDER_COUNTS_KEY, &order.asset_address, true);
+        Self::adjust_total(env, &OPEN_INTEREST_KEY, &order.asset_address, order.remaining_quantity);
+    }
+
+    /// Drop a filled, cancelled or expired order from its asset's live
+    /// orders and open interest
+    fn decrease_order_count(env: &Env, order: &OrderCommitment) {
+        Self::adjust_count(env, &ORDER_COUNTS_KEY, &order.asset_address, false);
+        Self::adjust_total(env, &OPEN_INTEREST_KEY, &order.asset_address, -order.remaining_quantity);
+    }
+
+    /// Add or remove one from the counter kept for `address` under `key`
+    fn adjust_count(env: &Env, key: &Symbol, address: &Address, increase: bool) {
+        let mut counts: Map<Address, u32> = env.storage().instance().get(key).unwrap_or(Map::new(env));
+        let count = counts.get(address.clone()).unwrap_or(0);
+        let count = if increase {
+            count.saturating_add(1)
+        } else {
+            count.saturating_sub(1)
+        };
+        if count == 0 {
+            counts.remove(address.clone());
+        } else {
+            counts.set(address.clone(), count);
+        }
+        env.storage().instance().set(key, &counts);
+    }
+
+    /// An asset's orders, in submission order
+    fn asset_orders(env: &Env, asset_address: &Address) -> Vec<OrderCommitment> {
+        Self::indexed_orders(env, &OrderIndex::Asset(asset_address.clone()))
+    }
+
+    /// A trader's orders, in submission order
+    fn trader_orders(env: &Env, trader: &Address) -> Vec<OrderCommitment> {
+        Self::indexed_orders(env, &OrderIndex::Trader(trader.clone()))
+    }
+
+    /// Every asset that has had an order submitted, in first-seen order
+    fn order_assets(env: &Env) -> Vec<Address> {
+        env.storage().instance().get(&ORDER_ASSETS_KEY).unwrap_or(vec![env])
+    }
+
+    /// Verify an order is still active and has not expired
+    fn require_open(env: &Env, order: &OrderCommitment) -> Result<(), OrderbookError> {
+        match order.status {
+            OrderStatus::Matched | OrderStatus::Settled => {
+                return Err(OrderbookError::OrderAlreadyMatched);
+            }
+            OrderStatus::Cancelled => {
+                return Err(OrderbookError::OrderAlreadyCancelled);
+            }
+            OrderStatus::Expired => {
+                return Err(OrderbookError::OrderExpired);
+            }
+            OrderStatus::Active | OrderStatus::PartiallyFilled => {}
+        }
+
+        if order.expiry <= env.ledger().timestamp() {
+            return Err(OrderbookError::OrderExpired);
+        }
+        Ok(())
+    }
+
+    /// Lock a new order's escrow in the settlement contract
+    fn lock_order_funds(
+        env: &Env,
+        trader: &Address,
+        lock_asset: &Address,
+        amount: i128,
+    ) -> Result<(), OrderbookError> {
+        let settlement_address = Self::get_settlement(env.clone());
+        let settlement_client = settlement_wasm::Client::new(env, &settlement_address);
+
+        if settlement_client.get_available_balance(trader, lock_asset) < amount {
+            return Err(OrderbookError::InsufficientFundsForOrder);
+        }
+        settlement_client.increase_lock(&env.current_contract_address(), trader, lock_asset, &amount);
+        Ok(())
+    }
+
+    /// Reject an order that would take the asset past its open interest cap
+    fn require_within_open_interest_cap(env: &Env, asset_address: &Address, quantity: i128) -> Result<(), OrderbookError> {
+        let cap = Self::get_asset_config(env.clone(), asset_address.clone()).max_open_interest;
+        if cap == 0 {
+            return Ok(());
+        }
+        let open_interest = Self::get_open_interest(env.clone(), asset_address.clone());
+        if open_interest.checked_add(quantity).is_none_or(|total| total > cap) {
+            return Err(OrderbookError::OpenInterestCapReached);
+        }
+        Ok(())
+    }
+
+    fn require_within_notional_limit(env: &Env, trader: &Address, amount: i128) -> Result<(), OrderbookError> {
+        let max_notional = Self::get_config(env.clone()).max_outstanding_notional;
+        if max_notional == 0 {
+            return Ok(());
+        }
+        let outstanding = Self::get_outstanding_notional(env.clone(), trader.clone());
+        if outstanding.checked_add(amount).is_none_or(|total| total > max_notional) {
+            return Err(OrderbookError::NotionalLimitExceeded);
+        }
+        Ok(())
+    }
+
+    /// Set how much escrow an order has locked, moving its trader's
+    /// outstanding notional by the change in the lock's value
+    ///
+    /// Settled orders no longer count, so their locks change freely.
+    fn set_order_lock(env: &Env, order: &mut OrderCommitment, amount: i128) {
+        if order.status != OrderStatus::Settled {
+            let delta = Self::lock_value(order, amount) - Self::lock_value(order, order.locked_amount);
+            Self::adjust_total(env, &NOTIONAL_KEY, &order.trader, delta);
+        }
+        order.locked_amount = amount;
+    }
+
+    /// Value of `amount` of an order's lock in the settlement asset: a buy
+    /// locks the quote asset itself, a sell's asset is valued at its limit
+    /// price
+    fn lock_value(order: &OrderCommitment, amount: i128) -> i128 {
+        match order.side {
+            OrderSide::Buy => amount,
+            OrderSide::Sell => amount.saturating_mul(order.limit_price),
+        }
+    }
+
+    /// Add `delta` to the running total kept for `address` under `key`
+    fn adjust_total(env: &Env, key: &Symbol, address: &Address, delta: i128) {
+        if delta == 0 {
+            return;
+        }
+        let mut totals: Map<Address, i128> = env.storage().instance().get(key).unwrap_or(Map::new(env));
+        let total = totals.get(address.clone()).unwrap_or(0).saturating_add(delta);
+        if total == 0 {
+            totals.remove(address.clone());
+        } else {
+            totals.set(address.clone(), total);
+        }
+        env.storage().instance().set(key, &totals);
+    }
+
+    /// Give back any escrow locked for an order that will no longer trade
+    fn release_order_lock(env: &Env, order: &mut OrderCommitment) {
+        if order.locked_amount > 0 {
+            let settlement_address = Self::get_settlement(env.clone());
+            let settlement_client = settlement_wasm::Client::new(env, &settlement_address);
+            settlement_client.decrease_lock(
+                &env.current_contract_address(),
+                &order.trader,
+                &order.locked_asset,
+                &order.locked_amount,
+            );
+            Self::set_order_lock(env, order, 0);
+        }
+    }
+
+    /// Release an order's lock through a settlement unlock proof
+    ///
+    /// The proof is bound to the order's commitment, trader and locked
+    /// asset; an amount above the order's own lock is rejected so one
+    /// order cannot free escrow held for another. Any part of the lock the
+    /// proof did not cover is released as usual.
+    fn unlock_order_with_proof(
+        env: &Env,
+        order: &mut OrderCommitment,
+        unlock_proof_bytes: &Bytes,
+        unlock_signals_bytes: &Bytes,
+    ) -> Result<(), OrderbookError> {
+        let settlement_address = Self::get_settlement(env.clone());
+        let settlement_client = settlement_wasm::Client::new(env, &settlement_address);
+        let unlocked = settlement_client.try_unlock_with_proof(
+            &env.current_contract_address(),
+            &order.trader,
+            &order.locked_asset,
+            &order.commitment,
+            unlock_proof_bytes,
+            unlock_signals_bytes,
+        );
+        let amount = match unlocked {
+            Ok(Ok(amount)) if amount <= order.locked_amount => amount,
+            Err(Ok(settlement_wasm::SettlementError::InsufficientLockedFunds)) => {
+                return Err(OrderbookError::InsufficientEscrow);
+            }
+            _ => return Err(OrderbookError::InvalidProof),
+        };
+        let remaining_lock = order.locked_amount - amount;
+        Self::set_order_lock(env, order, remaining_lock);
+        Self::release_order_lock(env, order);
+        Ok(())
+    }
+
+    /// Reject settling a match before its recording batch has closed
+    fn require_settlement_window(env: &Env, m: &MatchRecord) -> Result<(), OrderbookError> {
+        let interval = Self::get_asset_config(env.clone(), m.asset_address.clone()).settle_interval;
+        if interval == 0 {
+            return Ok(());
+        }
+        if env.ledger().timestamp() / interval <= m.timestamp / interval {
+            return Err(OrderbookError::NotInSettlementWindow);
+        }
+        Ok(())
+    }
+
+    /// Count a proof-verifying call against the trader's rate limit
+    ///
+    /// Attempts live in temporary storage and only need to outlast the
+    /// window they belong to. The write is rolled back with any failing
+    /// call, which is why callers report a rejected proof as a result
+    /// rather than an error.
+    fn consume_proof_attempt(env: &Env, trader: &Address) -> Result<(), OrderbookError> {
+        let config = Self::get_config(env.clone());
+        if config.proof_rate_limit == 0 {
+            return Ok(());
+        }
+
+        let key = (PROOF_ATTEMPTS_KEY, trader.clone());
+        let now = env.ledger().timestamp();
+        let mut attempts: ProofAttempts = env
+            .storage()
+            .temporary()
+            .get(&key)
+            .filter(|a: &ProofAttempts| now < a.window_start.saturating_add(config.proof_rate_window))
+            .unwrap_or(ProofAttempts { window_start: now, count: 0 });
+
+        if attempts.count >= config.proof_rate_limit {
+            return Err(OrderbookError::ProofRateLimited);
+        }
+        attempts.count += 1;
+
+        let ttl = (config.proof_rate_window / LEDGER_SECONDS + 1).min(env.storage().max_ttl() as u64) as u32;
+        env.storage().temporary().set(&key, &attempts);
+        env.storage().temporary().extend_ttl(&key, ttl, ttl);
+        Ok(())
+    }
+
+    /// Check if a matched order is still within the post-match cancel grace
+    fn in_cancel_grace(env: &Env, order: &OrderCommitment) -> bool {
+        let grace = Self::get_config(env.clone()).post_match_cancel_grace;
+        grace > 0 && env.ledger().timestamp() < order.last_match_timestamp.saturating_add(grace)
+    }
+
+    /// Remove every unsettled match filling `commitment` that was recorded
+    /// inside the cancel grace, reopening each counterparty order
+    ///
+    /// A partially filled order may have several such matches; all of them
+    /// are reverted so none is left pointing at a cancelled order.
+    fn revert_matches_in_grace(env: &Env, commitment: &BytesN<32>) -> Result<(), OrderbookError> {
+        let matches: Vec<MatchRecord> = env
+            .storage()
+            .instance()
+            .get(&MATCHES_KEY)
+            .unwrap_or(vec![env]);
+        let grace_start = env
+            .ledger()
+            .timestamp()
+            .saturating_sub(Self::get_config(env.clone()).post_match_cancel_grace);
+
+        let mut reverted: Vec<MatchRecord> = vec![env];
+        let mut updated_matches: Vec<MatchRecord> = vec![env];
+        for m in matches.iter() {
+            let fills_order = m.buy_commitment == *commitment || m.sell_commitment == *commitment;
+            if fills_order && !m.is_settled && m.timestamp > grace_start {
+                reverted.push_back(m);
+            } else {
+                updated_matches.push_back(m);
+            }
+        }
+        env.storage().instance().set(&MATCHES_KEY, &updated_matches);
+
+        for m in reverted.iter() {
+            Self::unwind_match(env, &m)?;
+            let counterparty = if m.buy_commitment == *commitment {
+                m.sell_commitment.clone()
+            } else {
+                m.buy_commitment.clone()
+            };
+            Self::reopen_order(env, &counterparty, m.quantity);
+            MatchReverted { match_id: m.match_id }.publish(env);
+        }
+        Ok(())
+    }
+
+    /// Back out the asset stats a match added when it was recorded
+    fn unwind_match(env: &Env, m: &MatchRecord) -> Result<(), OrderbookError> {
+        let excluded = [&m.buy_commitment, &m.sell_commitment]
+            .into_iter()
+            .any(|commitment| Self::load_order(env, commitment).is_some_and(|order| order.exclude_from_stats));
+        if !excluded {
+            let mut stats = Self::get_asset_stats(env.clone(), m.asset_address.clone());
+            stats.match_count = stats.match_count.saturating_sub(1);
+            stats.matched_volume = stats
+                .matched_volume
+                .checked_sub(m.quantity)
+                .ok_or(OrderbookError::ArithmeticOverflow)?;
+            Self::set_asset_stats(env, &m.asset_address, &stats);
+        }
+        Ok(())
+    }
+
+    /// Give a matched or partially filled order back `quantity`, returning
+    /// the reopened order
+    fn reopen_order(env: &Env, commitment: &BytesN<32>, quantity: i128) -> Option<OrderCommitment> {
+        let mut reopened = Self::load_order(env, commitment)?;
+        if reopened.status == OrderStatus::Matched {
+            Self::increase_order_count(env, &reopened);
+        } else if !reopened.status.is_open() {
+            return None;
+        }
+        reopened.filled_quantity -= quantity;
+        reopened.remaining_quantity += quantity;
+        Self::adjust_total(env, &OPEN_INTEREST_KEY, &reopened.asset_address, quantity);
+        reopened.status = if reopened.filled_quantity == 0 {
+            OrderStatus::Active
+        } else {
+            OrderStatus::PartiallyFilled
+        };
+        Self::store_order(env, &reopened);
+        Some(reopened)
+    }
+
+    /// Flip up to `max` active, past-expiry orders for an asset to `Expired`,
+    /// releasing any escrow locked for them
+    ///
+    /// # Returns
+    /// * The number of orders expired, and how many of them earn `keeper` a
+    ///   reward
+    fn expire_orders(env: &Env, asset_address: &Address, max: u32, keeper: Option<&Address>) -> (u32, u32) {
+        let now = env.ledger().timestamp();
+        let mut expired: u32 = 0;
+        let mut rewardable: u32 = 0;
+        for mut order in Self::asset_orders(env, asset_address).iter() {
+            if expired >= max {
+                break;
+            }
+            if order.status.is_open() && order.expiry <= now {
+                let own_order = keeper == Some(&order.trader);
+                if !own_order
+                    && (order.locked_amount > 0
+                        || order.expiry.saturating_sub(order.timestamp) >= KEEPER_MIN_LIFETIME_SECONDS)
+                {
+                    rewardable += 1;
+                }
+                Self::expire_open_order(env, &mut order);
+                expired += 1;
+            }
+        }
+        (expired, rewardable)
+    }
+
+    fn set_keeper_pool(env: &Env, token: &Address, pool: i128) {
+        let mut pools: Map<Address, i128> = env
+            .storage()
+            .instance()
+            .get(&KEEPER_POOL_KEY)
+            .unwrap_or(Map::new(env));
+        pools.set(token.clone(), pool);
+        env.storage().instance().set(&KEEPER_POOL_KEY, &pools);
+    }
+
+    /// Expire an open order, releasing its escrow and notifying any expiry
+    /// callback
+    ///
+    /// A callback that fails or rejects the call is ignored; its own state
+    /// changes are rolled back and the expiry stands.
+    fn expire_open_order(env: &Env, order: &mut OrderCommitment) {
+        order.status = OrderStatus::Expired;
+        Self::release_order_lock(env, order);
+        Self::decrease_order_count(env, order);
+        Self::store_order(env, order);
+        OrderExpired {
+            commitment: order.commitment.clone(),
+            expiry_callback: order.expiry_callback.clone(),
+        }
+        .publish(env);
+
+        if let Some(callback) = &order.expiry_callback {
+            let args = vec![env, order.commitment.to_val()];
+            let _ = env.try_invoke_contract::<Val, soroban_sdk::Error>(callback, &EXPIRY_CALLBACK_FN, args);
+        }
+    }
+
+    fn set_asset_stats(env: &Env, asset_address: &Address, asset_stats: &AssetStats) {
+        let mut stats: Map<Address, AssetStats> = env
+            .storage()
+            .instance()
+            .get(&STATS_KEY)
+            .unwrap_or(Map::new(env));
+        stats.set(asset_address.clone(), asset_stats.clone());
+        env.storage().instance().set(&STATS_KEY, &stats);
+    }
+
+    /// Validate one side of a match and return the order marked as matched
+    fn fill_order(
+        env: &Env,
+        order: OrderCommitment,
+        asset_address: &Address,
+        quantity: i128,
+    ) -> Result<OrderCommitment, OrderbookError> {
+        if order.asset_address != *asset_address {
+            return Err(OrderbookError::AssetMismatch);
+        }
+        if quantity > order.remaining_quantity {
+            return Err(OrderbookError::InsufficientRemainingQuantity);
+        }
+        // Neither the fill nor the remainder it leaves open may fall below
+        // the floor, as no later match could take that remainder
+        let remainder = order.remaining_quantity - quantity;
+        if quantity < order.min_display_quantity || (remainder > 0 && remainder < order.min_display_quantity) {
+            return Err(OrderbookError::BelowMinimumQuantity);
+        }
+        if quantity < order.remaining_quantity && quantity < order.min_fill_quantity {
+            return Err(OrderbookError::BelowMinFill);
+        }
+
+        let now = env.ledger().timestamp();
+        let cooldown = Self::get_config(env.clone()).match_cooldown;
+        if order.last_match_timestamp != 0 && now < order.last_match_timestamp.saturating_add(cooldown) {
+            return Err(OrderbookError::MatchCooldown);
+        }
+
+        // A partial fill leaves the rest of the order on the book
+        let mut matched_order = order;
+        matched_order.last_match_timestamp = now;
+        matched_order.filled_quantity += quantity;
+        matched_order.remaining_quantity -= quantity;
+        Self::adjust_total(env, &OPEN_INTEREST_KEY, asset_address, -quantity);
+        matched_order.status = if matched_order.remaining_quantity == 0 {
+            Self::decrease_order_count(env, &matched_order);
+            OrderStatus::Matched
+        } else {
+            OrderStatus::PartiallyFilled
+        };
+        Ok(matched_order)
+    }
+
+    /// Order a pair of assets so both directions map to the same key
+    fn pair_key(asset_a: Address, asset_b: Address) -> (Address, Address) {
+        if asset_a <= asset_b {
+            (asset_a, asset_b)
+        } else {
+            (asset_b, asset_a)
+        }
+    }
+
+    /// Validate and store a new order for an already authenticated trader
+    fn submit_own_order(
+        env: &Env,
+        trader: &Address,
+        commitment: BytesN<32>,
+        asset_address: Address,
+        side: OrderSide,
+        expiry_seconds: u64,
+        quantity: i128,
+        limit_price: i128,
+        min_display_quantity: i128,
+    ) -> Result<SubmitReceipt, OrderbookError> {
+        if Self::is_paused(env.clone()) {
+            return Err(OrderbookError::ContractPaused);
+        }
+        if quantity <= 0 || min_display_quantity < 0 || min_display_quantity > quantity {
+            return Err(OrderbookError::InvalidQuantity);
+        }
+
+        if env.storage().persistent().has(&DataKey::Order(commitment.clone())) {
+            return Err(OrderbookError::DuplicateCommitment);
+        }
+
+        if Self::is_asset_delisted(env.clone(), asset_address.clone()) {
+            return Err(OrderbookError::AssetDelisted);
+        }
+        if Self::get_asset_config(env.clone(), asset_address.clone()).halted {
+            return Err(OrderbookError::AssetHalted);
+        }
+        Self::require_within_open_interest_cap(env, &asset_address, quantity)?;
+        let max_orders = Self::get_config(env.clone()).max_active_orders_per_asset;
+        if max_orders > 0 && Self::get_order_count_by_asset(env.clone(), asset_address.clone()) >= max_orders {
+            return Err(OrderbookError::TooManyActiveOrders);
+        }
+        if !Self::is_within_trading_hours(env.clone(), asset_address.clone()) {
+            return Err(OrderbookError::OutsideTradingHours);
+        }
+        if Self::get_asset_config(env.clone(), asset_address.clone()).accredited_only {
+            let registry = registry_wasm::Client::new(env, &Self::get_registry(env.clone()));
+            if !registry.is_accredited(trader) {
+                return Err(OrderbookError::AccreditationRequired);
+            }
+        }
+
+        let current_time = env.ledger().timestamp();
+        let expiry_seconds = if expiry_seconds == 0 {
+            Self::get_default_expiry(env.clone(), trader.clone())
+        } else {
+            expiry_seconds
+        };
+        if expiry_seconds > Self::get_config(env.clone()).max_expiry {
+            return Err(OrderbookError::InvalidExpiry);
+        }
+        let expiry = current_time
+            .checked_add(expiry_seconds)
+            .ok_or(OrderbookError::InvalidExpiry)?;
+
+        let config = Self::get_config(env.clone());
+        let (locked_asset, required_lock) = match config.funded_quote_asset {
+            Some(quote_asset) if config.require_funded_submission => {
+                if limit_price <= 0 {
+                    return Err(OrderbookError::InvalidLimitPrice);
+                }
+                // A sell locks the quantity it offers, a buy what it would
+                // pay at its limit price
+                let notional = quantity.checked_mul(limit_price).ok_or(OrderbookError::ArithmeticOverflow)?;
+                let (lock_asset, lock_amount) = match side {
+                    OrderSide::Sell => (asset_address.clone(), quantity),
+                    OrderSide::Buy => (quote_asset, notional),
+                };
+                Self::require_within_notional_limit(env, trader, notional)?;
+                Self::lock_order_funds(env, trader, &lock_asset, lock_amount)?;
+                (lock_asset, lock_amount)
+            }
+            _ => (asset_address.clone(), 0),
+        };
+
+        let tree_index: u32 = env.storage().instance().get(&ORDER_SEQ_KEY).unwrap_or(0);
+
+        let proof_validity = config.proof_validity;
+        let proof_valid_until = if proof_validity == 0 {
+            u64::MAX
+        } else {
+            current_time.saturating_add(proof_validity)
+        };
+
+        let order = OrderCommitment {
+            commitment: commitment.clone(),
+            trader: trader.clone(),
+            asset_address: asset_address.clone(),
+            side,
+            timestamp: current_time,
+            expiry,
+            status: OrderStatus::Active,
+            tree_index,
+            last_match_timestamp: 0,
+            proof_valid_until,
+            locked_asset,
+            locked_amount: 0,
+            limit_price,
+            expiry_callback: None,
+            exclude_from_stats: Self::is_stats_opt_out(env.clone(), trader.clone()),
+            peg_offset: None,
+            total_quantity: quantity,
+            filled_quantity: 0,
+            remaining_quantity: quantity,
+            min_fill_quantity: 0,
+            min_display_quantity,
+            nullifier: None,
+            cancelled_at: 0,
+            oco_group: None,
+            expected_root: None,
+            expected_root_index: 0,
+        };
+        if !order.exclude_from_stats {
+            let mut stats = Self::get_asset_stats(env.clone(), asset_address.clone());
+            stats.order_count += 1;
+            Self::set_asset_stats(env, &asset_address, &stats);
+        }
+
+        let mut order = order;
+        Self::set_order_lock(env, &mut order, required_lock);
+        Self::increase_order_count(env, &order);
+        Self::store_order(env, &order);
+        env.storage().instance().set(&ORDER_SEQ_KEY, &(tree_index + 1));
+
+        let asset_index = OrderIndex::Asset(asset_address.clone());
+        if Self::index_len(env, &asset_index) == 0 {
+            let mut assets = Self::order_assets(env);
+            assets.push_back(asset_address.clone());
+            env.storage().instance().set(&ORDER_ASSETS_KEY, &assets);
+        }
+        Self::push_index(env, asset_index, &commitment);
+        Self::push_index(env, OrderIndex::Trader(trader.clone()), &commitment);
+
+        OrderSubmitted {
+            commitment,
+            asset_address,
+            side,
+        }
+        .publish(env);
+
+        Ok(SubmitReceipt {
+            index: tree_index,
+            required_lock,
+            expiry,
+        })
+    }
+
+    /// Cancel one of the trader's own orders, releasing its lock
+    ///
+    /// Returns false, leaving the order untouched, if the verifier rejects
+    /// the ownership proof.
+    fn cancel_own_order(
+        env: &Env,
+        trader: &Address,
+        commitment: &BytesN<32>,
+        proof_bytes: &Bytes,
+        pub_signals_bytes: &Bytes,
+        unlock_proof: Option<(&Bytes, &Bytes)>,
+    ) -> Result<bool, OrderbookError> {
+        Self::consume_proof_attempt(env, trader)?;
+
+        let mut order = Self::load_order(env, commitment).ok_or(OrderbookError::OrderNotFound)?;
+
+        // Verify trader owns the order
+        if order.trader != *trader {
+            return Err(OrderbookError::UnauthorizedCancellation);
+        }
+
+        if env.ledger().timestamp() > order.proof_valid_until {
+            return Err(OrderbookError::ProofExpired);
+        }
+
+        // Check order is still active
+        let mut revert_match = false;
+        match order.status {
+            OrderStatus::Matched if Self::in_cancel_grace(env, &order) => {
+                revert_match = true;
+            }
+            OrderStatus::Matched | OrderStatus::Settled => {
+                return Err(OrderbookError::OrderAlreadyMatched);
+            }
+            OrderStatus::Cancelled => {
+                return Err(OrderbookError::OrderAlreadyCancelled);
+            }
+            OrderStatus::Active | OrderStatus::PartiallyFilled => {
+                let lifetime = Self::get_min_quote_lifetime(env.clone(), order.asset_address.clone());
+                if env.ledger().timestamp() < order.timestamp.saturating_add(lifetime) {
+                    return Err(OrderbookError::MinRestingNotElapsed);
+                }
+                // A partial fill inside the grace is unwound with the order
+                revert_match = order.status == OrderStatus::PartiallyFilled && Self::in_cancel_grace(env, &order);
+            }
+            _ => {}
+        }
+
+        if !Self::verify_ownership_proof(env, trader, commitment, proof_bytes, pub_signals_bytes)? {
+            return Ok(false);
+        }
+
+        // Expired and fully filled orders were already dropped from the
+        // live count
+        if order.status.is_open() {
+            Self::decrease_order_count(env, &order);
+        }
+        order.status = OrderStatus::Cancelled;
+        order.cancelled_at = env.ledger().timestamp();
+        match unlock_proof {
+            Some((unlock_proof_bytes, unlock_signals_bytes)) => {
+                Self::unlock_order_with_proof(env, &mut order, unlock_proof_bytes, unlock_signals_bytes)?
+            }
+            None => Self::release_order_lock(env, &mut order),
+        }
+        let oco_group = order.oco_group.clone();
+        Self::store_order(env, &order);
+
+        if revert_match {
+            Self::revert_matches_in_grace(env, commitment)?;
+        }
+
+        let mut cancelled: Vec<BytesN<32>> = vec![env, commitment.clone()];
+        if let Some(group) = oco_group {
+            Self::cancel_oco_siblings(env, &group, &mut cancelled);
+        }
+
+        for commitment in cancelled.iter() {
+            OrderCancelled { commitment }.publish(env);
+        }
+        Ok(true)
+    }
+
+    /// Cancel an open order, dropping it from the live counts and releasing
+    /// its escrow
+    fn cancel_open_order(env: &Env, order: &mut OrderCommitment) {
+        Self::decrease_order_count(env, order);
+        order.status = OrderStatus::Cancelled;
+        order.cancelled_at = env.ledger().timestamp();
+        Self::release_order_lock(env, order);
+    }
+
+    /// Cancel the open orders left in a one-cancels-other group, adding
+    /// their commitments to `cancelled`
+    fn cancel_oco_siblings(env: &Env, group: &BytesN<32>, cancelled: &mut Vec<BytesN<32>>) {
+        for mut sibling in Self::get_oco_group(env.clone(), group.clone()).iter() {
+            Self::cancel_open_order(env, &mut sibling);
+            Self::store_order(env, &sibling);
+            cancelled.push_back(sibling.commitment);
+        }
+    }
+
+    /// Apply `update` to one of the trader's own orders
+    fn update_own_order(
+        env: &Env,
+        trader: &Address,
+        commitment: &BytesN<32>,
+        update: impl FnOnce(&mut OrderCommitment),
+    ) -> Result<(), OrderbookError> {
+        let mut order = Self::load_order(env, commitment).ok_or(OrderbookError::OrderNotFound)?;
+        if order.trader != *trader {
+            return Err(OrderbookError::UnauthorizedCancellation);
+        }
+        update(&mut order);
+        Self::store_order(env, &order);
+        Ok(())
+    }
+
+    /// Check an order's expected root is within `max_root_lag` insertions of
+    /// the registry's whitelist
+    fn require_fresh_root(env: &Env, order: &OrderCommitment) -> Result<(), OrderbookError> {
+        let max_lag = Self::get_config(env.clone()).max_root_lag;
+        if order.expected_root.is_none() || max_lag == 0 {
+            return Ok(());
+        }
+        let registry = registry_wasm::Client::new(env, &Self::get_registry(env.clone()));
+        let lag = registry
+            .get_whitelist_count()
+            .saturating_sub(order.expected_root_index.saturating_add(1));
+        if lag > max_lag {
+            return Err(OrderbookError::RootTooStale);
+        }
+        Ok(())
+    }
+
+    /// Check a match price against a pegged order's mid-derived limit
+    fn require_within_peg(env: &Env, order: &OrderCommitment, price: i128) -> Result<(), OrderbookError> {
+        let Some(offset) = order.peg_offset else {
+            return Ok(());
+        };
+        let top = Self::get_top_of_book(env.clone(), order.asset_address.clone())
+            .ok_or(OrderbookError::TopOfBookNotPublished)?;
+        let mid = top
+            .best_bid
+            .checked_add(top.best_ask)
+            .map(|sum| sum / 2)
+            .ok_or(OrderbookError::ArithmeticOverflow)?;
+        let limit = mid.checked_add(offset).ok_or(OrderbookError::ArithmeticOverflow)?;
+        if limit <= 0 {
+            return Err(OrderbookError::PegPriceNotPositive);
+        }
+
+        let within = match order.side {
+            OrderSide::Buy => price <= limit,
+            OrderSide::Sell => price >= limit,
+        };
+        if !within {
+            return Err(OrderbookError::PriceBeyondPeg);
+        }
+        Ok(())
+    }
+
+    /// Reject a match whose cash leg would need rounding
+    fn require_exact_settlement(
+        env: &Env,
+        asset_address: &Address,
+        quantity: i128,
+        price: i128,
+    ) -> Result<(), OrderbookError> {
+        let Some(scale) = Self::get_asset_config(env.clone(), asset_address.clone()).exact_settlement_scale else {
+            return Ok(());
+        };
+        let notional = quantity.checked_mul(price).ok_or(OrderbookError::ArithmeticOverflow)?;
+        if notional % scale != 0 {
+            return Err(OrderbookError::InexactSettlement);
+        }
+        Ok(())
+    }
+
+    /// Fee owed on a match, zero during a fee holiday
+    fn compute_fee(env: &Env, quantity: i128, price: i128) -> Result<i128, OrderbookError> {
+        let now = env.ledger().timestamp();
+        let config = Self::get_config(env.clone());
+        if config.fee_holiday_start <= now && now < config.fee_holiday_end {
+            return Ok(0);
+        }
+
+        Self::bps_of_notional(quantity, price, config.fee_bps)
+    }
+
+    /// Maker rebate on a match, paid out of and capped at the fee
+    fn compute_rebate(env: &Env, quantity: i128, price: i128, fee: i128) -> Result<i128, OrderbookError> {
+        let rebate = Self::bps_of_notional(quantity, price, Self::get_config(env.clone()).maker_rebate_bps)?;
+        Ok(rebate.min(fee))
+    }
+
+    /// Basis points of a match's absolute notional, so negative-price
+    /// matches still pay
+    fn bps_of_notional(quantity: i128, price: i128, bps: u32) -> Result<i128, OrderbookError> {
+        quantity
+            .checked_mul(price)
+            .and_then(|notional| notional.checked_abs())
+            .and_then(|notional| notional.checked_mul(bps as i128))
+            .map(|scaled| scaled / BPS_DENOMINATOR)
+            .ok_or(OrderbookError::ArithmeticOverflow)
+    }
+
+    /// Record a match once the caller has been authorized
+    fn apply_match(
+        env: &Env,
+        match_id: BytesN<32>,
+        buy_commitment: BytesN<32>,
+        sell_commitment: BytesN<32>,
+        asset_address: Address,
+        buyer: Address,
+        seller: Address,
+        quantity: i128,
+        price: i128,
+        maker_side: Option<OrderSide>,
+    ) -> Result<(), OrderbookError> {
+        if Self::is_paused(env.clone()) {
+            return Err(OrderbookError::ContractPaused);
+        }
+        // Negative prices stay an opt-in per asset; a zero price never is
+        if quantity <= 0 || price == 0 {
+            return Err(OrderbookError::InvalidMatchParameters);
+        }
+        if price < 0 && !Self::get_asset_config(env.clone(), asset_address.clone()).negative_price_allowed {
+            return Err(OrderbookError::NegativePriceNotAllowed);
+        }
+        Self::require_exact_settlement(env, &asset_address, quantity, price)?;
+        // A retried call must not fill the orders a second time
+        if Self::get_match(env.clone(), match_id.clone()).is_some() {
+            return Err(OrderbookError::DuplicateMatch);
+        }
+
+        // Update order statuses
+        let (Some(buy_order), Some(sell_order)) = (
+            Self::load_order(env, &buy_commitment),
+            Self::load_order(env, &sell_commitment),
+        ) else {
+            return Err(OrderbookError::OrderNotFound);
+        };
+        // The off-chain engine may lag behind expiries and earlier fills
+        Self::require_open(env, &buy_order)?;
+        Self::require_open(env, &sell_order)?;
+        if buy_order.side != OrderSide::Buy || sell_order.side != OrderSide::Sell {
+            return Err(OrderbookError::InvalidOrderSide);
+        }
+        // Two commitments from one trader would be a wash trade
+        if buyer == seller || buy_order.trader == sell_order.trader {
+            return Err(OrderbookError::SelfMatchNotAllowed);
+        }
+        Self::require_within_peg(env, &buy_order, price)?;
+        Self::require_within_peg(env, &sell_order, price)?;
+        Self::require_fresh_root(env, &buy_order)?;
+        Self::require_fresh_root(env, &sell_order)?;
+        let exclude_from_stats = buy_order.exclude_from_stats || sell_order.exclude_from_stats;
+
+        let sell_asset = sell_order.asset_address.clone();
+        if sell_asset != asset_address
+            && !Self::is_pair_tradable(env.clone(), asset_address.clone(), sell_asset.clone())
+        {
+            return Err(OrderbookError::PairNotTradable);
+        }
+        Self::store_order(env, &Self::fill_order(env, buy_order.clone(), &asset_address, quantity)?);
+        Self::store_order(env, &Self::fill_order(env, sell_order.clone(), &sell_asset, quantity)?);
+
+        // Without a designation the order that rested first is the maker
+        let maker_side = maker_side.unwrap_or(
+            if (sell_order.timestamp, sell_order.tree_index) < (buy_order.timestamp, buy_order.tree_index) {
+                OrderSide::Sell
+            } else {
+                OrderSide::Buy
+            },
+        );
+        let fee = Self::compute_fee(env, quantity, price)?;
+        let rebate = Self::compute_rebate(env, quantity, price, fee)?;
+
+        if !exclude_from_stats {
+            let mut stats = Self::get_asset_stats(env.clone(), asset_address.clone());
+            stats.match_count += 1;
+            stats.matched_volume = stats
+                .matched_volume
+                .checked_add(quantity)
+                .ok_or(OrderbookError::ArithmeticOverflow)?;
+            Self::set_asset_stats(env, &asset_address, &stats);
+        }
+
+        // Create match record
+        let match_record = MatchRecord {
+            match_id: match_id.clone(),
+            buy_commitment,
+            sell_commitment,
+            asset_address,
+            buyer,
+            seller,
+            quantity,
+            price,
+            timestamp: env.ledger().timestamp(),
+            is_settled: false,
+            settled_timestamp: 0,
+            fee,
+            maker_side,
+            rebate,
+            settle_attempts: 0,
+            failed: false,
+        };
+
+        let mut matches: Vec<MatchRecord> = env
+            .storage()
+            .instance()
+            .get(&MATCHES_KEY)
+            .unwrap_or(vec![env]);
+        Self::publish_match_recorded(env, &match_record, false);
+        matches.push_back(match_record);
+        env.storage().instance().set(&MATCHES_KEY, &matches);
+
+        Ok(())
+    }
+
+    fn publish_match_recorded(env: &Env, m: &MatchRecord, reemitted: bool) {
+        MatchRecorded {
+            match_id: m.match_id.clone(),
+            asset_address: m.asset_address.clone(),
+            buy_commitment: m.buy_commitment.clone(),
+            sell_commitment: m.sell_commitment.clone(),
+            quantity: m.quantity,
+            price: m.price,
+            timestamp: m.timestamp,
+            reemitted,
+            decimals: Self::asset_decimals(env, &m.asset_address),
+        }
+        .publish(env);
+    }
+
+    /// Read an asset's display decimals from the registry
+    ///
+    /// Events must not fail matching, so an unreachable registry reads as
+    /// `None` too.
+    fn asset_decimals(env: &Env, asset_address: &Address) -> Option<u32> {
+        let registry = registry_wasm::Client::new(env, &Self::get_registry(env.clone()));
+        match registry.try_get_asset_decimals(asset_address) {
+            Ok(Ok(decimals)) => decimals,
+            _ => None,
+        }
+    }
+
+    /// Flag a match and its orders as settled
+    ///
+    /// `check_window` enforces the asset's settlement interval.
+    fn settle_match_record(
+        env: &Env,
+        match_id: &BytesN<32>,
+        check_window: bool,
+    ) -> Result<(), OrderbookError> {
+        let matches: Vec<MatchRecord> = env
+            .storage()
+            .instance()
+            .get(&MATCHES_KEY)
+            .unwrap_or(vec![env]);
+
+        let mut found = false;
+        let mut updated_matches: Vec<MatchRecord> = vec![env];
+
+        for m in matches.iter() {
+            if m.match_id == *match_id {
+                if m.failed {
+                    return Err(OrderbookError::MaxRetriesExceeded);
+                }
+                if check_window {
+                    Self::require_settlement_window(env, &m)?;
+                }
+                let mut settled = m.clone();
+                settled.is_settled = true;
+                if !m.is_settled {
+                    settled.settled_timestamp = env.ledger().timestamp();
+                }
+                updated_matches.push_back(settled);
+                found = true;
+            } else {
+                updated_matches.push_back(m);
+            }
+        }
+
+        if !found {
+            return Err(OrderbookError::MatchNotFound);
+        }
+
+        env.storage().instance().set(&MATCHES_KEY, &updated_matches);
+
+        // Also update order statuses to Settled
+        let match_record = Self::get_match(env.clone(), match_id.clone()).unwrap();
+        for commitment in [match_record.buy_commitment.clone(), match_record.sell_commitment.clone()] {
+            if let Some(mut order) = Self::load_order(env, &commitment)
+                && order.status == OrderStatus::Matched
+            {
+                // A settled order's remaining lock no longer counts
+                let value = Self::lock_value(&order, order.locked_amount);
+                Self::adjust_total(env, &NOTIONAL_KEY, &order.trader, -value);
+                order.status = OrderStatus::Settled;
+                Self::store_order(env, &order);
+            }
+        }
+
+        Ok(())
     }
 
-    // Internal helper
     fn require_admin(env: &Env, caller: &Address) -> Result<(), OrderbookError> {
         let admin: Address = env.storage().instance().get(&ADMIN_KEY).unwrap();
         if *caller != admin {
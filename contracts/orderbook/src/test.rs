@@ -1,7 +1,12 @@
 #![cfg(test)]
 
 use super::*;
-use soroban_sdk::{testutils::Address as _, BytesN, Env};
+use soroban_sdk::{
+    testutils::{Address as _, Events, Ledger, MockAuth, MockAuthInvoke},
+    token::StellarAssetClient,
+    xdr::ToXdr,
+    BytesN, Env, Event, IntoVal,
+};
 
 #[test]
 fn test_constructor() {
@@ -11,13 +16,83 @@ fn test_constructor() {
     let admin = Address::generate(&env);
     let registry = Address::generate(&env);
     let settlement = Address::generate(&env);
+    let verifier = Address::generate(&env);
+    let vk_bytes = Bytes::from_slice(&env, &[0u8; 100]);
 
-    let contract_id = env.register(DarkPoolOrderbook, (&admin, &registry, &settlement));
+    let contract_id = env.register(DarkPoolOrderbook, (&admin, &registry, &settlement, &verifier, &vk_bytes, false));
     let client = DarkPoolOrderbookClient::new(&env, &contract_id);
 
     assert_eq!(client.get_admin(), admin);
     assert_eq!(client.get_registry(), registry);
     assert_eq!(client.get_settlement(), settlement);
+    assert_eq!(client.get_verifier(), verifier);
+    assert!(!client.is_test_mode());
+}
+
+#[test]
+fn test_two_step_admin_transfer() {
+    let env = Env::default();
+    env.mock_all_auths();
+    let (client, admin) = setup_orderbook(&env);
+    let new_admin = Address::generate(&env);
+    let stranger = Address::generate(&env);
+
+    let result = client.try_propose_admin(&stranger, &new_admin);
+    assert_eq!(result, Err(Ok(OrderbookError::OnlyAdmin)));
+    let result = client.try_accept_admin(&new_admin);
+    assert_eq!(result, Err(Ok(OrderbookError::NotPendingAdmin)));
+
+    client.propose_admin(&admin, &new_admin);
+    assert_eq!(client.get_pending_admin(), Some(new_admin.clone()));
+    assert_eq!(client.get_admin(), admin);
+
+    // Only the proposed address can accept
+    let result = client.try_accept_admin(&stranger);
+    assert_eq!(result, Err(Ok(OrderbookError::NotPendingAdmin)));
+
+    client.accept_admin(&new_admin);
+    assert_eq!(client.get_admin(), new_admin);
+    assert_eq!(client.get_pending_admin(), None);
+
+    // The old admin lost its rights
+    let result = client.try_set_config(&admin, &client.get_config());
+    assert_eq!(result, Err(Ok(OrderbookError::OnlyAdmin)));
+    update_config(&client, &new_admin, |c| c.fee_bps = 10);
+}
+
+#[test]
+fn test_record_match_single_auth_when_matcher_is_admin() {
+    let env = Env::default();
+    env.mock_all_auths();
+    let (client, admin) = setup_orderbook(&env);
+    let asset = Address::generate(&env);
+    let (buyer, seller) = submit_pair(&env, &client, &asset, 1, 2);
+
+    // The admin also runs the matcher: exactly one authorization covers the call
+    let args = (
+        &admin, &commitment(&env, 10), &commitment(&env, 1), &commitment(&env, 2), &asset, &buyer, &seller, 1_000i128,
+        5i128, None::<OrderSide>,
+    );
+    client
+        .mock_auths(&[MockAuth {
+            address: &admin,
+            invoke: &MockAuthInvoke {
+                contract: &client.address,
+                fn_name: "record_match",
+                args: args.into_val(&env),
+                sub_invokes: &[],
+            },
+        }])
+        .record_match(
+            &admin, &commitment(&env, 10), &commitment(&env, 1), &commitment(&env, 2), &asset, &buyer, &seller,
+            &1_000, &5, &None,
+        );
+
+    let auths = env.auths();
+    assert_eq!(auths.len(), 1);
+    assert_eq!(auths[0].0, admin);
+    assert_eq!(client.get_matches().matches.len(), 1);
+    assert_eq!(client.get_asset_stats(&asset).match_count, 1);
 }
 
 #[test]
@@ -29,15 +104,23 @@ fn test_submit_order() {
     let registry = Address::generate(&env);
     let settlement = Address::generate(&env);
 
-    let contract_id = env.register(DarkPoolOrderbook, (&admin, &registry, &settlement));
+    let contract_id = register_orderbook(&env, &admin, &registry, &settlement);
     let client = DarkPoolOrderbookClient::new(&env, &contract_id);
 
     let trader = Address::generate(&env);
     let asset = Address::generate(&env);
     let commitment = BytesN::from_array(&env, &[1u8; 32]);
 
-    let index = client.submit_order(&trader, &commitment, &asset, &OrderSide::Buy, &3600);
-    assert_eq!(index, 0);
+    env.ledger().with_mut(|l| l.timestamp = 1_000);
+    let receipt = client.submit_order(&trader, &commitment, &asset, &OrderSide::Buy, &3600, &1_000, &0, &0);
+    assert_eq!(receipt.index, 0);
+    assert_eq!(receipt.required_lock, 0);
+    assert_eq!(receipt.expiry, 4_600);
+
+    let second = BytesN::from_array(&env, &[2u8; 32]);
+    let receipt = client.submit_order(&trader, &second, &asset, &OrderSide::Sell, &60, &1_000, &0, &0);
+    assert_eq!(receipt.index, 1);
+    assert_eq!(receipt.expiry, 1_060);
 
     let order = client.get_order(&commitment);
     assert!(order.is_some());
@@ -47,6 +130,43 @@ fn test_submit_order() {
     assert_eq!(order.status, OrderStatus::Active);
 }
 
+#[test]
+fn test_submit_orders_batch() {
+    let env = Env::default();
+    env.mock_all_auths();
+    let (client, _admin) = setup_orderbook(&env);
+    let trader = Address::generate(&env);
+    let asset = Address::generate(&env);
+    client.submit_order(&trader, &commitment(&env, 1), &asset, &OrderSide::Buy, &3600, &1_000, &0, &0);
+
+    let batch = vec![
+        &env,
+        commitment(&env, 2),
+        commitment(&env, 3),
+        commitment(&env, 4),
+        commitment(&env, 5),
+        commitment(&env, 6),
+    ];
+    let indices = client.submit_orders_batch(&trader, &batch, &asset, &OrderSide::Sell, &3600, &500, &0, &0);
+    assert_eq!(indices, vec![&env, 1u32, 2, 3, 4, 5]);
+    for id in 2..=6u8 {
+        let order = client.get_order(&commitment(&env, id)).unwrap();
+        assert_eq!(order.side, OrderSide::Sell);
+        assert_eq!(order.total_quantity, 500);
+    }
+
+    // A duplicate anywhere in the batch rolls back the orders before it
+    let batch = vec![&env, commitment(&env, 7), commitment(&env, 8), commitment(&env, 7)];
+    let result = client.try_submit_orders_batch(&trader, &batch, &asset, &OrderSide::Buy, &3600, &500, &0, &0);
+    assert_eq!(result, Err(Ok(OrderbookError::DuplicateCommitment)));
+    let batch = vec![&env, commitment(&env, 9), commitment(&env, 1)];
+    let result = client.try_submit_orders_batch(&trader, &batch, &asset, &OrderSide::Buy, &3600, &500, &0, &0);
+    assert_eq!(result, Err(Ok(OrderbookError::DuplicateCommitment)));
+    assert!(client.get_order(&commitment(&env, 7)).is_none());
+    assert!(client.get_order(&commitment(&env, 9)).is_none());
+    assert_eq!(client.get_active_orders(&asset).len(), 6);
+}
+
 #[test]
 fn test_cancel_order() {
     let env = Env::default();
@@ -56,24 +176,82 @@ fn test_cancel_order() {
     let registry = Address::generate(&env);
     let settlement = Address::generate(&env);
 
-    let contract_id = env.register(DarkPoolOrderbook, (&admin, &registry, &settlement));
+    let contract_id = register_orderbook(&env, &admin, &registry, &settlement);
     let client = DarkPoolOrderbookClient::new(&env, &contract_id);
 
     let trader = Address::generate(&env);
     let asset = Address::generate(&env);
     let commitment = BytesN::from_array(&env, &[1u8; 32]);
 
-    client.submit_order(&trader, &commitment, &asset, &OrderSide::Buy, &3600);
+    client.submit_order(&trader, &commitment, &asset, &OrderSide::Buy, &3600, &1_000, &0, &0);
 
     // Cancel the order
-    let proof = Bytes::from_slice(&env, &[0u8; 100]);
-    let signals = Bytes::from_slice(&env, &[0u8; 100]);
-    client.cancel_order(&trader, &commitment, &proof, &signals);
+    client.cancel_order(&trader, &commitment, &ownership_proof(&env), &ownership_signals(&env, &commitment));
 
     let order = client.get_order(&commitment).unwrap();
     assert_eq!(order.status, OrderStatus::Cancelled);
 }
 
+#[test]
+fn test_cancel_order_verifies_ownership_proof() {
+    let env = Env::default();
+    env.mock_all_auths();
+    let (client, _admin) = setup_orderbook(&env);
+
+    let trader = Address::generate(&env);
+    let asset = Address::generate(&env);
+    client.submit_order(&trader, &commitment(&env, 1), &asset, &OrderSide::Buy, &3600, &1_000, &0, &0);
+    client.submit_order(&trader, &commitment(&env, 2), &asset, &OrderSide::Buy, &3600, &1_000, &0, &0);
+
+    // The verifier rejects the proof
+    let bad_proof = Bytes::from_slice(&env, &[0u8; 4]);
+    assert!(!client.cancel_order(&trader, &commitment(&env, 1), &bad_proof, &ownership_signals(&env, &commitment(&env, 1))));
+
+    // A valid proof for order 2 cannot cancel order 1
+    let result = client.try_cancel_order(&trader, &commitment(&env, 1), &ownership_proof(&env), &ownership_signals(&env, &commitment(&env, 2)));
+    assert_eq!(result, Err(Ok(OrderbookError::InvalidProof)));
+
+    // Malformed signals are rejected too
+    let result = client.try_cancel_order(&trader, &commitment(&env, 1), &ownership_proof(&env), &Bytes::new(&env));
+    assert_eq!(result, Err(Ok(OrderbookError::InvalidProof)));
+    assert_eq!(client.get_order(&commitment(&env, 1)).unwrap().status, OrderStatus::Active);
+
+    // The verifier accepts a proof bound to the right commitment
+    assert!(client.cancel_order(&trader, &commitment(&env, 1), &ownership_proof(&env), &ownership_signals(&env, &commitment(&env, 1))));
+    assert_eq!(client.get_order(&commitment(&env, 1)).unwrap().status, OrderStatus::Cancelled);
+    assert_eq!(client.get_order(&commitment(&env, 2)).unwrap().status, OrderStatus::Active);
+}
+
+#[test]
+fn test_test_mode_bypasses_ownership_proof() {
+    let env = Env::default();
+    env.mock_all_auths();
+
+    let admin = Address::generate(&env);
+    let verifier = env.register(MockVerifier, ());
+    let vk_bytes = Bytes::from_slice(&env, &[0u8; 100]);
+    let contract_id = env.register(
+        DarkPoolOrderbook,
+        (&admin, &Address::generate(&env), &Address::generate(&env), &verifier, &vk_bytes, true),
+    );
+    let client = DarkPoolOrderbookClient::new(&env, &contract_id);
+    assert!(client.is_test_mode());
+
+    let trader = Address::generate(&env);
+    let asset = Address::generate(&env);
+    client.submit_order(&trader, &commitment(&env, 1), &asset, &OrderSide::Buy, &3600, &1_000, &0, &0);
+
+    // A proof the verifier would reject still cancels, and says so
+    let bad_proof = Bytes::from_slice(&env, &[0u8; 4]);
+    client.cancel_order(&trader, &commitment(&env, 1), &bad_proof, &Bytes::new(&env));
+    let bypass = TestModeBypass {
+        commitment: commitment(&env, 1),
+    };
+    let events = env.events().all().filter_by_contract(&contract_id);
+    assert_eq!(events.events().first(), Some(&bypass.to_xdr(&env, &contract_id)));
+    assert_eq!(client.get_order(&commitment(&env, 1)).unwrap().status, OrderStatus::Cancelled);
+}
+
 #[test]
 fn test_record_match() {
     let env = Env::default();
@@ -83,7 +261,7 @@ fn test_record_match() {
     let registry = Address::generate(&env);
     let settlement = Address::generate(&env);
 
-    let contract_id = env.register(DarkPoolOrderbook, (&admin, &registry, &settlement));
+    let contract_id = register_orderbook(&env, &admin, &registry, &settlement);
     let client = DarkPoolOrderbookClient::new(&env, &contract_id);
 
     let buyer = Address::generate(&env);
@@ -94,8 +272,8 @@ fn test_record_match() {
     let match_id = BytesN::from_array(&env, &[3u8; 32]);
 
     // Submit both orders
-    client.submit_order(&buyer, &buy_commitment, &asset, &OrderSide::Buy, &3600);
-    client.submit_order(&seller, &sell_commitment, &asset, &OrderSide::Sell, &3600);
+    client.submit_order(&buyer, &buy_commitment, &asset, &OrderSide::Buy, &3600, &1_000, &0, &0);
+    client.submit_order(&seller, &sell_commitment, &asset, &OrderSide::Sell, &3600, &1_000, &0, &0);
 
     // Record match
     client.record_match(
@@ -108,6 +286,7 @@ fn test_record_match() {
         &seller,
         &1000,
         &50000,
+        &None,
     );
 
     // Check orders are marked as matched
@@ -131,7 +310,7 @@ fn test_get_active_orders() {
     let registry = Address::generate(&env);
     let settlement = Address::generate(&env);
 
-    let contract_id = env.register(DarkPoolOrderbook, (&admin, &registry, &settlement));
+    let contract_id = register_orderbook(&env, &admin, &registry, &settlement);
     let client = DarkPoolOrderbookClient::new(&env, &contract_id);
 
     let trader = Address::generate(&env);
@@ -142,7 +321,7 @@ fn test_get_active_orders() {
         let mut commitment_arr = [0u8; 32];
         commitment_arr[0] = i;
         let commitment = BytesN::from_array(&env, &commitment_arr);
-        client.submit_order(&trader, &commitment, &asset, &OrderSide::Buy, &3600);
+        client.submit_order(&trader, &commitment, &asset, &OrderSide::Buy, &3600, &1_000, &0, &0);
     }
 
     let active_orders = client.get_active_orders(&asset);
@@ -158,7 +337,7 @@ fn test_get_orders_by_side() {
     let registry = Address::generate(&env);
     let settlement = Address::generate(&env);
 
-    let contract_id = env.register(DarkPoolOrderbook, (&admin, &registry, &settlement));
+    let contract_id = register_orderbook(&env, &admin, &registry, &settlement);
     let client = DarkPoolOrderbookClient::new(&env, &contract_id);
 
     let trader = Address::generate(&env);
@@ -169,7 +348,7 @@ fn test_get_orders_by_side() {
         let mut commitment_arr = [0u8; 32];
         commitment_arr[0] = i;
         let commitment = BytesN::from_array(&env, &commitment_arr);
-        client.submit_order(&trader, &commitment, &asset, &OrderSide::Buy, &3600);
+        client.submit_order(&trader, &commitment, &asset, &OrderSide::Buy, &3600, &1_000, &0, &0);
     }
 
     // Submit sell orders
@@ -177,12 +356,2966 @@ fn test_get_orders_by_side() {
         let mut commitment_arr = [0u8; 32];
         commitment_arr[0] = i;
         let commitment = BytesN::from_array(&env, &commitment_arr);
-        client.submit_order(&trader, &commitment, &asset, &OrderSide::Sell, &3600);
+        client.submit_order(&trader, &commitment, &asset, &OrderSide::Sell, &3600, &1_000, &0, &0);
+    }
+
+    let buy_orders = client.get_orders_by_asset(&asset, &Some(OrderSide::Buy), &None);
+    let sell_orders = client.get_orders_by_asset(&asset, &Some(OrderSide::Sell), &None);
+
+    assert_eq!(buy_orders.orders.len(), 3);
+    assert_eq!(sell_orders.orders.len(), 2);
+    assert!(!buy_orders.truncated);
+}
+
+#[test]
+fn test_get_orders_by_asset_paged() {
+    let env = Env::default();
+    env.mock_all_auths();
+    let (client, _admin) = setup_orderbook(&env);
+    let trader = Address::generate(&env);
+    let asset = Address::generate(&env);
+
+    // 20 buys interleaved with sells, which the side filter skips
+    for i in 0..20u8 {
+        client.submit_order(&trader, &commitment(&env, i), &asset, &OrderSide::Buy, &3600, &1_000, &0, &0);
+        client.submit_order(&trader, &commitment(&env, 100 + i), &asset, &OrderSide::Sell, &3600, &1_000, &0, &0);
+    }
+
+    let buys = Some(OrderSide::Buy);
+    let first = client.get_orders_by_asset_paged(&asset, &buys, &None, &0, &10);
+    let second = client.get_orders_by_asset_paged(&asset, &buys, &None, &10, &10);
+    assert_eq!(first.len(), 10);
+    assert_eq!(second.len(), 10);
+    for i in 0..10u8 {
+        assert_eq!(first.get(i as u32).unwrap().commitment, commitment(&env, i));
+        assert_eq!(second.get(i as u32).unwrap().commitment, commitment(&env, 10 + i));
+    }
+    assert_eq!(client.get_orders_by_asset_paged(&asset, &buys, &None, &20, &10).len(), 0);
+
+    let cancelled = client.get_orders_by_asset_paged(&asset, &buys, &Some(OrderStatus::Cancelled), &0, &10);
+    assert_eq!(cancelled.len(), 0);
+}
+
+#[test]
+fn test_get_orders_by_trader_paged() {
+    let env = Env::default();
+    env.mock_all_auths();
+    let (client, _admin) = setup_orderbook(&env);
+    let trader = Address::generate(&env);
+    let other = Address::generate(&env);
+    let asset = Address::generate(&env);
+
+    // Another trader's orders in between are not listed
+    for i in 0..5u8 {
+        client.submit_order(&trader, &commitment(&env, i), &asset, &OrderSide::Buy, &3600, &1_000, &0, &0);
+        client.submit_order(&other, &commitment(&env, 100 + i), &asset, &OrderSide::Sell, &3600, &1_000, &0, &0);
+    }
+
+    let first = client.get_orders_by_trader_paged(&trader, &0, &3);
+    let second = client.get_orders_by_trader_paged(&trader, &3, &3);
+    assert_eq!(first.len(), 3);
+    assert_eq!(second.len(), 2);
+    assert_eq!(first.get(0).unwrap().commitment, commitment(&env, 0));
+    assert_eq!(second.get(1).unwrap().commitment, commitment(&env, 4));
+    assert_eq!(client.get_orders_by_trader_paged(&trader, &5, &3).len(), 0);
+}
+
+fn setup_orderbook(env: &Env) -> (DarkPoolOrderbookClient<'_>, Address) {
+    let admin = Address::generate(env);
+    let registry = Address::generate(env);
+    let settlement = Address::generate(env);
+
+    let contract_id = register_orderbook(env, &admin, &registry, &settlement);
+    (DarkPoolOrderbookClient::new(env, &contract_id), admin)
+}
+
+/// Register an orderbook whose ownership verifier is `MockVerifier`
+fn register_orderbook(env: &Env, admin: &Address, registry: &Address, settlement: &Address) -> Address {
+    let verifier = env.register(MockVerifier, ());
+    let vk_bytes = Bytes::from_slice(env, &[0u8; 100]);
+    env.register(DarkPoolOrderbook, (admin, registry, settlement, &verifier, &vk_bytes, false))
+}
+
+/// A proof `MockVerifier` accepts
+fn ownership_proof(env: &Env) -> Bytes {
+    Bytes::from_slice(env, &[1u8; 4])
+}
+
+/// Public signals binding an ownership proof to `commitment`
+fn ownership_signals(env: &Env, commitment: &BytesN<32>) -> Bytes {
+    let mut signals = Bytes::from_slice(env, &1u32.to_be_bytes());
+    signals.append(&Bytes::from_slice(env, &commitment.to_array()));
+    signals
+}
+
+fn commitment(env: &Env, id: u8) -> BytesN<32> {
+    BytesN::from_array(env, &[id; 32])
+}
+
+fn update_config(client: &DarkPoolOrderbookClient, admin: &Address, update: impl FnOnce(&mut OrderbookConfig)) {
+    let mut config = client.get_config();
+    update(&mut config);
+    client.set_config(admin, &config);
+}
+
+fn update_asset_config(
+    client: &DarkPoolOrderbookClient,
+    admin: &Address,
+    asset: &Address,
+    update: impl FnOnce(&mut AssetConfig),
+) {
+    let mut config = client.get_asset_config(asset);
+    update(&mut config);
+    client.set_asset_config(admin, asset, &config);
+}
+
+#[test]
+fn test_unpaged_getters_signal_truncation() {
+    let env = Env::default();
+    env.mock_all_auths();
+    // Seeding past the cap outgrows the single instance storage entry
+    env.cost_estimate().budget().reset_unlimited();
+    env.cost_estimate().disable_resource_limits();
+    let (client, admin) = setup_orderbook(&env);
+
+    let buyer = Address::generate(&env);
+    let seller = Address::generate(&env);
+    let asset = Address::generate(&env);
+
+    for i in 0..=MAX_RETURN_ENTRIES {
+        let mut commitment_arr = [0u8; 32];
+        commitment_arr[0..4].copy_from_slice(&i.to_be_bytes());
+        let commitment = BytesN::from_array(&env, &commitment_arr);
+        client.submit_order(&buyer, &commitment, &asset, &OrderSide::Buy, &3600, &1_000, &0, &0);
+    }
+
+    let list = client.get_orders_by_asset(&asset, &None, &None);
+    assert_eq!(list.orders.len(), MAX_RETURN_ENTRIES);
+    assert!(list.truncated);
+
+    // Matches: exactly at the cap is not truncated
+    let sell_commitment = commitment(&env, 0xff);
+    client.submit_order(&seller, &sell_commitment, &asset, &OrderSide::Sell, &3600, &1_000, &0, &0);
+    let buy_commitment = client.get_orders_by_asset(&asset, &Some(OrderSide::Buy), &None).orders.get(0).unwrap().commitment;
+    for i in 0..MAX_RETURN_ENTRIES {
+        let mut match_arr = [0xeeu8; 32];
+        match_arr[0..4].copy_from_slice(&i.to_be_bytes());
+        let match_id = BytesN::from_array(&env, &match_arr);
+        client.record_match(
+            &admin, &match_id, &buy_commitment, &sell_commitment, &asset, &buyer, &seller, &1, &1, &None,
+        );
+    }
+    let list = client.get_matches();
+    assert_eq!(list.matches.len(), MAX_RETURN_ENTRIES);
+    assert!(!list.truncated);
+
+    client.record_match(
+        &admin, &commitment(&env, 0xee), &buy_commitment, &sell_commitment, &asset, &buyer, &seller, &1, &1, &None,
+    );
+    let list = client.get_matches();
+    assert_eq!(list.matches.len(), MAX_RETURN_ENTRIES);
+    assert!(list.truncated);
+
+    // The match past the cap is reachable by paging
+    let page = client.get_matches_paged(&MAX_RETURN_ENTRIES, &10);
+    assert_eq!(page.len(), 1);
+    assert_eq!(page.get(0).unwrap().match_id, commitment(&env, 0xee));
+    assert_eq!(client.get_matches_paged(&0, &(MAX_RETURN_ENTRIES + 1)).len(), MAX_RETURN_ENTRIES);
+    assert_eq!(client.get_matches_paged(&(MAX_RETURN_ENTRIES + 1), &10).len(), 0);
+}
+
+fn submit_pair(
+    env: &Env,
+    client: &DarkPoolOrderbookClient,
+    asset: &Address,
+    buy_id: u8,
+    sell_id: u8,
+) -> (Address, Address) {
+    let buyer = Address::generate(env);
+    let seller = Address::generate(env);
+    client.submit_order(&buyer, &commitment(env, buy_id), asset, &OrderSide::Buy, &100_000, &1_000, &0, &0);
+    client.submit_order(&seller, &commitment(env, sell_id), asset, &OrderSide::Sell, &100_000, &1_000, &0, &0);
+    (buyer, seller)
+}
+
+#[test]
+fn test_fee_holiday() {
+    let env = Env::default();
+    env.mock_all_auths();
+    let (client, admin) = setup_orderbook(&env);
+    let asset = Address::generate(&env);
+
+    update_config(&client, &admin, |c| c.fee_bps = 30);
+    update_config(&client, &admin, |c| {
+        c.fee_holiday_start = 1_000;
+        c.fee_holiday_end = 2_000;
+    });
+
+    // Inside the holiday: no fee
+    env.ledger().with_mut(|l| l.timestamp = 1_500);
+    let (buyer, seller) = submit_pair(&env, &client, &asset, 1, 2);
+    client.record_match(
+        &admin, &commitment(&env, 10), &commitment(&env, 1), &commitment(&env, 2), &asset, &buyer, &seller,
+        &1000, &50, &None,
+    );
+    assert_eq!(client.get_match(&commitment(&env, 10)).unwrap().fee, 0);
+
+    // After the holiday: normal fee on notional
+    env.ledger().with_mut(|l| l.timestamp = 2_000);
+    let (buyer, seller) = submit_pair(&env, &client, &asset, 3, 4);
+    client.record_match(
+        &admin, &commitment(&env, 11), &commitment(&env, 3), &commitment(&env, 4), &asset, &buyer, &seller,
+        &1000, &50, &None,
+    );
+    assert_eq!(client.get_match(&commitment(&env, 11)).unwrap().fee, 150);
+
+    let mut config = client.get_config();
+    config.fee_holiday_start = 5;
+    config.fee_holiday_end = 4;
+    assert_eq!(client.try_set_config(&admin, &config), Err(Ok(OrderbookError::InvalidFeeConfig)));
+    let mut config = client.get_config();
+    config.fee_bps = 10_001;
+    assert_eq!(client.try_set_config(&admin, &config), Err(Ok(OrderbookError::InvalidFeeConfig)));
+    let mut config = client.get_config();
+    config.maker_rebate_bps = 10_001;
+    assert_eq!(client.try_set_config(&admin, &config), Err(Ok(OrderbookError::InvalidFeeConfig)));
+}
+
+#[test]
+fn test_record_match_maker_designation() {
+    let env = Env::default();
+    env.mock_all_auths();
+    let (client, admin) = setup_orderbook(&env);
+    let asset = Address::generate(&env);
+
+    update_config(&client, &admin, |c| {
+        c.fee_bps = 25;
+        c.maker_rebate_bps = 10;
+    });
+
+    // Buyer designated maker: the seller pays the fee, the buyer earns the rebate
+    let (buyer, seller) = submit_pair(&env, &client, &asset, 1, 2);
+    client.record_match(
+        &admin, &commitment(&env, 10), &commitment(&env, 1), &commitment(&env, 2), &asset, &buyer, &seller,
+        &400, &125, &Some(OrderSide::Buy),
+    );
+    let record = client.get_match(&commitment(&env, 10)).unwrap();
+    assert_eq!(record.fee, 125);
+    assert_eq!(record.rebate, 50);
+
+    // Seller designated maker even though the buy order rested first
+    let (buyer, seller) = submit_pair(&env, &client, &asset, 3, 4);
+    client.record_match(
+        &admin, &commitment(&env, 11), &commitment(&env, 3), &commitment(&env, 4), &asset, &buyer, &seller,
+        &400, &125, &Some(OrderSide::Sell),
+    );
+    let record = client.get_match(&commitment(&env, 11)).unwrap();
+    assert_eq!(record.maker_side, OrderSide::Sell);
+    assert_eq!(record.fee, 125);
+    assert_eq!(record.rebate, 50);
+
+    // Without a designation the earlier order is the maker
+    let (buyer, seller) = submit_pair(&env, &client, &asset, 5, 6);
+    client.record_match(
+        &admin, &commitment(&env, 12), &commitment(&env, 5), &commitment(&env, 6), &asset, &buyer, &seller,
+        &400, &125, &None,
+    );
+    assert_eq!(client.get_match(&commitment(&env, 12)).unwrap().maker_side, OrderSide::Buy);
+
+    // The commitments must be on the sides they are matched as
+    let (buyer, seller) = submit_pair(&env, &client, &asset, 7, 8);
+    let result = client.try_record_match(
+        &admin, &commitment(&env, 13), &commitment(&env, 8), &commitment(&env, 7), &asset, &buyer, &seller,
+        &400, &125, &Some(OrderSide::Buy),
+    );
+    assert_eq!(result, Err(Ok(OrderbookError::InvalidOrderSide)));
+}
+
+#[test]
+fn test_submit_order_expiry_bounds() {
+    let env = Env::default();
+    env.mock_all_auths();
+    let (client, admin) = setup_orderbook(&env);
+    let asset = Address::generate(&env);
+    let trader = Address::generate(&env);
+    env.ledger().with_mut(|li| li.timestamp = 1_000);
+
+    // Zero resolves to the trader's default, which must also fit the cap
+    let receipt = client.submit_order(&trader, &commitment(&env, 1), &asset, &OrderSide::Buy, &0, &1_000, &0, &0);
+    assert_eq!(receipt.expiry, 1_000 + DEFAULT_EXPIRY_SECONDS);
+    client.set_default_expiry(&trader, &(DEFAULT_MAX_EXPIRY_SECONDS + 1));
+    let result = client.try_submit_order(&trader, &commitment(&env, 2), &asset, &OrderSide::Buy, &0, &1_000, &0, &0);
+    assert_eq!(result, Err(Ok(OrderbookError::InvalidExpiry)));
+
+    // Over the configured maximum
+    let max = DEFAULT_MAX_EXPIRY_SECONDS;
+    client.submit_order(&trader, &commitment(&env, 3), &asset, &OrderSide::Buy, &max, &1_000, &0, &0);
+    let result = client.try_submit_order(&trader, &commitment(&env, 4), &asset, &OrderSide::Buy, &(max + 1), &1_000, &0, &0);
+    assert_eq!(result, Err(Ok(OrderbookError::InvalidExpiry)));
+    update_config(&client, &admin, |c| c.max_expiry = 60);
+    assert_eq!(client.get_config().max_expiry, 60);
+    let result = client.try_submit_order(&trader, &commitment(&env, 4), &asset, &OrderSide::Buy, &61, &1_000, &0, &0);
+    assert_eq!(result, Err(Ok(OrderbookError::InvalidExpiry)));
+
+    // Overflow is rejected rather than panicking
+    update_config(&client, &admin, |c| c.max_expiry = u64::MAX);
+    let result = client.try_submit_order(&trader, &commitment(&env, 4), &asset, &OrderSide::Buy, &u64::MAX, &1_000, &0, &0);
+    assert_eq!(result, Err(Ok(OrderbookError::InvalidExpiry)));
+
+    let mut config = client.get_config();
+    config.max_expiry = 0;
+    assert_eq!(client.try_set_config(&admin, &config), Err(Ok(OrderbookError::InvalidExpiry)));
+    config.max_expiry = 60;
+    assert_eq!(client.try_set_config(&trader, &config), Err(Ok(OrderbookError::OnlyAdmin)));
+}
+
+#[test]
+fn test_submit_rejects_duplicate_commitment() {
+    let env = Env::default();
+    env.mock_all_auths();
+    let (client, _admin) = setup_orderbook(&env);
+    let asset = Address::generate(&env);
+    let trader = Address::generate(&env);
+
+    client.submit_order(&trader, &commitment(&env, 1), &asset, &OrderSide::Buy, &3600, &1_000, &0, &0);
+    let result = client.try_submit_order(&trader, &commitment(&env, 1), &asset, &OrderSide::Sell, &3600, &1_000, &0, &0);
+    assert_eq!(result, Err(Ok(OrderbookError::DuplicateCommitment)));
+    assert_eq!(client.get_order(&commitment(&env, 1)).unwrap().side, OrderSide::Buy);
+}
+
+#[test]
+fn test_cancel_among_thousands_leaves_others_untouched() {
+    let env = Env::default();
+    env.mock_all_auths();
+    env.cost_estimate().budget().reset_unlimited();
+    let (client, _admin) = setup_orderbook(&env);
+    let asset = Address::generate(&env);
+    let trader = Address::generate(&env);
+
+    let order_commitment = |i: u32| {
+        let mut bytes = [0u8; 32];
+        bytes[..4].copy_from_slice(&i.to_be_bytes());
+        BytesN::from_array(&env, &bytes)
+    };
+    client.submit_order(&trader, &order_commitment(0), &asset, &OrderSide::Buy, &3600, &1_000, &0, &0);
+
+    // Fan the submitted order out into a large book directly in storage, in
+    // batches small enough for each seeding call to stay within the limits
+    for batch in 0..100u32 {
+        env.as_contract(&client.address, || {
+            let template = DarkPoolOrderbook::load_order(&env, &order_commitment(0)).unwrap();
+            for i in (batch * 20).max(1)..(batch + 1) * 20 {
+                let mut order = template.clone();
+                order.commitment = order_commitment(i);
+                order.tree_index = i;
+                DarkPoolOrderbook::store_order(&env, &order);
+                DarkPoolOrderbook::push_index(&env, OrderIndex::Asset(asset.clone()), &order.commitment);
+            }
+        });
+    }
+    let before = client.get_active_orders_cursor(&asset, &1_230, &10);
+    assert_eq!(before.orders.len(), 10);
+
+    client.cancel_order(&trader, &order_commitment(1_234), &ownership_proof(&env), &ownership_signals(&env, &order_commitment(1_234)));
+
+    let mut expected = before.orders.clone();
+    expected.remove(4);
+    let after = client.get_active_orders_cursor(&asset, &1_230, &9);
+    assert!(after.orders == expected);
+    assert_eq!(after.next_cursor, Some(1_240));
+    assert_eq!(client.get_order(&order_commitment(1_234)).unwrap().status, OrderStatus::Cancelled);
+    assert_eq!(client.get_order(&order_commitment(1_233)).unwrap().status, OrderStatus::Active);
+    assert_eq!(client.get_order(&order_commitment(1_235)).unwrap().status, OrderStatus::Active);
+}
+
+#[test]
+fn test_record_match_rejects_self_match() {
+    let env = Env::default();
+    env.mock_all_auths();
+    let (client, admin) = setup_orderbook(&env);
+    let asset = Address::generate(&env);
+    let trader = Address::generate(&env);
+
+    client.submit_order(&trader, &commitment(&env, 1), &asset, &OrderSide::Buy, &3600, &1_000, &0, &0);
+    client.submit_order(&trader, &commitment(&env, 2), &asset, &OrderSide::Sell, &3600, &1_000, &0, &0);
+    let result = client.try_record_match(
+        &admin, &commitment(&env, 9), &commitment(&env, 1), &commitment(&env, 2), &asset, &trader, &trader,
+        &1_000, &5, &None,
+    );
+    assert_eq!(result, Err(Ok(OrderbookError::SelfMatchNotAllowed)));
+
+    // Misreporting the parties does not hide that both orders are the trader's
+    let result = client.try_record_match(
+        &admin, &commitment(&env, 9), &commitment(&env, 1), &commitment(&env, 2), &asset, &trader,
+        &Address::generate(&env), &1_000, &5, &None,
+    );
+    assert_eq!(result, Err(Ok(OrderbookError::SelfMatchNotAllowed)));
+    assert_eq!(client.get_order(&commitment(&env, 1)).unwrap().status, OrderStatus::Active);
+}
+
+#[test]
+fn test_record_match_rejects_same_side_orders() {
+    let env = Env::default();
+    env.mock_all_auths();
+    let (client, admin) = setup_orderbook(&env);
+    let asset = Address::generate(&env);
+    let buyer = Address::generate(&env);
+    let other_buyer = Address::generate(&env);
+
+    client.submit_order(&buyer, &commitment(&env, 1), &asset, &OrderSide::Buy, &3600, &1_000, &0, &0);
+    client.submit_order(&other_buyer, &commitment(&env, 2), &asset, &OrderSide::Buy, &3600, &1_000, &0, &0);
+    let result = client.try_record_match(
+        &admin, &commitment(&env, 9), &commitment(&env, 1), &commitment(&env, 2), &asset, &buyer, &other_buyer,
+        &1_000, &5, &None,
+    );
+    assert_eq!(result, Err(Ok(OrderbookError::InvalidOrderSide)));
+    assert_eq!(client.get_order(&commitment(&env, 1)).unwrap().status, OrderStatus::Active);
+    assert_eq!(client.get_order(&commitment(&env, 2)).unwrap().status, OrderStatus::Active);
+}
+
+#[test]
+fn test_record_match_rejects_closed_orders() {
+    let env = Env::default();
+    env.mock_all_auths();
+    let (client, admin) = setup_orderbook(&env);
+    let asset = Address::generate(&env);
+    let buyer = Address::generate(&env);
+    let seller = Address::generate(&env);
+
+    client.submit_order(&buyer, &commitment(&env, 1), &asset, &OrderSide::Buy, &60, &1_000, &0, &0);
+    client.submit_order(&seller, &commitment(&env, 2), &asset, &OrderSide::Sell, &3600, &1_000, &0, &0);
+    env.ledger().with_mut(|l| l.timestamp += 61);
+
+    // Expired on the ledger even though no one has swept it yet
+    let result = client.try_record_match(
+        &admin, &commitment(&env, 9), &commitment(&env, 1), &commitment(&env, 2), &asset, &buyer, &seller,
+        &1_000, &5, &None,
+    );
+    assert_eq!(result, Err(Ok(OrderbookError::OrderExpired)));
+    assert_eq!(client.get_order(&commitment(&env, 2)).unwrap().status, OrderStatus::Active);
+
+    // A fully filled order cannot be matched a second time
+    client.submit_order(&buyer, &commitment(&env, 3), &asset, &OrderSide::Buy, &3600, &1_000, &0, &0);
+    client.record_match(
+        &admin, &commitment(&env, 10), &commitment(&env, 3), &commitment(&env, 2), &asset, &buyer, &seller,
+        &1_000, &5, &None,
+    );
+    client.submit_order(&buyer, &commitment(&env, 4), &asset, &OrderSide::Buy, &3600, &1_000, &0, &0);
+    let result = client.try_record_match(
+        &admin, &commitment(&env, 11), &commitment(&env, 4), &commitment(&env, 2), &asset, &buyer, &seller,
+        &1_000, &5, &None,
+    );
+    assert_eq!(result, Err(Ok(OrderbookError::OrderAlreadyMatched)));
+}
+
+#[test]
+fn test_match_cooldown() {
+    let env = Env::default();
+    env.mock_all_auths();
+    let (client, admin) = setup_orderbook(&env);
+    let asset = Address::generate(&env);
+
+    update_config(&client, &admin, |c| c.match_cooldown = 60);
+    env.ledger().with_mut(|l| l.timestamp = 1_000);
+
+    let (buyer, seller) = submit_pair(&env, &client, &asset, 1, 2);
+    client.record_match(
+        &admin, &commitment(&env, 10), &commitment(&env, 1), &commitment(&env, 2), &asset, &buyer, &seller,
+        &100, &10, &None,
+    );
+    assert_eq!(client.get_order(&commitment(&env, 1)).unwrap().last_match_timestamp, 1_000);
+
+    // Filling the same orders again inside the cooldown is rejected
+    env.ledger().with_mut(|l| l.timestamp = 1_059);
+    let result = client.try_record_match(
+        &admin, &commitment(&env, 11), &commitment(&env, 1), &commitment(&env, 2), &asset, &buyer, &seller,
+        &100, &10, &None,
+    );
+    assert_eq!(result, Err(Ok(OrderbookError::MatchCooldown)));
+
+    // Once the cooldown has elapsed the fill goes through
+    env.ledger().with_mut(|l| l.timestamp = 1_060);
+    client.record_match(
+        &admin, &commitment(&env, 11), &commitment(&env, 1), &commitment(&env, 2), &asset, &buyer, &seller,
+        &100, &10, &None,
+    );
+    assert_eq!(client.get_order(&commitment(&env, 2)).unwrap().last_match_timestamp, 1_060);
+}
+
+#[test]
+fn test_delist_asset() {
+    let env = Env::default();
+    env.mock_all_auths();
+    let (client, admin) = setup_orderbook(&env);
+
+    let trader = Address::generate(&env);
+    let delisted = Address::generate(&env);
+    let other = Address::generate(&env);
+
+    for i in 0..5 {
+        client.submit_order(&trader, &commitment(&env, i), &delisted, &OrderSide::Buy, &3600, &1_000, &0, &0);
     }
+    client.submit_order(&trader, &commitment(&env, 9), &other, &OrderSide::Buy, &3600, &1_000, &0, &0);
+
+    assert_eq!(client.delist_asset(&admin, &delisted, &3), 3);
+    assert!(client.is_asset_delisted(&delisted));
+
+    // Halted against new orders straight away
+    let result = client.try_submit_order(&trader, &commitment(&env, 20), &delisted, &OrderSide::Sell, &3600, &1_000, &0, &0);
+    assert_eq!(result, Err(Ok(OrderbookError::AssetDelisted)));
+
+    // Second batch drains the rest, third finds nothing left
+    assert_eq!(client.delist_asset(&admin, &delisted, &3), 2);
+    assert_eq!(client.delist_asset(&admin, &delisted, &3), 0);
+    assert_eq!(client.get_active_orders(&delisted).len(), 0);
+
+    // Other assets are untouched
+    assert_eq!(client.get_active_orders(&other).len(), 1);
+    assert!(!client.is_asset_delisted(&other));
+}
+
+#[test]
+fn test_pause() {
+    let env = Env::default();
+    env.mock_all_auths();
+    let (client, admin) = setup_orderbook(&env);
+    let asset = Address::generate(&env);
+
+    let (buyer, seller) = submit_pair(&env, &client, &asset, 1, 2);
+    let result = client.try_set_paused(&buyer, &true);
+    assert_eq!(result, Err(Ok(OrderbookError::OnlyAdmin)));
+
+    client.set_paused(&admin, &true);
+    assert!(client.is_paused());
+
+    // New orders and matches are rejected
+    let result = client.try_submit_order(&buyer, &commitment(&env, 3), &asset, &OrderSide::Buy, &3600, &1_000, &0, &0);
+    assert_eq!(result, Err(Ok(OrderbookError::ContractPaused)));
+    let result = client.try_record_match(
+        &admin, &commitment(&env, 10), &commitment(&env, 1), &commitment(&env, 2), &asset, &buyer, &seller,
+        &1_000, &5, &None,
+    );
+    assert_eq!(result, Err(Ok(OrderbookError::ContractPaused)));
+
+    // Traders can still exit
+    client.cancel_order(&seller, &commitment(&env, 2), &ownership_proof(&env), &ownership_signals(&env, &commitment(&env, 2)));
+    assert_eq!(client.get_order(&commitment(&env, 2)).unwrap().status, OrderStatus::Cancelled);
+
+    client.set_paused(&admin, &false);
+    assert!(!client.is_paused());
+    client.submit_order(&buyer, &commitment(&env, 3), &asset, &OrderSide::Buy, &3600, &1_000, &0, &0);
+    assert_eq!(client.get_order(&commitment(&env, 3)).unwrap().status, OrderStatus::Active);
+}
+
+#[test]
+fn test_expire_orders_for_asset() {
+    let env = Env::default();
+    env.mock_all_auths();
+    let (client, admin) = setup_orderbook(&env);
+
+    let trader = Address::generate(&env);
+    let target = Address::generate(&env);
+    let other = Address::generate(&env);
+    for i in 0..3 {
+        client.submit_order(&trader, &commitment(&env, i), &target, &OrderSide::Buy, &3600, &1_000, &0, &0);
+    }
+    client.cancel_order(&trader, &commitment(&env, 2), &ownership_proof(&env), &ownership_signals(&env, &commitment(&env, 2)));
+    client.submit_order(&trader, &commitment(&env, 9), &other, &OrderSide::Sell, &3600, &1_000, &0, &0);
+
+    let result = client.try_expire_orders_for_asset(&trader, &target);
+    assert_eq!(result, Err(Ok(OrderbookError::OnlyAdmin)));
+
+    assert_eq!(client.expire_orders_for_asset(&admin, &target), 2);
+    assert_eq!(client.get_order(&commitment(&env, 0)).unwrap().status, OrderStatus::Expired);
+    assert_eq!(client.get_order(&commitment(&env, 1)).unwrap().status, OrderStatus::Expired);
+    assert_eq!(client.get_order(&commitment(&env, 2)).unwrap().status, OrderStatus::Cancelled);
+    assert_eq!(client.get_order(&commitment(&env, 9)).unwrap().status, OrderStatus::Active);
+    assert_eq!(client.expire_orders_for_asset(&admin, &target), 0);
+}
+
+#[test]
+fn test_record_match_rejects_invalid_parameters() {
+    let env = Env::default();
+    env.mock_all_auths();
+    let (client, admin) = setup_orderbook(&env);
+    let asset = Address::generate(&env);
+
+    let (buyer, seller) = submit_pair(&env, &client, &asset, 1, 2);
+    for (quantity, price) in [(0, 5), (-100, 5), (100, 0)] {
+        let result = client.try_record_match(
+            &admin, &commitment(&env, 10), &commitment(&env, 1), &commitment(&env, 2), &asset, &buyer, &seller,
+            &quantity, &price, &None,
+        );
+        assert_eq!(result, Err(Ok(OrderbookError::InvalidMatchParameters)));
+    }
+    let order = client.get_order(&commitment(&env, 1)).unwrap();
+    assert_eq!(order.status, OrderStatus::Active);
+    assert_eq!(order.remaining_quantity, 1_000);
+
+    client.record_match(
+        &admin, &commitment(&env, 10), &commitment(&env, 1), &commitment(&env, 2), &asset, &buyer, &seller,
+        &100, &5, &None,
+    );
+    assert_eq!(client.get_order(&commitment(&env, 1)).unwrap().remaining_quantity, 900);
+}
+
+#[test]
+fn test_record_match_rejects_duplicate_match_id() {
+    let env = Env::default();
+    env.mock_all_auths();
+    let (client, admin) = setup_orderbook(&env);
+    let asset = Address::generate(&env);
+
+    let (buyer, seller) = submit_pair(&env, &client, &asset, 1, 2);
+    client.record_match(
+        &admin, &commitment(&env, 10), &commitment(&env, 1), &commitment(&env, 2), &asset, &buyer, &seller,
+        &400, &5, &None,
+    );
+    let result = client.try_record_match(
+        &admin, &commitment(&env, 10), &commitment(&env, 1), &commitment(&env, 2), &asset, &buyer, &seller,
+        &400, &5, &None,
+    );
+    assert_eq!(result, Err(Ok(OrderbookError::DuplicateMatch)));
+    assert_eq!(client.get_order(&commitment(&env, 1)).unwrap().remaining_quantity, 600);
+    assert_eq!(client.get_matches().matches.len(), 1);
+}
+
+#[test]
+fn test_negative_price_rejected_by_default() {
+    let env = Env::default();
+    env.mock_all_auths();
+    let (client, admin) = setup_orderbook(&env);
+    let asset = Address::generate(&env);
+
+    let (buyer, seller) = submit_pair(&env, &client, &asset, 1, 2);
+    let result = client.try_record_match(
+        &admin, &commitment(&env, 10), &commitment(&env, 1), &commitment(&env, 2), &asset, &buyer, &seller,
+        &100, &-5, &None,
+    );
+    assert_eq!(result, Err(Ok(OrderbookError::NegativePriceNotAllowed)));
+    assert_eq!(client.get_order(&commitment(&env, 1)).unwrap().status, OrderStatus::Active);
+}
+
+#[test]
+fn test_negative_price_allowed_for_asset() {
+    let env = Env::default();
+    env.mock_all_auths();
+    let (client, admin) = setup_orderbook(&env);
+    let asset = Address::generate(&env);
+
+    update_config(&client, &admin, |c| c.fee_bps = 100);
+    update_asset_config(&client, &admin, &asset, |c| c.negative_price_allowed = true);
+    assert!(client.get_asset_config(&asset).negative_price_allowed);
+
+    let (buyer, seller) = submit_pair(&env, &client, &asset, 1, 2);
+    client.record_match(
+        &admin, &commitment(&env, 10), &commitment(&env, 1), &commitment(&env, 2), &asset, &buyer, &seller,
+        &100, &-5, &None,
+    );
+
+    // Fee is charged on the absolute notional
+    assert_eq!(client.get_match(&commitment(&env, 10)).unwrap().fee, 5);
+}
+
+#[test]
+fn test_sweep_expired() {
+    let env = Env::default();
+    env.mock_all_auths();
+    let (client, _admin) = setup_orderbook(&env);
+    let asset = Address::generate(&env);
+    let trader = Address::generate(&env);
+
+    for id in 1..=3u8 {
+        client.submit_order(&trader, &commitment(&env, id), &asset, &OrderSide::Buy, &60, &1_000, &0, &0);
+    }
+    client.submit_order(&trader, &commitment(&env, 4), &asset, &OrderSide::Sell, &3_600, &1_000, &0, &0);
+    assert_eq!(client.sweep_expired(&asset, &10), 0);
+
+    env.ledger().with_mut(|li| li.timestamp += 61);
+
+    // Capped work: page through the expired orders
+    assert_eq!(client.sweep_expired(&asset, &2), 2);
+    assert_eq!(client.sweep_expired(&asset, &2), 1);
+    assert_eq!(client.sweep_expired(&asset, &2), 0);
+
+    for id in 1..=3u8 {
+        assert_eq!(client.get_order(&commitment(&env, id)).unwrap().status, OrderStatus::Expired);
+    }
+    assert_eq!(client.get_order(&commitment(&env, 4)).unwrap().status, OrderStatus::Active);
+}
+
+#[test]
+fn test_order_counts() {
+    let env = Env::default();
+    env.mock_all_auths();
+    let (client, admin) = setup_orderbook(&env);
+    let asset_a = Address::generate(&env);
+    let asset_b = Address::generate(&env);
+    let trader = Address::generate(&env);
+    assert_eq!(client.get_order_count(), 0);
+    assert_eq!(client.get_order_count_by_asset(&asset_a), 0);
+
+    for id in 1..=3u8 {
+        client.submit_order(&trader, &commitment(&env, id), &asset_a, &OrderSide::Buy, &60, &1_000, &0, &0);
+    }
+    client.submit_order(&trader, &commitment(&env, 4), &asset_b, &OrderSide::Sell, &3_600, &1_000, &0, &0);
+    client.submit_order(&trader, &commitment(&env, 5), &asset_b, &OrderSide::Sell, &3_600, &1_000, &0, &0);
+    assert_eq!(client.get_order_count(), 5);
+    assert_eq!(client.get_order_count_by_asset(&asset_a), 3);
+    assert_eq!(client.get_order_count_by_asset(&asset_b), 2);
+
+    client.cancel_order(&trader, &commitment(&env, 1), &ownership_proof(&env), &ownership_signals(&env, &commitment(&env, 1)));
+    assert_eq!(client.get_order_count_by_asset(&asset_a), 2);
+    assert_eq!(client.get_order_count(), 4);
+
+    // Expiry drops orders too, and cancelling an expired order does not count it twice
+    env.ledger().with_mut(|li| li.timestamp += 61);
+    assert_eq!(client.sweep_expired(&asset_a, &10), 2);
+    assert_eq!(client.get_order_count_by_asset(&asset_a), 0);
+    client.cancel_order(&trader, &commitment(&env, 2), &ownership_proof(&env), &ownership_signals(&env, &commitment(&env, 2)));
+    assert_eq!(client.get_order_count(), 2);
+
+    assert_eq!(client.global_cancel(&admin, &10), 2);
+    assert_eq!(client.get_order_count_by_asset(&asset_b), 0);
+    assert_eq!(client.get_order_count(), 0);
+
+    // Only a full fill takes an order off the book, and reverting it puts
+    // the order back
+    let (buyer, seller) = submit_pair(&env, &client, &asset_a, 6, 7);
+    client.record_match(
+        &admin, &commitment(&env, 10), &commitment(&env, 6), &commitment(&env, 7), &asset_a, &buyer, &seller,
+        &400, &5, &None,
+    );
+    assert_eq!(client.get_order_count_by_asset(&asset_a), 2);
+    client.record_match(
+        &admin, &commitment(&env, 11), &commitment(&env, 6), &commitment(&env, 7), &asset_a, &buyer, &seller,
+        &600, &5, &None,
+    );
+    assert_eq!(client.get_order_count_by_asset(&asset_a), 0);
+    client.revert_match(&admin, &commitment(&env, 11));
+    assert_eq!(client.get_order_count_by_asset(&asset_a), 2);
+}
+
+#[test]
+fn test_oco_group() {
+    let env = Env::default();
+    env.mock_all_auths();
+    let (client, _) = setup_orderbook(&env);
+    let asset = Address::generate(&env);
+    let trader = Address::generate(&env);
+    let group = BytesN::from_array(&env, &[7u8; 32]);
+
+    for i in 1..=4u8 {
+        client.submit_order(&trader, &commitment(&env, i), &asset, &OrderSide::Sell, &3600, &1_000, &0, &0);
+    }
+    for i in 1..=3u8 {
+        client.set_oco_group(&trader, &commitment(&env, i), &Some(group.clone()));
+    }
+
+    let linked = client.get_oco_group(&group);
+    assert_eq!(linked.len(), 3);
+    for (order, id) in linked.iter().zip(1..=3u8) {
+        assert_eq!(order.commitment, commitment(&env, id));
+    }
+
+    // Another trader cannot join the group
+    let outsider = Address::generate(&env);
+    client.submit_order(&outsider, &commitment(&env, 5), &asset, &OrderSide::Sell, &3600, &1_000, &0, &0);
+    let result = client.try_set_oco_group(&outsider, &commitment(&env, 5), &Some(group.clone()));
+    assert_eq!(result, Err(Ok(OrderbookError::UnauthorizedCancellation)));
+
+    // Cancelling one leg takes down its siblings but no unlinked order
+    client.cancel_order(&trader, &commitment(&env, 2), &ownership_proof(&env), &ownership_signals(&env, &commitment(&env, 2)));
+    for i in 1..=3u8 {
+        assert_eq!(client.get_order(&commitment(&env, i)).unwrap().status, OrderStatus::Cancelled);
+    }
+    assert_eq!(client.get_order(&commitment(&env, 4)).unwrap().status, OrderStatus::Active);
+    assert_eq!(client.get_order_count_by_asset(&asset), 2);
+    assert_eq!(client.get_oco_group(&group).len(), 0);
+}
+
+#[test]
+fn test_max_root_lag() {
+    let env = Env::default();
+    env.mock_all_auths();
+    let admin = Address::generate(&env);
+    let registry_id = env.register(MockRegistry, ());
+    let registry = MockRegistryClient::new(&env, &registry_id);
+    let client = DarkPoolOrderbookClient::new(&env, &register_orderbook(&env, &admin, &registry_id, &Address::generate(&env)));
+    let asset = Address::generate(&env);
+
+    registry.set_whitelist_count(&5);
+    update_config(&client, &admin, |c| c.max_root_lag = 3);
+    let (buyer, seller) = submit_pair(&env, &client, &asset, 1, 2);
+    client.set_expected_root(&buyer, &commitment(&env, 1), &Some(4));
+    let order = client.get_order(&commitment(&env, 1)).unwrap();
+    assert_eq!(order.expected_root, Some(BytesN::from_array(&env, &[4u8; 32])));
+    assert_eq!(order.expected_root_index, 4);
+    let result = client.try_set_expected_root(&buyer, &commitment(&env, 1), &Some(5));
+    assert_eq!(result, Err(Ok(OrderbookError::RootTooStale)));
+
+    // Three insertions behind is still within the lag
+    registry.set_whitelist_count(&8);
+    client.record_match(
+        &admin, &commitment(&env, 10), &commitment(&env, 1), &commitment(&env, 2), &asset, &buyer, &seller,
+        &400, &5, &None,
+    );
+
+    // A fourth insertion ages the root past it
+    registry.set_whitelist_count(&9);
+    let result = client.try_record_match(
+        &admin, &commitment(&env, 11), &commitment(&env, 1), &commitment(&env, 2), &asset, &buyer, &seller,
+        &400, &5, &None,
+    );
+    assert_eq!(result, Err(Ok(OrderbookError::RootTooStale)));
+
+    // Unpinned orders and a disabled check are unaffected
+    update_config(&client, &admin, |c| c.max_root_lag = 0);
+    client.record_match(
+        &admin, &commitment(&env, 11), &commitment(&env, 1), &commitment(&env, 2), &asset, &buyer, &seller,
+        &400, &5, &None,
+    );
+    update_config(&client, &admin, |c| c.max_root_lag = 3);
+    client.set_expected_root(&buyer, &commitment(&env, 1), &None);
+    client.record_match(
+        &admin, &commitment(&env, 12), &commitment(&env, 1), &commitment(&env, 2), &asset, &buyer, &seller,
+        &200, &5, &None,
+    );
+}
+
+#[test]
+fn test_extend_expiries_batch() {
+    let env = Env::default();
+    env.mock_all_auths();
+    let (client, _admin) = setup_orderbook(&env);
+    let asset = Address::generate(&env);
+    let trader = Address::generate(&env);
+    let other = Address::generate(&env);
+    env.ledger().with_mut(|li| li.timestamp = 1_000);
+
+    for id in 1..=3u8 {
+        client.submit_order(&trader, &commitment(&env, id), &asset, &OrderSide::Buy, &60, &1_000, &0, &0);
+    }
+    client.submit_order(&other, &commitment(&env, 4), &asset, &OrderSide::Sell, &60, &1_000, &0, &0);
+    client.submit_order(&trader, &commitment(&env, 5), &asset, &OrderSide::Sell, &60, &1_000, &0, &0);
+    client.cancel_order(&trader, &commitment(&env, 5), &ownership_proof(&env), &ownership_signals(&env, &commitment(&env, 5)));
+
+    // Another trader's order, a cancelled order and an unknown commitment are skipped
+    let commitments = vec![
+        &env,
+        commitment(&env, 1),
+        commitment(&env, 2),
+        commitment(&env, 3),
+        commitment(&env, 4),
+        commitment(&env, 5),
+        commitment(&env, 9),
+    ];
+    assert_eq!(client.extend_expiries_batch(&trader, &commitments, &600), 3);
+    for id in 1..=3u8 {
+        assert_eq!(client.get_order(&commitment(&env, id)).unwrap().expiry, 1_660);
+    }
+    assert_eq!(client.get_order(&commitment(&env, 4)).unwrap().expiry, 1_060);
+    assert_eq!(client.get_order(&commitment(&env, 5)).unwrap().expiry, 1_060);
+
+    // Extensions past the admin-set maximum are skipped
+    assert_eq!(client.extend_expiries_batch(&trader, &commitments, &DEFAULT_MAX_EXPIRY_SECONDS), 0);
+    assert_eq!(client.get_order(&commitment(&env, 1)).unwrap().expiry, 1_660);
+}
+
+#[test]
+fn test_expire_batch_pays_keeper() {
+    let env = Env::default();
+    env.mock_all_auths();
+    let (client, admin) = setup_orderbook(&env);
+
+    let reward_token = env.register_stellar_asset_contract_v2(Address::generate(&env)).address();
+    StellarAssetClient::new(&env, &reward_token).mint(&admin, &250);
+    let mut config = client.get_config();
+    config.keeper_reward_token = Some(reward_token.clone());
+    assert_eq!(client.try_set_config(&admin, &config), Err(Ok(OrderbookError::InvalidQuantity)));
+    update_config(&client, &admin, |c| {
+        c.keeper_reward_token = Some(reward_token.clone());
+        c.keeper_reward_per_order = 100;
+    });
+    assert_eq!(client.try_fund_keeper_pool(&admin, &0), Err(Ok(OrderbookError::InvalidQuantity)));
+    assert_eq!(client.fund_keeper_pool(&admin, &250), 250);
+
+    let trader = Address::generate(&env);
+    let keeper = Address::generate(&env);
+    let asset = Address::generate(&env);
+    client.submit_order(&keeper, &commitment(&env, 8), &asset, &OrderSide::Buy, &3600, &1_000, &0, &0);
+    for i in 0..4 {
+        client.submit_order(&trader, &commitment(&env, i), &asset, &OrderSide::Buy, &3600, &1_000, &0, &0);
+    }
+    client.submit_order(&trader, &commitment(&env, 9), &asset, &OrderSide::Buy, &7200, &1_000, &0, &0);
+    env.ledger().with_mut(|l| l.timestamp += 3601);
+
+    let token_client = token::Client::new(&env, &reward_token);
+
+    // Expiring your own orders earns nothing
+    assert_eq!(client.expire_batch(&keeper, &asset, &1), 1);
+    assert_eq!(client.get_order(&commitment(&env, 8)).unwrap().status, OrderStatus::Expired);
+    assert_eq!(token_client.balance(&keeper), 0);
+    assert_eq!(client.get_keeper_pool(), 250);
+
+    assert_eq!(client.expire_batch(&keeper, &asset, &2), 2);
+    assert_eq!(token_client.balance(&keeper), 200);
+    assert_eq!(client.get_keeper_pool(), 50);
+    assert_eq!(client.get_order(&commitment(&env, 0)).unwrap().status, OrderStatus::Expired);
+
+    // Payout is capped by what is left in the pool
+    assert_eq!(client.expire_batch(&keeper, &asset, &10), 2);
+    assert_eq!(token_client.balance(&keeper), 250);
+    assert_eq!(client.get_keeper_pool(), 0);
+
+    // Unexpired orders are left alone; an empty pool pays nothing
+    assert_eq!(client.expire_batch(&keeper, &asset, &10), 0);
+    assert_eq!(client.get_order(&commitment(&env, 9)).unwrap().status, OrderStatus::Active);
+    assert_eq!(token_client.balance(&keeper), 250);
+}
+
+#[test]
+fn test_keeper_reward_skips_short_lived_orders() {
+    let env = Env::default();
+    env.mock_all_auths();
+    let (client, admin) = setup_orderbook(&env);
+
+    let reward_token = env.register_stellar_asset_contract_v2(Address::generate(&env)).address();
+    let other_token = env.register_stellar_asset_contract_v2(Address::generate(&env)).address();
+    StellarAssetClient::new(&env, &reward_token).mint(&admin, &1_000);
+    update_config(&client, &admin, |c| {
+        c.keeper_reward_token = Some(reward_token.clone());
+        c.keeper_reward_per_order = 100;
+    });
+    client.fund_keeper_pool(&admin, &1_000);
+
+    // Unfunded orders that lived less than the minimum earn nothing
+    let trader = Address::generate(&env);
+    let asset = Address::generate(&env);
+    for i in 0..3 {
+        client.submit_order(&trader, &commitment(&env, i), &asset, &OrderSide::Buy, &60, &1_000, &0, &0);
+    }
+    env.ledger().with_mut(|l| l.timestamp += 61);
+    let keeper = Address::generate(&env);
+    assert_eq!(client.expire_batch(&keeper, &asset, &10), 3);
+    assert_eq!(token::Client::new(&env, &reward_token).balance(&keeper), 0);
+    assert_eq!(client.get_keeper_pool(), 1_000);
+
+    // Switching tokens returns the old pool to the admin
+    update_config(&client, &admin, |c| {
+        c.keeper_reward_token = Some(other_token.clone());
+        c.keeper_reward_per_order = 100;
+    });
+    assert_eq!(client.get_keeper_pool(), 0);
+    assert_eq!(token::Client::new(&env, &reward_token).balance(&admin), 1_000);
+    update_config(&client, &admin, |c| {
+        c.keeper_reward_token = Some(reward_token.clone());
+        c.keeper_reward_per_order = 100;
+    });
+    assert_eq!(client.get_keeper_pool(), 0);
+}
+
+#[test]
+fn test_cross_asset_pairs() {
+    let env = Env::default();
+    env.mock_all_auths();
+    let admin = Address::generate(&env);
+    let registry = env.register(MockRegistry, ());
+    let client = DarkPoolOrderbookClient::new(&env, &register_orderbook(&env, &admin, &registry, &Address::generate(&env)));
+    let asset_a = Address::generate(&env);
+    let asset_b = Address::generate(&env);
+    let asset_c = Address::generate(&env);
+
+    client.set_tradable_pair(&admin, &asset_b, &asset_a, &true);
+    assert!(client.is_pair_tradable(&asset_a, &asset_b));
+    assert!(!client.is_pair_tradable(&asset_a, &asset_c));
+
+    let buyer = Address::generate(&env);
+    let seller = Address::generate(&env);
+    client.submit_order(&buyer, &commitment(&env, 1), &asset_a, &OrderSide::Buy, &3600, &100, &0, &0);
+    client.submit_order(&seller, &commitment(&env, 2), &asset_b, &OrderSide::Sell, &3600, &100, &0, &0);
+    client.submit_order(&seller, &commitment(&env, 3), &asset_c, &OrderSide::Sell, &3600, &100, &0, &0);
+
+    // A pair that was never enabled is rejected
+    let result = client.try_record_match(
+        &admin, &commitment(&env, 10), &commitment(&env, 1), &commitment(&env, 3), &asset_a, &buyer, &seller,
+        &100, &5, &None,
+    );
+    assert_eq!(result, Err(Ok(OrderbookError::PairNotTradable)));
+
+    client.record_match(
+        &admin, &commitment(&env, 11), &commitment(&env, 1), &commitment(&env, 2), &asset_a, &buyer, &seller,
+        &100, &5, &None,
+    );
+    assert_eq!(client.get_order(&commitment(&env, 2)).unwrap().status, OrderStatus::Matched);
+
+    // Disabling the pair in the other direction disables it for both
+    client.set_tradable_pair(&admin, &asset_a, &asset_b, &false);
+    assert!(!client.is_pair_tradable(&asset_b, &asset_a));
+}
+
+#[test]
+fn test_settle_interval() {
+    let env = Env::default();
+    env.mock_all_auths();
+    let (client, admin) = setup_orderbook(&env);
+    let asset = Address::generate(&env);
+
+    update_asset_config(&client, &admin, &asset, |c| c.settle_interval = 600);
+    assert_eq!(client.get_asset_config(&asset).settle_interval, 600);
+
+    env.ledger().with_mut(|l| l.timestamp = 1_300);
+    let (buyer, seller) = submit_pair(&env, &client, &asset, 1, 2);
+    client.record_match(
+        &admin, &commitment(&env, 10), &commitment(&env, 1), &commitment(&env, 2), &asset, &buyer, &seller,
+        &100, &5, &None,
+    );
+
+    // Still inside the batch the match was recorded in
+    env.ledger().with_mut(|l| l.timestamp = 1_799);
+    let result = client.try_mark_settled(&admin, &commitment(&env, 10));
+    assert_eq!(result, Err(Ok(OrderbookError::NotInSettlementWindow)));
+    assert!(!client.get_match(&commitment(&env, 10)).unwrap().is_settled);
+
+    // The next batch boundary opens settlement
+    env.ledger().with_mut(|l| l.timestamp = 1_800);
+    client.mark_settled(&admin, &commitment(&env, 10));
+    assert!(client.get_match(&commitment(&env, 10)).unwrap().is_settled);
+
+    // Clearing the interval lets matches settle immediately
+    update_asset_config(&client, &admin, &asset, |c| c.settle_interval = 0);
+    let (buyer, seller) = submit_pair(&env, &client, &asset, 3, 4);
+    client.record_match(
+        &admin, &commitment(&env, 11), &commitment(&env, 3), &commitment(&env, 4), &asset, &buyer, &seller,
+        &100, &5, &None,
+    );
+    client.mark_settled(&admin, &commitment(&env, 11));
+}
+
+/// Accepts any proof whose first byte is 1
+#[contract]
+pub struct MockVerifier;
+
+#[contractimpl]
+impl MockVerifier {
+    pub fn verify_proof_bytes(_env: Env, _vk_bytes: Bytes, proof_bytes: Bytes, _pub_signals_bytes: Bytes) -> bool {
+        proof_bytes.get(0) == Some(1)
+    }
+}
+
+/// Orderbook wired to a real settlement contract, with funded submission on
+fn setup_funded_orderbook(env: &Env) -> (DarkPoolOrderbookClient<'_>, settlement_wasm::Client<'_>, Address) {
+    let admin = Address::generate(env);
+    let registry = env.register(MockRegistry, ());
+    let verifier = env.register(MockVerifier, ());
+    let vk_bytes = Bytes::from_slice(env, &[0u8; 100]);
+    let settlement_id = env.register(settlement_wasm::WASM, (&admin, &registry, &verifier, &vk_bytes, &false));
+    let settlement = settlement_wasm::Client::new(env, &settlement_id);
+
+    let contract_id = register_orderbook(env, &admin, &registry, &settlement_id);
+    settlement.set_orderbook(&admin, &contract_id);
+    (DarkPoolOrderbookClient::new(env, &contract_id), settlement, admin)
+}
+
+/// Register a token and allowlist it on the settlement contract
+fn create_allowed_token(env: &Env, settlement: &settlement_wasm::Client, admin: &Address) -> Address {
+    let token = env.register_stellar_asset_contract_v2(Address::generate(env)).address();
+    settlement.set_token_allowed(admin, &token, &true);
+    token
+}
+
+#[test]
+fn test_funded_submission_locks_escrow() {
+    let env = Env::default();
+    env.mock_all_auths();
+    let (client, settlement, admin) = setup_funded_orderbook(&env);
+
+    let asset = create_allowed_token(&env, &settlement, &admin);
+    let quote = create_allowed_token(&env, &settlement, &admin);
+    let mut config = client.get_config();
+    config.require_funded_submission = true;
+    assert_eq!(client.try_set_config(&admin, &config), Err(Ok(OrderbookError::QuoteAssetNotConfigured)));
+    update_config(&client, &admin, |c| {
+        c.require_funded_submission = true;
+        c.funded_quote_asset = Some(quote.clone());
+    });
+
+    let trader = Address::generate(&env);
+    StellarAssetClient::new(&env, &asset).mint(&trader, &100);
+    StellarAssetClient::new(&env, &quote).mint(&trader, &5_000);
+    settlement.deposit(&trader, &asset, &100);
+    settlement.deposit(&trader, &quote, &5_000);
+
+    // A funded order needs a limit price, and a sell must cover its quantity
+    let result = client.try_submit_order(&trader, &commitment(&env, 1), &asset, &OrderSide::Buy, &3600, &1_000, &0, &0);
+    assert_eq!(result, Err(Ok(OrderbookError::InvalidLimitPrice)));
+    let result = client.try_submit_order(&trader, &commitment(&env, 1), &asset, &OrderSide::Sell, &3600, &101, &10, &0);
+    assert_eq!(result, Err(Ok(OrderbookError::InsufficientFundsForOrder)));
+    assert_eq!(settlement.get_locked_balance(&trader, &asset), 0);
+
+    let receipt = client.submit_order(&trader, &commitment(&env, 1), &asset, &OrderSide::Sell, &3600, &100, &10, &0);
+    assert_eq!(receipt.required_lock, 100);
+    assert_eq!(settlement.get_locked_balance(&trader, &asset), 100);
+
+    let receipt = client.submit_order(&trader, &commitment(&env, 2), &asset, &OrderSide::Buy, &3600, &1_000, &4, &0);
+    assert_eq!(receipt.required_lock, 4_000);
+    assert_eq!(settlement.get_locked_balance(&trader, &quote), 4_000);
+
+    // Cancelling releases the order's lock
+    client.cancel_order(&trader, &commitment(&env, 2), &ownership_proof(&env), &ownership_signals(&env, &commitment(&env, 2)));
+    assert_eq!(settlement.get_locked_balance(&trader, &quote), 0);
+    assert_eq!(client.get_order(&commitment(&env, 2)).unwrap().locked_amount, 0);
+}
+
+/// Public signals for a settlement unlock of `amount` bound to an order
+fn unlock_signals(env: &Env, amount: i128, commitment: &BytesN<32>, trader: &Address, asset: &Address) -> Bytes {
+    let mut amount_signal = [0u8; 32];
+    amount_signal[16..].copy_from_slice(&amount.to_be_bytes());
+    let mut signals = Bytes::from_slice(env, &4u32.to_be_bytes());
+    signals.extend_from_slice(&amount_signal);
+    signals.append(&Bytes::from_array(env, &commitment.to_array()));
+    for address in [trader, asset] {
+        let hash: BytesN<32> = env.crypto().sha256(&address.clone().to_xdr(env)).into();
+        signals.append(&Bytes::from_array(env, &hash.to_array()));
+    }
+    signals
+}
+
+#[test]
+fn test_cancel_with_unlock_proof() {
+    let env = Env::default();
+    env.mock_all_auths();
+    let (client, settlement, admin) = setup_funded_orderbook(&env);
+    settlement.set_cancel_vk(&admin, &Bytes::from_slice(&env, &[0u8; 100]));
+
+    let asset = create_allowed_token(&env, &settlement, &admin);
+    let quote = create_allowed_token(&env, &settlement, &admin);
+    update_config(&client, &admin, |c| {
+        c.require_funded_submission = true;
+        c.funded_quote_asset = Some(quote.clone());
+    });
+
+    let trader = funded_trader(&env, &settlement, &quote, 5_000);
+    let other = commitment(&env, 1);
+    let order = commitment(&env, 2);
+    client.submit_order(&trader, &other, &asset, &OrderSide::Buy, &3600, &100, &10, &0);
+    client.submit_order(&trader, &order, &asset, &OrderSide::Buy, &3600, &100, &30, &0);
+    assert_eq!(settlement.get_locked_balance(&trader, &quote), 4_000);
+
+    // The proof must be bound to this order and may not free more than its lock
+    let cancel = |signals: &Bytes| {
+        client.try_cancel_order_with_unlock_proof(
+            &trader,
+            &order,
+            &ownership_proof(&env),
+            &ownership_signals(&env, &order),
+            &ownership_proof(&env),
+            signals,
+        )
+    };
+    let wrong_order = unlock_signals(&env, 3_000, &other, &trader, &quote);
+    assert_eq!(cancel(&wrong_order), Err(Ok(OrderbookError::InvalidProof)));
+    let wrong_asset = unlock_signals(&env, 3_000, &order, &trader, &asset);
+    assert_eq!(cancel(&wrong_asset), Err(Ok(OrderbookError::InvalidProof)));
+    let too_much = unlock_signals(&env, 3_001, &order, &trader, &quote);
+    assert_eq!(cancel(&too_much), Err(Ok(OrderbookError::InvalidProof)));
+    assert_eq!(settlement.get_locked_balance(&trader, &quote), 4_000);
+
+    // A partial unlock proof still releases the rest of the order's lock
+    cancel(&unlock_signals(&env, 2_000, &order, &trader, &quote)).unwrap().unwrap();
+    assert_eq!(settlement.get_locked_balance(&trader, &quote), 1_000);
+    let cancelled = client.get_order(&order).unwrap();
+    assert_eq!(cancelled.status, OrderStatus::Cancelled);
+    assert_eq!(cancelled.locked_amount, 0);
+}
+
+/// Mint and deposit `amount` of a token for a fresh trader
+fn funded_trader(env: &Env, settlement: &settlement_wasm::Client, token: &Address, amount: i128) -> Address {
+    let trader = Address::generate(env);
+    StellarAssetClient::new(env, token).mint(&trader, &amount);
+    settlement.deposit(&trader, token, &amount);
+    trader
+}
+
+#[test]
+fn test_delist_releases_locks() {
+    let env = Env::default();
+    env.mock_all_auths();
+    let (client, settlement, admin) = setup_funded_orderbook(&env);
+
+    let asset = create_allowed_token(&env, &settlement, &admin);
+    let quote = create_allowed_token(&env, &settlement, &admin);
+    update_config(&client, &admin, |c| {
+        c.require_funded_submission = true;
+        c.funded_quote_asset = Some(quote.clone());
+    });
+
+    let seller = funded_trader(&env, &settlement, &asset, 100);
+    let buyer = funded_trader(&env, &settlement, &quote, 1_000);
+    client.submit_order(&seller, &commitment(&env, 1), &asset, &OrderSide::Sell, &3600, &100, &10, &0);
+    client.submit_order(&buyer, &commitment(&env, 2), &asset, &OrderSide::Buy, &3600, &100, &10, &0);
+
+    assert_eq!(client.delist_asset(&admin, &asset, &10), 2);
+    assert_eq!(settlement.get_locked_balance(&seller, &asset), 0);
+    assert_eq!(settlement.get_locked_balance(&buyer, &quote), 0);
+    assert_eq!(client.get_order(&commitment(&env, 1)).unwrap().locked_amount, 0);
+}
+
+#[test]
+fn test_expiry_sweeps_release_locks() {
+    let env = Env::default();
+    env.mock_all_auths();
+    let (client, settlement, admin) = setup_funded_orderbook(&env);
+
+    let asset = create_allowed_token(&env, &settlement, &admin);
+    let quote = create_allowed_token(&env, &settlement, &admin);
+    update_config(&client, &admin, |c| {
+        c.require_funded_submission = true;
+        c.funded_quote_asset = Some(quote.clone());
+    });
+
+    let seller = funded_trader(&env, &settlement, &asset, 100);
+    let buyer = funded_trader(&env, &settlement, &quote, 1_000);
+    client.submit_order(&seller, &commitment(&env, 1), &asset, &OrderSide::Sell, &60, &100, &10, &0);
+    client.submit_order(&buyer, &commitment(&env, 2), &asset, &OrderSide::Buy, &60, &100, &10, &0);
+    env.ledger().with_mut(|li| li.timestamp += 61);
+
+    // Both the rewarded crank and the plain sweep give the escrow back
+    assert_eq!(client.expire_batch(&Address::generate(&env), &asset, &1), 1);
+    assert_eq!(settlement.get_locked_balance(&seller, &asset), 0);
+    assert_eq!(settlement.get_locked_balance(&buyer, &quote), 1_000);
+    assert_eq!(client.sweep_expired(&asset, &1), 1);
+    assert_eq!(settlement.get_locked_balance(&buyer, &quote), 0);
+    assert_eq!(client.get_order(&commitment(&env, 2)).unwrap().locked_amount, 0);
+}
+
+#[test]
+fn test_revert_match_releases_expired_locks() {
+    let env = Env::default();
+    env.mock_all_auths();
+    let (client, settlement, admin) = setup_funded_orderbook(&env);
+
+    let asset = create_allowed_token(&env, &settlement, &admin);
+    let quote = create_allowed_token(&env, &settlement, &admin);
+    update_config(&client, &admin, |c| {
+        c.require_funded_submission = true;
+        c.funded_quote_asset = Some(quote.clone());
+    });
+
+    let seller = funded_trader(&env, &settlement, &asset, 100);
+    let buyer = funded_trader(&env, &settlement, &quote, 1_000);
+    client.submit_order(&seller, &commitment(&env, 1), &asset, &OrderSide::Sell, &60, &100, &10, &0);
+    client.submit_order(&buyer, &commitment(&env, 2), &asset, &OrderSide::Buy, &60, &100, &10, &0);
+    client.record_match(
+        &admin, &commitment(&env, 10), &commitment(&env, 2), &commitment(&env, 1), &asset, &buyer, &seller,
+        &40, &10, &None,
+    );
+    env.ledger().with_mut(|li| li.timestamp += 61);
+
+    client.revert_match(&admin, &commitment(&env, 10));
+    assert_eq!(client.get_order(&commitment(&env, 1)).unwrap().status, OrderStatus::Expired);
+    assert_eq!(client.get_order(&commitment(&env, 2)).unwrap().status, OrderStatus::Expired);
+    assert_eq!(settlement.get_locked_balance(&seller, &asset), 0);
+    assert_eq!(settlement.get_locked_balance(&buyer, &quote), 0);
+}
+
+#[test]
+fn test_cancel_and_withdraw() {
+    let env = Env::default();
+    env.mock_all_auths();
+    let (client, settlement, admin) = setup_funded_orderbook(&env);
+
+    let asset = create_allowed_token(&env, &settlement, &admin);
+    let quote = create_allowed_token(&env, &settlement, &admin);
+    update_config(&client, &admin, |c| {
+        c.require_funded_submission = true;
+        c.funded_quote_asset = Some(quote.clone());
+    });
+
+    let trader = Address::generate(&env);
+    StellarAssetClient::new(&env, &asset).mint(&trader, &100);
+    settlement.deposit(&trader, &asset, &100);
+    client.submit_order(&trader, &commitment(&env, 1), &asset, &OrderSide::Sell, &3600, &100, &10, &0);
+    let token_client = token::Client::new(&env, &asset);
+    assert_eq!(token_client.balance(&trader), 0);
+
+    let withdrawn = client.cancel_and_withdraw(&trader, &commitment(&env, 1), &asset, &ownership_proof(&env), &ownership_signals(&env, &commitment(&env, 1)));
+    assert_eq!(withdrawn, Some(100));
+    assert_eq!(client.get_order(&commitment(&env, 1)).unwrap().status, OrderStatus::Cancelled);
+    assert_eq!(token_client.balance(&trader), 100);
+    assert_eq!(settlement.get_escrow_balance(&trader, &asset), 0);
+
+    // The order can only be cancelled once
+    let result = client.try_cancel_and_withdraw(&trader, &commitment(&env, 1), &asset, &ownership_proof(&env), &ownership_signals(&env, &commitment(&env, 1)));
+    assert_eq!(result, Err(Ok(OrderbookError::OrderAlreadyCancelled)));
+}
+
+#[test]
+fn test_funded_submission_rejects_unfunded() {
+    let env = Env::default();
+    env.mock_all_auths();
+    let (client, settlement, admin) = setup_funded_orderbook(&env);
+
+    let asset = create_allowed_token(&env, &settlement, &admin);
+    let quote = create_allowed_token(&env, &settlement, &admin);
+    update_config(&client, &admin, |c| {
+        c.require_funded_submission = true;
+        c.funded_quote_asset = Some(quote.clone());
+    });
+
+    let trader = Address::generate(&env);
+    StellarAssetClient::new(&env, &quote).mint(&trader, &1_000);
+    settlement.deposit(&trader, &quote, &1_000);
+
+    let result = client.try_submit_order(&trader, &commitment(&env, 1), &asset, &OrderSide::Buy, &3600, &1_000, &2, &0);
+    assert_eq!(result, Err(Ok(OrderbookError::InsufficientFundsForOrder)));
+    assert!(client.get_order(&commitment(&env, 1)).is_none());
+    assert_eq!(settlement.get_locked_balance(&trader, &quote), 0);
+}
+
+#[test]
+fn test_funded_submission_disabled() {
+    let env = Env::default();
+    env.mock_all_auths();
+    let (client, settlement, admin) = setup_funded_orderbook(&env);
+
+    let asset = Address::generate(&env);
+    let quote = Address::generate(&env);
+    update_config(&client, &admin, |c| c.require_funded_submission = false);
+
+    // No escrow needed and nothing is locked
+    let trader = Address::generate(&env);
+    let receipt = client.submit_order(&trader, &commitment(&env, 1), &asset, &OrderSide::Buy, &3600, &1_000, &1_000, &0);
+    assert_eq!(receipt.required_lock, 0);
+    assert_eq!(settlement.get_locked_balance(&trader, &quote), 0);
+}
+
+#[test]
+fn test_proof_validity_window() {
+    let env = Env::default();
+    env.mock_all_auths();
+    let (client, admin) = setup_orderbook(&env);
+    let asset = Address::generate(&env);
+    let trader = Address::generate(&env);
+
+    update_config(&client, &admin, |c| c.proof_validity = 300);
+    env.ledger().with_mut(|l| l.timestamp = 1_000);
+    client.submit_order(&trader, &commitment(&env, 1), &asset, &OrderSide::Buy, &3600, &1_000, &0, &0);
+    client.submit_order(&trader, &commitment(&env, 2), &asset, &OrderSide::Buy, &3600, &1_000, &0, &0);
+    assert_eq!(client.get_order(&commitment(&env, 1)).unwrap().proof_valid_until, 1_300);
+
+    // Inside the proof window
+    env.ledger().with_mut(|l| l.timestamp = 1_300);
+    client.cancel_order(&trader, &commitment(&env, 1), &ownership_proof(&env), &ownership_signals(&env, &commitment(&env, 1)));
+
+    // Past the proof window, though the order itself has not expired
+    env.ledger().with_mut(|l| l.timestamp = 1_301);
+    let result = client.try_cancel_order(&trader, &commitment(&env, 2), &ownership_proof(&env), &ownership_signals(&env, &commitment(&env, 2)));
+    assert_eq!(result, Err(Ok(OrderbookError::ProofExpired)));
+    assert_eq!(client.get_order(&commitment(&env, 2)).unwrap().status, OrderStatus::Active);
+}
+
+#[test]
+fn test_post_match_cancel_grace() {
+    let env = Env::default();
+    env.mock_all_auths();
+    let (client, admin) = setup_orderbook(&env);
+    let asset = Address::generate(&env);
+
+    update_config(&client, &admin, |c| c.post_match_cancel_grace = 60);
+    env.ledger().with_mut(|l| l.timestamp = 1_000);
+
+    client.submit_order(&Address::generate(&env), &commitment(&env, 9), &asset, &OrderSide::Buy, &3600, &1_000, &0, &0);
+    let (buyer, seller) = submit_pair(&env, &client, &asset, 1, 2);
+    client.record_match(
+        &admin, &commitment(&env, 10), &commitment(&env, 1), &commitment(&env, 2), &asset, &buyer, &seller,
+        &1_000, &5, &None,
+    );
+
+    // Inside the grace: the match is reverted and the seller's order reopens
+    env.ledger().with_mut(|l| l.timestamp = 1_059);
+    client.cancel_order(&buyer, &commitment(&env, 1), &ownership_proof(&env), &ownership_signals(&env, &commitment(&env, 1)));
+    assert_eq!(client.get_order(&commitment(&env, 1)).unwrap().status, OrderStatus::Cancelled);
+    assert_eq!(client.get_order(&commitment(&env, 2)).unwrap().status, OrderStatus::Active);
+    assert!(client.get_match(&commitment(&env, 10)).is_none());
+    // The filled order had already left the live count
+    assert_eq!(client.get_order_count_by_asset(&asset), 2);
+
+    assert_eq!(client.get_asset_stats(&asset).match_count, 0);
+
+    // Every fill inside the grace is reverted, not just the first
+    let (buyer, seller) = submit_pair(&env, &client, &asset, 5, 6);
+    let other_seller = Address::generate(&env);
+    client.submit_order(&other_seller, &commitment(&env, 7), &asset, &OrderSide::Sell, &3600, &1_000, &0, &0);
+    client.record_match(
+        &admin, &commitment(&env, 12), &commitment(&env, 5), &commitment(&env, 6), &asset, &buyer, &seller,
+        &400, &5, &None,
+    );
+    client.record_match(
+        &admin, &commitment(&env, 13), &commitment(&env, 5), &commitment(&env, 7), &asset, &buyer, &other_seller,
+        &600, &5, &None,
+    );
+    client.cancel_order(&buyer, &commitment(&env, 5), &ownership_proof(&env), &ownership_signals(&env, &commitment(&env, 5)));
+    assert!(client.get_match(&commitment(&env, 12)).is_none());
+    assert!(client.get_match(&commitment(&env, 13)).is_none());
+    assert_eq!(client.get_order(&commitment(&env, 6)).unwrap().remaining_quantity, 1_000);
+    assert_eq!(client.get_order(&commitment(&env, 7)).unwrap().status, OrderStatus::Active);
+    assert_eq!(client.get_asset_stats(&asset).matched_volume, 0);
+
+    // A partially filled order takes its fills inside the grace with it
+    let (buyer, seller) = submit_pair(&env, &client, &asset, 14, 15);
+    client.record_match(
+        &admin, &commitment(&env, 16), &commitment(&env, 14), &commitment(&env, 15), &asset, &buyer, &seller,
+        &400, &5, &None,
+    );
+    assert_eq!(client.get_order(&commitment(&env, 14)).unwrap().status, OrderStatus::PartiallyFilled);
+    client.cancel_order(&buyer, &commitment(&env, 14), &ownership_proof(&env), &ownership_signals(&env, &commitment(&env, 14)));
+    assert!(client.get_match(&commitment(&env, 16)).is_none());
+    let reopened = client.get_order(&commitment(&env, 15)).unwrap();
+    assert_eq!((reopened.status, reopened.remaining_quantity), (OrderStatus::Active, 1_000));
+
+    // Outside the grace: matched orders stay uncancellable
+    let (buyer, seller) = submit_pair(&env, &client, &asset, 3, 4);
+    client.record_match(
+        &admin, &commitment(&env, 11), &commitment(&env, 3), &commitment(&env, 4), &asset, &buyer, &seller,
+        &1_000, &5, &None,
+    );
+    env.ledger().with_mut(|l| l.timestamp += 60);
+    let result = client.try_cancel_order(&seller, &commitment(&env, 4), &ownership_proof(&env), &ownership_signals(&env, &commitment(&env, 4)));
+    assert_eq!(result, Err(Ok(OrderbookError::OrderAlreadyMatched)));
+    assert!(client.get_match(&commitment(&env, 11)).is_some());
+}
+
+#[test]
+fn test_revert_match() {
+    let env = Env::default();
+    env.mock_all_auths();
+    let (client, admin) = setup_orderbook(&env);
+    let asset = Address::generate(&env);
+
+    env.ledger().with_mut(|l| l.timestamp = 1_000);
+    update_config(&client, &admin, |c| c.fee_bps = 100);
+    let (buyer, seller) = submit_pair(&env, &client, &asset, 1, 2);
+    client.record_match(
+        &admin, &commitment(&env, 10), &commitment(&env, 1), &commitment(&env, 2), &asset, &buyer, &seller,
+        &1_000, &5, &None,
+    );
+    assert_eq!(client.get_order(&commitment(&env, 1)).unwrap().status, OrderStatus::Matched);
+    assert_eq!(client.get_asset_stats(&asset).match_count, 1);
+
+    client.revert_match(&admin, &commitment(&env, 10));
+    let reverted = MatchReverted {
+        match_id: commitment(&env, 10),
+    };
+    assert_eq!(
+        env.events().all().filter_by_contract(&client.address),
+        [reverted.to_xdr(&env, &client.address)]
+    );
+    assert!(client.get_match(&commitment(&env, 10)).is_none());
+    // The stats the match added are backed out
+    let stats = client.get_asset_stats(&asset);
+    assert_eq!((stats.match_count, stats.matched_volume), (0, 0));
+    for id in [1u8, 2] {
+        let order = client.get_order(&commitment(&env, id)).unwrap();
+        assert_eq!(order.status, OrderStatus::Active);
+        assert_eq!(order.remaining_quantity, 1_000);
+    }
+    let result = client.try_revert_match(&admin, &commitment(&env, 10));
+    assert_eq!(result, Err(Ok(OrderbookError::MatchNotFound)));
+
+    // Settled matches stay final
+    client.record_match(
+        &admin, &commitment(&env, 11), &commitment(&env, 1), &commitment(&env, 2), &asset, &buyer, &seller,
+        &400, &5, &None,
+    );
+    client.mark_settled(&admin, &commitment(&env, 11));
+    let result = client.try_revert_match(&admin, &commitment(&env, 11));
+    assert_eq!(result, Err(Ok(OrderbookError::MatchAlreadySettled)));
+
+    // An order that expired while matched does not return to the book
+    client.record_match(
+        &admin, &commitment(&env, 12), &commitment(&env, 1), &commitment(&env, 2), &asset, &buyer, &seller,
+        &600, &5, &None,
+    );
+    env.ledger().with_mut(|l| l.timestamp = 200_000);
+    client.revert_match(&admin, &commitment(&env, 12));
+    let order = client.get_order(&commitment(&env, 1)).unwrap();
+    assert_eq!(order.status, OrderStatus::Expired);
+    assert_eq!(order.remaining_quantity, 600);
+    assert_eq!(client.get_order_count_by_asset(&asset), 0);
+}
+
+#[test]
+fn test_max_settle_attempts() {
+    let env = Env::default();
+    env.mock_all_auths();
+    let (client, admin) = setup_orderbook(&env);
+    let asset = Address::generate(&env);
+
+    update_config(&client, &admin, |c| c.max_settle_attempts = 3);
+    let (buyer, seller) = submit_pair(&env, &client, &asset, 1, 2);
+    client.record_match(
+        &admin, &commitment(&env, 10), &commitment(&env, 1), &commitment(&env, 2), &asset, &buyer, &seller,
+        &400, &5, &None,
+    );
+
+    assert_eq!(client.report_settle_failure(&admin, &commitment(&env, 10)), 1);
+    assert_eq!(client.report_settle_failure(&admin, &commitment(&env, 10)), 2);
+    assert!(!client.get_match(&commitment(&env, 10)).unwrap().failed);
+    assert_eq!(client.report_settle_failure(&admin, &commitment(&env, 10)), 3);
+
+    // The third failure exhausts the retries
+    let m = client.get_match(&commitment(&env, 10)).unwrap();
+    assert_eq!(m.settle_attempts, 3);
+    assert!(m.failed);
+    let result = client.try_report_settle_failure(&admin, &commitment(&env, 10));
+    assert_eq!(result, Err(Ok(OrderbookError::MaxRetriesExceeded)));
+    let result = client.try_mark_settled(&admin, &commitment(&env, 10));
+    assert_eq!(result, Err(Ok(OrderbookError::MaxRetriesExceeded)));
+    assert!(!client.get_match(&commitment(&env, 10)).unwrap().is_settled);
+
+    let result = client.try_report_settle_failure(&admin, &commitment(&env, 11));
+    assert_eq!(result, Err(Ok(OrderbookError::MatchNotFound)));
+}
+
+#[test]
+fn test_min_quote_lifetime() {
+    let env = Env::default();
+    env.mock_all_auths();
+    let (client, admin) = setup_orderbook(&env);
+    let asset_a = Address::generate(&env);
+    let asset_b = Address::generate(&env);
+    let asset_c = Address::generate(&env);
+    let trader = Address::generate(&env);
+
+    update_config(&client, &admin, |c| c.min_resting_seconds = 30);
+    update_asset_config(&client, &admin, &asset_a, |c| c.min_quote_lifetime = Some(60));
+    update_asset_config(&client, &admin, &asset_b, |c| c.min_quote_lifetime = Some(120));
+    assert_eq!(client.get_asset_config(&asset_a).min_quote_lifetime, Some(60));
+    assert_eq!(client.get_asset_config(&asset_c).min_quote_lifetime, None);
+
+    env.ledger().with_mut(|l| l.timestamp = 1_000);
+    client.submit_order(&trader, &commitment(&env, 1), &asset_a, &OrderSide::Buy, &3600, &1_000, &0, &0);
+    client.submit_order(&trader, &commitment(&env, 2), &asset_b, &OrderSide::Buy, &3600, &1_000, &0, &0);
+    client.submit_order(&trader, &commitment(&env, 3), &asset_c, &OrderSide::Buy, &3600, &1_000, &0, &0);
+    let cancel = |id: u8| {
+        client.try_cancel_order(&trader, &commitment(&env, id), &ownership_proof(&env), &ownership_signals(&env, &commitment(&env, id)))
+    };
+
+    env.ledger().with_mut(|l| l.timestamp = 1_059);
+    assert_eq!(cancel(1), Err(Ok(OrderbookError::MinRestingNotElapsed)));
+    assert_eq!(cancel(2), Err(Ok(OrderbookError::MinRestingNotElapsed)));
+    // Asset C falls back to the global minimum
+    assert!(cancel(3).is_ok());
+
+    env.ledger().with_mut(|l| l.timestamp = 1_060);
+    assert!(cancel(1).is_ok());
+    assert_eq!(cancel(2), Err(Ok(OrderbookError::MinRestingNotElapsed)));
+
+    env.ledger().with_mut(|l| l.timestamp = 1_120);
+    assert!(cancel(2).is_ok());
+
+    // Clearing an override restores the global value
+    update_asset_config(&client, &admin, &asset_b, |c| c.min_quote_lifetime = None);
+    client.submit_order(&trader, &commitment(&env, 4), &asset_b, &OrderSide::Buy, &3600, &1_000, &0, &0);
+    env.ledger().with_mut(|l| l.timestamp = 1_149);
+    assert_eq!(cancel(4), Err(Ok(OrderbookError::MinRestingNotElapsed)));
+    env.ledger().with_mut(|l| l.timestamp = 1_150);
+    assert!(cancel(4).is_ok());
+}
+
+#[test]
+fn test_proof_rate_limit() {
+    let env = Env::default();
+    env.mock_all_auths();
+    let (client, admin) = setup_orderbook(&env);
+    let asset = Address::generate(&env);
+    let trader = Address::generate(&env);
+    let other_trader = Address::generate(&env);
+
+    update_config(&client, &admin, |c| {
+        c.proof_rate_limit = 2;
+        c.proof_rate_window = 60;
+    });
+    env.ledger().with_mut(|l| l.timestamp = 1_000);
+    for i in 0..4u8 {
+        client.submit_order(&trader, &commitment(&env, i), &asset, &OrderSide::Buy, &3600, &1_000, &0, &0);
+    }
+    client.submit_order(&other_trader, &commitment(&env, 10), &asset, &OrderSide::Buy, &3600, &1_000, &0, &0);
+
+    client.cancel_order(&trader, &commitment(&env, 0), &ownership_proof(&env), &ownership_signals(&env, &commitment(&env, 0)));
+    client.cancel_order(&trader, &commitment(&env, 1), &ownership_proof(&env), &ownership_signals(&env, &commitment(&env, 1)));
+    let result = client.try_cancel_order(&trader, &commitment(&env, 2), &ownership_proof(&env), &ownership_signals(&env, &commitment(&env, 2)));
+    assert_eq!(result, Err(Ok(OrderbookError::ProofRateLimited)));
+
+    // Limits are per trader, and rejected proofs use up the allowance
+    let bad_proof = Bytes::from_slice(&env, &[0u8; 4]);
+    for _ in 0..2 {
+        assert!(!client.cancel_order(&other_trader, &commitment(&env, 10), &bad_proof, &ownership_signals(&env, &commitment(&env, 10))));
+    }
+    let result = client.try_cancel_order(&other_trader, &commitment(&env, 10), &ownership_proof(&env), &ownership_signals(&env, &commitment(&env, 10)));
+    assert_eq!(result, Err(Ok(OrderbookError::ProofRateLimited)));
+    assert_eq!(client.get_order(&commitment(&env, 10)).unwrap().status, OrderStatus::Active);
+
+    // A new window restores the allowance
+    env.ledger().with_mut(|l| l.timestamp = 1_060);
+    client.cancel_order(&trader, &commitment(&env, 2), &ownership_proof(&env), &ownership_signals(&env, &commitment(&env, 2)));
+    assert_eq!(client.get_order(&commitment(&env, 2)).unwrap().status, OrderStatus::Cancelled);
+}
+
+#[test]
+fn test_global_cancel() {
+    let env = Env::default();
+    env.mock_all_auths();
+    let (client, settlement, admin) = setup_funded_orderbook(&env);
+
+    let asset_a = create_allowed_token(&env, &settlement, &admin);
+    let asset_b = create_allowed_token(&env, &settlement, &admin);
+    let trader = Address::generate(&env);
+    StellarAssetClient::new(&env, &asset_a).mint(&trader, &300);
+    settlement.deposit(&trader, &asset_a, &300);
+
+    // Funded sells on asset A, unfunded buys on asset B
+    update_config(&client, &admin, |c| {
+        c.require_funded_submission = true;
+        c.funded_quote_asset = Some(asset_b.clone());
+    });
+    for i in 0..3u8 {
+        client.submit_order(&trader, &commitment(&env, i), &asset_a, &OrderSide::Sell, &3600, &100, &10, &0);
+    }
+    update_config(&client, &admin, |c| c.require_funded_submission = false);
+    for i in 3..5u8 {
+        client.submit_order(&trader, &commitment(&env, i), &asset_b, &OrderSide::Buy, &3600, &1_000, &0, &0);
+    }
+    assert_eq!(settlement.get_locked_balance(&trader, &asset_a), 300);
+
+    assert_eq!(client.global_cancel(&admin, &2), 2);
+    assert_eq!(settlement.get_locked_balance(&trader, &asset_a), 100);
+    assert_eq!(client.get_order(&commitment(&env, 2)).unwrap().status, OrderStatus::Active);
+
+    assert_eq!(client.global_cancel(&admin, &2), 2);
+    assert_eq!(client.global_cancel(&admin, &2), 1);
+    assert_eq!(client.global_cancel(&admin, &2), 0);
+
+    assert_eq!(settlement.get_locked_balance(&trader, &asset_a), 0);
+    for i in 0..5u8 {
+        assert_eq!(client.get_order(&commitment(&env, i)).unwrap().status, OrderStatus::Cancelled);
+    }
+    assert!(client.get_active_orders(&asset_a).is_empty());
+    assert!(client.get_active_orders(&asset_b).is_empty());
+}
+
+#[test]
+fn test_cancel_all_for_trader() {
+    let env = Env::default();
+    env.mock_all_auths();
+    let (client, _) = setup_orderbook(&env);
+    let asset_a = Address::generate(&env);
+    let asset_b = Address::generate(&env);
+    let trader = Address::generate(&env);
+    let other = Address::generate(&env);
+
+    client.submit_order(&trader, &commitment(&env, 1), &asset_a, &OrderSide::Buy, &3600, &1_000, &0, &0);
+    client.submit_order(&trader, &commitment(&env, 2), &asset_a, &OrderSide::Sell, &3600, &1_000, &0, &0);
+    client.submit_order(&trader, &commitment(&env, 3), &asset_b, &OrderSide::Buy, &3600, &1_000, &0, &0);
+    client.submit_order(&other, &commitment(&env, 4), &asset_a, &OrderSide::Buy, &3600, &1_000, &0, &0);
+    client.submit_order(&other, &commitment(&env, 5), &asset_b, &OrderSide::Sell, &3600, &1_000, &0, &0);
+
+    // Scoped to one asset
+    assert_eq!(client.cancel_all_for_trader(&trader, &Some(asset_a.clone())), 2);
+    for (id, status) in [
+        (1u8, OrderStatus::Cancelled),
+        (2, OrderStatus::Cancelled),
+        (3, OrderStatus::Active),
+        (4, OrderStatus::Active),
+        (5, OrderStatus::Active),
+    ] {
+        assert_eq!(client.get_order(&commitment(&env, id)).unwrap().status, status);
+    }
+
+    // Across the whole book, still leaving the other trader alone
+    assert_eq!(client.cancel_all_for_trader(&trader, &None), 1);
+    assert_eq!(client.get_order(&commitment(&env, 3)).unwrap().status, OrderStatus::Cancelled);
+    assert_eq!(client.get_order(&commitment(&env, 4)).unwrap().status, OrderStatus::Active);
+    assert_eq!(client.get_order(&commitment(&env, 5)).unwrap().status, OrderStatus::Active);
+    assert_eq!(client.cancel_all_for_trader(&trader, &None), 0);
+}
+
+#[test]
+fn test_expiry_callback() {
+    let env = Env::default();
+    env.mock_all_auths();
+    let (client, _admin) = setup_orderbook(&env);
+    let asset = Address::generate(&env);
+    let trader = Address::generate(&env);
+    let callback = env.register(MockExpiryCallback, ());
+    let failing = env.register(MockExpiryCallback, ());
+    let callback_client = MockExpiryCallbackClient::new(&env, &callback);
+    let failing_client = MockExpiryCallbackClient::new(&env, &failing);
+    failing_client.set_failing(&true);
+
+    for i in 0..4u8 {
+        client.submit_order(&trader, &commitment(&env, i), &asset, &OrderSide::Buy, &60, &1_000, &0, &0);
+    }
+    client.set_expiry_callback(&trader, &commitment(&env, 1), &Some(callback.clone()));
+    client.set_expiry_callback(&trader, &commitment(&env, 2), &Some(failing.clone()));
+    let not_a_contract = Address::generate(&env);
+    client.set_expiry_callback(&trader, &commitment(&env, 3), &Some(not_a_contract.clone()));
+
+    let result = client.try_set_expiry_callback(&Address::generate(&env), &commitment(&env, 0), &None);
+    assert_eq!(result, Err(Ok(OrderbookError::UnauthorizedCancellation)));
+
+    // Callbacks are invoked on expiry; one that fails or is not a contract
+    // does not stop the batch
+    env.ledger().with_mut(|l| l.timestamp += 61);
+    assert_eq!(client.expire_batch(&Address::generate(&env), &asset, &10), 4);
+    let expired = |id: u8, expiry_callback: Option<Address>| {
+        OrderExpired {
+            commitment: commitment(&env, id),
+            expiry_callback,
+        }
+        .to_xdr(&env, &client.address)
+    };
+    assert_eq!(
+        env.events().all().filter_by_contract(&client.address),
+        [
+            expired(0, None),
+            expired(1, Some(callback)),
+            expired(2, Some(failing)),
+            expired(3, Some(not_a_contract)),
+        ]
+    );
+    assert_eq!(client.get_order(&commitment(&env, 2)).unwrap().status, OrderStatus::Expired);
+
+    // The failing callback's own writes are rolled back
+    assert_eq!(callback_client.expired(), vec![&env, commitment(&env, 1)]);
+    assert_eq!(failing_client.expired(), Vec::<BytesN<32>>::new(&env));
+}
+
+#[contract]
+pub struct MockExpiryCallback;
+
+#[contractimpl]
+impl MockExpiryCallback {
+    pub fn set_failing(env: Env, failing: bool) {
+        env.storage().instance().set(&symbol_short!("failing"), &failing);
+    }
+
+    pub fn on_expiry(env: Env, commitment: BytesN<32>) {
+        let mut expired = Self::expired(env.clone());
+        expired.push_back(commitment);
+        env.storage().instance().set(&symbol_short!("expired"), &expired);
+        if env.storage().instance().get(&symbol_short!("failing")).unwrap_or(false) {
+            panic!("callback failed");
+        }
+    }
+
+    pub fn expired(env: Env) -> Vec<BytesN<32>> {
+        env.storage().instance().get(&symbol_short!("expired")).unwrap_or(Vec::new(&env))
+    }
+}
+
+#[test]
+fn test_exact_settlement_accepts_exact_match() {
+    let env = Env::default();
+    env.mock_all_auths();
+    let (client, admin) = setup_orderbook(&env);
+    let asset = Address::generate(&env);
+
+    update_asset_config(&client, &admin, &asset, |c| c.exact_settlement_scale = Some(100));
+    assert_eq!(client.get_asset_config(&asset).exact_settlement_scale, Some(100));
+
+    // 40 * 250 = 10_000, evenly divisible by 100
+    let (buyer, seller) = submit_pair(&env, &client, &asset, 1, 2);
+    client.record_match(
+        &admin, &commitment(&env, 10), &commitment(&env, 1), &commitment(&env, 2), &asset, &buyer, &seller,
+        &40, &250, &None,
+    );
+    assert!(client.get_match(&commitment(&env, 10)).is_some());
+
+    let mut config = client.get_asset_config(&asset);
+    config.exact_settlement_scale = Some(0);
+    assert_eq!(client.try_set_asset_config(&admin, &asset, &config), Err(Ok(OrderbookError::InvalidPriceScale)));
+}
+
+#[test]
+fn test_exact_settlement_rejects_inexact_match() {
+    let env = Env::default();
+    env.mock_all_auths();
+    let (client, admin) = setup_orderbook(&env);
+    let asset = Address::generate(&env);
+
+    update_asset_config(&client, &admin, &asset, |c| c.exact_settlement_scale = Some(100));
+
+    // 3 * 333 = 999 leaves a remainder
+    let (buyer, seller) = submit_pair(&env, &client, &asset, 1, 2);
+    let result = client.try_record_match(
+        &admin, &commitment(&env, 10), &commitment(&env, 1), &commitment(&env, 2), &asset, &buyer, &seller,
+        &3, &333, &None,
+    );
+    assert_eq!(result, Err(Ok(OrderbookError::InexactSettlement)));
+
+    // Without the flag the same match goes through
+    update_asset_config(&client, &admin, &asset, |c| c.exact_settlement_scale = None);
+    client.record_match(
+        &admin, &commitment(&env, 10), &commitment(&env, 1), &commitment(&env, 2), &asset, &buyer, &seller,
+        &3, &333, &None,
+    );
+}
+
+#[test]
+fn test_stats_opt_out() {
+    let env = Env::default();
+    env.mock_all_auths();
+    let (client, admin) = setup_orderbook(&env);
+    let asset = Address::generate(&env);
+
+    let public_buyer = Address::generate(&env);
+    let public_seller = Address::generate(&env);
+    let private_seller = Address::generate(&env);
+    client.set_stats_opt_out(&private_seller, &true);
+
+    client.submit_order(&public_buyer, &commitment(&env, 1), &asset, &OrderSide::Buy, &3600, &1_000, &0, &0);
+    client.submit_order(&public_seller, &commitment(&env, 2), &asset, &OrderSide::Sell, &3600, &1_000, &0, &0);
+    client.submit_order(&public_buyer, &commitment(&env, 3), &asset, &OrderSide::Buy, &3600, &1_000, &0, &0);
+    client.submit_order(&private_seller, &commitment(&env, 4), &asset, &OrderSide::Sell, &3600, &1_000, &0, &0);
+    assert!(client.get_order(&commitment(&env, 4)).unwrap().exclude_from_stats);
+
+    client.record_match(
+        &admin, &commitment(&env, 10), &commitment(&env, 1), &commitment(&env, 2), &asset, &public_buyer,
+        &public_seller, &100, &5, &None,
+    );
+    // The flagged order still trades but doesn't move the aggregates
+    client.record_match(
+        &admin, &commitment(&env, 11), &commitment(&env, 3), &commitment(&env, 4), &asset, &public_buyer,
+        &private_seller, &70, &5, &None,
+    );
+
+    let stats = client.get_asset_stats(&asset);
+    assert_eq!(stats, AssetStats { order_count: 3, match_count: 1, matched_volume: 100 });
+}
+
+#[test]
+fn test_pegged_order_tracks_mid() {
+    let env = Env::default();
+    env.mock_all_auths();
+    let (client, admin) = setup_orderbook(&env);
+    let asset = Address::generate(&env);
+
+    let (buyer, seller) = submit_pair(&env, &client, &asset, 1, 2);
+    client.set_peg_offset(&seller, &commitment(&env, 2), &Some(2));
+
+    // Mid 100: the pegged sell's limit is 102
+    client.publish_top_of_book(&admin, &asset, &98, &102);
+    let result = client.try_record_match(
+        &admin, &commitment(&env, 10), &commitment(&env, 1), &commitment(&env, 2), &asset, &buyer, &seller,
+        &10, &101, &None,
+    );
+    assert_eq!(result, Err(Ok(OrderbookError::PriceBeyondPeg)));
+
+    // Mid drops to 98: the limit follows to 100 and the same price fills
+    client.publish_top_of_book(&admin, &asset, &96, &100);
+    client.record_match(
+        &admin, &commitment(&env, 10), &commitment(&env, 1), &commitment(&env, 2), &asset, &buyer, &seller,
+        &1_000, &101, &None,
+    );
+    assert_eq!(client.get_order(&commitment(&env, 2)).unwrap().status, OrderStatus::Matched);
+}
+
+#[test]
+fn test_pegged_order_rejects_non_positive_price() {
+    let env = Env::default();
+    env.mock_all_auths();
+    let (client, admin) = setup_orderbook(&env);
+    let asset = Address::generate(&env);
+
+    let (buyer, seller) = submit_pair(&env, &client, &asset, 1, 2);
+    client.set_peg_offset(&buyer, &commitment(&env, 1), &Some(-100));
+    client.publish_top_of_book(&admin, &asset, &98, &102);
+
+    let result = client.try_record_match(
+        &admin, &commitment(&env, 10), &commitment(&env, 1), &commitment(&env, 2), &asset, &buyer, &seller,
+        &10, &1, &None,
+    );
+    assert_eq!(result, Err(Ok(OrderbookError::PegPriceNotPositive)));
+}
+
+#[test]
+fn test_trading_hours() {
+    let env = Env::default();
+    env.mock_all_auths();
+    let (client, admin) = setup_orderbook(&env);
+
+    let asset = Address::generate(&env);
+    env.ledger().with_mut(|l| l.timestamp = 12 * 3600);
+    assert!(client.is_within_trading_hours(&asset));
+
+    // Halted
+    update_asset_config(&client, &admin, &asset, |c| c.halted = true);
+    let trader = Address::generate(&env);
+    assert_eq!(
+        client.try_submit_order(&trader, &commitment(&env, 1), &asset, &OrderSide::Buy, &3600, &1_000, &0, &0),
+        Err(Ok(OrderbookError::AssetHalted))
+    );
+    update_asset_config(&client, &admin, &asset, |c| c.halted = false);
+
+    // Outside trading hours, including a window wrapping past midnight
+    update_asset_config(&client, &admin, &asset, |c| {
+        c.trading_open_seconds = 13 * 3600;
+        c.trading_close_seconds = 20 * 3600;
+    });
+    assert!(!client.is_within_trading_hours(&asset));
+    env.ledger().with_mut(|l| l.timestamp = 86_400 + 14 * 3600);
+    assert!(client.is_within_trading_hours(&asset));
+
+    update_asset_config(&client, &admin, &asset, |c| {
+        c.trading_open_seconds = 22 * 3600;
+        c.trading_close_seconds = 2 * 3600;
+    });
+    assert!(!client.is_within_trading_hours(&asset));
+    env.ledger().with_mut(|l| l.timestamp = 2 * 86_400 + 3600);
+    assert!(client.is_within_trading_hours(&asset));
+
+    let mut config = client.get_asset_config(&asset);
+    config.trading_open_seconds = 86_400;
+    assert_eq!(client.try_set_asset_config(&admin, &asset, &config), Err(Ok(OrderbookError::InvalidTradingHours)));
+
+    // Equal open and close trade around the clock
+    update_asset_config(&client, &admin, &asset, |c| {
+        c.trading_open_seconds = 3600;
+        c.trading_close_seconds = 3600;
+    });
+    assert!(client.is_within_trading_hours(&asset));
+}
+#[test]
+fn test_reduce_order_releases_lock() {
+    let env = Env::default();
+    env.mock_all_auths();
+    let (client, settlement, admin) = setup_funded_orderbook(&env);
+
+    let asset = create_allowed_token(&env, &settlement, &admin);
+    let quote = create_allowed_token(&env, &settlement, &admin);
+    update_config(&client, &admin, |c| {
+        c.require_funded_submission = true;
+        c.funded_quote_asset = Some(quote.clone());
+    });
+
+    let trader = Address::generate(&env);
+    StellarAssetClient::new(&env, &quote).mint(&trader, &4_000);
+    settlement.deposit(&trader, &quote, &4_000);
+
+    let order_id = commitment(&env, 1);
+    client.submit_order(&trader, &order_id, &asset, &OrderSide::Buy, &3600, &100, &40, &0);
+    let proof = ownership_proof(&env);
+    let signals = ownership_signals(&env, &order_id);
+
+    // The ownership proof must verify and be bound to this order
+    assert_eq!(client.reduce_order(&trader, &order_id, &75, &Bytes::from_slice(&env, &[0u8; 4]), &signals), None);
+    assert_eq!(
+        client.try_reduce_order(&trader, &order_id, &75, &proof, &ownership_signals(&env, &commitment(&env, 2))),
+        Err(Ok(OrderbookError::InvalidProof))
+    );
+
+    // Cutting the size by a quarter releases a quarter of the lock
+    assert_eq!(client.reduce_order(&trader, &order_id, &75, &proof, &signals), Some(1_000));
+    let order = client.get_order(&order_id).unwrap();
+    assert_eq!(order.total_quantity, 75);
+    assert_eq!(order.locked_amount, 3_000);
+    assert_eq!(settlement.get_locked_balance(&trader, &quote), 3_000);
+    assert_eq!(settlement.get_available_balance(&trader, &quote), 1_000);
+
+    assert_eq!(
+        client.try_reduce_order(&trader, &order_id, &80, &proof, &signals),
+        Err(Ok(OrderbookError::QuantityIncreaseNotAllowed))
+    );
+    assert_eq!(
+        client.try_reduce_order(&trader, &order_id, &-1, &proof, &signals),
+        Err(Ok(OrderbookError::InvalidQuantity))
+    );
+    assert_eq!(
+        client.try_reduce_order(&Address::generate(&env), &order_id, &50, &proof, &signals),
+        Err(Ok(OrderbookError::UnauthorizedCancellation))
+    );
+
+    // Reducing an unfilled order to nothing cancels it
+    assert_eq!(client.reduce_order(&trader, &order_id, &0, &proof, &signals), Some(3_000));
+    let order = client.get_order(&order_id).unwrap();
+    assert_eq!(order.status, OrderStatus::Cancelled);
+    assert_eq!(order.locked_amount, 0);
+    assert_eq!(settlement.get_locked_balance(&trader, &quote), 0);
+    assert_eq!(client.get_order_count_by_asset(&asset), 0);
+}
+
+#[test]
+fn test_reduce_order_to_filled_size_completes_it() {
+    let env = Env::default();
+    env.mock_all_auths();
+    let (client, admin) = setup_orderbook(&env);
+    let asset = Address::generate(&env);
+    let (buyer, seller) = submit_pair(&env, &client, &asset, 1, 2);
+    client.record_match(
+        &admin, &commitment(&env, 100), &commitment(&env, 1), &commitment(&env, 2), &asset, &buyer, &seller,
+        &400, &5, &None,
+    );
+    assert_eq!(client.get_order(&commitment(&env, 1)).unwrap().status, OrderStatus::PartiallyFilled);
+
+    let signals = ownership_signals(&env, &commitment(&env, 1));
+    client.reduce_order(&buyer, &commitment(&env, 1), &400, &ownership_proof(&env), &signals);
+    let order = client.get_order(&commitment(&env, 1)).unwrap();
+    assert_eq!(order.status, OrderStatus::Matched);
+    assert_eq!(order.remaining_quantity, 0);
+    assert!(client.get_active_orders(&asset).iter().all(|o| o.commitment != commitment(&env, 1)));
+}
+
+struct PrivateMatch {
+    asset: Address,
+    quote: Address,
+    buyer: Address,
+    seller: Address,
+}
+
+/// Fund a buyer and seller and submit locked orders for them
+fn setup_private_match(
+    env: &Env,
+    client: &DarkPoolOrderbookClient,
+    settlement: &settlement_wasm::Client,
+    admin: &Address,
+    buy_id: u8,
+    sell_id: u8,
+) -> PrivateMatch {
+    let asset = create_allowed_token(env, settlement, admin);
+    let quote = create_allowed_token(env, settlement, admin);
+    update_config(client, admin, |c| {
+        c.require_funded_submission = true;
+        c.funded_quote_asset = Some(quote.clone());
+    });
+
+    let buyer = Address::generate(env);
+    let seller = Address::generate(env);
+    StellarAssetClient::new(env, &quote).mint(&buyer, &5_000);
+    StellarAssetClient::new(env, &asset).mint(&seller, &100);
+    settlement.deposit(&buyer, &quote, &5_000);
+    settlement.deposit(&seller, &asset, &100);
+
+    client.submit_order(&buyer, &commitment(env, buy_id), &asset, &OrderSide::Buy, &3600, &100, &50, &0);
+    client.submit_order(&seller, &commitment(env, sell_id), &asset, &OrderSide::Sell, &3600, &100, &50, &0);
+    MockRegistryClient::new(env, &client.get_registry()).set_settlement_asset(&asset, &Some(quote.clone()));
+    PrivateMatch { asset, quote, buyer, seller }
+}
+
+/// Assert a failed private match left the book and escrow untouched
+fn assert_private_match_rolled_back(
+    env: &Env,
+    client: &DarkPoolOrderbookClient,
+    settlement: &settlement_wasm::Client,
+    m: &PrivateMatch,
+    buy_id: u8,
+    sell_id: u8,
+    nullifiers: &[BytesN<32>],
+) {
+    assert_eq!(client.get_pending_matches().len(), 0);
+    for id in [buy_id, sell_id] {
+        let order = client.get_order(&commitment(env, id)).unwrap();
+        assert_eq!(order.status, OrderStatus::Active);
+        assert_eq!(order.filled_quantity, 0);
+    }
+    assert_eq!(settlement.get_locked_balance(&m.buyer, &m.quote), 5_000);
+    assert_eq!(settlement.get_locked_balance(&m.seller, &m.asset), 100);
+    assert_eq!(settlement.get_escrow_balance(&m.buyer, &m.asset), 0);
+    assert_eq!(settlement.get_escrow_balance(&m.seller, &m.quote), 0);
+    for nullifier in nullifiers {
+        assert!(!settlement.is_nullifier_used(nullifier));
+    }
+}
+
+#[test]
+fn test_match_and_settle_private() {
+    let env = Env::default();
+    env.mock_all_auths();
+    let (client, settlement, admin) = setup_funded_orderbook(&env);
+    let m = setup_private_match(&env, &client, &settlement, &admin, 1, 2);
+
+    let proof = Bytes::from_slice(&env, &[1u8; 4]);
+    let signals = Bytes::from_slice(&env, &[0u8; 4]);
+    let buy_nullifier = BytesN::from_array(&env, &[11u8; 32]);
+    let sell_nullifier = BytesN::from_array(&env, &[12u8; 32]);
+
+    let match_id = client.match_and_settle_private(
+        &admin,
+        &commitment(&env, 1),
+        &commitment(&env, 2),
+        &proof,
+        &signals,
+        &buy_nullifier,
+        &sell_nullifier,
+        &100,
+        &50,
+    );
+
+    let record = client.get_match(&match_id).unwrap();
+    assert!(record.is_settled);
+    assert_eq!(record.buyer, m.buyer);
+    assert_eq!(record.seller, m.seller);
+    for id in [1u8, 2] {
+        let order = client.get_order(&commitment(&env, id)).unwrap();
+        assert_eq!(order.status, OrderStatus::Settled);
+        assert_eq!(order.locked_amount, 0);
+    }
+
+    assert!(settlement.is_nullifier_used(&buy_nullifier));
+    assert!(settlement.is_nullifier_used(&sell_nullifier));
+    assert_eq!(settlement.get_escrow_balance(&m.buyer, &m.asset), 100);
+    assert_eq!(settlement.get_escrow_balance(&m.seller, &m.quote), 5_000);
+    assert_eq!(settlement.get_locked_balance(&m.buyer, &m.quote), 0);
+    assert_eq!(settlement.get_locked_balance(&m.seller, &m.asset), 0);
+}
+
+#[test]
+fn test_match_and_settle_private_partial_fills() {
+    let env = Env::default();
+    env.mock_all_auths();
+    let (client, settlement, admin) = setup_funded_orderbook(&env);
+    let m = setup_private_match(&env, &client, &settlement, &admin, 1, 2);
+
+    // Two fills of the same pair get distinct ids and each pays its own notional
+    let proof = Bytes::from_slice(&env, &[1u8; 4]);
+    let mut match_ids = vec![&env];
+    for nullifier in [11u8, 13] {
+        match_ids.push_back(client.match_and_settle_private(
+            &admin,
+            &commitment(&env, 1),
+            &commitment(&env, 2),
+            &proof,
+            &proof,
+            &BytesN::from_array(&env, &[nullifier; 32]),
+            &BytesN::from_array(&env, &[nullifier + 1; 32]),
+            &50,
+            &50,
+        ));
+    }
+    assert_ne!(match_ids.get(0), match_ids.get(1));
+    for match_id in match_ids.iter() {
+        assert!(client.get_match(&match_id).unwrap().is_settled);
+    }
+    assert_eq!(settlement.get_escrow_balance(&m.buyer, &m.asset), 100);
+    assert_eq!(settlement.get_escrow_balance(&m.seller, &m.quote), 5_000);
+    assert_eq!(client.get_order(&commitment(&env, 1)).unwrap().status, OrderStatus::Settled);
+}
+
+#[test]
+fn test_match_and_settle_private_requires_settlement_asset() {
+    let env = Env::default();
+    env.mock_all_auths();
+    let (client, settlement, admin) = setup_funded_orderbook(&env);
+    let m = setup_private_match(&env, &client, &settlement, &admin, 1, 2);
+    let registry = MockRegistryClient::new(&env, &client.get_registry());
+
+    let proof = Bytes::from_slice(&env, &[1u8; 4]);
+    let buy_nullifier = BytesN::from_array(&env, &[11u8; 32]);
+    let sell_nullifier = BytesN::from_array(&env, &[12u8; 32]);
+    let settle = || {
+        client.try_match_and_settle_private(
+            &admin,
+            &commitment(&env, 1),
+            &commitment(&env, 2),
+            &proof,
+            &proof,
+            &buy_nullifier,
+            &sell_nullifier,
+            &100,
+            &50,
+        )
+    };
+
+    // Clearing the asset in the registry blocks settlement immediately
+    registry.set_settlement_asset(&m.asset, &None);
+    assert_eq!(settle(), Err(Ok(OrderbookError::NoSettlementAsset)));
+    assert_private_match_rolled_back(
+        &env,
+        &client,
+        &settlement,
+        &m,
+        1,
+        2,
+        &[buy_nullifier.clone(), sell_nullifier.clone()],
+    );
+
+    registry.set_settlement_asset(&m.asset, &Some(m.quote.clone()));
+    assert!(settle().is_ok());
+    assert_eq!(settlement.get_escrow_balance(&m.seller, &m.quote), 5_000);
+}
+
+#[test]
+fn test_match_and_settle_private_bad_proof_rolls_back() {
+    let env = Env::default();
+    env.mock_all_auths();
+    let (client, settlement, admin) = setup_funded_orderbook(&env);
+    let m = setup_private_match(&env, &client, &settlement, &admin, 1, 2);
+
+    let bad_proof = Bytes::from_slice(&env, &[0u8; 4]);
+    let buy_nullifier = BytesN::from_array(&env, &[11u8; 32]);
+    let sell_nullifier = BytesN::from_array(&env, &[12u8; 32]);
+    let result = client.try_match_and_settle_private(
+        &admin,
+        &commitment(&env, 1),
+        &commitment(&env, 2),
+        &bad_proof,
+        &bad_proof,
+        &buy_nullifier,
+        &sell_nullifier,
+        &100,
+        &50,
+    );
+    assert_eq!(result, Err(Ok(OrderbookError::InvalidProof)));
+    assert_private_match_rolled_back(&env, &client, &settlement, &m, 1, 2, &[buy_nullifier, sell_nullifier]);
+}
+
+#[test]
+fn test_match_and_settle_private_reused_nullifier_rolls_back() {
+    let env = Env::default();
+    env.mock_all_auths();
+    let (client, settlement, admin) = setup_funded_orderbook(&env);
+
+    let proof = Bytes::from_slice(&env, &[1u8; 4]);
+    let spent = BytesN::from_array(&env, &[11u8; 32]);
+    setup_private_match(&env, &client, &settlement, &admin, 1, 2);
+    client.match_and_settle_private(
+        &admin,
+        &commitment(&env, 1),
+        &commitment(&env, 2),
+        &proof,
+        &proof,
+        &spent,
+        &BytesN::from_array(&env, &[12u8; 32]),
+        &100,
+        &50,
+    );
+
+    let m = setup_private_match(&env, &client, &settlement, &admin, 3, 4);
+    let fresh = BytesN::from_array(&env, &[13u8; 32]);
+    let result = client.try_match_and_settle_private(
+        &admin,
+        &commitment(&env, 3),
+        &commitment(&env, 4),
+        &proof,
+        &proof,
+        &spent,
+        &fresh,
+        &100,
+        &50,
+    );
+    assert_eq!(result, Err(Ok(OrderbookError::NullifierUsed)));
+    assert_private_match_rolled_back(&env, &client, &settlement, &m, 3, 4, &[fresh]);
+}
+
+#[test]
+fn test_match_and_settle_private_insufficient_escrow_rolls_back() {
+    let env = Env::default();
+    env.mock_all_auths();
+    let (client, settlement, admin) = setup_funded_orderbook(&env);
+    let m = setup_private_match(&env, &client, &settlement, &admin, 1, 2);
+
+    let proof = Bytes::from_slice(&env, &[1u8; 4]);
+    let buy_nullifier = BytesN::from_array(&env, &[11u8; 32]);
+    let sell_nullifier = BytesN::from_array(&env, &[12u8; 32]);
+    let result = client.try_match_and_settle_private(
+        &admin,
+        &commitment(&env, 1),
+        &commitment(&env, 2),
+        &proof,
+        &proof,
+        &buy_nullifier,
+        &sell_nullifier,
+        &100,
+        &60,
+    );
+    assert_eq!(result, Err(Ok(OrderbookError::InsufficientEscrow)));
+    assert_private_match_rolled_back(&env, &client, &settlement, &m, 1, 2, &[buy_nullifier, sell_nullifier]);
+}
+
+#[test]
+fn test_get_active_orders_cursor() {
+    let env = Env::default();
+    env.mock_all_auths();
+    let (client, _admin) = setup_orderbook(&env);
+    let asset = Address::generate(&env);
+    let other_asset = Address::generate(&env);
+    let trader = Address::generate(&env);
+
+    for i in 0..7u8 {
+        client.submit_order(&trader, &commitment(&env, i), &asset, &OrderSide::Buy, &3600, &1_000, &0, &0);
+        client.submit_order(&trader, &commitment(&env, 100 + i), &other_asset, &OrderSide::Buy, &3600, &1_000, &0, &0);
+    }
+    client.cancel_order(&trader, &commitment(&env, 3), &ownership_proof(&env), &ownership_signals(&env, &commitment(&env, 3)));
+
+    let mut seen: Vec<BytesN<32>> = vec![&env];
+    let mut cursor = 0;
+    let mut pages = 0;
+    loop {
+        let page = client.get_active_orders_cursor(&asset, &cursor, &2);
+        assert!(page.orders.len() <= 2);
+        for order in page.orders.iter() {
+            assert_eq!(order.asset_address, asset);
+            seen.push_back(order.commitment);
+        }
+        pages += 1;
+        match page.next_cursor {
+            Some(next) => cursor = next,
+            None => break,
+        }
+    }
+
+    assert_eq!(pages, 3);
+    assert_eq!(seen.len(), 6);
+    assert!(!seen.contains(commitment(&env, 3)));
+    assert_eq!(client.get_active_orders(&asset).len(), 6);
+
+    // A zero limit still makes progress
+    let page = client.get_active_orders_cursor(&asset, &0, &0);
+    assert_eq!(page.orders.len(), 1);
+    assert_eq!(page.next_cursor, Some(1));
+}
+
+#[test]
+fn test_order_and_match_events() {
+    let env = Env::default();
+    env.mock_all_auths();
+    let (client, admin) = setup_orderbook(&env);
+    let asset = Address::generate(&env);
+    let buyer = Address::generate(&env);
+    let seller = Address::generate(&env);
+
+    client.submit_order(&buyer, &commitment(&env, 1), &asset, &OrderSide::Buy, &3600, &1_000, &0, &0);
+    let submitted = OrderSubmitted {
+        commitment: commitment(&env, 1),
+        asset_address: asset.clone(),
+        side: OrderSide::Buy,
+    };
+    assert_eq!(
+        env.events().all().filter_by_contract(&client.address),
+        [submitted.to_xdr(&env, &client.address)]
+    );
+
+    client.cancel_order(&buyer, &commitment(&env, 1), &ownership_proof(&env), &ownership_signals(&env, &commitment(&env, 1)));
+    let cancelled = OrderCancelled {
+        commitment: commitment(&env, 1),
+    };
+    assert_eq!(
+        env.events().all().filter_by_contract(&client.address),
+        [cancelled.to_xdr(&env, &client.address)]
+    );
+
+    client.submit_order(&buyer, &commitment(&env, 2), &asset, &OrderSide::Buy, &3600, &1_000, &0, &0);
+    client.submit_order(&seller, &commitment(&env, 3), &asset, &OrderSide::Sell, &3600, &1_000, &0, &0);
+    client.record_match(
+        &admin, &commitment(&env, 9), &commitment(&env, 2), &commitment(&env, 3), &asset, &buyer, &seller,
+        &1_000, &5, &None,
+    );
+    let recorded = MatchRecorded {
+        match_id: commitment(&env, 9),
+        asset_address: asset.clone(),
+        buy_commitment: commitment(&env, 2),
+        sell_commitment: commitment(&env, 3),
+        quantity: 1_000,
+        price: 5,
+        timestamp: 0,
+        reemitted: false,
+        decimals: None,
+    };
+    assert_eq!(
+        env.events().all().filter_by_contract(&client.address),
+        [recorded.to_xdr(&env, &client.address)]
+    );
+}
+
+#[test]
+fn test_match_event_carries_asset_decimals() {
+    let env = Env::default();
+    env.mock_all_auths();
+
+    let admin = Address::generate(&env);
+    let vk_bytes = Bytes::from_slice(&env, &[0u8; 100]);
+    let registry_id = env.register(registry_wasm::WASM, (&admin, &Address::generate(&env), &vk_bytes));
+    let registry = registry_wasm::Client::new(&env, &registry_id);
+    let contract_id = register_orderbook(&env, &admin, &registry_id, &Address::generate(&env));
+    let client = DarkPoolOrderbookClient::new(&env, &contract_id);
+
+    let asset = Address::generate(&env);
+    registry.register_asset(
+        &admin,
+        &registry_wasm::RWAAsset {
+            token_address: asset.clone(),
+            symbol: symbol_short!("TBILL"),
+            asset_type: registry_wasm::AssetType::TreasuryBond,
+            min_trade_size: 1,
+            max_order_size: 1_000_000,
+            is_active: true,
+        },
+    );
+    registry.set_asset_decimals(&admin, &asset, &6);
+
+    let (buyer, seller) = submit_pair(&env, &client, &asset, 1, 2);
+    client.record_match(
+        &admin, &commitment(&env, 9), &commitment(&env, 1), &commitment(&env, 2), &asset, &buyer, &seller,
+        &1_000, &5, &None,
+    );
+    let recorded = MatchRecorded {
+        match_id: commitment(&env, 9),
+        asset_address: asset,
+        buy_commitment: commitment(&env, 1),
+        sell_commitment: commitment(&env, 2),
+        quantity: 1_000,
+        price: 5,
+        timestamp: 0,
+        reemitted: false,
+        decimals: Some(6),
+    };
+    assert_eq!(
+        env.events().all().filter_by_contract(&client.address),
+        [recorded.to_xdr(&env, &client.address)]
+    );
+}
+
+#[test]
+fn test_reemit_match_events() {
+    let env = Env::default();
+    env.mock_all_auths();
+    let (client, admin) = setup_orderbook(&env);
+    let asset = Address::generate(&env);
+
+    for i in 0..3u8 {
+        let (buyer, seller) = submit_pair(&env, &client, &asset, 2 * i, 2 * i + 1);
+        client.record_match(
+            &admin, &commitment(&env, 100 + i), &commitment(&env, 2 * i), &commitment(&env, 2 * i + 1), &asset,
+            &buyer, &seller, &10, &5, &None,
+        );
+    }
+    let original = MatchRecorded {
+        match_id: commitment(&env, 102),
+        asset_address: asset.clone(),
+        buy_commitment: commitment(&env, 4),
+        sell_commitment: commitment(&env, 5),
+        quantity: 10,
+        price: 5,
+        timestamp: 0,
+        reemitted: false,
+        decimals: None,
+    };
+    assert_eq!(
+        env.events().all().filter_by_contract(&client.address),
+        [original.to_xdr(&env, &client.address)]
+    );
+
+    // Replay the last two matches; the range is clamped to what exists
+    assert_eq!(client.reemit_match_events(&admin, &1, &5), 2);
+    let replayed = |i: u8| {
+        MatchRecorded {
+            match_id: commitment(&env, 100 + i),
+            asset_address: asset.clone(),
+            buy_commitment: commitment(&env, 2 * i),
+            sell_commitment: commitment(&env, 2 * i + 1),
+            quantity: 10,
+            price: 5,
+            timestamp: 0,
+            reemitted: true,
+            decimals: None,
+        }
+        .to_xdr(&env, &client.address)
+    };
+    assert_eq!(
+        env.events().all().filter_by_contract(&client.address),
+        [replayed(1), replayed(2)]
+    );
+
+    assert_eq!(
+        client.try_reemit_match_events(&Address::generate(&env), &0, &1),
+        Err(Ok(OrderbookError::OnlyAdmin))
+    );
+}
+
+#[test]
+fn test_zero_expiry_uses_default() {
+    let env = Env::default();
+    env.mock_all_auths();
+    let (client, _admin) = setup_orderbook(&env);
+    let asset = Address::generate(&env);
+    let trader = Address::generate(&env);
+    env.ledger().with_mut(|l| l.timestamp = 1_000);
+
+    // No stored preference: the global default applies
+    let receipt = client.submit_order(&trader, &commitment(&env, 1), &asset, &OrderSide::Buy, &0, &1_000, &0, &0);
+    assert_eq!(receipt.expiry, 1_000 + DEFAULT_EXPIRY_SECONDS);
+
+    client.set_default_expiry(&trader, &7_200);
+    assert_eq!(client.get_default_expiry(&trader), 7_200);
+    let receipt = client.submit_order(&trader, &commitment(&env, 2), &asset, &OrderSide::Buy, &0, &1_000, &0, &0);
+    assert_eq!(receipt.expiry, 8_200);
+    assert_eq!(client.get_active_orders(&asset).len(), 2);
+
+    // An explicit expiry still wins, and other traders keep the global default
+    let receipt = client.submit_order(&trader, &commitment(&env, 3), &asset, &OrderSide::Buy, &60, &1_000, &0, &0);
+    assert_eq!(receipt.expiry, 1_060);
+    assert_eq!(client.get_default_expiry(&Address::generate(&env)), DEFAULT_EXPIRY_SECONDS);
+
+    client.set_default_expiry(&trader, &0);
+    assert_eq!(client.get_default_expiry(&trader), DEFAULT_EXPIRY_SECONDS);
+}
+
+#[test]
+fn test_settled_timestamp() {
+    let env = Env::default();
+    env.mock_all_auths();
+    let (client, admin) = setup_orderbook(&env);
+    let asset = Address::generate(&env);
+
+    env.ledger().with_mut(|l| l.timestamp = 1_000);
+    let (buyer, seller) = submit_pair(&env, &client, &asset, 1, 2);
+    client.record_match(
+        &admin, &commitment(&env, 10), &commitment(&env, 1), &commitment(&env, 2), &asset, &buyer, &seller,
+        &10, &5, &None,
+    );
+    let record = client.get_match(&commitment(&env, 10)).unwrap();
+    assert_eq!(record.timestamp, 1_000);
+    assert_eq!(record.settled_timestamp, 0);
+
+    env.ledger().with_mut(|l| l.timestamp = 1_045);
+    client.mark_settled(&admin, &commitment(&env, 10));
+    let record = client.get_match(&commitment(&env, 10)).unwrap();
+    assert_eq!(record.settled_timestamp, 1_045);
+    assert_eq!(record.settled_timestamp - record.timestamp, 45);
+
+    // Marking again keeps the original settlement time
+    env.ledger().with_mut(|l| l.timestamp = 2_000);
+    client.mark_settled(&admin, &commitment(&env, 10));
+    assert_eq!(client.get_match(&commitment(&env, 10)).unwrap().settled_timestamp, 1_045);
+}
+
+/// Registry stand-in that reports accreditation, eligibility and settlement
+/// assets from storage
+#[contract]
+pub struct MockRegistry;
+
+#[contractimpl]
+impl MockRegistry {
+    pub fn set_accredited(env: Env, trader: Address) {
+        env.storage().instance().set(&trader, &true);
+    }
+
+    pub fn is_accredited(env: Env, trader: Address) -> bool {
+        env.storage().instance().get(&trader).unwrap_or(false)
+    }
+
+    pub fn set_settlement_asset(env: Env, asset: Address, settlement_asset: Option<Address>) {
+        env.storage().instance().set(&(symbol_short!("settle_as"), asset), &settlement_asset);
+    }
+
+    pub fn get_settlement_asset(env: Env, asset: Address) -> Option<Address> {
+        env.storage().instance().get(&(symbol_short!("settle_as"), asset)).flatten()
+    }
+
+    pub fn set_whitelist_count(env: Env, count: u32) {
+        env.storage().instance().set(&symbol_short!("wl_count"), &count);
+    }
+
+    pub fn get_whitelist_count(env: Env) -> u32 {
+        env.storage().instance().get(&symbol_short!("wl_count")).unwrap_or(0)
+    }
+
+    pub fn get_root_at(env: Env, index: u32) -> Option<BytesN<32>> {
+        (index < Self::get_whitelist_count(env.clone())).then(|| BytesN::from_array(&env, &[index as u8; 32]))
+    }
+
+    pub fn set_blacklisted(env: Env, trader: Address, blacklisted: bool) {
+        env.storage().instance().set(&(symbol_short!("blocked"), trader), &blacklisted);
+    }
+
+    pub fn is_participant_eligible(env: Env, trader: Address) -> bool {
+        !env.storage().instance().get(&(symbol_short!("blocked"), trader)).unwrap_or(false)
+    }
+}
+
+#[test]
+fn test_accredited_only_asset() {
+    let env = Env::default();
+    env.mock_all_auths();
+
+    let admin = Address::generate(&env);
+    let registry_id = env.register(MockRegistry, ());
+    let registry = MockRegistryClient::new(&env, &registry_id);
+    let contract_id = register_orderbook(&env, &admin, &registry_id, &Address::generate(&env));
+    let client = DarkPoolOrderbookClient::new(&env, &contract_id);
+
+    let asset = Address::generate(&env);
+    let accredited = Address::generate(&env);
+    let retail = Address::generate(&env);
+    registry.set_accredited(&accredited);
+
+    // Unrestricted assets accept anyone
+    client.submit_order(&retail, &commitment(&env, 1), &asset, &OrderSide::Buy, &3600, &1_000, &0, &0);
+
+    update_asset_config(&client, &admin, &asset, |c| c.accredited_only = true);
+    assert!(client.get_asset_config(&asset).accredited_only);
+    let result = client.try_submit_order(&retail, &commitment(&env, 2), &asset, &OrderSide::Buy, &3600, &1_000, &0, &0);
+    assert_eq!(result, Err(Ok(OrderbookError::AccreditationRequired)));
+    client.submit_order(&accredited, &commitment(&env, 3), &asset, &OrderSide::Sell, &3600, &1_000, &0, &0);
+
+    update_asset_config(&client, &admin, &asset, |c| c.accredited_only = false);
+    client.submit_order(&retail, &commitment(&env, 2), &asset, &OrderSide::Buy, &3600, &1_000, &0, &0);
+
+    let result = client.try_set_asset_config(&retail, &asset, &client.get_asset_config(&asset));
+    assert_eq!(result, Err(Ok(OrderbookError::OnlyAdmin)));
+}
+
+#[test]
+fn test_set_order_nullifier() {
+    let env = Env::default();
+    env.mock_all_auths();
+    let (client, _admin) = setup_orderbook(&env);
+    let asset = Address::generate(&env);
+    let (buyer, seller) = submit_pair(&env, &client, &asset, 1, 2);
+
+    let nullifier = BytesN::from_array(&env, &[11u8; 32]);
+    assert_eq!(client.get_order(&commitment(&env, 1)).unwrap().nullifier, None);
+    client.set_order_nullifier(&buyer, &commitment(&env, 1), &nullifier);
+    assert_eq!(client.get_order(&commitment(&env, 1)).unwrap().nullifier, Some(nullifier.clone()));
+
+    let result = client.try_set_order_nullifier(&seller, &commitment(&env, 1), &nullifier);
+    assert_eq!(result, Err(Ok(OrderbookError::UnauthorizedCancellation)));
+}
+#[test]
+fn test_max_outstanding_notional() {
+    let env = Env::default();
+    env.mock_all_auths();
+    let (client, settlement, admin) = setup_funded_orderbook(&env);
+
+    let asset = create_allowed_token(&env, &settlement, &admin);
+    let quote = create_allowed_token(&env, &settlement, &admin);
+    update_config(&client, &admin, |c| {
+        c.require_funded_submission = true;
+        c.funded_quote_asset = Some(quote.clone());
+        c.max_outstanding_notional = 5_000;
+    });
+
+    let trader = Address::generate(&env);
+    StellarAssetClient::new(&env, &quote).mint(&trader, &10_000);
+    settlement.deposit(&trader, &quote, &10_000);
+    StellarAssetClient::new(&env, &asset).mint(&trader, &100);
+    settlement.deposit(&trader, &asset, &100);
+
+    client.submit_order(&trader, &commitment(&env, 1), &asset, &OrderSide::Buy, &3600, &1_000, &3, &0);
+    client.submit_order(&trader, &commitment(&env, 2), &asset, &OrderSide::Buy, &3600, &1_000, &2, &0);
+    assert_eq!(client.get_outstanding_notional(&trader), 5_000);
+
+    let result = client.try_submit_order(&trader, &commitment(&env, 3), &asset, &OrderSide::Buy, &3600, &1_000, &1, &0);
+    assert_eq!(result, Err(Ok(OrderbookError::NotionalLimitExceeded)));
+
+    // Cancelling frees room under the cap
+    client.cancel_order(&trader, &commitment(&env, 1), &ownership_proof(&env), &ownership_signals(&env, &commitment(&env, 1)));
+    assert_eq!(client.get_outstanding_notional(&trader), 2_000);
+
+    // A sell is valued at its limit price, not its asset quantity
+    client.submit_order(&trader, &commitment(&env, 3), &asset, &OrderSide::Sell, &3600, &100, &30, &0);
+    assert_eq!(client.get_outstanding_notional(&trader), 5_000);
+
+    // Expired orders count until they are swept
+    env.ledger().with_mut(|l| l.timestamp += 3_601);
+    assert_eq!(client.get_outstanding_notional(&trader), 5_000);
+    client.sweep_expired(&asset, &10);
+    assert_eq!(client.get_outstanding_notional(&trader), 0);
+
+    let mut config = client.get_config();
+    config.max_outstanding_notional = -1;
+    assert_eq!(client.try_set_config(&admin, &config), Err(Ok(OrderbookError::InvalidQuantity)));
+}
+
+#[test]
+fn test_max_open_interest() {
+    let env = Env::default();
+    env.mock_all_auths();
+    let (client, admin) = setup_orderbook(&env);
+    let asset = Address::generate(&env);
+    let trader = Address::generate(&env);
+    let seller = Address::generate(&env);
+
+    update_asset_config(&client, &admin, &asset, |c| c.max_open_interest = 2_500);
+    assert_eq!(client.get_asset_config(&asset).max_open_interest, 2_500);
+    client.submit_order(&trader, &commitment(&env, 1), &asset, &OrderSide::Buy, &3600, &1_000, &0, &0);
+    client.submit_order(&seller, &commitment(&env, 2), &asset, &OrderSide::Sell, &3600, &1_000, &0, &0);
+    assert_eq!(client.get_open_interest(&asset), 2_000);
+
+    // A third order would take open interest past the cap
+    let result = client.try_submit_order(&trader, &commitment(&env, 3), &asset, &OrderSide::Buy, &3600, &1_000, &0, &0);
+    assert_eq!(result, Err(Ok(OrderbookError::OpenInterestCapReached)));
+    client.submit_order(&trader, &commitment(&env, 3), &asset, &OrderSide::Buy, &3600, &500, &0, &0);
+    assert_eq!(client.get_open_interest(&asset), 2_500);
+
+    // Cancelling frees room for new orders again
+    client.cancel_order(&trader, &commitment(&env, 1), &ownership_proof(&env), &ownership_signals(&env, &commitment(&env, 1)));
+    assert_eq!(client.get_open_interest(&asset), 1_500);
+    client.submit_order(&trader, &commitment(&env, 4), &asset, &OrderSide::Buy, &3600, &1_000, &0, &0);
+    assert_eq!(client.get_open_interest(&asset), 2_500);
+
+    // Fills take the matched quantity off both sides
+    client.record_match(
+        &admin,
+        &commitment(&env, 10),
+        &commitment(&env, 4),
+        &commitment(&env, 2),
+        &asset,
+        &trader,
+        &seller,
+        &400,
+        &1,
+        &None,
+    );
+    assert_eq!(client.get_open_interest(&asset), 1_700);
+
+    // Expired orders count until they are swept
+    env.ledger().with_mut(|l| l.timestamp += 3_601);
+    assert_eq!(client.get_open_interest(&asset), 1_700);
+    client.sweep_expired(&asset, &10);
+    assert_eq!(client.get_open_interest(&asset), 0);
+
+    // Other assets and a removed cap are unaffected
+    client.submit_order(&trader, &commitment(&env, 5), &Address::generate(&env), &OrderSide::Buy, &3600, &5_000, &0, &0);
+    update_asset_config(&client, &admin, &asset, |c| c.max_open_interest = 0);
+    client.submit_order(&trader, &commitment(&env, 6), &asset, &OrderSide::Buy, &3600, &5_000, &0, &0);
+
+    let mut config = client.get_asset_config(&asset);
+    config.max_open_interest = -1;
+    assert_eq!(client.try_set_asset_config(&admin, &asset, &config), Err(Ok(OrderbookError::InvalidQuantity)));
+}
+
+#[test]
+fn test_max_active_orders_per_asset() {
+    let env = Env::default();
+    env.mock_all_auths();
+    let (client, admin) = setup_orderbook(&env);
+    let asset = Address::generate(&env);
+    let trader = Address::generate(&env);
+
+    update_config(&client, &admin, |c| c.max_active_orders_per_asset = 3);
+    assert_eq!(client.get_config().max_active_orders_per_asset, 3);
+    for i in 1..=3u8 {
+        client.submit_order(&trader, &commitment(&env, i), &asset, &OrderSide::Buy, &3600, &1_000, &0, &0);
+    }
+    let result = client.try_submit_order(&trader, &commitment(&env, 4), &asset, &OrderSide::Buy, &3600, &1_000, &0, &0);
+    assert_eq!(result, Err(Ok(OrderbookError::TooManyActiveOrders)));
+
+    // The cap is per asset, and a cancel frees a slot
+    client.submit_order(&trader, &commitment(&env, 5), &Address::generate(&env), &OrderSide::Buy, &3600, &1_000, &0, &0);
+    client.cancel_order(&trader, &commitment(&env, 1), &ownership_proof(&env), &ownership_signals(&env, &commitment(&env, 1)));
+    client.submit_order(&trader, &commitment(&env, 4), &asset, &OrderSide::Buy, &3600, &1_000, &0, &0);
+}
+
+#[test]
+fn test_partial_fills() {
+    let env = Env::default();
+    env.mock_all_auths();
+    let (client, admin) = setup_orderbook(&env);
+    let asset = Address::generate(&env);
+
+    let buyer = Address::generate(&env);
+    let seller_a = Address::generate(&env);
+    let seller_b = Address::generate(&env);
+    client.submit_order(&buyer, &commitment(&env, 1), &asset, &OrderSide::Buy, &3600, &1_000, &0, &0);
+    client.submit_order(&seller_a, &commitment(&env, 2), &asset, &OrderSide::Sell, &3600, &400, &0, &0);
+    client.submit_order(&seller_b, &commitment(&env, 3), &asset, &OrderSide::Sell, &3600, &800, &0, &0);
+
+    // Partial: the buy stays on the book with its remainder
+    client.record_match(
+        &admin, &commitment(&env, 10), &commitment(&env, 1), &commitment(&env, 2), &asset, &buyer, &seller_a,
+        &400, &5, &None,
+    );
+    let buy = client.get_order(&commitment(&env, 1)).unwrap();
+    assert_eq!(buy.status, OrderStatus::PartiallyFilled);
+    assert_eq!(buy.remaining_quantity, 600);
+    assert_eq!(buy.filled_quantity, 400);
+    assert_eq!(client.get_order(&commitment(&env, 2)).unwrap().status, OrderStatus::Matched);
+
+    // Over-fill: more than the buy has left
+    let result = client.try_record_match(
+        &admin, &commitment(&env, 11), &commitment(&env, 1), &commitment(&env, 3), &asset, &buyer, &seller_b,
+        &700, &5, &None,
+    );
+    assert_eq!(result, Err(Ok(OrderbookError::InsufficientRemainingQuantity)));
+
+    // Full: the rest of the buy fills and only then is it matched
+    client.record_match(
+        &admin, &commitment(&env, 11), &commitment(&env, 1), &commitment(&env, 3), &asset, &buyer, &seller_b,
+        &600, &5, &None,
+    );
+    let buy = client.get_order(&commitment(&env, 1)).unwrap();
+    assert_eq!(buy.status, OrderStatus::Matched);
+    assert_eq!(buy.remaining_quantity, 0);
+    let sell = client.get_order(&commitment(&env, 3)).unwrap();
+    assert_eq!(sell.status, OrderStatus::PartiallyFilled);
+    assert_eq!(sell.remaining_quantity, 200);
+
+    // Settling leaves the partly filled sell on the book
+    client.mark_settled(&admin, &commitment(&env, 11));
+    assert_eq!(client.get_order(&commitment(&env, 1)).unwrap().status, OrderStatus::Settled);
+    assert_eq!(client.get_order(&commitment(&env, 3)).unwrap().status, OrderStatus::PartiallyFilled);
+}
+
+#[test]
+fn test_min_fill_quantity() {
+    let env = Env::default();
+    env.mock_all_auths();
+    let (client, admin) = setup_orderbook(&env);
+    let asset = Address::generate(&env);
+
+    let buyer = Address::generate(&env);
+    let seller = Address::generate(&env);
+    client.submit_order(&buyer, &commitment(&env, 1), &asset, &OrderSide::Buy, &3600, &1_000, &0, &0);
+    client.submit_order(&seller, &commitment(&env, 2), &asset, &OrderSide::Sell, &3600, &5_000, &0, &0);
+    client.set_min_fill_quantity(&buyer, &commitment(&env, 1), &300);
+    assert_eq!(client.get_order(&commitment(&env, 1)).unwrap().min_fill_quantity, 300);
+
+    let result = client.try_set_min_fill_quantity(&buyer, &commitment(&env, 1), &-1);
+    assert_eq!(result, Err(Ok(OrderbookError::InvalidQuantity)));
+    let result = client.try_set_min_fill_quantity(&seller, &commitment(&env, 1), &0);
+    assert_eq!(result, Err(Ok(OrderbookError::UnauthorizedCancellation)));
+
+    // A dust partial fill is rejected
+    let result = client.try_record_match(
+        &admin, &commitment(&env, 10), &commitment(&env, 1), &commitment(&env, 2), &asset, &buyer, &seller,
+        &299, &5, &None,
+    );
+    assert_eq!(result, Err(Ok(OrderbookError::BelowMinFill)));
+
+    // A partial fill above the minimum is accepted
+    client.record_match(
+        &admin, &commitment(&env, 10), &commitment(&env, 1), &commitment(&env, 2), &asset, &buyer, &seller,
+        &800, &5, &None,
+    );
+    assert_eq!(client.get_order(&commitment(&env, 1)).unwrap().remaining_quantity, 200);
+
+    // Filling the last 200 completes the order, so it is allowed below the minimum
+    client.record_match(
+        &admin, &commitment(&env, 11), &commitment(&env, 1), &commitment(&env, 2), &asset, &buyer, &seller,
+        &200, &5, &None,
+    );
+    assert_eq!(client.get_order(&commitment(&env, 1)).unwrap().status, OrderStatus::Matched);
+}
+
+#[test]
+fn test_min_fill_quantity_boundary() {
+    let env = Env::default();
+    env.mock_all_auths();
+    let (client, admin) = setup_orderbook(&env);
+    let asset = Address::generate(&env);
+
+    let (buyer, seller) = submit_pair(&env, &client, &asset, 1, 2);
+    client.set_min_fill_quantity(&seller, &commitment(&env, 2), &250);
+
+    let result = client.try_record_match(
+        &admin, &commitment(&env, 10), &commitment(&env, 1), &commitment(&env, 2), &asset, &buyer, &seller,
+        &249, &5, &None,
+    );
+    assert_eq!(result, Err(Ok(OrderbookError::BelowMinFill)));
+
+    // A fill of exactly the minimum is accepted
+    client.record_match(
+        &admin, &commitment(&env, 10), &commitment(&env, 1), &commitment(&env, 2), &asset, &buyer, &seller,
+        &250, &5, &None,
+    );
+    assert_eq!(client.get_order(&commitment(&env, 2)).unwrap().remaining_quantity, 750);
+}
+
+#[test]
+fn test_min_display_quantity() {
+    let env = Env::default();
+    env.mock_all_auths();
+    let (client, admin) = setup_orderbook(&env);
+    let asset = Address::generate(&env);
+
+    let buyer = Address::generate(&env);
+    let seller = Address::generate(&env);
+    let result = client.try_submit_order(&seller, &commitment(&env, 2), &asset, &OrderSide::Sell, &3600, &1_000, &0, &-1);
+    assert_eq!(result, Err(Ok(OrderbookError::InvalidQuantity)));
+    let result =
+        client.try_submit_order(&seller, &commitment(&env, 2), &asset, &OrderSide::Sell, &3600, &1_000, &0, &1_001);
+    assert_eq!(result, Err(Ok(OrderbookError::InvalidQuantity)));
+
+    client.submit_order(&buyer, &commitment(&env, 1), &asset, &OrderSide::Buy, &3600, &5_000, &0, &0);
+    client.submit_order(&seller, &commitment(&env, 2), &asset, &OrderSide::Sell, &3600, &1_000, &0, &400);
+    assert_eq!(client.get_order(&commitment(&env, 2)).unwrap().min_display_quantity, 400);
+
+    let result = client.try_record_match(
+        &admin, &commitment(&env, 10), &commitment(&env, 1), &commitment(&env, 2), &asset, &buyer, &seller,
+        &399, &5, &None,
+    );
+    assert_eq!(result, Err(Ok(OrderbookError::BelowMinimumQuantity)));
+
+    // A match of exactly the minimum is accepted
+    client.record_match(
+        &admin, &commitment(&env, 10), &commitment(&env, 1), &commitment(&env, 2), &asset, &buyer, &seller,
+        &400, &5, &None,
+    );
+    assert_eq!(client.get_order(&commitment(&env, 2)).unwrap().remaining_quantity, 600);
+
+    // A fill may not leave a remainder below the minimum, but may take it all
+    let result = client.try_record_match(
+        &admin, &commitment(&env, 11), &commitment(&env, 1), &commitment(&env, 2), &asset, &buyer, &seller,
+        &400, &5, &None,
+    );
+    assert_eq!(result, Err(Ok(OrderbookError::BelowMinimumQuantity)));
+    client.record_match(
+        &admin, &commitment(&env, 11), &commitment(&env, 1), &commitment(&env, 2), &asset, &buyer, &seller,
+        &600, &5, &None,
+    );
+    assert_eq!(client.get_order(&commitment(&env, 2)).unwrap().status, OrderStatus::Matched);
+}
+
+#[test]
+fn test_min_display_quantity_applies_to_full_fills() {
+    let env = Env::default();
+    env.mock_all_auths();
+    let (client, admin) = setup_orderbook(&env);
+    let asset = Address::generate(&env);
+
+    let buyer = Address::generate(&env);
+    let seller = Address::generate(&env);
+    client.submit_order(&buyer, &commitment(&env, 1), &asset, &OrderSide::Buy, &3600, &1_000, &0, &300);
+    client.submit_order(&seller, &commitment(&env, 2), &asset, &OrderSide::Sell, &3600, &200, &0, &0);
+
+    // The sell is fully filled by 200, but that is below the buy's floor
+    let result = client.try_record_match(
+        &admin, &commitment(&env, 10), &commitment(&env, 1), &commitment(&env, 2), &asset, &buyer, &seller,
+        &200, &5, &None,
+    );
+    assert_eq!(result, Err(Ok(OrderbookError::BelowMinimumQuantity)));
+    assert_eq!(client.get_order(&commitment(&env, 2)).unwrap().status, OrderStatus::Active);
+}
+
+#[test]
+fn test_partially_filled_status() {
+    let env = Env::default();
+    env.mock_all_auths();
+    let (client, admin) = setup_orderbook(&env);
+    let asset = Address::generate(&env);
+
+    let (buyer, seller) = submit_pair(&env, &client, &asset, 1, 2);
+    client.record_match(
+        &admin, &commitment(&env, 10), &commitment(&env, 1), &commitment(&env, 2), &asset, &buyer, &seller,
+        &250, &5, &None,
+    );
+
+    let order = client.get_order(&commitment(&env, 1)).unwrap();
+    assert_eq!(order.status, OrderStatus::PartiallyFilled);
+    assert_eq!(order.remaining_quantity, 750);
+
+    // Still matchable, so still listed as active
+    assert_eq!(client.get_active_orders(&asset).len(), 2);
 
-    let buy_orders = client.get_orders_by_asset(&asset, &Some(OrderSide::Buy));
-    let sell_orders = client.get_orders_by_asset(&asset, &Some(OrderSide::Sell));
+    let partial = client.get_orders_by_asset(&asset, &None, &Some(OrderStatus::PartiallyFilled));
+    assert_eq!(partial.orders.len(), 2);
+    let bids = client.get_orders_by_asset(&asset, &Some(OrderSide::Buy), &Some(OrderStatus::PartiallyFilled));
+    assert_eq!(bids.orders.len(), 1);
+    assert_eq!(client.get_orders_by_asset(&asset, &None, &Some(OrderStatus::Active)).orders.len(), 0);
 
-    assert_eq!(buy_orders.len(), 3);
-    assert_eq!(sell_orders.len(), 2);
+    // A partially filled order can still be cancelled
+    client.cancel_order(&buyer, &commitment(&env, 1), &ownership_proof(&env), &ownership_signals(&env, &commitment(&env, 1)));
+    assert_eq!(client.get_order(&commitment(&env, 1)).unwrap().status, OrderStatus::Cancelled);
 }
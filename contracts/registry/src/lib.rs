@@ -2,10 +2,12 @@
 
 use soroban_sdk::{
     contract, contracterror, contractimpl, contracttype, symbol_short, vec,
-    Address, Bytes, BytesN, Env, Symbol, Vec,
+    Address, Bytes, BytesN, Env, Map, Symbol, Vec,
 };
 
-use lean_imt_bn254::{LeanIMTBN254, TREE_DEPTH_KEY, TREE_LEAVES_KEY, TREE_ROOT_KEY};
+use lean_imt_bn254::{
+    compute_root_from_path, LeanIMTBN254, TREE_DEPTH_KEY, TREE_LEAVES_KEY, TREE_ROOT_KEY,
+};
 
 #[cfg(test)]
 mod test;
@@ -16,10 +18,18 @@ const VERIFIER_KEY: Symbol = symbol_short!("verifier");
 const ELIGIBILITY_VK_KEY: Symbol = symbol_short!("elig_vk");
 const PARTICIPANTS_KEY: Symbol = symbol_short!("parts");
 const ASSETS_KEY: Symbol = symbol_short!("assets");
+const ROOT_HISTORY_KEY: Symbol = symbol_short!("root_hist");
+const ACCREDITED_KEY: Symbol = symbol_short!("accredit");
+const SETTLE_ASSET_KEY: Symbol = symbol_short!("settle_as");
+const DECIMALS_KEY: Symbol = symbol_short!("decimals");
 
 // Merkle tree depth for whitelist
 const WHITELIST_TREE_DEPTH: u32 = 20;
 
+// Number of recent whitelist roots kept so proofs against a slightly stale
+// root still verify
+pub const ROOT_HISTORY_SIZE: u32 = 30;
+
 #[contracterror]
 #[derive(Copy, Clone, Debug, Eq, PartialEq, PartialOrd, Ord)]
 #[repr(u32)]
@@ -284,6 +294,89 @@ impl DarkPoolRegistry {
         Ok(())
     }
 
+    /// Set or clear the asset an RWA settles against
+    ///
+    /// # Arguments
+    /// * `admin` - Must be the admin address
+    /// * `token_address` - Registered RWA asset
+    /// * `settlement_asset` - Quote asset used to pay for it, or `None` to clear
+    pub fn set_settlement_asset(
+        env: Env,
+        admin: Address,
+        token_address: Address,
+        settlement_asset: Option<Address>,
+    ) -> Result<(), RegistryError> {
+        admin.require_auth();
+        Self::require_admin(&env, &admin)?;
+
+        if Self::get_asset(env.clone(), token_address.clone()).is_none() {
+            return Err(RegistryError::AssetNotFound);
+        }
+
+        let mut settlement_assets: Map<Address, Address> = env
+            .storage()
+            .instance()
+            .get(&SETTLE_ASSET_KEY)
+            .unwrap_or(Map::new(&env));
+        match settlement_asset {
+            Some(settlement_asset) => settlement_assets.set(token_address, settlement_asset),
+            None => {
+                settlement_assets.remove(token_address);
+            }
+        }
+        env.storage().instance().set(&SETTLE_ASSET_KEY, &settlement_assets);
+        Ok(())
+    }
+
+    /// Get the asset an RWA settles against, if configured
+    pub fn get_settlement_asset(env: Env, token_address: Address) -> Option<Address> {
+        let settlement_assets: Map<Address, Address> = env
+            .storage()
+            .instance()
+            .get(&SETTLE_ASSET_KEY)
+            .unwrap_or(Map::new(&env));
+        settlement_assets.get(token_address)
+    }
+
+    /// Set the number of decimal places an RWA's amounts are shown with
+    ///
+    /// # Arguments
+    /// * `admin` - Must be the admin address
+    /// * `token_address` - Registered RWA asset
+    /// * `decimals` - Decimal places used to render its amounts
+    pub fn set_asset_decimals(
+        env: Env,
+        admin: Address,
+        token_address: Address,
+        decimals: u32,
+    ) -> Result<(), RegistryError> {
+        admin.require_auth();
+        Self::require_admin(&env, &admin)?;
+
+        if Self::get_asset(env.clone(), token_address.clone()).is_none() {
+            return Err(RegistryError::AssetNotFound);
+        }
+
+        let mut asset_decimals: Map<Address, u32> = env
+            .storage()
+            .instance()
+            .get(&DECIMALS_KEY)
+            .unwrap_or(Map::new(&env));
+        asset_decimals.set(token_address, decimals);
+        env.storage().instance().set(&DECIMALS_KEY, &asset_decimals);
+        Ok(())
+    }
+
+    /// Get an RWA's configured decimal places, if set
+    pub fn get_asset_decimals(env: Env, token_address: Address) -> Option<u32> {
+        let asset_decimals: Map<Address, u32> = env
+            .storage()
+            .instance()
+            .get(&DECIMALS_KEY)
+            .unwrap_or(Map::new(&env));
+        asset_decimals.get(token_address)
+    }
+
     /// Get the current whitelist Merkle root
     pub fn get_whitelist_root(env: Env) -> BytesN<32> {
         env.storage()
@@ -292,6 +385,54 @@ impl DarkPoolRegistry {
             .unwrap_or(BytesN::from_array(&env, &[0u8; 32]))
     }
 
+    /// Check a whitelist inclusion proof against the current root
+    ///
+    /// # Arguments
+    /// * `leaf` - The participant's id hash
+    /// * `path` - Sibling hashes from the leaf level up, one per tree level
+    /// * `index` - The leaf's position in the tree
+    pub fn verify_merkle_proof(env: Env, leaf: BytesN<32>, path: Vec<BytesN<32>>, index: u32) -> bool {
+        let depth: u32 = env
+            .storage()
+            .instance()
+            .get(&TREE_DEPTH_KEY)
+            .unwrap_or(WHITELIST_TREE_DEPTH);
+        if path.len() != depth {
+            return false;
+        }
+        compute_root_from_path(&env, &leaf, &path, index) == Self::get_whitelist_root(env)
+    }
+
+    /// Check if a root is the current whitelist root or one of the recent ones
+    pub fn is_known_root(env: Env, root: BytesN<32>) -> bool {
+        if root == Self::get_whitelist_root(env.clone()) {
+            return true;
+        }
+        let history: Vec<BytesN<32>> = env
+            .storage()
+            .instance()
+            .get(&ROOT_HISTORY_KEY)
+            .unwrap_or(vec![&env]);
+        history.contains(&root)
+    }
+
+    /// Get the whitelist root produced by the insertion at `index`
+    ///
+    /// Returns `None` if that insertion hasn't happened yet or has fallen
+    /// out of the last `ROOT_HISTORY_SIZE` insertions.
+    pub fn get_root_at(env: Env, index: u32) -> Option<BytesN<32>> {
+        let count = Self::get_whitelist_count(env.clone());
+        if index >= count || count - index > ROOT_HISTORY_SIZE {
+            return None;
+        }
+        let history: Vec<BytesN<32>> = env
+            .storage()
+            .instance()
+            .get(&ROOT_HISTORY_KEY)
+            .unwrap_or(vec![&env]);
+        history.get(index % ROOT_HISTORY_SIZE)
+    }
+
     /// Get all registered participants
     pub fn get_participants(env: Env) -> Vec<Participant> {
         env.storage()
@@ -325,12 +466,51 @@ impl DarkPoolRegistry {
             .get(&PARTICIPANTS_KEY)
             .unwrap_or(vec![&env]);
 
-        for p in participants.iter() {
-            if p.trading_address == trading_address {
-                return Some(p);
-            }
+        participants.iter().find(|p| p.trading_address == trading_address)
+    }
+
+    /// Record whether a participant is an accredited investor
+    ///
+    /// # Arguments
+    /// * `admin` - Must be the admin address
+    /// * `trading_address` - Address of the participant
+    /// * `accredited` - Whether the participant is accredited
+    pub fn set_accredited(
+        env: Env,
+        admin: Address,
+        trading_address: Address,
+        accredited: bool,
+    ) -> Result<(), RegistryError> {
+        admin.require_auth();
+        Self::require_admin(&env, &admin)?;
+
+        if Self::get_participant(env.clone(), trading_address.clone()).is_none() {
+            return Err(RegistryError::ParticipantNotFound);
         }
-        None
+
+        let mut accredited_set: Map<Address, bool> = env
+            .storage()
+            .instance()
+            .get(&ACCREDITED_KEY)
+            .unwrap_or(Map::new(&env));
+        if accredited {
+            accredited_set.set(trading_address, true);
+        } else {
+            accredited_set.remove(trading_address);
+        }
+        env.storage().instance().set(&ACCREDITED_KEY, &accredited_set);
+        Ok(())
+    }
+
+    /// Check if a participant is an eligible, accredited investor
+    pub fn is_accredited(env: Env, trading_address: Address) -> bool {
+        let accredited_set: Map<Address, bool> = env
+            .storage()
+            .instance()
+            .get(&ACCREDITED_KEY)
+            .unwrap_or(Map::new(&env));
+        accredited_set.get(trading_address.clone()).unwrap_or(false)
+            && Self::is_participant_eligible(env, trading_address)
     }
 
     /// Check if a participant is eligible (active and KYC not expired)
@@ -375,12 +555,7 @@ impl DarkPoolRegistry {
             .get(&ASSETS_KEY)
             .unwrap_or(vec![&env]);
 
-        for a in assets.iter() {
-            if a.token_address == token_address {
-                return Some(a);
-            }
-        }
-        None
+        assets.iter().find(|a| a.token_address == token_address)
     }
 
     /// Check if an asset is eligible for trading
@@ -416,7 +591,7 @@ impl DarkPoolRegistry {
             .instance()
             .get(&TREE_LEAVES_KEY)
             .unwrap_or(vec![&env]);
-        leaves.len() as u32
+        leaves.len()
     }
 
     // Internal helper functions
@@ -430,6 +605,23 @@ impl DarkPoolRegistry {
         Ok(())
     }
 
+    /// Store the root after insertion `index` in the ring buffer
+    fn record_root(env: &Env, index: u32, root: BytesN<32>) {
+        let mut history: Vec<BytesN<32>> = env
+            .storage()
+            .instance()
+            .get(&ROOT_HISTORY_KEY)
+            .unwrap_or(vec![env]);
+
+        let slot = index % ROOT_HISTORY_SIZE;
+        if slot < history.len() {
+            history.set(slot, root);
+        } else {
+            history.push_back(root);
+        }
+        env.storage().instance().set(&ROOT_HISTORY_KEY, &history);
+    }
+
     /// Add an ID hash to the whitelist Merkle tree
     fn add_to_whitelist_tree(env: &Env, id_hash: BytesN<32>) -> Result<u32, RegistryError> {
         // Load current tree state
@@ -447,7 +639,7 @@ impl DarkPoolRegistry {
             .storage()
             .instance()
             .get(&TREE_ROOT_KEY)
-            .unwrap_or(BytesN::from_array(&env, &[0u8; 32]));
+            .unwrap_or(BytesN::from_array(env, &[0u8; 32]));
 
         // Create tree and insert
         let mut tree = LeanIMTBN254::from_storage(env, leaves, depth, root);
@@ -462,6 +654,8 @@ impl DarkPoolRegistry {
         env.storage().instance().set(&TREE_DEPTH_KEY, &new_depth);
         env.storage().instance().set(&TREE_ROOT_KEY, &new_root);
 
+        Self::record_root(env, leaf_index, new_root);
+
         Ok(leaf_index)
     }
 }
@@ -107,6 +107,83 @@ fn test_deactivate_participant() {
     assert!(!client.is_participant_eligible(&participant.trading_address));
 }
 
+#[test]
+fn test_accredited_participant() {
+    let env = Env::default();
+    env.mock_all_auths();
+
+    let admin = Address::generate(&env);
+    let verifier = Address::generate(&env);
+    let vk_bytes = Bytes::from_slice(&env, &[0u8; 100]);
+
+    let contract_id = env.register(DarkPoolRegistry, (&admin, &verifier, &vk_bytes));
+    let client = DarkPoolRegistryClient::new(&env, &contract_id);
+
+    let participant = create_test_participant(&env);
+    client.register_participant(&admin, &participant);
+    assert!(!client.is_accredited(&participant.trading_address));
+
+    client.set_accredited(&admin, &participant.trading_address, &true);
+    assert!(client.is_accredited(&participant.trading_address));
+
+    // Accreditation lapses with eligibility
+    client.deactivate_participant(&admin, &participant.trading_address);
+    assert!(!client.is_accredited(&participant.trading_address));
+
+    let result = client.try_set_accredited(&admin, &Address::generate(&env), &true);
+    assert_eq!(result, Err(Ok(RegistryError::ParticipantNotFound)));
+}
+
+#[test]
+fn test_settlement_asset() {
+    let env = Env::default();
+    env.mock_all_auths();
+
+    let admin = Address::generate(&env);
+    let verifier = Address::generate(&env);
+    let vk_bytes = Bytes::from_slice(&env, &[0u8; 100]);
+
+    let contract_id = env.register(DarkPoolRegistry, (&admin, &verifier, &vk_bytes));
+    let client = DarkPoolRegistryClient::new(&env, &contract_id);
+
+    let asset = create_test_asset(&env);
+    client.register_asset(&admin, &asset);
+    assert_eq!(client.get_settlement_asset(&asset.token_address), None);
+
+    let quote = Address::generate(&env);
+    client.set_settlement_asset(&admin, &asset.token_address, &Some(quote.clone()));
+    assert_eq!(client.get_settlement_asset(&asset.token_address), Some(quote));
+
+    client.set_settlement_asset(&admin, &asset.token_address, &None);
+    assert_eq!(client.get_settlement_asset(&asset.token_address), None);
+
+    let result = client.try_set_settlement_asset(&admin, &Address::generate(&env), &None);
+    assert_eq!(result, Err(Ok(RegistryError::AssetNotFound)));
+}
+
+#[test]
+fn test_asset_decimals() {
+    let env = Env::default();
+    env.mock_all_auths();
+
+    let admin = Address::generate(&env);
+    let verifier = Address::generate(&env);
+    let vk_bytes = Bytes::from_slice(&env, &[0u8; 100]);
+
+    let contract_id = env.register(DarkPoolRegistry, (&admin, &verifier, &vk_bytes));
+    let client = DarkPoolRegistryClient::new(&env, &contract_id);
+
+    let asset = create_test_asset(&env);
+    client.register_asset(&admin, &asset);
+    assert_eq!(client.get_asset_decimals(&asset.token_address), None);
+
+    client.set_asset_decimals(&admin, &asset.token_address, &6);
+    assert_eq!(client.get_asset_decimals(&asset.token_address), Some(6));
+
+    let result = client.try_set_asset_decimals(&admin, &Address::generate(&env), &6);
+    assert_eq!(result, Err(Ok(RegistryError::AssetNotFound)));
+}
+
 #[test]
 fn test_whitelist_root_changes() {
     let env = Env::default();
@@ -127,3 +204,91 @@ fn test_whitelist_root_changes() {
     let new_root = client.get_whitelist_root();
     assert_ne!(initial_root, new_root);
 }
+
+/// Append a leaf and root directly to storage, standing in for a real
+/// registration (each Poseidon path recompute is too slow to repeat here)
+fn simulate_insertion(env: &Env, contract_id: &Address, seed: u8) -> BytesN<32> {
+    let root = BytesN::from_array(env, &[seed; 32]);
+    env.as_contract(contract_id, || {
+        let mut leaves: Vec<BytesN<32>> =
+            env.storage().instance().get(&TREE_LEAVES_KEY).unwrap();
+        let index = leaves.len();
+        leaves.push_back(root.clone());
+        env.storage().instance().set(&TREE_LEAVES_KEY, &leaves);
+        env.storage().instance().set(&TREE_ROOT_KEY, &root);
+        DarkPoolRegistry::record_root(env, index, root.clone());
+    });
+    root
+}
+
+#[test]
+fn test_root_history() {
+    let env = Env::default();
+    env.mock_all_auths();
+
+    let admin = Address::generate(&env);
+    let verifier = Address::generate(&env);
+    let vk_bytes = Bytes::from_slice(&env, &[0u8; 100]);
+
+    let contract_id = env.register(DarkPoolRegistry, (&admin, &verifier, &vk_bytes));
+    let client = DarkPoolRegistryClient::new(&env, &contract_id);
+
+    assert!(client.get_root_at(&0).is_none());
+
+    client.register_participant(&admin, &create_test_participant(&env));
+    let first_root = client.get_whitelist_root();
+    assert_eq!(client.get_root_at(&0), Some(first_root.clone()));
+    assert!(client.is_known_root(&first_root));
+
+    let mut recent_root = first_root.clone();
+    for i in 1..ROOT_HISTORY_SIZE {
+        recent_root = simulate_insertion(&env, &contract_id, i as u8);
+    }
+
+    // The first root is still the oldest entry in the window
+    assert!(client.is_known_root(&first_root));
+    assert_eq!(client.get_root_at(&ROOT_HISTORY_SIZE), None);
+
+    // One more insertion pushes it out
+    let latest_root = simulate_insertion(&env, &contract_id, ROOT_HISTORY_SIZE as u8);
+    assert!(!client.is_known_root(&first_root));
+    assert!(client.get_root_at(&0).is_none());
+    assert!(client.is_known_root(&recent_root));
+    assert_eq!(client.get_root_at(&(ROOT_HISTORY_SIZE - 1)), Some(recent_root));
+    assert_eq!(client.get_root_at(&ROOT_HISTORY_SIZE), Some(latest_root));
+    assert!(!client.is_known_root(&BytesN::from_array(&env, &[200u8; 32])));
+}
+
+#[test]
+fn test_verify_merkle_proof() {
+    let env = Env::default();
+    env.mock_all_auths();
+
+    let admin = Address::generate(&env);
+    let verifier = Address::generate(&env);
+    let vk_bytes = Bytes::from_slice(&env, &[0u8; 100]);
+
+    let contract_id = env.register(DarkPoolRegistry, (&admin, &verifier, &vk_bytes));
+    let client = DarkPoolRegistryClient::new(&env, &contract_id);
+
+    let participant = create_test_participant(&env);
+    let index = client.register_participant(&admin, &participant);
+
+    // Build the same tree off-chain to produce the sibling path
+    let mut tree = LeanIMTBN254::new(&env, 20);
+    tree.insert(participant.id_hash.clone()).unwrap();
+    let (siblings, _) = tree.generate_proof(index).unwrap();
+    let mut path: Vec<BytesN<32>> = vec![&env];
+    for sibling in siblings.iter() {
+        path.push_back(sibling.to_bytes());
+    }
+
+    assert!(client.verify_merkle_proof(&participant.id_hash, &path, &index));
+
+    // Tampered sibling, wrong leaf, or truncated path all fail
+    let mut tampered = path.clone();
+    tampered.set(3, BytesN::from_array(&env, &[9u8; 32]));
+    assert!(!client.verify_merkle_proof(&participant.id_hash, &tampered, &index));
+    assert!(!client.verify_merkle_proof(&BytesN::from_array(&env, &[2u8; 32]), &path, &index));
+    assert!(!client.verify_merkle_proof(&participant.id_hash, &path.slice(0..19), &index));
+}
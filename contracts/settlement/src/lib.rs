@@ -1,8 +1,9 @@
 #![no_std]
+#![allow(clippy::too_many_arguments)]
 
 use soroban_sdk::{
-    contract, contracterror, contractimpl, contracttype, symbol_short, token, vec,
-    Address, Bytes, BytesN, Env, Map, Symbol, Vec,
+    contract, contracterror, contractevent, contractimpl, contracttype, symbol_short, token, vec,
+    xdr::ToXdr, Address, Bytes, BytesN, Env, Map, Symbol, Vec,
 };
 
 #[cfg(test)]
@@ -31,6 +32,17 @@ const NULLIFIERS_KEY: Symbol = symbol_short!("nulls");
 const ESCROW_KEY: Symbol = symbol_short!("escrow");
 const LOCKED_KEY: Symbol = symbol_short!("locked");
 const SETTLEMENTS_KEY: Symbol = symbol_short!("settls");
+const WITHDRAWERS_KEY: Symbol = symbol_short!("wdrawers");
+const ORDERBOOK_KEY: Symbol = symbol_short!("orderbook");
+const NETTING_KEY: Symbol = symbol_short!("netting");
+const TOKENS_KEY: Symbol = symbol_short!("tokens");
+const TEST_MODE_KEY: Symbol = symbol_short!("test_mode");
+const FROZEN_KEY: Symbol = symbol_short!("frozen");
+const CANCEL_VK_KEY: Symbol = symbol_short!("cancel_vk");
+
+// Most entries a paged getter returns in one call, to stay within Soroban's
+// return value limits
+pub const MAX_PAGE_SIZE: u32 = 100;
 
 #[contracterror]
 #[derive(Copy, Clone, Debug, Eq, PartialEq, PartialOrd, Ord)]
@@ -48,6 +60,12 @@ pub enum SettlementError {
     AlreadySettled = 10,
     InsufficientLockedFunds = 11,
     TransferFailed = 12,
+    WithdrawerNotApproved = 13,
+    OnlyOrderbook = 14,
+    TokenNotAllowed = 15,
+    BalanceFrozen = 16,
+    CancelVkNotSet = 17,
+    InvalidAmount = 18,
 }
 
 /// Settlement record for completed trades
@@ -64,6 +82,87 @@ pub struct SettlementRecord {
     pub nullifier: BytesN<32>,
 }
 
+/// Side of a delivery-vs-payment settlement
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+#[contracttype]
+#[repr(u32)]
+pub enum SettlementLeg {
+    Asset = 0,
+    Cash = 1,
+}
+
+/// Emitted for each escrow transfer made to settle a match
+///
+/// `decimals` is the asset's display precision as configured in the
+/// registry when the event was emitted, `None` if unset.
+#[contractevent]
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct LegSettled {
+    #[topic]
+    pub match_id: BytesN<32>,
+    #[topic]
+    pub leg: SettlementLeg,
+    pub from: Address,
+    pub to: Address,
+    pub asset: Address,
+    pub amount: i128,
+    pub decimals: Option<u32>,
+}
+
+/// Emitted whenever a test-mode deployment skips proof verification
+///
+/// Indexers should treat any settlement accompanied by this event as
+/// unproven.
+#[contractevent]
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct TestModeBypass {
+    #[topic]
+    pub match_id: BytesN<32>,
+}
+
+/// Emitted when two participants' netted flows are settled as one transfer
+#[contractevent]
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct NettingFlushed {
+    #[topic]
+    pub from: Address,
+    #[topic]
+    pub to: Address,
+    #[topic]
+    pub asset: Address,
+    pub amount: i128,
+}
+
+/// Flows accrued between two participants in one asset during a netting
+/// batch, seen from one side: `credits` flowed to it, `debits` away from it
+#[derive(Clone, Debug, PartialEq, Eq)]
+#[contracttype]
+pub struct NettingAccount {
+    pub credits: i128,
+    pub debits: i128,
+    pub opened_at: u64,
+}
+
+/// Netting account for a pair of participants, stored with the lower
+/// address first so both directions share one entry
+#[derive(Clone)]
+#[contracttype]
+pub struct NettingKey {
+    pub party_a: Address,
+    pub party_b: Address,
+    pub asset: Address,
+}
+
+/// Escrow accounting for an asset next to the tokens actually held
+///
+/// A solvent contract has `actual_balance` equal to `internal_total`.
+#[derive(Clone, Debug, PartialEq, Eq)]
+#[contracttype]
+pub struct Solvency {
+    pub internal_total: i128,
+    pub actual_balance: i128,
+}
+
 /// Escrow balance for a participant and asset
 #[derive(Clone)]
 #[contracttype]
@@ -72,6 +171,14 @@ pub struct EscrowKey {
     pub asset: Address,
 }
 
+/// Withdrawal delegation from a participant to a custodian
+#[derive(Clone)]
+#[contracttype]
+pub struct WithdrawerKey {
+    pub participant: Address,
+    pub delegate: Address,
+}
+
 #[contract]
 pub struct DarkPoolSettlement;
 
@@ -84,17 +191,21 @@ impl DarkPoolSettlement {
     /// * `registry_address` - Address of the registry contract
     /// * `verifier_address` - Address of the Groth16 verifier contract
     /// * `settlement_vk_bytes` - Serialized verification key for settlement proofs
+    /// * `test_mode` - Skip proof verification; only for tests and testnets,
+    ///   and fixed for the life of the contract
     pub fn __constructor(
         env: Env,
         admin: Address,
         registry_address: Address,
         verifier_address: Address,
         settlement_vk_bytes: Bytes,
+        test_mode: bool,
     ) {
         env.storage().instance().set(&ADMIN_KEY, &admin);
         env.storage().instance().set(&REGISTRY_KEY, &registry_address);
         env.storage().instance().set(&VERIFIER_KEY, &verifier_address);
         env.storage().instance().set(&SETTLEMENT_VK_KEY, &settlement_vk_bytes);
+        env.storage().instance().set(&TEST_MODE_KEY, &test_mode);
 
         // Initialize empty nullifiers list
         let nullifiers: Vec<BytesN<32>> = vec![&env];
@@ -118,10 +229,11 @@ impl DarkPoolSettlement {
         amount: i128,
     ) -> Result<i128, SettlementError> {
         depositor.require_auth();
+        Self::require_allowed_token(&env, &asset_address)?;
 
         // Transfer tokens from depositor to contract
         let token_client = token::Client::new(&env, &asset_address);
-        token_client.transfer(&depositor, &env.current_contract_address(), &amount);
+        token_client.transfer(&depositor, env.current_contract_address(), &amount);
 
         // Update escrow balance
         let new_balance = Self::add_escrow_balance(&env, &depositor, &asset_address, amount);
@@ -129,6 +241,49 @@ impl DarkPoolSettlement {
         Ok(new_balance)
     }
 
+    /// Fund several participants' escrow from one depositor in one transfer
+    ///
+    /// `amounts[i]` is credited to `participants[i]`. Every amount must be
+    /// positive and the lists the same length, otherwise nothing moves.
+    ///
+    /// # Arguments
+    /// * `depositor` - Address paying for the deposits (must authenticate)
+    /// * `participants` - Participants whose escrow is credited
+    /// * `asset` - Token contract address
+    /// * `amounts` - Amount to credit to each participant
+    ///
+    /// # Returns
+    /// * The total deposited
+    pub fn deposit_batch(
+        env: Env,
+        depositor: Address,
+        participants: Vec<Address>,
+        asset: Address,
+        amounts: Vec<i128>,
+    ) -> Result<i128, SettlementError> {
+        depositor.require_auth();
+        Self::require_allowed_token(&env, &asset)?;
+
+        if participants.len() != amounts.len() {
+            return Err(SettlementError::InvalidAmount);
+        }
+        let mut total: i128 = 0;
+        for amount in amounts.iter() {
+            if amount <= 0 {
+                return Err(SettlementError::InvalidAmount);
+            }
+            total = total.checked_add(amount).ok_or(SettlementError::InvalidAmount)?;
+        }
+
+        let token_client = token::Client::new(&env, &asset);
+        token_client.transfer(&depositor, env.current_contract_address(), &total);
+
+        for (participant, amount) in participants.iter().zip(amounts.iter()) {
+            Self::add_escrow_balance(&env, &participant, &asset, amount);
+        }
+        Ok(total)
+    }
+
     /// Withdraw tokens from escrow
     ///
     /// # Arguments
@@ -143,71 +298,447 @@ impl DarkPoolSettlement {
     ) -> Result<i128, SettlementError> {
         withdrawer.require_auth();
 
-        // Check available (unlocked) balance
-        let escrow_balance = Self::get_escrow_balance(env.clone(), withdrawer.clone(), asset_address.clone());
-        let locked_balance = Self::get_locked_balance(env.clone(), withdrawer.clone(), asset_address.clone());
-        let available = escrow_balance - locked_balance;
+        Self::withdraw_available(&env, &withdrawer, &asset_address, amount)
+    }
 
-        if available < amount {
-            return Err(SettlementError::InsufficientBalance);
-        }
+    /// Approve a delegate (e.g. a custodian) to withdraw on the participant's behalf
+    ///
+    /// # Arguments
+    /// * `participant` - Owner of the escrow (must authenticate)
+    /// * `delegate` - Address allowed to call `withdraw_for`
+    pub fn approve_withdrawer(env: Env, participant: Address, delegate: Address) {
+        participant.require_auth();
 
-        // Subtract from escrow
-        let new_balance = Self::subtract_escrow_balance(&env, &withdrawer, &asset_address, amount)?;
+        let key = WithdrawerKey { participant, delegate };
+        let mut withdrawers: Map<WithdrawerKey, bool> = env
+            .storage()
+            .instance()
+            .get(&WITHDRAWERS_KEY)
+            .unwrap_or(Map::new(&env));
+        withdrawers.set(key, true);
+        env.storage().instance().set(&WITHDRAWERS_KEY, &withdrawers);
+    }
 
-        // Transfer tokens from contract to withdrawer
-        let token_client = token::Client::new(&env, &asset_address);
-        token_client.transfer(&env.current_contract_address(), &withdrawer, &amount);
+    /// Revoke a previously approved withdrawal delegate
+    pub fn revoke_withdrawer(env: Env, participant: Address, delegate: Address) {
+        participant.require_auth();
 
-        Ok(new_balance)
+        let key = WithdrawerKey { participant, delegate };
+        let mut withdrawers: Map<WithdrawerKey, bool> = env
+            .storage()
+            .instance()
+            .get(&WITHDRAWERS_KEY)
+            .unwrap_or(Map::new(&env));
+        withdrawers.remove(key);
+        env.storage().instance().set(&WITHDRAWERS_KEY, &withdrawers);
     }
 
-    /// Lock escrow for a pending order
+    /// Withdraw tokens from a participant's escrow using a stored delegation
+    ///
+    /// Tokens are always paid out to the participant, never to the delegate.
     ///
     /// # Arguments
-    /// * `trader` - Address of the trader
+    /// * `delegate` - Approved delegate (must authenticate)
+    /// * `participant` - Owner of the escrow
     /// * `asset_address` - Token contract address
-    /// * `amount` - Amount to lock
-    pub fn lock_escrow(
+    /// * `amount` - Amount to withdraw
+    pub fn withdraw_for(
         env: Env,
-        trader: Address,
+        delegate: Address,
+        participant: Address,
         asset_address: Address,
         amount: i128,
-    ) -> Result<(), SettlementError> {
-        trader.require_auth();
+    ) -> Result<i128, SettlementError> {
+        delegate.require_auth();
+
+        if !Self::is_approved_withdrawer(env.clone(), participant.clone(), delegate) {
+            return Err(SettlementError::WithdrawerNotApproved);
+        }
 
-        let escrow_balance = Self::get_escrow_balance(env.clone(), trader.clone(), asset_address.clone());
-        let locked_balance = Self::get_locked_balance(env.clone(), trader.clone(), asset_address.clone());
-        let available = escrow_balance - locked_balance;
+        Self::withdraw_available(&env, &participant, &asset_address, amount)
+    }
 
-        if available < amount {
+    /// Lock more of a participant's escrow after an order is amended upward
+    ///
+    /// # Arguments
+    /// * `caller` - Must be the configured orderbook contract
+    /// * `participant` - Owner of the escrow
+    /// * `asset` - Token contract address
+    /// * `additional` - Extra amount to move from available to locked
+    pub fn increase_lock(
+        env: Env,
+        caller: Address,
+        participant: Address,
+        asset: Address,
+        additional: i128,
+    ) -> Result<(), SettlementError> {
+        caller.require_auth();
+        Self::require_orderbook(&env, &caller)?;
+        if additional <= 0 {
+            return Err(SettlementError::InvalidAmount);
+        }
+
+        let available = Self::get_available_balance(env.clone(), participant.clone(), asset.clone());
+        if available < additional {
             return Err(SettlementError::InsufficientEscrow);
         }
 
-        Self::add_locked_balance(&env, &trader, &asset_address, amount);
+        Self::add_locked_balance(&env, &participant, &asset, additional);
         Ok(())
     }
 
-    /// Unlock escrow when an order is cancelled
+    /// Release part of a participant's lock after an order is amended downward
     ///
     /// # Arguments
-    /// * `trader` - Address of the trader
-    /// * `asset_address` - Token contract address
-    /// * `amount` - Amount to unlock
-    pub fn unlock_escrow(
+    /// * `caller` - Must be the configured orderbook contract
+    /// * `participant` - Owner of the escrow
+    /// * `asset` - Token contract address
+    /// * `amount` - Amount to move from locked back to available
+    pub fn decrease_lock(
         env: Env,
-        trader: Address,
-        asset_address: Address,
+        caller: Address,
+        participant: Address,
+        asset: Address,
         amount: i128,
     ) -> Result<(), SettlementError> {
-        trader.require_auth();
+        caller.require_auth();
+        Self::require_orderbook(&env, &caller)?;
+        if amount <= 0 {
+            return Err(SettlementError::InvalidAmount);
+        }
 
-        let locked_balance = Self::get_locked_balance(env.clone(), trader.clone(), asset_address.clone());
-        if locked_balance < amount {
-            return Err(SettlementError::InsufficientLockedFunds);
+        Self::subtract_locked_balance(&env, &participant, &asset, amount)
+    }
+
+    /// Release the amount a cancellation proof shows was locked for an order
+    ///
+    /// The proof is checked against the cancellation verification key. Its
+    /// public signals are, in order, the locked amount (big-endian), the
+    /// order commitment, and the SHA-256 of the participant's and the
+    /// asset's XDR, so a proof only unlocks the escrow it was made for.
+    ///
+    /// # Arguments
+    /// * `caller` - Must be the configured orderbook contract
+    /// * `participant` - Owner of the escrow
+    /// * `asset` - Token contract address
+    /// * `commitment` - Commitment of the order being cancelled
+    /// * `proof_bytes` - Serialized ZK proof
+    /// * `pub_signals_bytes` - Serialized public signals
+    ///
+    /// # Returns
+    /// * The amount unlocked
+    pub fn unlock_with_proof(
+        env: Env,
+        caller: Address,
+        participant: Address,
+        asset: Address,
+        commitment: BytesN<32>,
+        proof_bytes: Bytes,
+        pub_signals_bytes: Bytes,
+    ) -> Result<i128, SettlementError> {
+        caller.require_auth();
+        Self::require_orderbook(&env, &caller)?;
+
+        let vk_bytes: Bytes = env
+            .storage()
+            .instance()
+            .get(&CANCEL_VK_KEY)
+            .ok_or(SettlementError::CancelVkNotSet)?;
+        let verifier_address: Address = env.storage().instance().get(&VERIFIER_KEY).unwrap();
+        let verifier_client = verifier_wasm::Client::new(&env, &verifier_address);
+        if !verifier_client.verify_proof_bytes(&vk_bytes, &proof_bytes, &pub_signals_bytes) {
+            return Err(SettlementError::InvalidProof);
+        }
+
+        let pub_signals = Self::parse_public_signals(&env, &pub_signals_bytes)?;
+        let participant_hash: BytesN<32> = env.crypto().sha256(&participant.clone().to_xdr(&env)).into();
+        let asset_hash: BytesN<32> = env.crypto().sha256(&asset.clone().to_xdr(&env)).into();
+        let bound = vec![&env, commitment, participant_hash, asset_hash];
+        if pub_signals.len() != 4 || pub_signals.slice(1..) != bound {
+            return Err(SettlementError::InvalidProof);
+        }
+        let amount_signal = pub_signals.get(0).unwrap().to_array();
+        let (high, low) = amount_signal.split_at(16);
+        let amount = i128::from_be_bytes(low.try_into().unwrap());
+        if high.iter().any(|b| *b != 0) || amount <= 0 {
+            return Err(SettlementError::InvalidProof);
+        }
+
+        Self::subtract_locked_balance(&env, &participant, &asset, amount)?;
+        Ok(amount)
+    }
+
+    /// Swap two participants' locked balances in one delivery-vs-payment step
+    ///
+    /// `party_a`'s locked `amount_a` of `asset_a` becomes escrow of `party_b`,
+    /// and `party_b`'s locked `amount_b` of `asset_b` becomes escrow of
+    /// `party_a`. Both locks are checked first, so either both sides move or
+    /// neither does.
+    ///
+    /// # Arguments
+    /// * `caller` - Must be the configured orderbook contract
+    pub fn swap_locked(
+        env: Env,
+        caller: Address,
+        party_a: Address,
+        asset_a: Address,
+        amount_a: i128,
+        party_b: Address,
+        asset_b: Address,
+        amount_b: i128,
+    ) -> Result<(), SettlementError> {
+        caller.require_auth();
+        Self::require_orderbook(&env, &caller)?;
+        if amount_a <= 0 || amount_b <= 0 {
+            return Err(SettlementError::InvalidAmount);
+        }
+        Self::require_allowed_token(&env, &asset_a)?;
+        Self::require_allowed_token(&env, &asset_b)?;
+
+        Self::check_transferable(&env, &party_a, &asset_a, amount_a)?;
+        Self::check_transferable(&env, &party_b, &asset_b, amount_b)?;
+
+        Self::transfer_from_escrow(&env, &party_a, &party_b, &asset_a, amount_a)?;
+        Self::transfer_from_escrow(&env, &party_b, &party_a, &asset_b, amount_b)
+    }
+
+    /// Accrue a flow between two participants to their netting account
+    /// instead of settling it
+    ///
+    /// Every flow is recorded against its counterparty, so a flush only
+    /// ever moves escrow between the two and never creates or burns it.
+    ///
+    /// # Arguments
+    /// * `matcher` - Must be the admin
+    /// * `from` - Participant paying the flow
+    /// * `to` - Participant receiving the flow
+    /// * `asset` - Token contract address
+    /// * `amount` - Positive amount flowing from `from` to `to`
+    ///
+    /// # Returns
+    /// * The account as seen from `from`
+    pub fn accrue_netting(
+        env: Env,
+        matcher: Address,
+        from: Address,
+        to: Address,
+        asset: Address,
+        amount: i128,
+    ) -> Result<NettingAccount, SettlementError> {
+        matcher.require_auth();
+        Self::require_admin(&env, &matcher)?;
+        if amount <= 0 || from == to {
+            return Err(SettlementError::InvalidAmount);
+        }
+
+        let (key, from_is_a) = Self::netting_key(&from, &to, &asset);
+        let mut accounts: Map<NettingKey, NettingAccount> = env
+            .storage()
+            .instance()
+            .get(&NETTING_KEY)
+            .unwrap_or(Map::new(&env));
+        let mut account = accounts.get(key.clone()).unwrap_or(NettingAccount {
+            credits: 0,
+            debits: 0,
+            opened_at: env.ledger().timestamp(),
+        });
+        // The stored account is seen from `party_a`
+        let total = if from_is_a { &mut account.debits } else { &mut account.credits };
+        *total = total.checked_add(amount).ok_or(SettlementError::InvalidAmount)?;
+        accounts.set(key, account.clone());
+        env.storage().instance().set(&NETTING_KEY, &accounts);
+        Ok(Self::netting_view(account, from_is_a))
+    }
+
+    /// Close a participant's netting batch in an asset, moving their net
+    /// position in a single escrow adjustment
+    ///
+    /// Every open account the participant has in the asset is closed. Each
+    /// counterparty pays or receives its own side of the batch, so escrow
+    /// only moves between the accounts' parties and the total is unchanged.
+    ///
+    /// # Arguments
+    /// * `matcher` - Must be the admin
+    /// * `participant` - Participant whose batch is closed
+    /// * `asset` - Token contract address
+    ///
+    /// # Returns
+    /// * The net amount moved, positive if `participant` received it and
+    ///   negative if `participant` paid it
+    pub fn flush_netting(
+        env: Env,
+        matcher: Address,
+        participant: Address,
+        asset: Address,
+    ) -> Result<i128, SettlementError> {
+        matcher.require_auth();
+        Self::require_admin(&env, &matcher)?;
+
+        let mut accounts: Map<NettingKey, NettingAccount> = env
+            .storage()
+            .instance()
+            .get(&NETTING_KEY)
+            .unwrap_or(Map::new(&env));
+
+        // Net each counterparty's side of the batch, positive if it pays
+        let mut flows: Vec<(Address, i128)> = vec![&env];
+        let mut net: i128 = 0;
+        for (key, account) in accounts.clone().iter() {
+            let party_is_a = key.party_a == participant;
+            if key.asset != asset || !(party_is_a || key.party_b == participant) {
+                continue;
+            }
+            let counterparty = if party_is_a { key.party_b.clone() } else { key.party_a.clone() };
+            let account = Self::netting_view(account, party_is_a);
+            let flow = account
+                .credits
+                .checked_sub(account.debits)
+                .ok_or(SettlementError::InvalidAmount)?;
+            net = net.checked_add(flow).ok_or(SettlementError::InvalidAmount)?;
+            flows.push_back((counterparty, flow));
+            accounts.remove(key);
         }
 
-        Self::subtract_locked_balance(&env, &trader, &asset_address, amount)?;
+        // Payers are debited before anyone is credited
+        for (counterparty, flow) in flows.iter() {
+            if flow > 0 {
+                Self::debit_netted(&env, &counterparty, &asset, flow)?;
+            }
+        }
+        if net < 0 {
+            let amount = net.checked_abs().ok_or(SettlementError::InvalidAmount)?;
+            Self::debit_netted(&env, &participant, &asset, amount)?;
+        } else if net > 0 {
+            Self::add_escrow_balance(&env, &participant, &asset, net);
+        }
+        for (counterparty, flow) in flows.iter() {
+            if flow == 0 {
+                continue;
+            }
+            let (from, to) = if flow > 0 {
+                (counterparty, participant.clone())
+            } else {
+                Self::add_escrow_balance(&env, &counterparty, &asset, -flow);
+                (participant.clone(), counterparty)
+            };
+            NettingFlushed {
+                from,
+                to,
+                asset: asset.clone(),
+                amount: flow.abs(),
+            }
+            .publish(&env);
+        }
+
+        env.storage().instance().set(&NETTING_KEY, &accounts);
+        Ok(net)
+    }
+
+    /// Get the open netting account between two participants for an asset,
+    /// as seen from `party`, if any
+    pub fn get_netting_account(
+        env: Env,
+        party: Address,
+        counterparty: Address,
+        asset: Address,
+    ) -> Option<NettingAccount> {
+        let (key, party_is_a) = Self::netting_key(&party, &counterparty, &asset);
+        let accounts: Map<NettingKey, NettingAccount> = env
+            .storage()
+            .instance()
+            .get(&NETTING_KEY)
+            .unwrap_or(Map::new(&env));
+        accounts.get(key).map(|account| Self::netting_view(account, party_is_a))
+    }
+
+    /// Allow or disallow a token for deposits and settlement (admin only)
+    pub fn set_token_allowed(
+        env: Env,
+        admin: Address,
+        token: Address,
+        allowed: bool,
+    ) -> Result<(), SettlementError> {
+        admin.require_auth();
+        Self::require_admin(&env, &admin)?;
+
+        let mut tokens: Map<Address, bool> = env
+            .storage()
+            .instance()
+            .get(&TOKENS_KEY)
+            .unwrap_or(Map::new(&env));
+        if allowed {
+            tokens.set(token, true);
+        } else {
+            tokens.remove(token);
+        }
+        env.storage().instance().set(&TOKENS_KEY, &tokens);
+        Ok(())
+    }
+
+    /// Check if a token is on the settlement allowlist
+    pub fn is_token_allowed(env: Env, token: Address) -> bool {
+        let tokens: Map<Address, bool> = env
+            .storage()
+            .instance()
+            .get(&TOKENS_KEY)
+            .unwrap_or(Map::new(&env));
+        tokens.get(token).unwrap_or(false)
+    }
+
+    /// Freeze or unfreeze one participant's balance of an asset (admin only)
+    ///
+    /// A frozen balance cannot be withdrawn or transferred out in
+    /// settlement; other participants and assets are unaffected.
+    pub fn freeze_balance(
+        env: Env,
+        admin: Address,
+        participant: Address,
+        asset: Address,
+        frozen: bool,
+    ) -> Result<(), SettlementError> {
+        admin.require_auth();
+        Self::require_admin(&env, &admin)?;
+
+        let mut balances: Map<EscrowKey, bool> = env
+            .storage()
+            .instance()
+            .get(&FROZEN_KEY)
+            .unwrap_or(Map::new(&env));
+        let key = EscrowKey { participant, asset };
+        if frozen {
+            balances.set(key, true);
+        } else {
+            balances.remove(key);
+        }
+        env.storage().instance().set(&FROZEN_KEY, &balances);
+        Ok(())
+    }
+
+    /// Check if a participant's balance of an asset is frozen
+    pub fn is_balance_frozen(env: Env, participant: Address, asset: Address) -> bool {
+        let balances: Map<EscrowKey, bool> = env
+            .storage()
+            .instance()
+            .get(&FROZEN_KEY)
+            .unwrap_or(Map::new(&env));
+        balances.get(EscrowKey { participant, asset }).unwrap_or(false)
+    }
+
+    /// Set the verification key for cancellation proofs (admin only)
+    pub fn set_cancel_vk(env: Env, admin: Address, vk_bytes: Bytes) -> Result<(), SettlementError> {
+        admin.require_auth();
+        Self::require_admin(&env, &admin)?;
+
+        env.storage().instance().set(&CANCEL_VK_KEY, &vk_bytes);
+        Ok(())
+    }
+
+    /// Set the orderbook contract allowed to adjust locks (admin only)
+    pub fn set_orderbook(env: Env, admin: Address, orderbook: Address) -> Result<(), SettlementError> {
+        admin.require_auth();
+        Self::require_admin(&env, &admin)?;
+
+        env.storage().instance().set(&ORDERBOOK_KEY, &orderbook);
         Ok(())
     }
 
@@ -296,21 +827,10 @@ impl DarkPoolSettlement {
         }
 
         // Verify ZK proof
-        let verifier_address: Address = env.storage().instance().get(&VERIFIER_KEY).unwrap();
-        let vk_bytes: Bytes = env.storage().instance().get(&SETTLEMENT_VK_KEY).unwrap();
-
-        let verifier_client = verifier_wasm::Client::new(&env, &verifier_address);
-
-        let is_valid = verifier_client.verify_proof_bytes(&vk_bytes, &proof_bytes, &pub_signals_bytes);
-        if !is_valid {
-            return Err(SettlementError::InvalidProof);
-        }
-
-        // Execute atomic swap - seller sends asset to buyer
-        Self::transfer_from_escrow(&env, &seller, &buyer, &asset_address, quantity)?;
+        Self::verify_settlement_proof(&env, &match_id, &proof_bytes, &pub_signals_bytes)?;
 
-        // Buyer sends payment to seller
-        Self::transfer_from_escrow(&env, &buyer, &seller, &payment_asset, price)?;
+        // Execute atomic swap - asset leg and cash leg both move or neither does
+        Self::settle_legs(&env, &match_id, &buyer, &seller, &asset_address, &payment_asset, quantity, price)?;
 
         // Mark nullifier as used
         Self::mark_nullifier_used(&env, &nullifier);
@@ -327,14 +847,73 @@ impl DarkPoolSettlement {
             nullifier: nullifier.clone(),
         };
 
-        // Store settlement record
-        let mut settlements: Vec<SettlementRecord> = env
-            .storage()
-            .instance()
-            .get(&SETTLEMENTS_KEY)
-            .unwrap_or(vec![&env]);
-        settlements.push_back(record.clone());
-        env.storage().instance().set(&SETTLEMENTS_KEY, &settlements);
+        Self::store_settlement(&env, &record);
+
+        Ok(record)
+    }
+
+    /// Settle a match recorded by the orderbook in the same transaction
+    ///
+    /// Verifies the proof, spends both orders' nullifiers and moves the
+    /// locked funds. Any failure returns an error so the orderbook can abort
+    /// the whole transaction, including the match it just recorded.
+    ///
+    /// # Arguments
+    /// * `caller` - Must be the configured orderbook contract
+    /// * `match_id` - Unique identifier for this match
+    /// * `buyer` - Buyer's address
+    /// * `seller` - Seller's address
+    /// * `asset_address` - The RWA token being traded
+    /// * `payment_asset` - The payment token
+    /// * `quantity` - Amount of RWA tokens
+    /// * `price` - Total price in payment tokens
+    /// * `proof_bytes` - Serialized ZK proof
+    /// * `pub_signals_bytes` - Serialized public signals
+    /// * `buy_nullifier` - Nullifier of the buy order
+    /// * `sell_nullifier` - Nullifier of the sell order
+    pub fn settle_private(
+        env: Env,
+        caller: Address,
+        match_id: BytesN<32>,
+        buyer: Address,
+        seller: Address,
+        asset_address: Address,
+        payment_asset: Address,
+        quantity: i128,
+        price: i128,
+        proof_bytes: Bytes,
+        pub_signals_bytes: Bytes,
+        buy_nullifier: BytesN<32>,
+        sell_nullifier: BytesN<32>,
+    ) -> Result<SettlementRecord, SettlementError> {
+        caller.require_auth();
+        Self::require_orderbook(&env, &caller)?;
+
+        if buy_nullifier == sell_nullifier
+            || Self::is_nullifier_used(env.clone(), buy_nullifier.clone())
+            || Self::is_nullifier_used(env.clone(), sell_nullifier.clone())
+        {
+            return Err(SettlementError::NullifierUsed);
+        }
+
+        Self::verify_settlement_proof(&env, &match_id, &proof_bytes, &pub_signals_bytes)?;
+
+        Self::settle_legs(&env, &match_id, &buyer, &seller, &asset_address, &payment_asset, quantity, price)?;
+
+        Self::mark_nullifier_used(&env, &buy_nullifier);
+        Self::mark_nullifier_used(&env, &sell_nullifier);
+
+        let record = SettlementRecord {
+            match_id,
+            buyer,
+            seller,
+            asset_address,
+            quantity,
+            price,
+            timestamp: env.ledger().timestamp(),
+            nullifier: buy_nullifier,
+        };
+        Self::store_settlement(&env, &record);
 
         Ok(record)
     }
@@ -349,6 +928,45 @@ impl DarkPoolSettlement {
         nullifiers.contains(&nullifier)
     }
 
+    /// Get up to `limit` consumed nullifiers starting at index `start`, oldest first
+    ///
+    /// `limit` is capped at `MAX_PAGE_SIZE`.
+    pub fn get_nullifiers_paged(env: Env, start: u32, limit: u32) -> Vec<BytesN<32>> {
+        let nullifiers: Vec<BytesN<32>> = env
+            .storage()
+            .instance()
+            .get(&NULLIFIERS_KEY)
+            .unwrap_or(vec![&env]);
+
+        let len = nullifiers.len();
+        if start >= len {
+            return vec![&env];
+        }
+        let end = start.saturating_add(limit.min(MAX_PAGE_SIZE)).min(len);
+        nullifiers.slice(start..end)
+    }
+
+    /// Get the total number of consumed nullifiers
+    pub fn get_nullifier_count(env: Env) -> u32 {
+        let nullifiers: Vec<BytesN<32>> = env
+            .storage()
+            .instance()
+            .get(&NULLIFIERS_KEY)
+            .unwrap_or(vec![&env]);
+        nullifiers.len()
+    }
+
+    /// Check if a delegate may withdraw on behalf of a participant
+    pub fn is_approved_withdrawer(env: Env, participant: Address, delegate: Address) -> bool {
+        let key = WithdrawerKey { participant, delegate };
+        let withdrawers: Map<WithdrawerKey, bool> = env
+            .storage()
+            .instance()
+            .get(&WITHDRAWERS_KEY)
+            .unwrap_or(Map::new(&env));
+        withdrawers.get(key).unwrap_or(false)
+    }
+
     /// Get escrow balance for a participant and asset
     pub fn get_escrow_balance(env: Env, participant: Address, asset: Address) -> i128 {
         let key = EscrowKey {
@@ -363,6 +981,34 @@ impl DarkPoolSettlement {
         escrow.get(key).unwrap_or(0)
     }
 
+    /// Compare the sum of escrow balances for an asset with the contract's
+    /// token balance
+    ///
+    /// Fails with `InvalidAmount` if the balances sum past `i128::MAX`,
+    /// which a solvent contract can never reach.
+    pub fn get_solvency(env: Env, asset: Address) -> Result<Solvency, SettlementError> {
+        let escrow: Map<EscrowKey, i128> = env
+            .storage()
+            .instance()
+            .get(&ESCROW_KEY)
+            .unwrap_or(Map::new(&env));
+
+        let mut internal_total: i128 = 0;
+        for (key, balance) in escrow.iter() {
+            if key.asset == asset {
+                internal_total = internal_total
+                    .checked_add(balance)
+                    .ok_or(SettlementError::InvalidAmount)?;
+            }
+        }
+
+        let token_client = token::Client::new(&env, &asset);
+        Ok(Solvency {
+            internal_total,
+            actual_balance: token_client.balance(&env.current_contract_address()),
+        })
+    }
+
     /// Get locked balance for a participant and asset
     pub fn get_locked_balance(env: Env, participant: Address, asset: Address) -> i128 {
         let key = EscrowKey {
@@ -400,12 +1046,7 @@ impl DarkPoolSettlement {
             .get(&SETTLEMENTS_KEY)
             .unwrap_or(vec![&env]);
 
-        for s in settlements.iter() {
-            if s.match_id == match_id {
-                return Some(s);
-            }
-        }
-        None
+        settlements.iter().find(|s| s.match_id == match_id)
     }
 
     /// Get admin address
@@ -418,13 +1059,105 @@ impl DarkPoolSettlement {
         env.storage().instance().get(&REGISTRY_KEY).unwrap()
     }
 
+    /// Check if the contract was deployed in test mode, skipping proof
+    /// verification
+    pub fn is_test_mode(env: Env) -> bool {
+        env.storage().instance().get(&TEST_MODE_KEY).unwrap_or(false)
+    }
+
     /// Get verifier address
     pub fn get_verifier(env: Env) -> Address {
         env.storage().instance().get(&VERIFIER_KEY).unwrap()
     }
 
+    /// Get the orderbook address, if configured
+    pub fn get_orderbook(env: Env) -> Option<Address> {
+        env.storage().instance().get(&ORDERBOOK_KEY)
+    }
+
     // Internal helper functions
 
+    fn require_admin(env: &Env, caller: &Address) -> Result<(), SettlementError> {
+        let admin: Address = env.storage().instance().get(&ADMIN_KEY).unwrap();
+        if *caller != admin {
+            return Err(SettlementError::OnlyAdmin);
+        }
+        Ok(())
+    }
+
+    fn require_orderbook(env: &Env, caller: &Address) -> Result<(), SettlementError> {
+        match Self::get_orderbook(env.clone()) {
+            Some(orderbook) if orderbook == *caller => Ok(()),
+            _ => Err(SettlementError::OnlyOrderbook),
+        }
+    }
+
+    fn withdraw_available(
+        env: &Env,
+        owner: &Address,
+        asset_address: &Address,
+        amount: i128,
+    ) -> Result<i128, SettlementError> {
+        Self::require_not_frozen(env, owner, asset_address)?;
+
+        // Check available (unlocked) balance
+        let available = Self::get_available_balance(env.clone(), owner.clone(), asset_address.clone());
+        if available < amount {
+            return Err(SettlementError::InsufficientBalance);
+        }
+
+        // Subtract from escrow
+        let new_balance = Self::subtract_escrow_balance(env, owner, asset_address, amount)?;
+
+        // Transfer tokens from contract to owner
+        let token_client = token::Client::new(env, asset_address);
+        token_client.transfer(&env.current_contract_address(), owner, &amount);
+
+        Ok(new_balance)
+    }
+
+    /// Verify a settlement proof, or skip it loudly in test mode
+    fn verify_settlement_proof(
+        env: &Env,
+        match_id: &BytesN<32>,
+        proof_bytes: &Bytes,
+        pub_signals_bytes: &Bytes,
+    ) -> Result<(), SettlementError> {
+        if Self::is_test_mode(env.clone()) {
+            TestModeBypass {
+                match_id: match_id.clone(),
+            }
+            .publish(env);
+            return Ok(());
+        }
+
+        let verifier_address: Address = env.storage().instance().get(&VERIFIER_KEY).unwrap();
+        let vk_bytes: Bytes = env.storage().instance().get(&SETTLEMENT_VK_KEY).unwrap();
+        let verifier_client = verifier_wasm::Client::new(env, &verifier_address);
+        if !verifier_client.verify_proof_bytes(&vk_bytes, proof_bytes, pub_signals_bytes) {
+            return Err(SettlementError::InvalidProof);
+        }
+        Ok(())
+    }
+
+    /// Reject outflows from a balance the admin has frozen
+    fn require_not_frozen(env: &Env, participant: &Address, asset: &Address) -> Result<(), SettlementError> {
+        if Self::is_balance_frozen(env.clone(), participant.clone(), asset.clone()) {
+            return Err(SettlementError::BalanceFrozen);
+        }
+        Ok(())
+    }
+
+    /// Reject tokens the admin has not allowlisted before crediting them
+    ///
+    /// Only inflows are gated; existing escrow can always be withdrawn.
+    fn require_allowed_token(env: &Env, token: &Address) -> Result<(), SettlementError> {
+        if !Self::is_token_allowed(env.clone(), token.clone()) {
+            return Err(SettlementError::TokenNotAllowed);
+        }
+        Ok(())
+    }
+
     fn add_escrow_balance(env: &Env, participant: &Address, asset: &Address, amount: i128) -> i128 {
         let key = EscrowKey {
             participant: participant.clone(),
@@ -434,7 +1167,7 @@ impl DarkPoolSettlement {
             .storage()
             .instance()
             .get(&ESCROW_KEY)
-            .unwrap_or(Map::new(&env));
+            .unwrap_or(Map::new(env));
 
         let current = escrow.get(key.clone()).unwrap_or(0);
         let new_balance = current + amount;
@@ -457,7 +1190,7 @@ impl DarkPoolSettlement {
             .storage()
             .instance()
             .get(&ESCROW_KEY)
-            .unwrap_or(Map::new(&env));
+            .unwrap_or(Map::new(env));
 
         let current = escrow.get(key.clone()).unwrap_or(0);
         if current < amount {
@@ -479,7 +1212,7 @@ impl DarkPoolSettlement {
             .storage()
             .instance()
             .get(&LOCKED_KEY)
-            .unwrap_or(Map::new(&env));
+            .unwrap_or(Map::new(env));
 
         let current = locked.get(key.clone()).unwrap_or(0);
         locked.set(key, current + amount);
@@ -500,7 +1233,7 @@ impl DarkPoolSettlement {
             .storage()
             .instance()
             .get(&LOCKED_KEY)
-            .unwrap_or(Map::new(&env));
+            .unwrap_or(Map::new(env));
 
         let current = locked.get(key.clone()).unwrap_or(0);
         if current < amount {
@@ -512,6 +1245,42 @@ impl DarkPoolSettlement {
         Ok(())
     }
 
+    /// Take a netted payment out of a participant's available escrow
+    fn debit_netted(env: &Env, participant: &Address, asset: &Address, amount: i128) -> Result<(), SettlementError> {
+        Self::require_not_frozen(env, participant, asset)?;
+        if Self::get_available_balance(env.clone(), participant.clone(), asset.clone()) < amount {
+            return Err(SettlementError::InsufficientEscrow);
+        }
+        Self::subtract_escrow_balance(env, participant, asset, amount)?;
+        Ok(())
+    }
+
+    /// Key a pair's netting account, and whether `party` is its `party_a`
+    fn netting_key(party: &Address, counterparty: &Address, asset: &Address) -> (NettingKey, bool) {
+        let party_is_a = party < counterparty;
+        let (party_a, party_b) = if party_is_a { (party, counterparty) } else { (counterparty, party) };
+        let key = NettingKey {
+            party_a: party_a.clone(),
+            party_b: party_b.clone(),
+            asset: asset.clone(),
+        };
+        (key, party_is_a)
+    }
+
+    /// Show a stored netting account from `party_a`'s side, or swapped
+    /// round for `party_b`
+    fn netting_view(account: NettingAccount, as_party_a: bool) -> NettingAccount {
+        if as_party_a {
+            account
+        } else {
+            NettingAccount {
+                credits: account.debits,
+                debits: account.credits,
+                opened_at: account.opened_at,
+            }
+        }
+    }
+
     fn transfer_from_escrow(
         env: &Env,
         from: &Address,
@@ -519,6 +1288,8 @@ impl DarkPoolSettlement {
         asset: &Address,
         amount: i128,
     ) -> Result<(), SettlementError> {
+        Self::require_not_frozen(env, from, asset)?;
+
         // Subtract from sender's escrow and locked
         Self::subtract_locked_balance(env, from, asset, amount)?;
         Self::subtract_escrow_balance(env, from, asset, amount)?;
@@ -529,6 +1300,92 @@ impl DarkPoolSettlement {
         Ok(())
     }
 
+    /// Delivery-vs-payment: seller delivers the asset, buyer pays the cash.
+    ///
+    /// Both legs are checked before any balance is mutated, so a failing
+    /// cash leg never leaves the asset leg applied. Each leg emits a
+    /// `LegSettled` event tagged with the match id.
+    fn settle_legs(
+        env: &Env,
+        match_id: &BytesN<32>,
+        buyer: &Address,
+        seller: &Address,
+        asset_address: &Address,
+        payment_asset: &Address,
+        quantity: i128,
+        price: i128,
+    ) -> Result<(), SettlementError> {
+        Self::require_allowed_token(env, asset_address)?;
+        Self::require_allowed_token(env, payment_asset)?;
+        Self::check_transferable(env, seller, asset_address, quantity)?;
+        Self::check_transferable(env, buyer, payment_asset, price)?;
+
+        Self::transfer_from_escrow(env, seller, buyer, asset_address, quantity)?;
+        LegSettled {
+            match_id: match_id.clone(),
+            leg: SettlementLeg::Asset,
+            from: seller.clone(),
+            to: buyer.clone(),
+            asset: asset_address.clone(),
+            amount: quantity,
+            decimals: Self::asset_decimals(env, asset_address),
+        }
+        .publish(env);
+
+        Self::transfer_from_escrow(env, buyer, seller, payment_asset, price)?;
+        LegSettled {
+            match_id: match_id.clone(),
+            leg: SettlementLeg::Cash,
+            from: buyer.clone(),
+            to: seller.clone(),
+            asset: payment_asset.clone(),
+            amount: price,
+            decimals: Self::asset_decimals(env, payment_asset),
+        }
+        .publish(env);
+        Ok(())
+    }
+
+    /// Read an asset's display decimals from the registry
+    ///
+    /// Events must not fail settlement, so an unreachable registry reads as
+    /// `None` too.
+    fn asset_decimals(env: &Env, asset: &Address) -> Option<u32> {
+        let registry_client = registry_wasm::Client::new(env, &Self::get_registry(env.clone()));
+        match registry_client.try_get_asset_decimals(asset) {
+            Ok(Ok(decimals)) => decimals,
+            _ => None,
+        }
+    }
+
+    /// Verify `from` has enough locked and escrowed funds to send `amount`
+    fn check_transferable(
+        env: &Env,
+        from: &Address,
+        asset: &Address,
+        amount: i128,
+    ) -> Result<(), SettlementError> {
+        let locked = Self::get_locked_balance(env.clone(), from.clone(), asset.clone());
+        if locked.checked_sub(amount).is_none_or(|rest| rest < 0) {
+            return Err(SettlementError::InsufficientLockedFunds);
+        }
+        let escrow = Self::get_escrow_balance(env.clone(), from.clone(), asset.clone());
+        if escrow.checked_sub(amount).is_none_or(|rest| rest < 0) {
+            return Err(SettlementError::InsufficientEscrow);
+        }
+        Ok(())
+    }
+
+    fn store_settlement(env: &Env, record: &SettlementRecord) {
+        let mut settlements: Vec<SettlementRecord> = env
+            .storage()
+            .instance()
+            .get(&SETTLEMENTS_KEY)
+            .unwrap_or(vec![env]);
+        settlements.push_back(record.clone());
+        env.storage().instance().set(&SETTLEMENTS_KEY, &settlements);
+    }
+
     fn mark_nullifier_used(env: &Env, nullifier: &BytesN<32>) {
         let mut nullifiers: Vec<BytesN<32>> = env
             .storage()
@@ -1,97 +1,847 @@
 #![cfg(test)]
 
 use super::*;
-use soroban_sdk::{testutils::Address as _, Bytes, BytesN, Env};
+use soroban_sdk::{
+    testutils::{Address as _, Events},
+    token::StellarAssetClient,
+    Bytes, BytesN, Env, Event,
+};
 
 // Note: Full integration tests require deploying the verifier and registry contracts first.
 // These are basic unit tests for escrow functionality.
 
+fn register_settlement(env: &Env) -> Address {
+    register_settlement_with(env, &Address::generate(env), false)
+}
+
+fn register_settlement_with(env: &Env, verifier: &Address, test_mode: bool) -> Address {
+    let admin = Address::generate(env);
+    let registry = Address::generate(env);
+    let vk_bytes = Bytes::from_slice(env, &[0u8; 100]);
+    env.register(DarkPoolSettlement, (&admin, &registry, verifier, &vk_bytes, test_mode))
+}
+
+fn create_token(env: &Env, client: &DarkPoolSettlementClient) -> Address {
+    let issuer = Address::generate(env);
+    let token = env.register_stellar_asset_contract_v2(issuer).address();
+    client.set_token_allowed(&client.get_admin(), &token, &true);
+    token
+}
+
+/// Allowlist tokens directly in storage, for tests that seed escrow by hand
+fn allow_tokens(env: &Env, contract_id: &Address, tokens: &[&Address]) {
+    env.as_contract(contract_id, || {
+        let mut allowed: Map<Address, bool> = env.storage().instance().get(&TOKENS_KEY).unwrap_or(Map::new(env));
+        for token in tokens {
+            allowed.set((*token).clone(), true);
+        }
+        env.storage().instance().set(&TOKENS_KEY, &allowed);
+    });
+}
+
 #[test]
 fn test_escrow_balance_tracking() {
     let env = Env::default();
+    let contract_id = register_settlement(&env);
 
     let participant = Address::generate(&env);
     let asset = Address::generate(&env);
 
-    // Initially zero
-    let balance = DarkPoolSettlement::get_escrow_balance(env.clone(), participant.clone(), asset.clone());
-    assert_eq!(balance, 0);
+    env.as_contract(&contract_id, || {
+        // Initially zero
+        let balance = DarkPoolSettlement::get_escrow_balance(env.clone(), participant.clone(), asset.clone());
+        assert_eq!(balance, 0);
 
-    // Add balance
-    DarkPoolSettlement::add_escrow_balance(&env, &participant, &asset, 1000);
-    let balance = DarkPoolSettlement::get_escrow_balance(env.clone(), participant.clone(), asset.clone());
-    assert_eq!(balance, 1000);
+        // Add balance
+        DarkPoolSettlement::add_escrow_balance(&env, &participant, &asset, 1000);
+        let balance = DarkPoolSettlement::get_escrow_balance(env.clone(), participant.clone(), asset.clone());
+        assert_eq!(balance, 1000);
 
-    // Add more
-    DarkPoolSettlement::add_escrow_balance(&env, &participant, &asset, 500);
-    let balance = DarkPoolSettlement::get_escrow_balance(env.clone(), participant.clone(), asset.clone());
-    assert_eq!(balance, 1500);
+        // Add more
+        DarkPoolSettlement::add_escrow_balance(&env, &participant, &asset, 500);
+        let balance = DarkPoolSettlement::get_escrow_balance(env.clone(), participant.clone(), asset.clone());
+        assert_eq!(balance, 1500);
+    });
 }
 
 #[test]
 fn test_locked_balance_tracking() {
     let env = Env::default();
+    let contract_id = register_settlement(&env);
 
     let participant = Address::generate(&env);
     let asset = Address::generate(&env);
 
-    // Add escrow first
-    DarkPoolSettlement::add_escrow_balance(&env, &participant, &asset, 1000);
+    env.as_contract(&contract_id, || {
+        // Add escrow first
+        DarkPoolSettlement::add_escrow_balance(&env, &participant, &asset, 1000);
 
-    // Lock some
-    DarkPoolSettlement::add_locked_balance(&env, &participant, &asset, 400);
-    let locked = DarkPoolSettlement::get_locked_balance(env.clone(), participant.clone(), asset.clone());
-    assert_eq!(locked, 400);
+        // Lock some
+        DarkPoolSettlement::add_locked_balance(&env, &participant, &asset, 400);
+        let locked = DarkPoolSettlement::get_locked_balance(env.clone(), participant.clone(), asset.clone());
+        assert_eq!(locked, 400);
 
-    // Available should be escrow - locked
-    let available = DarkPoolSettlement::get_available_balance(env.clone(), participant.clone(), asset.clone());
-    assert_eq!(available, 600);
+        // Available should be escrow - locked
+        let available = DarkPoolSettlement::get_available_balance(env.clone(), participant.clone(), asset.clone());
+        assert_eq!(available, 600);
+    });
 }
 
 #[test]
 fn test_nullifier_tracking() {
     let env = Env::default();
+    let contract_id = register_settlement(&env);
 
     let nullifier = BytesN::from_array(&env, &[1u8; 32]);
 
-    // Initialize nullifiers storage
-    let nullifiers: Vec<BytesN<32>> = vec![&env];
-    env.storage().instance().set(&symbol_short!("nulls"), &nullifiers);
-
-    // Should not be used initially
-    assert!(!DarkPoolSettlement::is_nullifier_used(env.clone(), nullifier.clone()));
+    env.as_contract(&contract_id, || {
+        // Should not be used initially
+        assert!(!DarkPoolSettlement::is_nullifier_used(env.clone(), nullifier.clone()));
 
-    // Mark as used
-    DarkPoolSettlement::mark_nullifier_used(&env, &nullifier);
+        // Mark as used
+        DarkPoolSettlement::mark_nullifier_used(&env, &nullifier);
 
-    // Should be used now
-    assert!(DarkPoolSettlement::is_nullifier_used(env.clone(), nullifier.clone()));
+        // Should be used now
+        assert!(DarkPoolSettlement::is_nullifier_used(env.clone(), nullifier.clone()));
+    });
 }
 
 #[test]
 fn test_escrow_transfer() {
     let env = Env::default();
+    let contract_id = register_settlement(&env);
 
     let alice = Address::generate(&env);
     let bob = Address::generate(&env);
     let asset = Address::generate(&env);
 
-    // Give Alice some balance and lock it
-    DarkPoolSettlement::add_escrow_balance(&env, &alice, &asset, 1000);
-    DarkPoolSettlement::add_locked_balance(&env, &alice, &asset, 1000);
+    env.as_contract(&contract_id, || {
+        // Give Alice some balance and lock it
+        DarkPoolSettlement::add_escrow_balance(&env, &alice, &asset, 1000);
+        DarkPoolSettlement::add_locked_balance(&env, &alice, &asset, 1000);
+
+        // Transfer from Alice to Bob
+        let result = DarkPoolSettlement::transfer_from_escrow(&env, &alice, &bob, &asset, 500);
+        assert!(result.is_ok());
+
+        // Check balances
+        let alice_balance = DarkPoolSettlement::get_escrow_balance(env.clone(), alice.clone(), asset.clone());
+        let bob_balance = DarkPoolSettlement::get_escrow_balance(env.clone(), bob.clone(), asset.clone());
+
+        assert_eq!(alice_balance, 500);
+        assert_eq!(bob_balance, 500);
+
+        // Alice's locked balance should also decrease
+        let alice_locked = DarkPoolSettlement::get_locked_balance(env.clone(), alice.clone(), asset.clone());
+        assert_eq!(alice_locked, 500);
+    });
+}
+
+#[test]
+fn test_withdraw_for_approved_delegate() {
+    let env = Env::default();
+    env.mock_all_auths();
+
+    let contract_id = register_settlement(&env);
+    let client = DarkPoolSettlementClient::new(&env, &contract_id);
+
+    let token = create_token(&env, &client);
+    let participant = Address::generate(&env);
+    let custodian = Address::generate(&env);
+    StellarAssetClient::new(&env, &token).mint(&participant, &1000);
+
+    client.deposit(&participant, &token, &1000);
+    client.approve_withdrawer(&participant, &custodian);
+    assert!(client.is_approved_withdrawer(&participant, &custodian));
+
+    let remaining = client.withdraw_for(&custodian, &participant, &token, &400);
+    assert_eq!(remaining, 600);
+
+    // Funds go back to the participant, not the custodian
+    let token_client = token::Client::new(&env, &token);
+    assert_eq!(token_client.balance(&participant), 400);
+    assert_eq!(token_client.balance(&custodian), 0);
+}
+
+#[test]
+fn test_deposit_batch() {
+    let env = Env::default();
+    env.mock_all_auths();
+
+    let contract_id = register_settlement(&env);
+    let client = DarkPoolSettlementClient::new(&env, &contract_id);
+
+    let token = create_token(&env, &client);
+    let operator = Address::generate(&env);
+    StellarAssetClient::new(&env, &token).mint(&operator, &10_000);
+    let participants = vec![&env, Address::generate(&env), Address::generate(&env), Address::generate(&env)];
+
+    let total = client.deposit_batch(&operator, &participants, &token, &vec![&env, 1_000, 2_500, 500]);
+    assert_eq!(total, 4_000);
+    for (participant, expected) in participants.iter().zip([1_000, 2_500, 500]) {
+        assert_eq!(client.get_escrow_balance(&participant, &token), expected);
+    }
+    let token_client = token::Client::new(&env, &token);
+    assert_eq!(token_client.balance(&operator), 6_000);
+    assert_eq!(token_client.balance(&contract_id), 4_000);
+
+    // An invalid amount or a length mismatch moves nothing
+    let result = client.try_deposit_batch(&operator, &participants, &token, &vec![&env, 1_000, 0, 500]);
+    assert_eq!(result, Err(Ok(SettlementError::InvalidAmount)));
+    let result = client.try_deposit_batch(&operator, &participants, &token, &vec![&env, 1_000, 500]);
+    assert_eq!(result, Err(Ok(SettlementError::InvalidAmount)));
+    assert_eq!(token_client.balance(&operator), 6_000);
+    assert_eq!(client.get_escrow_balance(&participants.get(0).unwrap(), &token), 1_000);
+}
+
+#[test]
+fn test_token_allowlist() {
+    let env = Env::default();
+    env.mock_all_auths();
+
+    let contract_id = register_settlement(&env);
+    let client = DarkPoolSettlementClient::new(&env, &contract_id);
+    let admin = client.get_admin();
+
+    let allowed = create_token(&env, &client);
+    let untrusted = env.register_stellar_asset_contract_v2(Address::generate(&env)).address();
+    let participant = Address::generate(&env);
+    StellarAssetClient::new(&env, &allowed).mint(&participant, &1000);
+    StellarAssetClient::new(&env, &untrusted).mint(&participant, &1000);
+    assert!(client.is_token_allowed(&allowed));
+    assert!(!client.is_token_allowed(&untrusted));
+
+    client.deposit(&participant, &allowed, &1000);
+    let result = client.try_deposit(&participant, &untrusted, &1000);
+    assert_eq!(result, Err(Ok(SettlementError::TokenNotAllowed)));
+    assert_eq!(token::Client::new(&env, &untrusted).balance(&participant), 1000);
+
+    // Removing a token blocks new deposits but never traps existing escrow
+    client.set_token_allowed(&admin, &allowed, &false);
+    StellarAssetClient::new(&env, &allowed).mint(&participant, &100);
+    let result = client.try_deposit(&participant, &allowed, &100);
+    assert_eq!(result, Err(Ok(SettlementError::TokenNotAllowed)));
+    client.withdraw(&participant, &allowed, &500);
+    assert_eq!(client.get_escrow_balance(&participant, &allowed), 500);
+    assert_eq!(token::Client::new(&env, &allowed).balance(&participant), 600);
+
+    let result = client.try_set_token_allowed(&participant, &untrusted, &true);
+    assert_eq!(result, Err(Ok(SettlementError::OnlyAdmin)));
+}
+
+#[test]
+fn test_freeze_balance() {
+    let env = Env::default();
+    env.mock_all_auths();
+
+    let contract_id = register_settlement(&env);
+    let client = DarkPoolSettlementClient::new(&env, &contract_id);
+    let admin = client.get_admin();
+    let orderbook = Address::generate(&env);
+    client.set_orderbook(&admin, &orderbook);
+
+    let rwa = create_token(&env, &client);
+    let cash = create_token(&env, &client);
+    let suspect = Address::generate(&env);
+    let other = Address::generate(&env);
+    StellarAssetClient::new(&env, &cash).mint(&suspect, &1_000);
+    StellarAssetClient::new(&env, &rwa).mint(&suspect, &1_000);
+    StellarAssetClient::new(&env, &cash).mint(&other, &1_000);
+    client.deposit(&suspect, &cash, &1_000);
+    client.deposit(&suspect, &rwa, &1_000);
+    client.deposit(&other, &cash, &1_000);
+
+    client.freeze_balance(&admin, &suspect, &cash, &true);
+    assert!(client.is_balance_frozen(&suspect, &cash));
+    assert!(!client.is_balance_frozen(&suspect, &rwa));
+
+    // Withdrawals and settlement transfers out of the frozen balance fail
+    let result = client.try_withdraw(&suspect, &cash, &100);
+    assert_eq!(result, Err(Ok(SettlementError::BalanceFrozen)));
+    client.increase_lock(&orderbook, &suspect, &cash, &100);
+    client.increase_lock(&orderbook, &other, &cash, &100);
+    let result = client.try_swap_locked(&orderbook, &suspect, &cash, &100, &other, &cash, &100);
+    assert_eq!(result, Err(Ok(SettlementError::BalanceFrozen)));
+
+    // Other assets and other participants are unaffected
+    client.withdraw(&suspect, &rwa, &100);
+    client.withdraw(&other, &cash, &100);
+
+    let result = client.try_freeze_balance(&suspect, &suspect, &cash, &false);
+    assert_eq!(result, Err(Ok(SettlementError::OnlyAdmin)));
+
+    client.freeze_balance(&admin, &suspect, &cash, &false);
+    assert!(!client.is_balance_frozen(&suspect, &cash));
+    client.withdraw(&suspect, &cash, &100);
+    assert_eq!(client.get_escrow_balance(&suspect, &cash), 900);
+}
+
+#[test]
+fn test_withdraw_for_unapproved_delegate() {
+    let env = Env::default();
+    env.mock_all_auths();
+
+    let contract_id = register_settlement(&env);
+    let client = DarkPoolSettlementClient::new(&env, &contract_id);
+
+    let token = create_token(&env, &client);
+    let participant = Address::generate(&env);
+    let stranger = Address::generate(&env);
+    StellarAssetClient::new(&env, &token).mint(&participant, &1000);
+
+    client.deposit(&participant, &token, &1000);
+
+    let result = client.try_withdraw_for(&stranger, &participant, &token, &400);
+    assert_eq!(result, Err(Ok(SettlementError::WithdrawerNotApproved)));
+
+    // A revoked delegate is rejected as well
+    client.approve_withdrawer(&participant, &stranger);
+    client.revoke_withdrawer(&participant, &stranger);
+    let result = client.try_withdraw_for(&stranger, &participant, &token, &400);
+    assert_eq!(result, Err(Ok(SettlementError::WithdrawerNotApproved)));
+
+    assert_eq!(client.get_escrow_balance(&participant, &token), 1000);
+}
+
+#[test]
+fn test_settle_legs_cash_leg_failure_skips_asset_leg() {
+    let env = Env::default();
+    let contract_id = register_settlement(&env);
+
+    let buyer = Address::generate(&env);
+    let seller = Address::generate(&env);
+    let rwa = Address::generate(&env);
+    let cash = Address::generate(&env);
+    let match_id = BytesN::from_array(&env, &[9u8; 32]);
+    allow_tokens(&env, &contract_id, &[&rwa, &cash]);
+
+    env.as_contract(&contract_id, || {
+        // Seller has the asset locked, buyer only has half the cash locked
+        DarkPoolSettlement::add_escrow_balance(&env, &seller, &rwa, 100);
+        DarkPoolSettlement::add_locked_balance(&env, &seller, &rwa, 100);
+        DarkPoolSettlement::add_escrow_balance(&env, &buyer, &cash, 5000);
+        DarkPoolSettlement::add_locked_balance(&env, &buyer, &cash, 2500);
+
+        let result = DarkPoolSettlement::settle_legs(&env, &match_id, &buyer, &seller, &rwa, &cash, 100, 5000);
+        assert_eq!(result, Err(SettlementError::InsufficientLockedFunds));
+
+        // Asset leg was not performed
+        assert_eq!(DarkPoolSettlement::get_escrow_balance(env.clone(), seller.clone(), rwa.clone()), 100);
+        assert_eq!(DarkPoolSettlement::get_locked_balance(env.clone(), seller.clone(), rwa.clone()), 100);
+        assert_eq!(DarkPoolSettlement::get_escrow_balance(env.clone(), buyer.clone(), rwa.clone()), 0);
+
+        // Once fully funded, both legs move together
+        DarkPoolSettlement::add_locked_balance(&env, &buyer, &cash, 2500);
+        let result = DarkPoolSettlement::settle_legs(&env, &match_id, &buyer, &seller, &rwa, &cash, 100, 5000);
+        assert!(result.is_ok());
+        assert_eq!(DarkPoolSettlement::get_escrow_balance(env.clone(), buyer.clone(), rwa.clone()), 100);
+        assert_eq!(DarkPoolSettlement::get_escrow_balance(env.clone(), seller.clone(), cash.clone()), 5000);
+    });
+}
+
+#[test]
+fn test_increase_and_decrease_lock() {
+    let env = Env::default();
+    env.mock_all_auths();
+
+    let contract_id = register_settlement(&env);
+    let client = DarkPoolSettlementClient::new(&env, &contract_id);
+
+    let token = create_token(&env, &client);
+    let participant = Address::generate(&env);
+    let orderbook = Address::generate(&env);
+    StellarAssetClient::new(&env, &token).mint(&participant, &1000);
+    client.deposit(&participant, &token, &1000);
+
+    // Only the configured orderbook may adjust locks
+    let result = client.try_increase_lock(&orderbook, &participant, &token, &200);
+    assert_eq!(result, Err(Ok(SettlementError::OnlyOrderbook)));
+
+    client.set_orderbook(&client.get_admin(), &orderbook);
+    client.increase_lock(&orderbook, &participant, &token, &300);
+    client.increase_lock(&orderbook, &participant, &token, &200);
+    assert_eq!(client.get_locked_balance(&participant, &token), 500);
+    assert_eq!(client.get_available_balance(&participant, &token), 500);
+
+    client.decrease_lock(&orderbook, &participant, &token, &100);
+    assert_eq!(client.get_locked_balance(&participant, &token), 400);
+
+    // The trader cannot release an order's lock directly
+    let result = client.try_decrease_lock(&participant, &participant, &token, &400);
+    assert_eq!(result, Err(Ok(SettlementError::OnlyOrderbook)));
+
+    let result = client.try_decrease_lock(&orderbook, &participant, &token, &401);
+    assert_eq!(result, Err(Ok(SettlementError::InsufficientLockedFunds)));
+
+    // Negative adjustments would move funds the other way
+    let result = client.try_increase_lock(&orderbook, &participant, &token, &-100);
+    assert_eq!(result, Err(Ok(SettlementError::InvalidAmount)));
+    let result = client.try_decrease_lock(&orderbook, &participant, &token, &-100);
+    assert_eq!(result, Err(Ok(SettlementError::InvalidAmount)));
+    let result = client.try_decrease_lock(&orderbook, &participant, &token, &0);
+    assert_eq!(result, Err(Ok(SettlementError::InvalidAmount)));
+    assert_eq!(client.get_locked_balance(&participant, &token), 400);
+}
+
+#[test]
+fn test_increase_lock_exceeds_available() {
+    let env = Env::default();
+    env.mock_all_auths();
+
+    let contract_id = register_settlement(&env);
+    let client = DarkPoolSettlementClient::new(&env, &contract_id);
+
+    let token = create_token(&env, &client);
+    let participant = Address::generate(&env);
+    let orderbook = Address::generate(&env);
+    StellarAssetClient::new(&env, &token).mint(&participant, &1000);
+    client.deposit(&participant, &token, &1000);
+    client.set_orderbook(&client.get_admin(), &orderbook);
+    client.increase_lock(&orderbook, &participant, &token, &800);
+
+    let result = client.try_increase_lock(&orderbook, &participant, &token, &201);
+    assert_eq!(result, Err(Ok(SettlementError::InsufficientEscrow)));
+    assert_eq!(client.get_locked_balance(&participant, &token), 800);
+}
+
+#[test]
+fn test_get_nullifiers_paged() {
+    let env = Env::default();
+    let contract_id = register_settlement(&env);
+    let client = DarkPoolSettlementClient::new(&env, &contract_id);
+
+    env.as_contract(&contract_id, || {
+        for i in 0..5u8 {
+            DarkPoolSettlement::mark_nullifier_used(&env, &BytesN::from_array(&env, &[i; 32]));
+        }
+    });
+    assert_eq!(client.get_nullifier_count(), 5);
+
+    let page = client.get_nullifiers_paged(&0, &2);
+    assert_eq!(page.len(), 2);
+    assert_eq!(page.get(0).unwrap(), BytesN::from_array(&env, &[0u8; 32]));
+    assert_eq!(page.get(1).unwrap(), BytesN::from_array(&env, &[1u8; 32]));
+
+    let page = client.get_nullifiers_paged(&2, &2);
+    assert_eq!(page.get(0).unwrap(), BytesN::from_array(&env, &[2u8; 32]));
+
+    // The last page is short, and paging past the end is empty
+    let page = client.get_nullifiers_paged(&4, &2);
+    assert_eq!(page.len(), 1);
+    assert_eq!(page.get(0).unwrap(), BytesN::from_array(&env, &[4u8; 32]));
+    assert_eq!(client.get_nullifiers_paged(&5, &2).len(), 0);
+
+    // However large the limit, one page holds at most MAX_PAGE_SIZE
+    env.as_contract(&contract_id, || {
+        for i in 5..=MAX_PAGE_SIZE as u8 {
+            DarkPoolSettlement::mark_nullifier_used(&env, &BytesN::from_array(&env, &[i; 32]));
+        }
+    });
+    assert_eq!(client.get_nullifiers_paged(&0, &u32::MAX).len(), MAX_PAGE_SIZE);
+    assert_eq!(client.get_nullifiers_paged(&1, &u32::MAX).len(), MAX_PAGE_SIZE);
+}
+
+#[test]
+fn test_settle_legs_emit_match_events() {
+    let env = Env::default();
+    env.mock_all_auths();
+
+    // Registry with display decimals configured for the RWA only
+    let admin = Address::generate(&env);
+    let vk_bytes = Bytes::from_slice(&env, &[0u8; 100]);
+    let registry_id = env.register(registry_wasm::WASM, (&admin, &Address::generate(&env), &vk_bytes));
+    let registry = registry_wasm::Client::new(&env, &registry_id);
+    let rwa = Address::generate(&env);
+    let cash = Address::generate(&env);
+    registry.register_asset(
+        &admin,
+        &registry_wasm::RWAAsset {
+            token_address: rwa.clone(),
+            symbol: symbol_short!("TBILL"),
+            asset_type: registry_wasm::AssetType::TreasuryBond,
+            min_trade_size: 1,
+            max_order_size: 1_000_000,
+            is_active: true,
+        },
+    );
+    registry.set_asset_decimals(&admin, &rwa, &6);
+    let contract_id = env.register(
+        DarkPoolSettlement,
+        (&admin, &registry_id, &Address::generate(&env), &vk_bytes, false),
+    );
+
+    let buyer = Address::generate(&env);
+    let seller = Address::generate(&env);
+    let match_id = BytesN::from_array(&env, &[7u8; 32]);
+    allow_tokens(&env, &contract_id, &[&rwa, &cash]);
+
+    env.as_contract(&contract_id, || {
+        DarkPoolSettlement::add_escrow_balance(&env, &seller, &rwa, 100);
+        DarkPoolSettlement::add_locked_balance(&env, &seller, &rwa, 100);
+        DarkPoolSettlement::add_escrow_balance(&env, &buyer, &cash, 5000);
+        DarkPoolSettlement::add_locked_balance(&env, &buyer, &cash, 5000);
+
+        DarkPoolSettlement::settle_legs(&env, &match_id, &buyer, &seller, &rwa, &cash, 100, 5000).unwrap();
+    });
+
+    let asset_leg = LegSettled {
+        match_id: match_id.clone(),
+        leg: SettlementLeg::Asset,
+        from: seller.clone(),
+        to: buyer.clone(),
+        asset: rwa,
+        amount: 100,
+        decimals: Some(6),
+    };
+    let cash_leg = LegSettled {
+        match_id,
+        leg: SettlementLeg::Cash,
+        from: buyer,
+        to: seller,
+        asset: cash,
+        amount: 5000,
+        decimals: None,
+    };
+    assert_eq!(
+        env.events().all().filter_by_contract(&contract_id),
+        [asset_leg.to_xdr(&env, &contract_id), cash_leg.to_xdr(&env, &contract_id)]
+    );
+}
+
+#[test]
+fn test_netting_flushes_one_net_transfer() {
+    let env = Env::default();
+    env.mock_all_auths();
+
+    let contract_id = register_settlement(&env);
+    let client = DarkPoolSettlementClient::new(&env, &contract_id);
+    let matcher = client.get_admin();
+
+    let token = create_token(&env, &client);
+    let alice = Address::generate(&env);
+    let bob = Address::generate(&env);
+    for participant in [&alice, &bob] {
+        StellarAssetClient::new(&env, &token).mint(participant, &1000);
+        client.deposit(participant, &token, &1000);
+    }
+
+    // Offsetting flows accrue against the counterparty without touching escrow
+    client.accrue_netting(&matcher, &bob, &alice, &token, &500);
+    client.accrue_netting(&matcher, &alice, &bob, &token, &300);
+    client.accrue_netting(&matcher, &bob, &alice, &token, &100);
+    client.accrue_netting(&matcher, &alice, &bob, &token, &450);
+    let account = client.get_netting_account(&alice, &bob, &token).unwrap();
+    assert_eq!((account.credits, account.debits), (600, 750));
+    let account = client.get_netting_account(&bob, &alice, &token).unwrap();
+    assert_eq!((account.credits, account.debits), (750, 600));
+    assert_eq!(client.get_escrow_balance(&alice, &token), 1000);
+
+    // The net moves from one side to the other, so total escrow is unchanged
+    assert_eq!(client.flush_netting(&matcher, &alice, &token), -150);
+    assert_eq!(
+        env.events().all().filter_by_contract(&contract_id),
+        [NettingFlushed { from: alice.clone(), to: bob.clone(), asset: token.clone(), amount: 150 }
+            .to_xdr(&env, &contract_id)]
+    );
+    assert_eq!(client.get_escrow_balance(&alice, &token), 850);
+    assert_eq!(client.get_escrow_balance(&bob, &token), 1150);
+    assert!(client.get_netting_account(&alice, &bob, &token).is_none());
+    assert_eq!(client.get_solvency(&token).internal_total, 2000);
+
+    // A net debit larger than available escrow is rejected and kept open
+    client.accrue_netting(&matcher, &alice, &bob, &token, &900);
+    let result = client.try_flush_netting(&matcher, &bob, &token);
+    assert_eq!(result, Err(Ok(SettlementError::InsufficientEscrow)));
+    assert!(client.get_netting_account(&alice, &bob, &token).is_some());
+
+    // A flush closes every counterparty's account at once, so a receipt
+    // from one can cover a payment to another
+    let carol = Address::generate(&env);
+    StellarAssetClient::new(&env, &token).mint(&carol, &1000);
+    client.deposit(&carol, &token, &1000);
+    client.accrue_netting(&matcher, &carol, &alice, &token, &200);
+    assert_eq!(client.flush_netting(&matcher, &alice, &token), -700);
+    assert_eq!(client.get_escrow_balance(&alice, &token), 150);
+    assert_eq!(client.get_escrow_balance(&bob, &token), 2050);
+    assert_eq!(client.get_escrow_balance(&carol, &token), 800);
+    assert!(client.get_netting_account(&alice, &carol, &token).is_none());
+    assert_eq!(client.get_solvency(&token).internal_total, 3000);
+    assert_eq!(client.flush_netting(&matcher, &alice, &token), 0);
+
+    // Flows must be positive and between two different participants
+    for amount in [0, -1, i128::MIN] {
+        let result = client.try_accrue_netting(&matcher, &alice, &bob, &token, &amount);
+        assert_eq!(result, Err(Ok(SettlementError::InvalidAmount)));
+    }
+    let result = client.try_accrue_netting(&matcher, &alice, &alice, &token, &1);
+    assert_eq!(result, Err(Ok(SettlementError::InvalidAmount)));
+    client.accrue_netting(&matcher, &bob, &carol, &token, &i128::MAX);
+    let result = client.try_accrue_netting(&matcher, &bob, &carol, &token, &1);
+    assert_eq!(result, Err(Ok(SettlementError::InvalidAmount)));
+}
+
+#[test]
+fn test_get_solvency_surfaces_discrepancy() {
+    let env = Env::default();
+    env.mock_all_auths();
+    let contract_id = register_settlement(&env);
+    let client = DarkPoolSettlementClient::new(&env, &contract_id);
+
+    let asset = create_token(&env, &client);
+    let alice = Address::generate(&env);
+    let bob = Address::generate(&env);
+    StellarAssetClient::new(&env, &asset).mint(&alice, &600);
+    StellarAssetClient::new(&env, &asset).mint(&bob, &400);
+    client.deposit(&alice, &asset, &600);
+    client.deposit(&bob, &asset, &400);
+
+    let solvency = client.get_solvency(&asset);
+    assert_eq!(solvency.internal_total, 1_000);
+    assert_eq!(solvency.actual_balance, 1_000);
+
+    // Tokens sent straight to the contract bypass escrow accounting
+    StellarAssetClient::new(&env, &asset).mint(&contract_id, &50);
+    let solvency = client.get_solvency(&asset);
+    assert_eq!(solvency.internal_total, 1_000);
+    assert_eq!(solvency.actual_balance, 1_050);
+
+    // Escrow credited without tokens behind it
+    env.as_contract(&contract_id, || {
+        DarkPoolSettlement::add_escrow_balance(&env, &alice, &asset, 100);
+    });
+    let solvency = client.get_solvency(&asset);
+    assert_eq!(solvency.internal_total, 1_100);
+    assert_eq!(solvency.actual_balance, 1_050);
+
+    // A total past i128::MAX is reported rather than wrapped
+    env.as_contract(&contract_id, || {
+        DarkPoolSettlement::add_escrow_balance(&env, &alice, &asset, i128::MAX - 700);
+    });
+    assert_eq!(client.try_get_solvency(&asset), Err(Ok(SettlementError::InvalidAmount)));
+}
+
+#[test]
+fn test_swap_locked() {
+    let env = Env::default();
+    env.mock_all_auths();
+
+    let contract_id = register_settlement(&env);
+    let client = DarkPoolSettlementClient::new(&env, &contract_id);
+    let orderbook = Address::generate(&env);
+    client.set_orderbook(&client.get_admin(), &orderbook);
+
+    let rwa = create_token(&env, &client);
+    let cash = create_token(&env, &client);
+    let buyer = Address::generate(&env);
+    let seller = Address::generate(&env);
+    StellarAssetClient::new(&env, &cash).mint(&buyer, &5_000);
+    StellarAssetClient::new(&env, &rwa).mint(&seller, &100);
+    client.deposit(&buyer, &cash, &5_000);
+    client.deposit(&seller, &rwa, &100);
+    client.increase_lock(&orderbook, &buyer, &cash, &5_000);
+    client.increase_lock(&orderbook, &seller, &rwa, &60);
+
+    // The seller's lock can't cover 100, so neither side moves
+    let result = client.try_swap_locked(&orderbook, &buyer, &cash, &5_000, &seller, &rwa, &100);
+    assert_eq!(result, Err(Ok(SettlementError::InsufficientLockedFunds)));
+    assert_eq!(client.get_locked_balance(&buyer, &cash), 5_000);
+    assert_eq!(client.get_escrow_balance(&seller, &cash), 0);
+
+    let result = client.try_swap_locked(&Address::generate(&env), &buyer, &cash, &3_000, &seller, &rwa, &60);
+    assert_eq!(result, Err(Ok(SettlementError::OnlyOrderbook)));
+
+    let result = client.try_swap_locked(&orderbook, &buyer, &cash, &3_000, &seller, &rwa, &-60);
+    assert_eq!(result, Err(Ok(SettlementError::InvalidAmount)));
+
+    client.swap_locked(&orderbook, &buyer, &cash, &3_000, &seller, &rwa, &60);
+    assert_eq!(client.get_escrow_balance(&buyer, &cash), 2_000);
+    assert_eq!(client.get_locked_balance(&buyer, &cash), 2_000);
+    assert_eq!(client.get_escrow_balance(&buyer, &rwa), 60);
+    assert_eq!(client.get_escrow_balance(&seller, &rwa), 40);
+    assert_eq!(client.get_locked_balance(&seller, &rwa), 0);
+    assert_eq!(client.get_escrow_balance(&seller, &cash), 3_000);
+}
+
+/// Verifier stand-in that rejects every proof
+#[contract]
+pub struct RejectingVerifier;
+
+#[contractimpl]
+impl RejectingVerifier {
+    pub fn verify_proof_bytes(_env: Env, _vk_bytes: Bytes, _proof_bytes: Bytes, _pub_signals_bytes: Bytes) -> bool {
+        false
+    }
+}
+
+/// Seed locked escrow for both sides of a private settlement
+fn seed_private_settlement(env: &Env, contract_id: &Address) -> (Address, Address, Address, Address) {
+    let buyer = Address::generate(env);
+    let seller = Address::generate(env);
+    let rwa = Address::generate(env);
+    let cash = Address::generate(env);
+    allow_tokens(env, contract_id, &[&rwa, &cash]);
+    env.as_contract(contract_id, || {
+        DarkPoolSettlement::add_escrow_balance(env, &seller, &rwa, 100);
+        DarkPoolSettlement::add_locked_balance(env, &seller, &rwa, 100);
+        DarkPoolSettlement::add_escrow_balance(env, &buyer, &cash, 5000);
+        DarkPoolSettlement::add_locked_balance(env, &buyer, &cash, 5000);
+    });
+    (buyer, seller, rwa, cash)
+}
+
+#[test]
+fn test_test_mode_bypasses_verifier() {
+    let env = Env::default();
+    env.mock_all_auths();
+
+    let verifier = env.register(RejectingVerifier, ());
+    let contract_id = register_settlement_with(&env, &verifier, true);
+    let client = DarkPoolSettlementClient::new(&env, &contract_id);
+    assert!(client.is_test_mode());
+    let orderbook = Address::generate(&env);
+    client.set_orderbook(&client.get_admin(), &orderbook);
+
+    let (buyer, seller, rwa, cash) = seed_private_settlement(&env, &contract_id);
+    let match_id = BytesN::from_array(&env, &[7u8; 32]);
+    let proof = Bytes::from_slice(&env, &[0u8; 4]);
+    client.settle_private(
+        &orderbook,
+        &match_id,
+        &buyer,
+        &seller,
+        &rwa,
+        &cash,
+        &100,
+        &5000,
+        &proof,
+        &proof,
+        &BytesN::from_array(&env, &[1u8; 32]),
+        &BytesN::from_array(&env, &[2u8; 32]),
+    );
+
+    let bypass = TestModeBypass { match_id };
+    let events = env.events().all().filter_by_contract(&contract_id);
+    assert_eq!(events.events().first(), Some(&bypass.to_xdr(&env, &contract_id)));
+    assert_eq!(client.get_escrow_balance(&buyer, &rwa), 100);
+    assert_eq!(client.get_escrow_balance(&seller, &cash), 5000);
+}
+
+#[test]
+fn test_proof_required_outside_test_mode() {
+    let env = Env::default();
+    env.mock_all_auths();
+
+    let verifier = env.register(RejectingVerifier, ());
+    let contract_id = register_settlement_with(&env, &verifier, false);
+    let client = DarkPoolSettlementClient::new(&env, &contract_id);
+    assert!(!client.is_test_mode());
+    let orderbook = Address::generate(&env);
+    client.set_orderbook(&client.get_admin(), &orderbook);
+
+    let (buyer, seller, rwa, cash) = seed_private_settlement(&env, &contract_id);
+    let proof = Bytes::from_slice(&env, &[0u8; 4]);
+    let result = client.try_settle_private(
+        &orderbook,
+        &BytesN::from_array(&env, &[7u8; 32]),
+        &buyer,
+        &seller,
+        &rwa,
+        &cash,
+        &100,
+        &5000,
+        &proof,
+        &proof,
+        &BytesN::from_array(&env, &[1u8; 32]),
+        &BytesN::from_array(&env, &[2u8; 32]),
+    );
+    assert_eq!(result.err(), Some(Ok(SettlementError::InvalidProof)));
+    assert_eq!(client.get_locked_balance(&seller, &rwa), 100);
+}
+
+/// Verifier stand-in that accepts every proof
+#[contract]
+pub struct AcceptingVerifier;
+
+#[contractimpl]
+impl AcceptingVerifier {
+    pub fn verify_proof_bytes(_env: Env, _vk_bytes: Bytes, _proof_bytes: Bytes, _pub_signals_bytes: Bytes) -> bool {
+        true
+    }
+}
+
+/// Serialize cancellation signals releasing `amount` of `asset` for
+/// `participant`'s order `commitment`
+fn unlock_signals(env: &Env, amount: i128, commitment: &BytesN<32>, participant: &Address, asset: &Address) -> Bytes {
+    let mut amount_signal = [0u8; 32];
+    amount_signal[16..].copy_from_slice(&amount.to_be_bytes());
+    let mut bytes = Bytes::from_slice(env, &4u32.to_be_bytes());
+    bytes.extend_from_slice(&amount_signal);
+    bytes.append(&Bytes::from_array(env, &commitment.to_array()));
+    for address in [participant, asset] {
+        let hash: BytesN<32> = env.crypto().sha256(&address.clone().to_xdr(env)).into();
+        bytes.append(&Bytes::from_array(env, &hash.to_array()));
+    }
+    bytes
+}
+
+#[test]
+fn test_unlock_with_proof() {
+    let env = Env::default();
+    env.mock_all_auths();
+
+    let verifier = env.register(AcceptingVerifier, ());
+    let contract_id = register_settlement_with(&env, &verifier, false);
+    let client = DarkPoolSettlementClient::new(&env, &contract_id);
+    let admin = client.get_admin();
+    let orderbook = Address::generate(&env);
+    client.set_orderbook(&admin, &orderbook);
+
+    let token = create_token(&env, &client);
+    let participant = Address::generate(&env);
+    StellarAssetClient::new(&env, &token).mint(&participant, &1000);
+    client.deposit(&participant, &token, &1000);
+    client.increase_lock(&orderbook, &participant, &token, &500);
+
+    let order = BytesN::from_array(&env, &[9u8; 32]);
+    let signals = |amount: i128| unlock_signals(&env, amount, &order, &participant, &token);
+    let proof = Bytes::from_slice(&env, &[0u8; 4]);
+    let result = client.try_unlock_with_proof(&orderbook, &participant, &token, &order, &proof, &signals(300));
+    assert_eq!(result, Err(Ok(SettlementError::CancelVkNotSet)));
+    client.set_cancel_vk(&admin, &Bytes::from_slice(&env, &[1u8; 100]));
 
-    // Transfer from Alice to Bob
-    let result = DarkPoolSettlement::transfer_from_escrow(&env, &alice, &bob, &asset, 500);
-    assert!(result.is_ok());
+    // Only the proven amount is released
+    assert_eq!(client.unlock_with_proof(&orderbook, &participant, &token, &order, &proof, &signals(300)), 300);
+    assert_eq!(client.get_locked_balance(&participant, &token), 200);
 
-    // Check balances
-    let alice_balance = DarkPoolSettlement::get_escrow_balance(env.clone(), alice.clone(), asset.clone());
-    let bob_balance = DarkPoolSettlement::get_escrow_balance(env.clone(), bob.clone(), asset.clone());
+    let result = client.try_unlock_with_proof(&orderbook, &participant, &token, &order, &proof, &signals(201));
+    assert_eq!(result, Err(Ok(SettlementError::InsufficientLockedFunds)));
+    let result = client.try_unlock_with_proof(&orderbook, &participant, &token, &order, &proof, &signals(-1));
+    assert_eq!(result, Err(Ok(SettlementError::InvalidProof)));
+    let result = client.try_unlock_with_proof(&participant, &participant, &token, &order, &proof, &signals(100));
+    assert_eq!(result, Err(Ok(SettlementError::OnlyOrderbook)));
 
-    assert_eq!(alice_balance, 500);
-    assert_eq!(bob_balance, 500);
+    // The proof is bound to one order, participant and asset
+    let other_order = BytesN::from_array(&env, &[8u8; 32]);
+    let result = client.try_unlock_with_proof(&orderbook, &participant, &token, &other_order, &proof, &signals(100));
+    assert_eq!(result, Err(Ok(SettlementError::InvalidProof)));
+    let other = Address::generate(&env);
+    let result = client.try_unlock_with_proof(&orderbook, &other, &token, &order, &proof, &signals(100));
+    assert_eq!(result, Err(Ok(SettlementError::InvalidProof)));
+    let other_token = create_token(&env, &client);
+    let result = client.try_unlock_with_proof(&orderbook, &participant, &other_token, &order, &proof, &signals(100));
+    assert_eq!(result, Err(Ok(SettlementError::InvalidProof)));
+    assert_eq!(client.get_locked_balance(&participant, &token), 200);
 
-    // Alice's locked balance should also decrease
-    let alice_locked = DarkPoolSettlement::get_locked_balance(env.clone(), alice.clone(), asset.clone());
-    assert_eq!(alice_locked, 500);
+    // A proof the verifier rejects unlocks nothing
+    let verifier = env.register(RejectingVerifier, ());
+    let contract_id = register_settlement_with(&env, &verifier, false);
+    let client = DarkPoolSettlementClient::new(&env, &contract_id);
+    client.set_orderbook(&client.get_admin(), &orderbook);
+    client.set_cancel_vk(&client.get_admin(), &Bytes::from_slice(&env, &[1u8; 100]));
+    let (_, seller, rwa, _) = seed_private_settlement(&env, &contract_id);
+    let result = client.try_unlock_with_proof(
+        &orderbook, &seller, &rwa, &order, &proof, &unlock_signals(&env, 100, &order, &seller, &rwa),
+    );
+    assert_eq!(result, Err(Ok(SettlementError::InvalidProof)));
+    assert_eq!(client.get_locked_balance(&seller, &rwa), 100);
 }
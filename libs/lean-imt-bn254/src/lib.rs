@@ -25,6 +25,25 @@ pub fn bytes_to_bn254_scalar(bytes_n: &BytesN<32>) -> Bn254Scalar {
     Bn254Scalar::from_bytes(bytes_n.clone())
 }
 
+/// Recomputes a Merkle root from a leaf and its sibling path
+///
+/// Bit `i` of `index` says whether the node at level `i` is a right child,
+/// matching the sibling order produced by `generate_proof`.
+pub fn compute_root_from_path(env: &Env, leaf: &BytesN<32>, path: &Vec<BytesN<32>>, index: u32) -> BytesN<32> {
+    let mut current = bytes_to_bn254_scalar(leaf);
+    for (level, sibling) in path.iter().enumerate() {
+        let sibling = bytes_to_bn254_scalar(&sibling);
+        let (left, right) = if (index >> level) & 1 == 0 {
+            (current, sibling)
+        } else {
+            (sibling, current)
+        };
+        let inputs = Vec::from_array(env, [left.to_u256(), right.to_u256()]);
+        current = Bn254Scalar::from_u256(poseidon2_hash::<3, Bn254Scalar>(env, &inputs));
+    }
+    bn254_scalar_to_bytes(&current)
+}
+
 /// Lean Incremental Merkle Tree implementation for BN254 curve
 /// Uses Poseidon2 hash function optimized for BN254 scalar field
 pub struct LeanIMTBN254 {
@@ -57,7 +76,7 @@ impl LeanIMTBN254 {
 
     /// Inserts a new leaf into the tree
     pub fn insert(&mut self, leaf: BytesN<32>) -> Result<(), &'static str> {
-        let current_count = self.leaves.len() as u32;
+        let current_count = self.leaves.len();
 
         if current_count >= self.capacity {
             return Err("Tree is at capacity: cannot insert more leaves");
@@ -85,7 +104,7 @@ impl LeanIMTBN254 {
 
     /// Gets the number of leaves that have been explicitly inserted
     pub fn get_leaf_count(&self) -> u32 {
-        self.leaves.len() as u32
+        self.leaves.len()
     }
 
     /// Gets the maximum capacity of the tree (2^depth)
@@ -100,7 +119,7 @@ impl LeanIMTBN254 {
 
     /// Generates a merkle proof for a given leaf index
     pub fn generate_proof(&self, leaf_index: u32) -> Option<(Vec<Bn254Scalar>, u32)> {
-        if leaf_index >= self.leaves.len() as u32 {
+        if leaf_index >= self.leaves.len() {
             return None;
         }
 
@@ -119,14 +138,14 @@ impl LeanIMTBN254 {
             let mut current_depth = 0;
 
             while current_depth < self.depth {
-                let sibling_index = if current_index % 2 == 0 {
+                let sibling_index = if current_index.is_multiple_of(2) {
                     current_index + 1
                 } else {
                     current_index - 1
                 };
 
                 let sibling_scalar = if current_depth == 0 {
-                    if sibling_index < self.leaves.len() as u32 {
+                    if sibling_index < self.leaves.len() {
                         let sibling_bytes = self.leaves.get(sibling_index).unwrap();
                         bytes_to_bn254_scalar(&sibling_bytes)
                     } else {
@@ -137,7 +156,7 @@ impl LeanIMTBN254 {
                 };
 
                 siblings.push_back(sibling_scalar);
-                current_index = current_index / 2;
+                current_index /= 2;
                 current_depth += 1;
             }
         }
@@ -156,7 +175,7 @@ impl LeanIMTBN254 {
         }
 
         if target_level == 0 {
-            if node_index < self.leaves.len() as u32 {
+            if node_index < self.leaves.len() {
                 let leaf_bytes = self.leaves.get(node_index).unwrap();
                 bytes_to_bn254_scalar(&leaf_bytes)
             } else {
@@ -176,7 +195,7 @@ impl LeanIMTBN254 {
 
     /// Incremental update using path recomputation
     fn incremental_update(&mut self) {
-        let leaf_index = (self.leaves.len() - 1) as u32;
+        let leaf_index = self.leaves.len() - 1;
 
         let leaf_bytes = self.leaves.get(leaf_index).unwrap();
         let leaf_scalar = bytes_to_bn254_scalar(&leaf_bytes);
@@ -197,14 +216,14 @@ impl LeanIMTBN254 {
         let mut current_scalar = leaf_scalar;
 
         while current_level < self.depth {
-            let sibling_index = if current_index % 2 == 0 {
+            let sibling_index = if current_index.is_multiple_of(2) {
                 current_index + 1
             } else {
                 current_index - 1
             };
 
             let sibling_scalar = if current_level == 0 {
-                if sibling_index < self.leaves.len() as u32 {
+                if sibling_index < self.leaves.len() {
                     let sibling_bytes = self.leaves.get(sibling_index).unwrap();
                     bytes_to_bn254_scalar(&sibling_bytes)
                 } else {
@@ -218,7 +237,7 @@ impl LeanIMTBN254 {
                 }
             };
 
-            let parent_scalar = if current_index % 2 == 0 {
+            let parent_scalar = if current_index.is_multiple_of(2) {
                 self.hash_pair_with_sponge(&mut sponge, current_scalar, sibling_scalar)
             } else {
                 self.hash_pair_with_sponge(&mut sponge, sibling_scalar, current_scalar)
@@ -228,7 +247,7 @@ impl LeanIMTBN254 {
             let parent_level = current_level + 1;
             self.cache_sparse_node(parent_level, parent_index, parent_scalar.clone());
 
-            current_index = current_index / 2;
+            current_index /= 2;
             current_level = parent_level;
             current_scalar = parent_scalar;
         }
@@ -350,10 +369,7 @@ impl LeanIMTBN254 {
 
     /// Gets a leaf at a specific index
     pub fn get_leaf(&self, index: usize) -> Option<BytesN<32>> {
-        match self.leaves.get(index.try_into().unwrap()) {
-            Some(leaf) => Some(leaf.clone()),
-            None => None,
-        }
+        self.leaves.get(index.try_into().unwrap())
     }
 
     /// Gets a leaf as Bn254Scalar at a specific index
@@ -414,6 +430,24 @@ mod tests {
 
         let (siblings, depth) = proof.unwrap();
         assert_eq!(depth, 3);
-        assert_eq!(siblings.len() as u32, 3);
+        assert_eq!(siblings.len(), 3);
+    }
+
+    #[test]
+    fn test_compute_root_from_path() {
+        let env = Env::default();
+        let mut tree = LeanIMTBN254::new(&env, 3);
+
+        let leaf = BytesN::from_array(&env, &[1u8; 32]);
+        tree.insert(leaf.clone()).unwrap();
+        tree.insert(BytesN::from_array(&env, &[2u8; 32])).unwrap();
+
+        let (siblings, _) = tree.generate_proof(0).unwrap();
+        let mut path = vec![&env];
+        for sibling in siblings.iter() {
+            path.push_back(bn254_scalar_to_bytes(&sibling));
+        }
+        assert_eq!(compute_root_from_path(&env, &leaf, &path, 0), tree.get_root());
+        assert_ne!(compute_root_from_path(&env, &leaf, &path, 1), tree.get_root());
     }
 }